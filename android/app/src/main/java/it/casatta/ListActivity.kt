@@ -26,6 +26,7 @@ class ListActivity : AppCompatActivity() , ItemsAdapter.ItemGesture {
     private var listOutput = Rust.ListOutput( emptyList(),  emptyList(),  emptyList())
     private val mapper: ObjectMapper = ObjectMapper().registerModule(KotlinModule())
     private var rawHexes: ArrayList<String> = ArrayList()
+    private var urParts: ArrayList<String> = ArrayList()
     private var diceLaunches: ArrayList<Int> = ArrayList()
     private var faces: Int = 0
     private var keyName: String? = null
@@ -357,8 +358,7 @@ class ListActivity : AppCompatActivity() , ItemsAdapter.ItemGesture {
     private fun saveWallet(content: String) {
         Log.d("LIST", "saveWallet $content")
         try {
-            val json = mapper.readValue(content, Rust.WalletJson::class.java)
-            Rust().importWallet(filesDir.toString(), json)
+            Rust().importWallet(filesDir.toString(), content)
         } catch (e: Exception) {
             Log.e("LIST", e.message?:"Null")
             setResultMessage(R.string.wallet_not_imported)
@@ -384,6 +384,20 @@ class ListActivity : AppCompatActivity() , ItemsAdapter.ItemGesture {
         }
     }
 
+    private fun importMerged(hexResult: String) {
+        when (intent.getIntExtra(C.WHAT, 0)) {
+            IMPORT_WALLET -> {
+                val bytes = decodeHexString(hexResult)
+                saveWallet(bytes!!.toString(Charsets.UTF_8))
+                finish()
+            }
+            IMPORT_PSBT -> {
+                savePsbt(hexResult, "hex")
+                finish()
+            }
+        }
+    }
+
     override fun onActivityResult(
         requestCode: Int,
         resultCode: Int,
@@ -393,6 +407,7 @@ class ListActivity : AppCompatActivity() , ItemsAdapter.ItemGesture {
         if (result != null) {
             if (result.contents == null) {
                 rawHexes.clear()
+                urParts.clear()
                 C.showMessageDialog(this, R.string.cancelled)
             } else {
                 val hexString = result.rawBytes.toHexString()
@@ -402,17 +417,18 @@ class ListActivity : AppCompatActivity() , ItemsAdapter.ItemGesture {
                         val hexResult = Rust().mergeQrs(filesDir.toString(), this.rawHexes)
                         rawHexes.clear()
                         Log.d("MAIN", "qr complete: $hexResult")
-                        when (intent.getIntExtra(C.WHAT, 0)) {
-                            IMPORT_WALLET -> {
-                                val bytes = decodeHexString(hexResult)
-                                saveWallet(bytes!!.toString(Charsets.UTF_8))
-                                finish()
-                            }
-                            IMPORT_PSBT -> {
-                                savePsbt(hexResult, "hex")
-                                finish()
-                            }
-                        }
+                        importMerged(hexResult)
+                    } catch (e: RustException) {
+                        launchScan("Next")
+                    }
+                } else if (result.contents.startsWith("UR:", ignoreCase = true)) {
+                    rawHexes.clear()
+                    this.urParts.add(result.contents)
+                    try {
+                        val hexResult = Rust().mergeUrs(filesDir.toString(), this.urParts)
+                        urParts.clear()
+                        Log.d("MAIN", "ur complete: $hexResult")
+                        importMerged(hexResult)
                     } catch (e: RustException) {
                         launchScan("Next")
                     }
@@ -6,6 +6,7 @@ import android.os.Bundle
 import androidx.appcompat.app.AlertDialog
 import androidx.appcompat.app.AppCompatActivity
 import kotlinx.android.synthetic.main.activity_main.*
+import java.math.BigDecimal
 
 class MainActivity : AppCompatActivity() {
 
@@ -42,12 +43,17 @@ class MainActivity : AppCompatActivity() {
                 val walletFile = "$filesDir/${Network.TYPE}/wallets/${wallet_text.text}/descriptor.json"
                 val psbtFile = "$filesDir/${Network.TYPE}/psbts/${psbt_text.text}/psbt.json"
                 try {
-                    val result = Rust().sign(filesDir.toString(), keyFile, walletFile, psbtFile)
-                    if (result.info.contains("Added signatures")) {
-                        AlertDialog.Builder(this).setMessage("Added signatures").create().show()
-                    } else {
-                        AlertDialog.Builder(this).setMessage("No signatures added").create().show()
-                    }
+                    // the signer checks the wallet balance is the one confirmed by the user
+                    val balances = Rust().print(filesDir.toString(), psbtFile).balances
+                    val prefix = "${wallet_text.text}: "
+                    val balance = balances.lines().find { it.startsWith(prefix) }?.removePrefix(prefix) ?: "0 BTC"
+                    AlertDialog.Builder(this)
+                        .setMessage("Wallet ${wallet_text.text} balance: $balance")
+                        .setPositiveButton("Sign") { _, _ ->
+                            sign(keyFile, walletFile, psbtFile, toSat(balance))
+                        }
+                        .setNegativeButton("Cancel", null)
+                        .create().show()
                 } catch (e: RustException) {
                     C.showMessageDialog(this, e.message?:"Null")
                 }
@@ -57,6 +63,21 @@ class MainActivity : AppCompatActivity() {
         C.showMessageIfInIntent(this, intent)
     }
 
+    private fun sign(keyFile: String, walletFile: String, psbtFile: String, confirmedBalance: Long) {
+        try {
+            val result = Rust().sign(filesDir.toString(), keyFile, walletFile, psbtFile, confirmedBalance = confirmedBalance)
+            if (result.info.contains("Added signatures")) {
+                AlertDialog.Builder(this).setMessage("Added signatures").create().show()
+            } else {
+                AlertDialog.Builder(this).setMessage("No signatures added").create().show()
+            }
+        } catch (e: RustException) {
+            C.showMessageDialog(this, e.message?:"Null")
+        }
+    }
+
+    private fun toSat(btc: String): Long = BigDecimal(btc.removeSuffix(" BTC")).movePointRight(8).longValueExact()
+
     override fun onActivityResult(
         requestCode: Int,
         resultCode: Int,
@@ -17,6 +17,7 @@ class Rust {
 
     data class ListOutput(
         val keys: List<MasterKeyOutput>,
+        val encrypted_keys: List<EncryptedKeyOutput> = listOf(),
         val wallets: List<CreateWalletOutput>,
         val psbts: List<PsbtJsonOutput>
     )
@@ -34,7 +35,15 @@ class Rust {
         val xprv: String,
         val mnemonic: String?,
         val dice: Dice?,
-        val fingerprint: String
+        val fingerprint: String,
+        val bip39_passphrase: Boolean = false
+    )
+
+    data class EncryptedKeyOutput(
+        val name: String,
+        val fingerprint: String,
+        val xpub: String,
+        val private_file: String
     )
 
     data class Dice(val launches: String, val faces: Int, val value: String)
@@ -52,7 +61,8 @@ class Rust {
         val fingerprints: List<String>,
         val required_sig: Int,
         val created_at_height: Int,
-        val daemon_opts: DaemonOpts?
+        val daemon_opts: DaemonOpts?,
+        val policy: String? = null
     )
 
     data class DaemonOpts(
@@ -72,6 +82,7 @@ class Rust {
         val psbt: PsbtJson,
         val file: String,
         val qr_files: List<String>,
+        val ur_qr_files: List<String> = listOf(),
         val unsigned_txid: String
     )
 
@@ -79,7 +90,9 @@ class Rust {
         val outpoint: String,
         val signatures: List<String>,
         val value: String,
-        val wallet_with_path: String?
+        val wallet_with_path: String?,
+        val sighash: String? = null,
+        val invalid_signatures: List<String> = listOf()
     )
 
     data class TxOut(
@@ -113,7 +126,8 @@ class Rust {
 
     data class GetAddressOutput(
         val address: String,
-        val path: String
+        val path: String,
+        val warning: String? = null
     )
 
     private external fun call(json: String): String
@@ -136,19 +150,21 @@ class Rust {
         return mapper.convertValue(json, ListOutput::class.java)
     }
 
-    fun random(datadir: String, keyName: String): JsonNode {
+    fun random(datadir: String, keyName: String, bip39Passphrase: String? = null): JsonNode {
         val node = JsonNodeFactory.instance.objectNode()
         node.put("key_name", keyName)
         node.put("qr_version", 14)
+        node.put("bip39_passphrase", bip39Passphrase)
         val req = JsonRpc("random", datadir, Network.TYPE, node)
         val reqString = mapper.writeValueAsString(req)
         return callJson(reqString)
     }
 
-    fun dice(datadir: String, keyName: String, faces: String, launches: ArrayList<Int>): JsonNode {
+    fun dice(datadir: String, keyName: String, faces: String, launches: ArrayList<Int>, bip39Passphrase: String? = null): JsonNode {
         val node = JsonNodeFactory.instance.objectNode()
         node.put("key_name", keyName)
         node.put("qr_version", 14)
+        node.put("bip39_passphrase", bip39Passphrase)
         node.put("faces", "_$faces")
         node.put("bits", "_256")
         val array = node.putArray("launches")
@@ -170,14 +186,24 @@ class Rust {
         return callJson(reqString).asText()
     }
 
-    fun importWallet(datadir: String, wallet: WalletJson) {
-        val node: JsonNode = mapper.valueToTree(wallet)
+    fun mergeUrs(datadir: String, parts: List<String>): String {
+        val node = JsonNodeFactory.instance.arrayNode()
+        for (part in parts) {
+            node.add(part)
+        }
+        val req = JsonRpc("merge_urs", datadir, Network.TYPE, node)
+        val reqString = mapper.writeValueAsString(req)
+        return callJson(reqString).asText()
+    }
+
+    fun importWallet(datadir: String, content: String) {
+        val node = JsonNodeFactory.instance.textNode(content)
         val req = JsonRpc("import_wallet", datadir, Network.TYPE, node)
         val reqString = mapper.writeValueAsString(req)
         callJson(reqString)
     }
 
-    fun sign(datadir: String, key: String, wallet: String, psbt: String): PsbtPrettyPrint {
+    fun sign(datadir: String, key: String, wallet: String, psbt: String, bip39Passphrase: String? = null, confirmedBalance: Long? = null): PsbtPrettyPrint {
         val node = JsonNodeFactory.instance.objectNode()
         node.put("key", key)
         node.put("wallet_descriptor_file", wallet)
@@ -185,18 +211,21 @@ class Rust {
         node.put("total_derivations", 100)
         node.put("qr_version", 14)
         node.put("allow_any_derivations", false)
+        node.put("bip39_passphrase", bip39Passphrase)
+        node.put("confirmed_balance", confirmedBalance)
         val req = JsonRpc("sign", datadir, Network.TYPE, node)
         val reqString = mapper.writeValueAsString(req)
         val json = callJson(reqString)
         return mapper.convertValue(json, PsbtPrettyPrint::class.java)
     }
 
-    fun restore(datadir: String, key: String, nature: String, value: String): JsonNode {
+    fun restore(datadir: String, key: String, nature: String, value: String, bip39Passphrase: String? = null): JsonNode {
         val node = JsonNodeFactory.instance.objectNode()
         node.put("key_name", key)
         node.put("nature", nature)
         node.put("value", value)
         node.put("qr_version", 14)
+        node.put("bip39_passphrase", bip39Passphrase)
         val req = JsonRpc("restore", datadir, Network.TYPE, node)
         val reqString = mapper.writeValueAsString(req)
         return callJson(reqString)
@@ -1,7 +1,8 @@
 use bitcoin::Network;
-use firma::{common, init_logger, offline, Result};
+use firma::{common, init_logger, offline, Error, Result};
 use serde_json::Value;
 use std::convert::TryInto;
+use std::io::{self, BufRead, Write};
 use structopt::StructOpt;
 use FirmaOfflineSubcommands::*;
 
@@ -36,6 +37,15 @@ enum FirmaOfflineSubcommands {
     /// Decode and print a PSBT
     Print(offline::print::PrintOptions),
 
+    /// Combine signed PSBTs and finalize them in a transaction ready to broadcast
+    Finalize(offline::finalize::FinalizeOptions),
+
+    /// Convert a PSBT to version 2 (BIP370) or back to version 0
+    ConvertPsbt(offline::convert_psbt::ConvertPsbtOptions),
+
+    /// Add an input and/or an output to a version 2 PSBT (BIP370) of a collaborative transaction
+    AddToPsbt(offline::add_to_psbt::AddToPsbtOptions),
+
     /// Restore a json key from xprv or mnemonic
     Restore(offline::restore::RestoreOptions),
 
@@ -44,15 +54,49 @@ enum FirmaOfflineSubcommands {
 
     /// Hard derive a master key from a master^2 key
     DeriveKey(offline::derive_key::DeriveKeyOptions),
+
+    /// Encrypt an existing unencrypted key file with a passphrase
+    EncryptKey(offline::encrypt_key::EncryptKeyOptions),
+
+    /// Split the entropy of a master key in SLIP-39 mnemonic shares
+    SplitKey(offline::split_key::SplitKeyOptions),
+
+    /// Export the xpub of a BIP48 multisig account of a key
+    ExportAccount(offline::export_account::ExportAccountOptions),
+
+    /// Export a wallet for Coldcard, Passport, Specter, Sparrow, Electrum or Bitcoin Core
+    ExportWallet(offline::export_wallet::ExportWalletOptions),
+
+    /// Create a wallet descriptor from the xpubs of the signers, register it with firma-online
+    /// register-wallet
+    CreateWallet(offline::create_wallet::CreateWalletOptions),
+
+    /// List main and change addresses of a wallet, to cross-check the ones of the online machine
+    ListAddresses(offline::verify_address::ListAddressesOptions),
+
+    /// Check an address belongs to a wallet and show its derivation path
+    VerifyAddress(offline::verify_address::VerifyAddressOptions),
+
+    /// Import a wallet from firma, Coldcard, Passport, Specter, Sparrow, Electrum or descriptors
+    ImportWallet(offline::import_wallet::ImportWalletOptions),
+
+    /// Import a PSBT, a key or a wallet from images of QR codes
+    ImportQr(offline::import_qr::ImportQrOptions),
 }
 
 fn main() -> Result<()> {
     init_logger();
-    let cmd = FirmaOfflineCommands::from_args();
+    let mut cmd = FirmaOfflineCommands::from_args();
 
-    let value = match launch_subcommand(&cmd) {
-        Ok(value) => value,
-        Err(e) => e.to_json(),
+    let value = loop {
+        match launch_subcommand(&cmd) {
+            Ok(value) => break value,
+            Err(e) => {
+                if !ask_missing_passphrase(&mut cmd, &e) {
+                    break e.to_json();
+                }
+            }
+        }
     };
 
     println!("{}", serde_json::to_string_pretty(&value)?);
@@ -68,8 +112,90 @@ fn launch_subcommand(cmd: &FirmaOfflineCommands) -> Result<Value> {
         Sign(opt) => offline::sign::start(&opt, net)?.try_into(),
         Random(opt) => offline::random::create_key(datadir, net, &opt)?.try_into(),
         Print(opt) => offline::print::start(datadir, net, &opt)?.try_into(),
+        Finalize(opt) => offline::finalize::start(opt)?.try_into(),
+        ConvertPsbt(opt) => offline::convert_psbt::start(opt)?.try_into(),
+        AddToPsbt(opt) => offline::add_to_psbt::start(net, opt)?.try_into(),
         Restore(opt) => offline::restore::start(datadir, net, &opt)?.try_into(),
         DeriveKey(opt) => offline::derive_key::start(datadir, net, &opt)?.try_into(),
+        EncryptKey(opt) => offline::encrypt_key::start(opt)?.try_into(),
+        SplitKey(opt) => offline::split_key::start(opt)?.try_into(),
+        ExportAccount(opt) => offline::export_account::start(datadir, net, opt)?.try_into(),
+        ExportWallet(opt) => offline::export_wallet::start(datadir, net, opt)?.try_into(),
+        CreateWallet(opt) => offline::create_wallet::start(datadir, net, opt)?.try_into(),
+        ListAddresses(opt) => offline::verify_address::list(datadir, net, opt)?.try_into(),
+        VerifyAddress(opt) => offline::verify_address::verify(datadir, net, opt)?.try_into(),
+        ImportWallet(opt) => offline::import_wallet::start(datadir, net, opt)?.try_into(),
+        ImportQr(opt) => offline::import_qr::start(datadir, net, opt)?.try_into(),
         List(opt) => common::list::list(datadir, net, &opt)?.try_into(),
     }
 }
+
+/// If `error` is caused by a missing passphrase, asks it on the terminal and sets it in `cmd`.
+/// Returns false if the passphrase is not requested or it can't be read, eg. stdin is closed
+fn ask_missing_passphrase(cmd: &mut FirmaOfflineCommands, error: &Error) -> bool {
+    let (field, prompt) = match (&mut cmd.subcommand, error) {
+        (Sign(opt), Error::MissingPassphrase) => (&mut opt.encryption_passphrase, "Key passphrase"),
+        (Sign(opt), Error::MissingBip39Passphrase) => {
+            (&mut opt.bip39_passphrase, "BIP39 passphrase")
+        }
+        (DeriveKey(opt), Error::MissingPassphrase) => {
+            (&mut opt.encryption_passphrase, "Key passphrase")
+        }
+        (DeriveKey(opt), Error::MissingBip39Passphrase) => {
+            (&mut opt.bip39_passphrase, "BIP39 passphrase")
+        }
+        (SplitKey(opt), Error::MissingPassphrase) => {
+            (&mut opt.encryption_passphrase, "Key passphrase")
+        }
+        (ExportAccount(opt), Error::MissingPassphrase) => {
+            (&mut opt.encryption_passphrase, "Key passphrase")
+        }
+        (ExportAccount(opt), Error::MissingBip39Passphrase) => {
+            (&mut opt.bip39_passphrase, "BIP39 passphrase")
+        }
+        _ => return false,
+    };
+    if field.is_some() {
+        return false;
+    }
+    eprint!("{}: ", prompt);
+    let _ = io::stderr().flush();
+    match read_line_without_echo() {
+        Ok(line) if !line.is_empty() => {
+            *field = Some(line.trim_end_matches(&['\r', '\n'][..]).to_string());
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Reads a line from stdin, if stdin is a terminal the typed characters are not shown
+#[cfg(unix)]
+fn read_line_without_echo() -> io::Result<String> {
+    let fd = libc::STDIN_FILENO;
+    let mut original: libc::termios = unsafe { std::mem::zeroed() };
+    let is_terminal = unsafe { libc::tcgetattr(fd, &mut original) } == 0;
+    if is_terminal {
+        let mut hidden = original;
+        hidden.c_lflag &= !libc::ECHO;
+        hidden.c_lflag |= libc::ECHONL;
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &hidden) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    let mut line = String::new();
+    let result = io::stdin().lock().read_line(&mut line);
+    if is_terminal {
+        unsafe { libc::tcsetattr(fd, libc::TCSANOW, &original) };
+    }
+    result.map(|_| line)
+}
+
+/// Hiding the input is supported only on unix, elsewhere the passphrase must be given with a file
+#[cfg(not(unix))]
+fn read_line_without_echo() -> io::Result<String> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "can't read the passphrase without echo",
+    ))
+}
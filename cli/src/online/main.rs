@@ -27,6 +27,12 @@ enum FirmaOnlineSubcommands {
     /// Create a new watch-only wallet
     CreateWallet(firma::online::create_wallet::CreateWalletOptions),
 
+    /// Import in the node a wallet created with firma-offline create-wallet
+    RegisterWallet(firma::online::create_wallet::RegisterWalletOptions),
+
+    /// Convert the legacy watch-only wallet in the node to a descriptor wallet
+    MigrateWallet,
+
     /// Rescan the blockchain, useful when importing an existing wallet
     Rescan(firma::online::rescan::RescanOptions),
 
@@ -44,6 +50,9 @@ enum FirmaOnlineSubcommands {
 
     /// View wallet coins
     ListCoins,
+
+    /// Export the wallet for Coldcard, Passport, Specter, Sparrow, Electrum or Bitcoin Core
+    ExportWallet(firma::offline::export_wallet::ExportWalletOptions),
 }
 
 fn main() -> Result<()> {
@@ -61,8 +70,24 @@ fn start() -> Result<Value> {
     debug!("firma-online start");
     let cmd = FirmaOnlineCommands::from_args();
 
+    if let ExportWallet(ref opt) = cmd.subcommand {
+        // the node is not needed to export the wallet
+        let (wallet, _) = cmd.context.load_wallet_and_index()?;
+        let datadir = &cmd.context.firma_datadir;
+        return firma::offline::export_wallet::export_wallet(
+            datadir,
+            cmd.context.network,
+            &wallet,
+            opt.format,
+            opt.qr_version,
+            &opt.qr_style,
+        )?
+        .try_into();
+    }
+
     let daemon_opts = match &cmd.subcommand {
         CreateWallet(ref opt) => opt.daemon_opts.clone(),
+        RegisterWallet(ref opt) => opt.daemon_opts.clone(),
         _ => {
             let (wallet, _) = cmd.context.load_wallet_and_index()?;
             wallet.daemon_opts.ok_or_else(|| Error::MissingDaemonOpts)?
@@ -76,7 +101,7 @@ fn start() -> Result<Value> {
         cmd.context.clone(),
     )?;
 
-    if let CreateWallet(_) = cmd.subcommand {
+    if let CreateWallet(_) | RegisterWallet(_) = cmd.subcommand {
         // do nothing, I need the else branch (!matches!() require too recent rust version)
     } else {
         wallet.load_if_unloaded(&cmd.context.wallet_name)?;
@@ -100,11 +125,18 @@ fn start() -> Result<Value> {
 
     match cmd.subcommand {
         CreateWallet(ref opt) => wallet.create(&daemon_opts, opt, result.blocks)?.try_into(),
-        GetAddress(ref opt) => wallet.get_address(opt.index, false)?.try_into(),
+        RegisterWallet(ref opt) => wallet
+            .register(&daemon_opts, opt, result.blocks)?
+            .try_into(),
+        GetAddress(ref opt) => wallet
+            .get_address(opt.index, false, opt.gap_limit)?
+            .try_into(),
         CreateTx(ref opt) => wallet.create_tx(opt)?.try_into(),
         SendTx(ref opt) => wallet.send_tx(opt)?.try_into(),
         Balance => wallet.balance()?.try_into(),
         Rescan(ref opt) => Ok(wallet.rescan(opt)?),
+        MigrateWallet => wallet.migrate(),
         ListCoins => wallet.list_coins()?.try_into(),
+        ExportWallet(_) => Err("export-wallet is handled without connecting to the node".into()),
     }
 }
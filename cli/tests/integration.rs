@@ -248,9 +248,15 @@ fn integration_test() {
     let coins_output = firma_2of3.online_list_coins().unwrap();
     assert!(!coins_output.coins.is_empty());
 
+    let encrypted = firma_2of2
+        .offline_encrypt_key(&r3.private_file_str().unwrap(), "passphrase")
+        .unwrap();
+    assert_eq!(encrypted.fingerprint, r3.key.fingerprint);
+
     let list_keys = firma_2of2.offline_list(Kind::Key).unwrap();
     assert!(list_keys.keys.iter().any(|k| k.key.name == r1.key.name));
     assert!(list_keys.keys.iter().any(|k| k.key.name == r2.key.name));
+    assert!(list_keys.encrypted_keys.iter().any(|k| k == &encrypted));
     let list_wallets = firma_2of2.offline_list(Kind::Wallet).unwrap();
     assert!(list_wallets
         .wallets
@@ -261,6 +267,73 @@ fn integration_test() {
     let result = firma_2of3.online_rescan(); // TODO test restore a wallet, find funds with rescan
     assert!(result.is_ok());
 
+    // receive on an address beyond the range imported in the node, the range is extended
+    let balance_before = firma_2of2.online_balance().unwrap();
+    let beyond = firma_2of2.online_get_address_at(1_000).unwrap();
+    assert!(beyond.warning.is_some()); // the addresses before it never received funds
+    let fund_beyond = 10_000;
+    client_send_to_address(&bitcoind.client, &beyond.address, fund_beyond).unwrap();
+    bitcoind.client.generate_to_address(1, &address).unwrap();
+    let balance_beyond = firma_2of2.online_balance().unwrap();
+    assert_eq!(
+        balance_before.confirmed.satoshi + fund_beyond,
+        balance_beyond.confirmed.satoshi
+    );
+
+    // create the wallet offline and register it in the node
+    let name_registered = "registered".to_string();
+    let firma_registered = FirmaCommand::new(&firma_exe_dir, &name_registered).unwrap();
+    let created_offline = firma_registered
+        .offline_create_wallet(2, &xpubs_2of3)
+        .unwrap();
+    assert!(created_offline.wallet.daemon_opts.is_none());
+    let registered = firma_registered
+        .online_register_wallet(&bitcoind.url, &cookie_file_str, false)
+        .unwrap();
+    assert_eq!(
+        registered.wallet.descriptor_main,
+        created_offline.wallet.descriptor_main
+    );
+    let address_registered = firma_registered.online_get_address().unwrap().address;
+    let fund_registered = 50_000;
+    client_send_to_address(&bitcoind.client, &address_registered, fund_registered).unwrap();
+    bitcoind.client.generate_to_address(1, &address).unwrap();
+    let balance_registered = firma_registered.online_balance().unwrap();
+    assert_eq!(fund_registered, balance_registered.confirmed.satoshi);
+
+    // the exported importdescriptors payload contains the wallet descriptors
+    let exported = firma_registered.online_export_wallet("core").unwrap();
+    assert!(exported
+        .content
+        .contains(&registered.wallet.descriptor_main));
+    assert!(exported
+        .content
+        .contains(&registered.wallet.descriptor_change));
+
+    // descriptor wallets require bitcoin core 0.21, migratewallet bitcoin core 24
+    let node_version = bitcoind.client.version().unwrap();
+    if node_version >= 210_000 {
+        let name_descriptors = "descriptors".to_string();
+        let firma_descriptors = FirmaCommand::new(&firma_exe_dir, &name_descriptors).unwrap();
+        firma_descriptors
+            .offline_create_wallet(2, &xpubs_2of3)
+            .unwrap();
+        firma_descriptors
+            .online_register_wallet(&bitcoind.url, &cookie_file_str, true)
+            .unwrap();
+        // the node extends the range of active descriptors by itself
+        let beyond = firma_descriptors.online_get_address_at(1_500).unwrap();
+        client_send_to_address(&bitcoind.client, &beyond.address, fund_beyond).unwrap();
+        bitcoind.client.generate_to_address(1, &address).unwrap();
+        let balance_descriptors = firma_descriptors.online_balance().unwrap();
+        assert_eq!(fund_beyond, balance_descriptors.confirmed.satoshi);
+    }
+    if node_version >= 240_000 {
+        firma_registered.online_migrate_wallet().unwrap();
+        let balance_migrated = firma_registered.online_balance().unwrap();
+        assert_eq!(balance_registered, balance_migrated);
+    }
+
     // stop bitcoind
     bitcoind.client.stop().unwrap();
     let ecode = bitcoind.wait().unwrap();
@@ -341,6 +414,39 @@ impl FirmaCommand {
         Ok(output)
     }
 
+    pub fn online_register_wallet(
+        &self,
+        node_url: &str,
+        cookie_file: &str,
+        descriptors: bool,
+    ) -> Result<CreateWalletOutput> {
+        let mut args = vec!["--url", node_url, "--cookie-file", cookie_file];
+        if descriptors {
+            args.push("--descriptors");
+        }
+        let result = self.online("register-wallet", args);
+        let value = map_json_error(result)?;
+        let output = from_value(value)?;
+        Ok(output)
+    }
+
+    fn online_migrate_wallet(&self) -> Result<Value> {
+        map_json_error(self.online("migrate-wallet", vec![]))
+    }
+
+    fn online_export_wallet(&self, format: &str) -> Result<ExportWalletOutput> {
+        let result = self.online("export-wallet", vec!["--format", format]);
+        let value = map_json_error(result)?;
+        Ok(from_value(value)?)
+    }
+
+    fn online_get_address_at(&self, index: u32) -> Result<GetAddressOutput> {
+        let index = index.to_string();
+        let result = self.online("get-address", vec!["--index", &index]);
+        let value = map_json_error(result)?;
+        Ok(from_value(value)?)
+    }
+
     fn online_get_address(&self) -> Result<GetAddressOutput> {
         Ok(from_value(self.online("get-address", vec![]).unwrap())?)
     }
@@ -411,6 +517,23 @@ impl FirmaCommand {
         Ok(value)
     }
 
+    pub fn offline_create_wallet(
+        &self,
+        required_sig: u8,
+        xpubs: &[String],
+    ) -> Result<CreateWalletOutput> {
+        let required_sig = format!("{}", required_sig);
+        let mut args = vec!["--wallet-name", &self.wallet_name, "-r", &required_sig];
+        for xpub in xpubs {
+            args.push("--xpub-file");
+            args.push(xpub);
+        }
+        let result = self.offline("create-wallet", args);
+        let value = map_json_error(result)?;
+        let output = from_value(value)?;
+        Ok(output)
+    }
+
     pub fn offline_random(&self, key_name: &str) -> Result<MasterKeyOutput> {
         let result = self.offline("random", vec!["--key-name", key_name]);
         let value = map_json_error(result)?;
@@ -470,6 +593,27 @@ impl FirmaCommand {
         Ok(output)
     }
 
+    pub fn offline_encrypt_key(
+        &self,
+        key_file: &str,
+        passphrase: &str,
+    ) -> Result<EncryptedKeyOutput> {
+        let passphrase_file = self.work_dir.path().join("passphrase");
+        std::fs::write(&passphrase_file, passphrase)?;
+        let result = self.offline(
+            "encrypt-key",
+            vec![
+                "--key",
+                key_file,
+                "--encryption-passphrase-file",
+                passphrase_file.to_str().unwrap(),
+            ],
+        );
+        let value = map_json_error(result)?;
+        let output = from_value(value)?;
+        Ok(output)
+    }
+
     fn offline_list(&self, kind: Kind) -> Result<ListOutput> {
         Ok(from_value(
             self.offline("list", vec!["--kind", &kind.to_string()])
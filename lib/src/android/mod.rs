@@ -1,10 +1,13 @@
 use crate::common::list::ListOptions;
 use crate::offline::descriptor::DeriveAddressOpts;
 use crate::offline::dice::DiceOptions;
+use crate::offline::finalize::FinalizeOptions;
+use crate::offline::import_wallet::{import_wallet, parse_wallet};
 use crate::offline::print::PrintOptions;
 use crate::offline::random::RandomOptions;
 use crate::offline::restore::RestoreOptions;
 use crate::offline::sign::SignOptions;
+use crate::qr::QrStyle;
 use crate::*;
 use android_logger::Config;
 use bitcoin::Network;
@@ -64,6 +67,13 @@ fn rust_call(c_str: &CStr) -> Result<CString> {
                 Err(e) => e.to_json(),
             }
         }
+        Some("merge_urs") => {
+            let parts: Vec<String> = serde_json::from_value(args.clone())?;
+            match crate::offline::import_qr::merge_urs(&parts) {
+                Ok(merged) => hex::encode(merged).into(),
+                Err(e) => e.to_json(),
+            }
+        }
         Some("sign") => {
             let opts: SignOptions = serde_json::from_value(args.clone())?;
             let result = crate::offline::sign::start(&opts, network)?;
@@ -79,6 +89,11 @@ fn rust_call(c_str: &CStr) -> Result<CString> {
             let result = crate::offline::print::start(datadir, network, &opts)?;
             serde_json::to_value(result)?
         }
+        Some("finalize") => {
+            let opts: FinalizeOptions = serde_json::from_value(args.clone())?;
+            let result = crate::offline::finalize::start(&opts)?;
+            serde_json::to_value(result)?
+        }
         Some("save_psbt") => {
             let opts: SavePSBTOptions = serde_json::from_value(args.clone())?;
             let result = crate::offline::sign::save_psbt_opt(datadir, network, &opts)?;
@@ -90,8 +105,11 @@ fn rust_call(c_str: &CStr) -> Result<CString> {
             serde_json::to_value(result)?
         }
         Some("import_wallet") => {
-            let wallet: WalletJson = serde_json::from_value(args.clone())?;
-            let result = crate::online::create_wallet::import_wallet(datadir, network, &wallet)?;
+            let wallet: WalletJson = match args {
+                Value::String(content) => parse_wallet(content, None)?,
+                _ => serde_json::from_value(args.clone())?,
+            };
+            let result = import_wallet(datadir, network, &wallet, 14, &QrStyle::default())?;
             serde_json::to_value(result)?
         }
         _ => {
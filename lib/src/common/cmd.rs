@@ -63,6 +63,14 @@ impl Context {
         Ok(path)
     }
 
+    /// Like `save_wallet` but overwriting the existing wallet
+    pub fn update_wallet(&self, wallet: &WalletJson) -> Result<PathBuf> {
+        let path = self.filename_for_wallet("descriptor.json")?;
+        info!("Updating wallet data in {:?}", &path);
+        fs::write(&path, serde_json::to_string_pretty(wallet)?)?;
+        Ok(path)
+    }
+
     pub fn save_index(&self, indexes: &WalletIndexes) -> Result<()> {
         let path = self.filename_for_wallet("indexes.json")?;
         info!("Saving index data in {:?}", path);
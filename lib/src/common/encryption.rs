@@ -0,0 +1,294 @@
+use crate::*;
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::{thread_rng, RngCore};
+use scrypt::ScryptParams;
+use serde::{Deserialize, Serialize};
+use std::ffi::{OsStr, OsString};
+use std::fs;
+
+/// Upper limits of the scrypt parameters read from key files, a crafted or corrupted file could
+/// otherwise make unlocking use gigabytes of memory or run for hours (N=2^20 and r=8 use 1 GiB)
+const MAX_LOG_N: u8 = 20;
+const MAX_R: u32 = 8;
+const MAX_P: u32 = 4;
+
+/// Parameters of the scrypt key derivation function used to derive the encryption key from the passphrase
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct KdfParams {
+    pub log_n: u8,
+    pub r: u32,
+    pub p: u32,
+    /// hex encoded random salt
+    pub salt: String,
+}
+
+impl KdfParams {
+    /// Parameters suggested by the scrypt paper for interactive logins (N=2^15, r=8, p=1)
+    pub fn new_random() -> Self {
+        Self::with_cost(15, 8, 1)
+    }
+
+    pub fn with_cost(log_n: u8, r: u32, p: u32) -> Self {
+        let mut salt = [0u8; 16];
+        thread_rng().fill_bytes(&mut salt);
+        KdfParams {
+            log_n,
+            r,
+            p,
+            salt: hex::encode(salt),
+        }
+    }
+
+    fn derive_key(&self, passphrase: &str) -> Result<[u8; 32]> {
+        if self.log_n > MAX_LOG_N || self.r > MAX_R || self.p > MAX_P {
+            return Err(Error::Generic(format!(
+                "scrypt parameters log_n:{} r:{} p:{} exceed the limits log_n:{} r:{} p:{}",
+                self.log_n, self.r, self.p, MAX_LOG_N, MAX_R, MAX_P
+            )));
+        }
+        let params = ScryptParams::new(self.log_n, self.r, self.p)
+            .map_err(|_| Error::Generic("invalid scrypt parameters".to_string()))?;
+        let salt = hex::decode(&self.salt)?;
+        let mut key = [0u8; 32];
+        scrypt::scrypt(passphrase.as_bytes(), &salt, &params, &mut key)
+            .map_err(|_| Error::Generic("invalid scrypt output length".to_string()))?;
+        Ok(key)
+    }
+}
+
+/// Encrypt the json serialization of the file content of `key` with ChaCha20-Poly1305, the key is
+/// derived from `passphrase` with scrypt. Public parts are left in clear but authenticated as
+/// associated data.
+pub fn encrypt_key(
+    key: &PrivateMasterKey,
+    passphrase: &str,
+    kdf: KdfParams,
+) -> Result<EncryptedMasterKey> {
+    encrypt_key_file(&key.into(), passphrase, kdf)
+}
+
+/// Like `encrypt_key` but starting from the content of a not encrypted key file
+pub fn encrypt_key_file(
+    file: &PrivateMasterKeyFile,
+    passphrase: &str,
+    kdf: KdfParams,
+) -> Result<EncryptedMasterKey> {
+    if passphrase.is_empty() {
+        return Err(Error::MissingPassphrase);
+    }
+    let (name, fingerprint, xpub) = match file {
+        PrivateMasterKeyFile::Plain(key) => (&key.name, key.fingerprint, key.xpub),
+        PrivateMasterKeyFile::Bip39Protected(key) => (&key.name, key.fingerprint, key.xpub),
+        PrivateMasterKeyFile::Encrypted(_) => return Err(Error::KeyAlreadyEncrypted),
+    };
+    let mut nonce = [0u8; 12];
+    thread_rng().fill_bytes(&mut nonce);
+    let mut encrypted = EncryptedMasterKey {
+        name: name.clone(),
+        fingerprint,
+        xpub,
+        kdf,
+        nonce: hex::encode(nonce),
+        ciphertext: String::new(),
+    };
+    let cipher_key = encrypted.kdf.derive_key(passphrase)?;
+    let cipher = ChaCha20Poly1305::new(&Key::from(cipher_key));
+    let plaintext = serde_json::to_vec(file)?;
+    let aad = encrypted.associated_data();
+    let payload = Payload {
+        msg: &plaintext,
+        aad: &aad,
+    };
+    let ciphertext = cipher
+        .encrypt(&Nonce::from(nonce), payload)
+        .map_err(|_| Error::Generic("encryption failed".to_string()))?;
+    encrypted.ciphertext = hex::encode(ciphertext);
+    Ok(encrypted)
+}
+
+pub fn decrypt_key(
+    encrypted: &EncryptedMasterKey,
+    passphrase: &str,
+) -> Result<PrivateMasterKeyFile> {
+    let cipher_key = encrypted.kdf.derive_key(passphrase)?;
+    let cipher = ChaCha20Poly1305::new(&Key::from(cipher_key));
+    let nonce_vec = hex::decode(&encrypted.nonce)?;
+    if nonce_vec.len() != 12 {
+        return Err(Error::WrongPassphrase);
+    }
+    let mut nonce = [0u8; 12];
+    nonce.copy_from_slice(&nonce_vec);
+    let ciphertext = hex::decode(&encrypted.ciphertext)?;
+    let aad = encrypted.associated_data();
+    let payload = Payload {
+        msg: &ciphertext,
+        aad: &aad,
+    };
+    let plaintext = cipher
+        .decrypt(&Nonce::from(nonce), payload)
+        .map_err(|_| Error::WrongPassphrase)?;
+    let key: PrivateMasterKeyFile = serde_json::from_slice(&plaintext)?;
+    let (fingerprint, xpub) = match &key {
+        PrivateMasterKeyFile::Plain(key) => (key.fingerprint, key.xpub),
+        PrivateMasterKeyFile::Bip39Protected(key) => (key.fingerprint, key.xpub),
+        PrivateMasterKeyFile::Encrypted(_) => return Err(Error::WrongPassphrase),
+    };
+    if fingerprint != encrypted.fingerprint || xpub != encrypted.xpub {
+        return Err(Error::WrongPassphrase);
+    }
+    Ok(key)
+}
+
+impl EncryptedMasterKey {
+    fn associated_data(&self) -> Vec<u8> {
+        format!("{}{}{}", self.name, self.fingerprint, self.xpub).into_bytes()
+    }
+}
+
+impl PrivateMasterKeyFile {
+    /// returns the private key, decrypting it with `encryption_passphrase` if the file is
+    /// encrypted and deriving it with `bip39_passphrase` if the seed is passphrase protected
+    pub fn unlock(
+        self,
+        encryption_passphrase: Option<&str>,
+        bip39_passphrase: Option<&str>,
+    ) -> Result<PrivateMasterKey> {
+        match self {
+            PrivateMasterKeyFile::Plain(key) => Ok(key),
+            PrivateMasterKeyFile::Bip39Protected(key) => {
+                let bip39_passphrase = bip39_passphrase.ok_or(Error::MissingBip39Passphrase)?;
+                key.unlock(bip39_passphrase)
+            }
+            PrivateMasterKeyFile::Encrypted(encrypted) => {
+                let passphrase = encryption_passphrase.ok_or(Error::MissingPassphrase)?;
+                decrypt_key(&encrypted, passphrase)?.unlock(None, bip39_passphrase)
+            }
+        }
+    }
+}
+
+/// Reads a passphrase from the file at `path`, used by command line options so that the passphrase
+/// doesn't end up in the shell history or in the process list. A trailing newline is not part of
+/// the passphrase.
+pub fn read_passphrase_file(path: &OsStr) -> std::result::Result<String, OsString> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| OsString::from(format!("can't read passphrase file: {}", e)))?;
+    Ok(content.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::encryption::{decrypt_key, encrypt_key, read_passphrase_file, KdfParams};
+    use crate::common::mnemonic::Mnemonic;
+    use crate::{Error, PrivateMasterKey, PrivateMasterKeyFile};
+    use bitcoin::Network;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let mnemonic = Mnemonic::new(&[0u8; 16]).unwrap();
+        let key = PrivateMasterKey::new(Network::Testnet, &mnemonic, None, "enc").unwrap();
+        let kdf = KdfParams::with_cost(4, 8, 1);
+
+        let encrypted = encrypt_key(&key, "passphrase", kdf.clone()).unwrap();
+        assert_eq!(encrypted.xpub, key.xpub);
+        assert!(!encrypted.ciphertext.contains(&key.xprv.to_string()));
+        assert_eq!(
+            decrypt_key(&encrypted, "passphrase").unwrap(),
+            PrivateMasterKeyFile::Plain(key.clone())
+        );
+        assert_eq!(
+            decrypt_key(&encrypted, "wrong").unwrap_err().to_string(),
+            Error::WrongPassphrase.to_string()
+        );
+
+        let mut tampered = encrypted.clone();
+        tampered.name = "other".to_string();
+        assert!(decrypt_key(&tampered, "passphrase").is_err());
+
+        let json = serde_json::to_string(&encrypted).unwrap();
+        let file: PrivateMasterKeyFile = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            file.clone().unlock(None, None).unwrap_err().to_string(),
+            Error::MissingPassphrase.to_string()
+        );
+        assert_eq!(file.unlock(Some("passphrase"), None).unwrap(), key);
+
+        let json = serde_json::to_string(&key).unwrap();
+        let file: PrivateMasterKeyFile = serde_json::from_str(&json).unwrap();
+        assert_eq!(file.unlock(None, None).unwrap(), key);
+
+        assert!(encrypt_key(&key, "", kdf).is_err());
+
+        for (log_n, r, p) in &[(21, 8, 1), (15, 9, 1), (15, 8, 5), (255, 8, 1)] {
+            let mut crafted = encrypted.clone();
+            crafted.kdf.log_n = *log_n;
+            crafted.kdf.r = *r;
+            crafted.kdf.p = *p;
+            let err = decrypt_key(&crafted, "passphrase").unwrap_err().to_string();
+            assert!(err.contains("exceed the limits"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_bip39_passphrase() {
+        let mnemonic = Mnemonic::new(&[0u8; 16]).unwrap();
+        let key =
+            PrivateMasterKey::new(Network::Testnet, &mnemonic, Some("25th"), "bip39").unwrap();
+        let no_pass = PrivateMasterKey::new(Network::Testnet, &mnemonic, None, "bip39").unwrap();
+        assert!(key.bip39_passphrase);
+        assert_ne!(key.fingerprint, no_pass.fingerprint);
+        assert_eq!(
+            PrivateMasterKey::new(Network::Testnet, &mnemonic, Some(""), "bip39").unwrap(),
+            no_pass
+        );
+
+        let file = PrivateMasterKeyFile::from(&key);
+        let json = serde_json::to_string(&file).unwrap();
+        assert!(!json.contains("xprv"));
+        let file: PrivateMasterKeyFile = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            file.clone().unlock(None, None).unwrap_err().to_string(),
+            Error::MissingBip39Passphrase.to_string()
+        );
+        assert_eq!(
+            file.clone()
+                .unlock(None, Some("wrong"))
+                .unwrap_err()
+                .to_string(),
+            Error::WrongBip39Passphrase.to_string()
+        );
+        assert_eq!(file.unlock(None, Some("25th")).unwrap(), key);
+
+        let kdf = KdfParams::with_cost(4, 8, 1);
+        let encrypted = encrypt_key(&key, "passphrase", kdf).unwrap();
+        let file = PrivateMasterKeyFile::Encrypted(encrypted);
+        assert_eq!(
+            file.clone()
+                .unlock(Some("passphrase"), None)
+                .unwrap_err()
+                .to_string(),
+            Error::MissingBip39Passphrase.to_string()
+        );
+        assert_eq!(file.unlock(Some("passphrase"), Some("25th")).unwrap(), key);
+    }
+
+    #[test]
+    fn test_read_passphrase_file() {
+        let temp_dir = TempDir::new("test_read_passphrase_file").unwrap();
+        let path = temp_dir.path().join("passphrase");
+        std::fs::write(&path, "pass phrase\n").unwrap();
+        assert_eq!(
+            read_passphrase_file(path.as_os_str()).unwrap(),
+            "pass phrase"
+        );
+        std::fs::write(&path, " pass phrase ").unwrap();
+        assert_eq!(
+            read_passphrase_file(path.as_os_str()).unwrap(),
+            " pass phrase "
+        );
+        let missing = temp_dir.path().join("missing");
+        assert!(read_passphrase_file(missing.as_os_str()).is_err());
+    }
+}
@@ -30,11 +30,29 @@ pub enum Error {
     NeedAtLeastOne,
     CannotRetrieveHomeDir,
     AddressFromDescriptorFails,
+    WrongDescriptorChecksum(String, String),
+    InvalidDescriptorChar(char),
     CaptureGroupNotFound(String),
     NonDefaultScript,
     ScriptEmpty,
     IncompatibleNetworks,
+    MissingPassphrase,
+    WrongPassphrase,
+    KeyAlreadyEncrypted,
+    MissingBip39Passphrase,
+    WrongBip39Passphrase,
     Mnemonic(crate::common::mnemonic::Error),
+    Slip39(crate::common::slip39::Error),
+    TimelockNotSatisfied(usize, String),
+    SighashNotAllowed(usize, String),
+    InvalidSignature(usize, String),
+    ChangeNotInWallet(usize, String),
+    BalanceNotConfirmed(String),
+    BalanceMismatch(String, String),
+    PsbtVersion(u32),
+    PsbtV2MissingField(String),
+    PsbtV2LocktimeConflict,
+    PsbtNotModifiable(String),
 
     // Internal Qr
     QrAtLeast2Pieces,
@@ -48,6 +66,17 @@ pub enum Error {
     QrLengthMismatch(usize, usize),
     QrUnsupportedVersion(i16),
     QrSplitMax16(usize),
+    QrImage(String),
+    QrNotFound(PathBuf),
+    QrUnsupportedMode(u8),
+    QrInvalidPayload,
+    QrEnvelope(String),
+
+    // Internal UR
+    UrInvalid(String),
+    UrChecksum,
+    UrUnexpectedType(String, String),
+    UrMissingParts(usize, usize),
 
     // External
     BitcoinRpc(bitcoincore_rpc::Error),
@@ -71,6 +100,7 @@ pub enum Error {
     Nul(std::ffi::NulError),
     ParseInt(std::num::ParseIntError),
     Miniscript(miniscript::Error),
+    MiniscriptPsbt(miniscript::psbt::Error),
     Bmp(qr_code::bmp_monochrome::BmpError),
 }
 
@@ -105,7 +135,9 @@ impl_error!(std::str::Utf8Error, Utf8);
 impl_error!(std::ffi::NulError, Nul);
 impl_error!(std::num::ParseIntError, ParseInt);
 impl_error!(miniscript::Error, Miniscript);
+impl_error!(miniscript::psbt::Error, MiniscriptPsbt);
 impl_error!(crate::common::mnemonic::Error, Mnemonic);
+impl_error!(crate::common::slip39::Error, Slip39);
 impl_error!(qr_code::bmp_monochrome::BmpError, Bmp);
 
 impl fmt::Display for Error {
@@ -126,6 +158,8 @@ impl fmt::Display for Error {
             Error::NeedAtLeastOne => write!(f, "Need at least one"),
             Error::CannotRetrieveHomeDir => write!(f, "Cannot retrieve home dir"),
             Error::AddressFromDescriptorFails => write!(f, "can't create address from descriptor"),
+            Error::WrongDescriptorChecksum(found, expected) => write!(f, "Wrong descriptor checksum {}, expected {}", found, expected),
+            Error::InvalidDescriptorChar(c) => write!(f, "Invalid character {:?} in descriptor", c),
             Error::CaptureGroupNotFound(s) => write!(f, "Capture group of {} not found", s),
             Error::NonDefaultScript => write!(f, "Non default script"),
             Error::ScriptEmpty => write!(f, "Script empty"),
@@ -138,6 +172,53 @@ impl fmt::Display for Error {
             Error::MissingRescanUpTo => write!(f, "Missing RescanUpTo"),
             Error::MissingHex => write!(f, "Missing hex"),
             Error::IncompatibleNetworks => write!(f, "Incompatible networks"),
+            Error::MissingPassphrase => write!(f, "Key file is encrypted, missing passphrase"),
+            Error::WrongPassphrase => write!(f, "Wrong passphrase or corrupted key file"),
+            Error::KeyAlreadyEncrypted => write!(f, "Key file is already encrypted"),
+            Error::MissingBip39Passphrase => {
+                write!(f, "Key is protected by a BIP39 passphrase, missing passphrase")
+            }
+            Error::WrongBip39Passphrase => write!(
+                f,
+                "Wrong BIP39 passphrase, derived fingerprint doesn't match the key"
+            ),
+            Error::TimelockNotSatisfied(i, locks) => write!(
+                f,
+                "Input #{} can be signed by this key only when {} is satisfied by nSequence or nLockTime",
+                i, locks
+            ),
+            Error::SighashNotAllowed(i, sighash) => write!(
+                f,
+                "Input #{} requests sighash {} which is not allowed, see --allow-sighash",
+                i, sighash
+            ),
+            Error::InvalidSignature(i, pubkey) => {
+                write!(f, "Input #{} has an invalid signature of key {}", i, pubkey)
+            }
+            Error::ChangeNotInWallet(i, wallet) => write!(
+                f,
+                "Output #{} has key derivations of wallet {} but it is not one of its addresses",
+                i, wallet
+            ),
+            Error::BalanceNotConfirmed(balance) => write!(
+                f,
+                "Strict mode requires confirming the wallet balance {}",
+                balance
+            ),
+            Error::BalanceMismatch(balance, confirmed) => write!(
+                f,
+                "Wallet balance {} differs from the confirmed {}",
+                balance, confirmed
+            ),
+            Error::PsbtVersion(version) => write!(f, "Unsupported PSBT version {}", version),
+            Error::PsbtV2MissingField(field) => write!(f, "PSBTv2 is missing the {}", field),
+            Error::PsbtV2LocktimeConflict => write!(
+                f,
+                "PSBTv2 inputs require both height and time locktimes, no locktime satisfies all"
+            ),
+            Error::PsbtNotModifiable(what) => {
+                write!(f, "PSBTv2 does not allow adding or removing {}", what)
+            }
 
             Error::QrAtLeast2Pieces => write!(f, "Need at least 2 different pieces to merge structured QR"),
             Error::QrTotalMismatch(i) => write!(f, "Total pieces in input {} does not match the encoded total, or different encoded totals", i ),
@@ -150,6 +231,22 @@ impl fmt::Display for Error {
             Error::QrLengthMismatch(calc, exp) => write!(f,  "calculated end {} greater than effective length {}", calc, exp ),
             Error::QrUnsupportedVersion(ver) => write!(f,  "Unsupported version {}", ver),
             Error::QrSplitMax16(req) => write!(f,  "Could split into max 16 qr, requested {}", req),
+            Error::QrImage(reason) => write!(f, "Can't read QR image: {}", reason),
+            Error::QrNotFound(path) => write!(f, "No readable QR code in {:?}", path),
+            Error::QrUnsupportedMode(mode) => write!(f, "Unsupported QR segment mode {}", mode),
+            Error::QrInvalidPayload => write!(f, "QR segments exceed the data"),
+            Error::QrEnvelope(reason) => write!(f, "Invalid QR envelope: {}", reason),
+
+            Error::UrInvalid(reason) => write!(f, "Invalid UR: {}", reason),
+            Error::UrChecksum => write!(f, "UR checksum mismatch"),
+            Error::UrUnexpectedType(expected, actual) => {
+                write!(f, "Expected UR type {} but got {}", expected, actual)
+            }
+            Error::UrMissingParts(received, total) => write!(
+                f,
+                "UR incomplete, recovered {} of {} fragments",
+                received, total
+            ),
 
             Error::BitcoinRpc(e) => write!(f, "{:?}", e),
             Error::Serde(e) => write!(f, "{:?}", e),
@@ -172,7 +269,9 @@ impl fmt::Display for Error {
             Error::Nul(e) => write!(f, "{:?}", e),
             Error::ParseInt(e) => write!(f, "{:?}", e),
             Error::Miniscript(e) => write!(f, "{:?}", e),
+            Error::MiniscriptPsbt(e) => write!(f, "{:?}", e),
             Error::Mnemonic(e) => write!(f, "{:?}", e),
+            Error::Slip39(e) => write!(f, "{:?}", e),
             Error::Bmp(e) => write!(f, "{:?}", e),
         }
     }
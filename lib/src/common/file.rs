@@ -1,8 +1,9 @@
+use crate::offline::descriptor::strip_checksum;
+use crate::qr::QrStyle;
 use crate::*;
 use bitcoin::Network;
 use log::info;
 use serde::{Deserialize, Serialize};
-use std::convert::Into;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::string::ToString;
@@ -22,7 +23,7 @@ pub enum KindAndName {
     PSBT(String),
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum Kind {
     #[serde(rename = "wallets")]
     Wallet,
@@ -138,26 +139,47 @@ pub fn save_public(public_key: &PublicMasterKey, output: &PathBuf) -> Result<()>
     save(serde_json::to_string_pretty(public_key)?, output)
 }
 
-pub fn save_private(private_key: &PrivateMasterKey, output: &PathBuf) -> Result<()> {
+/// if `encryption_passphrase` is given the key is saved encrypted, see `encryption::encrypt_key`
+pub fn save_private(
+    private_key: &PrivateMasterKey,
+    output: &PathBuf,
+    encryption_passphrase: Option<&str>,
+) -> Result<()> {
     if output.exists() {
         return Err(Error::FileExist(output.clone()));
     }
-    save(serde_json::to_string_pretty(private_key)?, output)
+    let value = match encryption_passphrase {
+        Some(passphrase) => {
+            let kdf = encryption::KdfParams::new_random();
+            let encrypted = encryption::encrypt_key(private_key, passphrase, kdf)?;
+            serde_json::to_string_pretty(&encrypted)?
+        }
+        None => serde_json::to_string_pretty(&PrivateMasterKeyFile::from(private_key))?,
+    };
+    save(value, output)
 }
 
+/// save the private key and the public key, with the xpub of its first BIP48 account, see
+/// `export_account` for the other accounts
 pub fn save_keys(
     datadir: &str,
     network: Network,
     key_name: &str,
     key: PrivateMasterKey,
     qr_version: i16,
+    qr_style: &QrStyle,
+    encryption_passphrase: Option<&str>,
 ) -> Result<MasterKeyOutput> {
     let option_name = Some(key_name.to_string());
     let path_builder = PathBuilder::new(datadir, network, Kind::Key, option_name.clone());
     let private_key_file = path_builder.file("PRIVATE.json")?;
     let public_key_file = path_builder.file("public.json")?;
-    save_private(&key, &private_key_file)?;
-    let public_master_key = key.clone().into();
+    save_private(&key, &private_key_file, encryption_passphrase)?;
+    let public_master_key = PublicMasterKey {
+        xpub: key.xpub,
+        origin: None,
+        account_xpub: Some(key.bip48_account(0)?.xpub),
+    };
     save_public(&public_master_key, &public_key_file)?;
 
     let path_for_qr = PathBuilder::new(datadir, network, Kind::Key, option_name).file("qr")?;
@@ -166,6 +188,7 @@ pub fn save_keys(
         public_master_key.xpub.to_string().as_bytes().to_vec(),
         path_for_qr,
         qr_version,
+        qr_style,
     )?;
 
     Ok(MasterKeyOutput {
@@ -186,9 +209,14 @@ pub fn read_psbt(path: &Path) -> Result<PSBT> {
     Ok(psbt_from_base64(&psbt_json.psbt)?.1)
 }
 
+/// read the wallet validating the descriptors checksums, a corrupted or tampered descriptor is an
+/// error instead of a wallet that doesn't recognize its addresses
 pub fn read_wallet(path: &PathBuf) -> Result<WalletJson> {
     let wallet = fs::read(path)?;
-    Ok(serde_json::from_slice(&wallet)?)
+    let wallet: WalletJson = serde_json::from_slice(&wallet)?;
+    strip_checksum(&wallet.descriptor_main)?;
+    strip_checksum(&wallet.descriptor_change)?;
+    Ok(wallet)
 }
 
 /*
@@ -1,16 +1,26 @@
+use crate::common::encryption::KdfParams;
+use crate::common::file::Kind;
 use crate::common::mnemonic::Mnemonic;
+use crate::common::qr::QrStyle;
+use crate::offline::export_wallet::ExportFormat;
 use crate::offline::sign::get_psbt_name;
-use crate::{psbt_from_base64, psbt_to_base64, DaemonOpts, PSBT};
+use crate::{io_err, psbt_from_base64, psbt_to_base64, DaemonOpts, PSBT};
 use bitcoin::bech32::FromBase32;
-use bitcoin::util::bip32::{DerivationPath, ExtendedPrivKey, ExtendedPubKey, Fingerprint};
+use bitcoin::util::bip32::{
+    ChildNumber, DerivationPath, ExtendedPrivKey, ExtendedPubKey, Fingerprint,
+};
 use bitcoin::util::psbt::{raw, Map};
-use bitcoin::{bech32, Address, Amount, Network, OutPoint, Txid};
+use bitcoin::{bech32, Address, Amount, Network, OutPoint, PublicKey, SigHashType, Txid};
 use bitcoincore_rpc::bitcoincore_rpc_json::WalletCreateFundedPsbtResult;
+use miniscript::descriptor::{DescriptorPublicKey, DescriptorXPub};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashSet;
 use std::convert::TryInto;
+use std::fmt;
+use std::io;
 use std::path::PathBuf;
+use std::str::FromStr;
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct PrivateMasterKey {
@@ -22,6 +32,42 @@ pub struct PrivateMasterKey {
     pub dice: Option<Dice>,
     pub name: String,
     pub fingerprint: Fingerprint,
+    /// true if the seed is derived from the mnemonic with a BIP39 passphrase, in this case the
+    /// key is saved as `Bip39ProtectedKey`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub bip39_passphrase: bool,
+}
+
+/// Key derived from the mnemonic with a BIP39 passphrase, the xprv is not saved and it's derived
+/// again when the passphrase is provided. `fingerprint` is the fingerprint of the key derived with
+/// the passphrase and it's used as hint to check the given passphrase.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Bip39ProtectedKey {
+    pub mnemonic: Mnemonic,
+    pub xpub: ExtendedPubKey,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dice: Option<Dice>,
+    pub name: String,
+    pub fingerprint: Fingerprint,
+}
+
+/// Content of an encrypted PRIVATE.json, `ciphertext` is the encrypted `PrivateMasterKeyFile` json
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct EncryptedMasterKey {
+    pub name: String,
+    pub fingerprint: Fingerprint,
+    pub xpub: ExtendedPubKey,
+    pub kdf: KdfParams,
+    pub nonce: String,
+    pub ciphertext: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum PrivateMasterKeyFile {
+    Encrypted(EncryptedMasterKey),
+    Plain(PrivateMasterKey),
+    Bip39Protected(Bip39ProtectedKey),
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
@@ -40,9 +86,32 @@ pub struct MasterKeyOutput {
     pub public_qr_files: Vec<PathBuf>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+/// A key which can't be read without a passphrase, either encrypted or BIP39 passphrase protected
+pub struct EncryptedKeyOutput {
+    pub name: String,
+    pub fingerprint: Fingerprint,
+    pub xpub: ExtendedPubKey,
+    pub private_file: PathBuf,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct PublicMasterKey {
     pub xpub: ExtendedPubKey,
+    /// Master key fingerprint and derivation path of `xpub`, when absent `xpub` is a master key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<KeyOrigin>,
+    /// Xpub of the first BIP48 multisig account of `xpub`, saved in the `public.json` of the keys
+    /// created here, wallets are created with it when present
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account_xpub: Option<ExtendedPubKey>,
+}
+
+/// Origin of an extended key, as in `[d34db33f/48'/0'/0'/2']xpub...` descriptor keys
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct KeyOrigin {
+    pub fingerprint: Fingerprint,
+    pub path: DerivationPath,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
@@ -57,6 +126,9 @@ pub struct PsbtJsonOutput {
     pub file: PathBuf,
     pub signatures: String,
     pub qr_files: Vec<PathBuf>,
+    /// frames of an animated QR of the PSBT as `ur:crypto-psbt`
+    #[serde(default)]
+    pub ur_qr_files: Vec<PathBuf>,
     pub unsigned_txid: Txid,
 }
 
@@ -67,6 +139,9 @@ pub struct WalletJson {
     pub descriptor_change: String,
     pub fingerprints: HashSet<Fingerprint>,
     pub required_sig: usize,
+    /// Miniscript policy the descriptors are compiled from, None for `multi` wallets
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub daemon_opts: Option<DaemonOpts>,
     pub created_at_height: u64,
@@ -76,6 +151,14 @@ pub struct WalletJson {
 pub struct WalletIndexes {
     pub main: u32,
     pub change: u32,
+    /// Last derivation index of the descriptors imported in the node, wallets created before this
+    /// field existed have the initial range
+    #[serde(default = "default_imported_range")]
+    pub imported_range: u32,
+}
+
+fn default_imported_range() -> u32 {
+    crate::DESCRIPTOR_RANGE
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
@@ -95,6 +178,30 @@ pub struct BalanceOutput {
 pub struct GetAddressOutput {
     pub address: Address,
     pub path: DerivationPath,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+}
+
+/// An address of the wallet with its derivation path and optionally its QR codes
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct WalletAddress {
+    pub address: Address,
+    pub path: DerivationPath,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub qr_files: Vec<PathBuf>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ListAddressesOutput {
+    pub name: String,
+    pub main: Vec<WalletAddress>,
+    pub change: Vec<WalletAddress>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct VerifyAddressOutput {
+    pub name: String,
+    pub address: WalletAddress,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
@@ -104,6 +211,27 @@ pub struct SendTxOutput {
     pub broadcasted: bool,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FinalizeOutput {
+    pub hex: String,
+    pub txid: Txid,
+    pub tx_file: PathBuf,
+    pub qr_files: Vec<PathBuf>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ConvertPsbtOutput {
+    pub psbt: String,
+    pub version: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ImportQrOutput {
+    pub kind: Kind,
+    pub file: PathBuf,
+    pub qr_files: Vec<PathBuf>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct CreateTxOutput {
     pub psbt_file: PathBuf,
@@ -119,9 +247,47 @@ pub struct CreateWalletOutput {
     pub wallet: WalletJson,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SplitKeyOutput {
+    pub name: String,
+    pub fingerprint: Fingerprint,
+    pub group_threshold: u8,
+    pub groups: Vec<SharesGroup>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ExportAccountOutput {
+    pub name: String,
+    pub account: u32,
+    pub key: PublicMasterKey,
+    pub public_file: PathBuf,
+    pub public_qr_files: Vec<PathBuf>,
+    /// QR codes of the account as `ur:crypto-account`
+    pub ur_qr_files: Vec<PathBuf>,
+}
+
+/// A wallet rendered in the format of another wallet or coordinator, see `export_wallet`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ExportWalletOutput {
+    pub name: String,
+    pub format: ExportFormat,
+    pub content: String,
+    pub file: PathBuf,
+    pub qr_files: Vec<PathBuf>,
+}
+
+/// SLIP-39 mnemonic shares of a group, `member_threshold` of them are needed to recover the group
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SharesGroup {
+    pub member_threshold: u8,
+    pub shares: Vec<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
 pub struct ListOutput {
     pub keys: Vec<MasterKeyOutput>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub encrypted_keys: Vec<EncryptedKeyOutput>,
     pub wallets: Vec<CreateWalletOutput>,
     pub psbts: Vec<PsbtJsonOutput>,
 }
@@ -148,10 +314,92 @@ pub struct ErrorJson {
 pub struct TxIn {
     pub outpoint: String,
     pub signatures: HashSet<Fingerprint>,
+    /// Public keys of the partial signatures which are not valid, they are not in `signatures`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub invalid_signatures: Vec<PublicKey>,
+    /// Sighash requested by the PSBT for this input, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sighash: Option<Sighash>,
     #[serde(flatten)]
     pub common: TxCommonInOut,
 }
 
+/// Signature hash types, named as in bitcoin core
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum Sighash {
+    #[serde(rename = "ALL")]
+    All,
+    #[serde(rename = "NONE")]
+    None,
+    #[serde(rename = "SINGLE")]
+    Single,
+    #[serde(rename = "ALL|ANYONECANPAY")]
+    AllPlusAnyoneCanPay,
+    #[serde(rename = "NONE|ANYONECANPAY")]
+    NonePlusAnyoneCanPay,
+    #[serde(rename = "SINGLE|ANYONECANPAY")]
+    SinglePlusAnyoneCanPay,
+}
+
+impl Sighash {
+    const VARIANTS: [Sighash; 6] = [
+        Sighash::All,
+        Sighash::None,
+        Sighash::Single,
+        Sighash::AllPlusAnyoneCanPay,
+        Sighash::NonePlusAnyoneCanPay,
+        Sighash::SinglePlusAnyoneCanPay,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Sighash::All => "ALL",
+            Sighash::None => "NONE",
+            Sighash::Single => "SINGLE",
+            Sighash::AllPlusAnyoneCanPay => "ALL|ANYONECANPAY",
+            Sighash::NonePlusAnyoneCanPay => "NONE|ANYONECANPAY",
+            Sighash::SinglePlusAnyoneCanPay => "SINGLE|ANYONECANPAY",
+        }
+    }
+}
+
+impl fmt::Display for Sighash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for Sighash {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let upper = s.to_uppercase();
+        Sighash::VARIANTS
+            .iter()
+            .find(|sighash| sighash.as_str() == upper)
+            .cloned()
+            .ok_or_else(|| {
+                io_err(
+                    "valid values are: ALL, NONE, SINGLE, ALL|ANYONECANPAY, NONE|ANYONECANPAY, \
+                    SINGLE|ANYONECANPAY",
+                )
+            })
+    }
+}
+
+impl From<SigHashType> for Sighash {
+    fn from(sighash: SigHashType) -> Self {
+        match sighash {
+            SigHashType::All => Sighash::All,
+            SigHashType::None => Sighash::None,
+            SigHashType::Single => Sighash::Single,
+            SigHashType::AllPlusAnyoneCanPay => Sighash::AllPlusAnyoneCanPay,
+            SigHashType::NonePlusAnyoneCanPay => Sighash::NonePlusAnyoneCanPay,
+            SigHashType::SinglePlusAnyoneCanPay => Sighash::SinglePlusAnyoneCanPay,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct TxOut {
     pub address: String,
@@ -195,6 +443,8 @@ pub struct Size {
 pub struct SavePSBTOptions {
     pub psbt: StringEncoding,
     pub qr_version: i16,
+    #[serde(flatten)]
+    pub qr_style: QrStyle,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
@@ -272,14 +522,118 @@ impl MasterKeyOutput {
     }
 }
 
+impl PublicMasterKey {
+    /// The origin of `xpub`, a key without origin is a master key
+    pub fn key_origin(&self) -> KeyOrigin {
+        self.origin.clone().unwrap_or_else(|| KeyOrigin {
+            fingerprint: self.xpub.fingerprint(),
+            path: vec![].into(),
+        })
+    }
+
+    /// The key used in wallet descriptors, the first BIP48 account if `account_xpub` is present
+    pub fn wallet_key(&self) -> crate::Result<PublicMasterKey> {
+        match self.account_xpub {
+            Some(account_xpub) => {
+                let origin = self.key_origin();
+                let mut path: Vec<ChildNumber> = origin.path.into();
+                path.extend(crate::bip48_path(self.xpub.network, 0)?.as_ref());
+                Ok(PublicMasterKey {
+                    xpub: account_xpub,
+                    origin: Some(KeyOrigin {
+                        fingerprint: origin.fingerprint,
+                        path: path.into(),
+                    }),
+                    account_xpub: None,
+                })
+            }
+            None => Ok(self.clone()),
+        }
+    }
+
+    /// Descriptor key with origin for the external (0) or internal (1) addresses,
+    /// eg. `[d34db33f/48'/0'/0'/2']xpub.../0/*`
+    pub fn descriptor_key(&self, int_or_ext: u32) -> DescriptorPublicKey {
+        let origin = self.key_origin();
+        DescriptorPublicKey::XPub(DescriptorXPub {
+            origin: Some((origin.fingerprint, origin.path)),
+            xpub: self.xpub,
+            derivation_path: vec![ChildNumber::Normal { index: int_or_ext }].into(),
+            is_wildcard: true,
+        })
+    }
+}
+
+/// Key with origin in the descriptor format `[d34db33f/48'/0'/0'/2']xpub...`, a master key without
+/// origin is the bare `xpub...`
+impl fmt::Display for PublicMasterKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let key = DescriptorPublicKey::XPub(DescriptorXPub {
+            origin: self
+                .origin
+                .clone()
+                .map(|origin| (origin.fingerprint, origin.path)),
+            xpub: self.xpub,
+            derivation_path: vec![].into(),
+            is_wildcard: false,
+        });
+        write!(f, "{}", key)
+    }
+}
+
+/// Parse a key in the format `[d34db33f/48'/0'/0'/2']xpub...` or a bare `xpub...`
+impl FromStr for PublicMasterKey {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let key =
+            DescriptorPublicKey::from_str(s).map_err(|e| crate::Error::Generic(e.to_string()))?;
+        match key {
+            DescriptorPublicKey::XPub(key) if key.derivation_path.as_ref().is_empty() => {
+                Ok(PublicMasterKey {
+                    xpub: key.xpub,
+                    origin: key
+                        .origin
+                        .map(|(fingerprint, path)| KeyOrigin { fingerprint, path }),
+                    account_xpub: None,
+                })
+            }
+            _ => Err("expected an extended public key without derivation steps".into()),
+        }
+    }
+}
+
 impl PrivateMasterKey {
+    /// Extended public key derived at `path`, with origin
+    pub fn public_key(&self, path: &DerivationPath) -> crate::Result<PublicMasterKey> {
+        let secp = bitcoin::secp256k1::Secp256k1::signing_only();
+        let xprv = self.xprv.derive_priv(&secp, path)?;
+        Ok(PublicMasterKey {
+            xpub: ExtendedPubKey::from_private(&secp, &xprv),
+            origin: Some(KeyOrigin {
+                fingerprint: self.fingerprint,
+                path: path.clone(),
+            }),
+            account_xpub: None,
+        })
+    }
+
+    /// Public key of the BIP48 native segwit multisig `account`, derived at
+    /// `m/48'/coin'/account'/2'`
+    pub fn bip48_account(&self, account: u32) -> crate::Result<PublicMasterKey> {
+        self.public_key(&crate::bip48_path(self.xprv.network, account)?)
+    }
+
+    /// Creates the master key from `mnemonic`, an empty `bip39_passphrase` is considered absent
     pub fn new(
         network: Network,
         mnemonic: &Mnemonic,
+        bip39_passphrase: Option<&str>,
         name: &str,
     ) -> crate::Result<PrivateMasterKey> {
         let secp = bitcoin::secp256k1::Secp256k1::signing_only();
-        let seed = mnemonic.to_seed(None);
+        let bip39_passphrase = bip39_passphrase.filter(|p| !p.is_empty());
+        let seed = mnemonic.to_seed(bip39_passphrase);
 
         let xprv = ExtendedPrivKey::new_master(network, &seed.0)?;
         let xpub = ExtendedPubKey::from_private(&secp, &xprv);
@@ -291,6 +645,7 @@ impl PrivateMasterKey {
             dice: None,
             name: name.to_string(),
             fingerprint: xpub.fingerprint(),
+            bip39_passphrase: bip39_passphrase.is_some(),
         })
     }
 
@@ -304,6 +659,40 @@ impl PrivateMasterKey {
             dice: None,
             name: name.to_string(),
             fingerprint: xpub.fingerprint(),
+            bip39_passphrase: false,
+        }
+    }
+}
+
+impl Bip39ProtectedKey {
+    /// Derives the private key from the mnemonic and `bip39_passphrase`, failing if the resulting
+    /// fingerprint doesn't match the stored one
+    pub fn unlock(self, bip39_passphrase: &str) -> crate::Result<PrivateMasterKey> {
+        let mut key = PrivateMasterKey::new(
+            self.xpub.network,
+            &self.mnemonic,
+            Some(bip39_passphrase),
+            &self.name,
+        )?;
+        if key.fingerprint != self.fingerprint || !key.bip39_passphrase {
+            return Err(crate::Error::WrongBip39Passphrase);
+        }
+        key.dice = self.dice;
+        Ok(key)
+    }
+}
+
+impl From<&PrivateMasterKey> for PrivateMasterKeyFile {
+    fn from(key: &PrivateMasterKey) -> Self {
+        match (&key.mnemonic, key.bip39_passphrase) {
+            (Some(mnemonic), true) => PrivateMasterKeyFile::Bip39Protected(Bip39ProtectedKey {
+                mnemonic: mnemonic.clone(),
+                xpub: key.xpub,
+                dice: key.dice.clone(),
+                name: key.name.clone(),
+                fingerprint: key.fingerprint,
+            }),
+            _ => PrivateMasterKeyFile::Plain(key.clone()),
         }
     }
 }
@@ -320,18 +709,93 @@ macro_rules! impl_try_into {
     };
 }
 impl_try_into!(MasterKeyOutput);
+impl_try_into!(EncryptedKeyOutput);
 impl_try_into!(PsbtPrettyPrint);
 impl_try_into!(CreateWalletOutput);
+impl_try_into!(ImportQrOutput);
 impl_try_into!(CreateTxOutput);
 impl_try_into!(SendTxOutput);
 impl_try_into!(BalanceOutput);
 impl_try_into!(ListCoinsOutput);
 impl_try_into!(GetAddressOutput);
 impl_try_into!(ListOutput);
+impl_try_into!(SplitKeyOutput);
+impl_try_into!(ExportAccountOutput);
+impl_try_into!(ExportWalletOutput);
+impl_try_into!(ListAddressesOutput);
+impl_try_into!(VerifyAddressOutput);
+impl_try_into!(FinalizeOutput);
+impl_try_into!(ConvertPsbtOutput);
 
 #[cfg(test)]
 mod tests {
-    use crate::WalletJson;
+    use crate::{PrivateMasterKey, PublicMasterKey, Sighash, WalletIndexes, WalletJson};
+    use bitcoin::util::bip32::{DerivationPath, ExtendedPrivKey};
+    use bitcoin::{Network, SigHashType};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_sighash() {
+        for sighash in Sighash::VARIANTS.iter() {
+            assert_eq!(&Sighash::from_str(sighash.as_str()).unwrap(), sighash);
+            let json = serde_json::to_string(sighash).unwrap();
+            assert_eq!(json, format!("\"{}\"", sighash));
+            assert_eq!(&serde_json::from_str::<Sighash>(&json).unwrap(), sighash);
+        }
+        assert_eq!(
+            Sighash::from_str("single|anyonecanpay").unwrap(),
+            Sighash::from(SigHashType::SinglePlusAnyoneCanPay)
+        );
+        assert!(Sighash::from_str("ANYONECANPAY").is_err());
+    }
+
+    #[test]
+    fn test_public_master_key() {
+        let xprv = ExtendedPrivKey::new_master(Network::Testnet, &[1u8; 32]).unwrap();
+        let key = PrivateMasterKey::from_xprv(xprv, "key");
+        let master: PublicMasterKey = key.clone().into();
+        assert_eq!(
+            master.key_origin(),
+            PublicMasterKey::from_str(&master.xpub.to_string())
+                .unwrap()
+                .key_origin()
+        );
+        assert_eq!(
+            master.to_string(),
+            format!("[{}]{}", key.fingerprint, key.xpub)
+        );
+
+        let path = DerivationPath::from_str("m/48'/1'/0'/2'").unwrap();
+        let account = key.public_key(&path).unwrap();
+        let account_str = format!("[{}/48'/1'/0'/2']{}", key.fingerprint, account.xpub);
+        assert_eq!(account.to_string(), account_str);
+        assert_eq!(PublicMasterKey::from_str(&account_str).unwrap(), account);
+        assert_eq!(
+            account.descriptor_key(1).to_string(),
+            format!("{}/1/*", account_str)
+        );
+        assert_ne!(account.xpub, key.xpub);
+
+        let json = serde_json::to_string(&account).unwrap();
+        assert_eq!(
+            serde_json::from_str::<PublicMasterKey>(&json).unwrap(),
+            account
+        );
+        let legacy = format!("{{\"xpub\":\"{}\"}}", key.xpub);
+        let legacy: PublicMasterKey = serde_json::from_str(&legacy).unwrap();
+        assert_eq!(legacy.key_origin(), master.key_origin());
+
+        assert!(PublicMasterKey::from_str(&format!("{}/0/*", account_str)).is_err());
+    }
+
+    #[test]
+    fn test_wallet_indexes() {
+        let indexes: WalletIndexes = serde_json::from_str(r#"{"main":3,"change":1}"#).unwrap();
+        assert_eq!(indexes.imported_range, 1000);
+        let indexes: WalletIndexes =
+            serde_json::from_str(r#"{"main":3,"change":1,"imported_range":2003}"#).unwrap();
+        assert_eq!(indexes.imported_range, 2003);
+    }
 
     #[test]
     fn test_cbor_wallet() {
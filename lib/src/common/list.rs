@@ -1,10 +1,10 @@
 use crate::offline::print::pretty_print;
-use crate::offline::sign::read_key;
+use crate::offline::sign::read_key_file;
 use crate::*;
 use bitcoin::Network;
 use log::{debug, warn};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
 
 #[derive(StructOpt, Debug, Serialize, Deserialize)]
@@ -29,7 +29,7 @@ pub fn list(datadir: &str, network: Network, opt: &ListOptions) -> Result<ListOu
                     debug!("try to read wallet {:?}", path);
                     match read_wallet(&path) {
                         Ok(wallet) => {
-                            let qr_files = read_qrs(&path)?;
+                            let qr_files = read_qrs(&path, "qr")?;
                             let wallet = CreateWalletOutput {
                                 qr_files, //TODO check if file exist?
                                 wallet,
@@ -49,13 +49,15 @@ pub fn list(datadir: &str, network: Network, opt: &ListOptions) -> Result<ListOu
                         Ok(psbt_json) => {
                             let (_, psbt) = psbt_from_base64(&psbt_json.psbt)?;
                             let pretty = pretty_print(&psbt, network, &[])?;
-                            let qr_files = read_qrs(&path)?;
+                            let qr_files = read_qrs(&path, "qr")?;
+                            let ur_qr_files = read_qrs(&path, "qr-ur")?;
                             let psbt_out = PsbtJsonOutput {
                                 psbt: psbt_json,
                                 signatures: signatures_needed(&pretty.inputs),
                                 unsigned_txid: psbt.global.unsigned_tx.txid(),
                                 file: path.clone(),
                                 qr_files,
+                                ur_qr_files,
                             };
                             list.psbts.push(psbt_out);
                         }
@@ -67,9 +69,9 @@ pub fn list(datadir: &str, network: Network, opt: &ListOptions) -> Result<ListOu
                 Kind::Key => {
                     path.push("PRIVATE.json");
                     debug!("try to read key {:?}", path);
-                    match read_key(&path) {
-                        Ok(key) => {
-                            let public_qr_files = read_qrs(&path)?;
+                    match read_key_file(&path) {
+                        Ok(PrivateMasterKeyFile::Plain(key)) => {
+                            let public_qr_files = read_qrs(&path, "qr")?;
                             let key = MasterKeyOutput {
                                 key,
                                 private_file: path.clone(),
@@ -78,6 +80,24 @@ pub fn list(datadir: &str, network: Network, opt: &ListOptions) -> Result<ListOu
                             };
                             list.keys.push(key);
                         }
+                        Ok(PrivateMasterKeyFile::Encrypted(encrypted)) => {
+                            let key = EncryptedKeyOutput {
+                                name: encrypted.name,
+                                fingerprint: encrypted.fingerprint,
+                                xpub: encrypted.xpub,
+                                private_file: path.clone(),
+                            };
+                            list.encrypted_keys.push(key);
+                        }
+                        Ok(PrivateMasterKeyFile::Bip39Protected(protected)) => {
+                            let key = EncryptedKeyOutput {
+                                name: protected.name,
+                                fingerprint: protected.fingerprint,
+                                xpub: protected.xpub,
+                                private_file: path.clone(),
+                            };
+                            list.encrypted_keys.push(key);
+                        }
                         Err(e) => {
                             warn!("Can't read key {:?}", e);
                         }
@@ -100,9 +120,9 @@ fn signatures_needed(inputs: &[TxIn]) -> String {
     }
 }
 
-fn read_qrs(path: &PathBuf) -> Result<Vec<PathBuf>> {
+fn read_qrs(path: &Path, dir_name: &str) -> Result<Vec<PathBuf>> {
     let mut path = path.parent().expect("root has no parent").to_path_buf();
-    path.push("qr");
+    path.push(dir_name);
     let mut vec = vec![];
     if path.is_dir() {
         for entry in std::fs::read_dir(path)? {
@@ -140,5 +160,6 @@ mod tests {
             .keys
             .iter()
             .any(|key| key.key.name == rand_opts.key_name));
+        assert!(list.encrypted_keys.is_empty());
     }
 }
@@ -55,26 +55,7 @@ impl FromStr for Mnemonic {
 
     /// Parse a string into a mnemonic, if valid according to the rules
     fn from_str(s: &str) -> Result<Mnemonic, Error> {
-        let words: Vec<_> = s.split(' ').collect();
-        if words.len() < 6 || words.len() % 6 != 0 {
-            return Err(Error::MnemonicNotDivisibleBy6);
-        }
-        let mut data = Vec::new();
-        let mut writer = BitStreamWriter::new(&mut data);
-        for word in &words {
-            if let Ok(idx) = WORDS.binary_search(word) {
-                writer.write(idx as u64, 11).unwrap();
-            } else {
-                return Err(Error::UnknownWord);
-            }
-        }
-        writer.flush().unwrap();
-        let l = data.len();
-        let (payload, checksum) = data.split_at(l - if l > 33 { 2 } else { 1 });
-        if Self::checksum(payload).as_slice() != checksum {
-            return Err(Error::ChecksumFailed);
-        }
-
+        Self::entropy(s)?;
         Ok(Mnemonic(s.to_string()))
     }
 }
@@ -148,6 +129,35 @@ impl Mnemonic {
         Ok(Mnemonic(mnemonic.join(" ")))
     }
 
+    /// returns the entropy encoded in this mnemonic
+    pub fn to_entropy(&self) -> Vec<u8> {
+        Self::entropy(&self.0).expect("checked at creation")
+    }
+
+    /// Parse and validate the words, returning the encoded entropy without the checksum
+    fn entropy(s: &str) -> Result<Vec<u8>, Error> {
+        let words: Vec<_> = s.split(' ').collect();
+        if words.len() < 6 || words.len() % 6 != 0 {
+            return Err(Error::MnemonicNotDivisibleBy6);
+        }
+        let mut data = Vec::new();
+        let mut writer = BitStreamWriter::new(&mut data);
+        for word in &words {
+            if let Ok(idx) = WORDS.binary_search(word) {
+                writer.write(idx as u64, 11).unwrap();
+            } else {
+                return Err(Error::UnknownWord);
+            }
+        }
+        writer.flush().unwrap();
+        let l = data.len();
+        let (payload, checksum) = data.split_at(l - if l > 33 { 2 } else { 1 });
+        if Self::checksum(payload).as_slice() != checksum {
+            return Err(Error::ChecksumFailed);
+        }
+        Ok(payload.to_vec())
+    }
+
     /// Compute the mnemonic checksum
     fn checksum(data: &[u8]) -> Vec<u8> {
         let mut checksum = Vec::new();
@@ -202,6 +212,7 @@ mod test {
                 Mnemonic::new(data.as_slice()).unwrap().to_string()
             );
             assert_eq!(seed.0, Vec::from_hex(values[2].as_str().unwrap()).unwrap());
+            assert_eq!(mnemonic.to_entropy(), data);
 
             if values.len() == 4 {
                 let pk = values[3].as_str().unwrap();
@@ -2,20 +2,32 @@ use crate::*;
 use bitcoin::blockdata::opcodes;
 use bitcoin::blockdata::script::Instruction::PushBytes;
 use bitcoin::consensus::{deserialize, serialize};
+use bitcoin::hashes::{hash160, Hash};
+use bitcoin::util::bip32::{ChildNumber, DerivationPath};
 use bitcoin::util::key;
 use bitcoin::{Network, Script, Transaction};
 use log::{LevelFilter, Metadata, Record};
+use miniscript::{Miniscript, Segwitv0};
 use std::fs::OpenOptions;
 use std::io::BufWriter;
 use std::io::Write;
 
 pub mod cmd;
+pub mod encryption;
 pub mod error;
 pub mod file;
 pub mod json;
 pub mod list;
 pub mod mnemonic;
+pub mod psbt_v2;
 pub mod qr;
+pub mod qr_reader;
+pub mod qr_render;
+pub mod slip39;
+pub mod ur;
+
+/// Last derivation index of the descriptors imported in the node
+pub const DESCRIPTOR_RANGE: u32 = 1000;
 
 static LOGGER: SimpleLogger = SimpleLogger;
 
@@ -51,16 +63,66 @@ pub fn init_logger() {
 
 impl From<PrivateMasterKey> for PublicMasterKey {
     fn from(private: PrivateMasterKey) -> Self {
-        PublicMasterKey { xpub: private.xpub }
+        PublicMasterKey {
+            xpub: private.xpub,
+            origin: Some(KeyOrigin {
+                fingerprint: private.fingerprint,
+                path: vec![].into(),
+            }),
+            account_xpub: None,
+        }
     }
 }
 
+/// BIP48 derivation path `m/48'/coin'/account'/2'` of native segwit multisig accounts, coin is 0
+/// for mainnet and 1 for the test networks
+pub fn bip48_path(network: Network, account: u32) -> Result<DerivationPath> {
+    let coin = match network {
+        Network::Bitcoin => 0,
+        _ => 1,
+    };
+    Ok(vec![
+        ChildNumber::from_hardened_idx(48)?,
+        ChildNumber::from_hardened_idx(coin)?,
+        ChildNumber::from_hardened_idx(account)?,
+        ChildNumber::from_hardened_idx(2)?,
+    ]
+    .into())
+}
+
+/// true if `path` is the derivation of a BIP48 native segwit multisig account of `network`
+pub fn is_bip48_account(path: &[ChildNumber], network: Network) -> bool {
+    path.len() == 4
+        && path[2].is_hardened()
+        && bip48_path(network, 0)
+            .map(|bip48| path[..2] == bip48.as_ref()[..2] && path[3] == bip48.as_ref()[3])
+            .unwrap_or(false)
+}
+
+/// Decode a base64 PSBT, version 2 PSBTs are converted to version 0 and the returned bytes are the
+/// ones of the converted PSBT
 pub fn psbt_from_base64(s: &str) -> Result<(Vec<u8>, PSBT)> {
     let bytes = base64::decode(s)?;
-    let psbt = deserialize(&bytes)?;
+    let psbt = psbt_from_bytes(&bytes)?;
+    if psbt_v2::psbt_version(&bytes)? == 2 {
+        return Ok((serialize(&psbt), psbt));
+    }
     Ok((bytes, psbt))
 }
 
+/// Deserialize a PSBT of version 0 or 2, the latter converted to version 0
+pub fn psbt_from_bytes(bytes: &[u8]) -> Result<PSBT> {
+    match psbt_v2::psbt_version(bytes) {
+        Ok(2) => psbt_v2::PsbtV2::from_bytes(bytes)?.to_v0(),
+        Ok(0) | Err(_) => {
+            let psbt = deserialize(bytes)?;
+            psbt_v2::check_v0(&psbt)?;
+            Ok(psbt)
+        }
+        Ok(version) => Err(Error::PsbtVersion(version)),
+    }
+}
+
 pub fn psbt_to_base64(psbt: &PSBT) -> (Vec<u8>, String) {
     let bytes = serialize(psbt);
     let string = base64::encode(&bytes);
@@ -77,8 +139,12 @@ pub fn estimate_weight(psbt: &PSBT) -> Result<usize> {
             (_, Some(witness_script)) => (witness_script, 1), // factor=1 for segwit discount
             _ => return Err("both redeem and witness script are None".into()),
         };
-        //TODO signature are less in NofM where N<M
-        let current = script.len() + expected_signatures(script)? * 72; // using 72 as average signature size
+        let current = match Miniscript::<bitcoin::PublicKey, Segwitv0>::parse(script) {
+            // miniscript gives the worst case among the spending paths, signatures of 73 bytes
+            Ok(miniscript) if factor == 1 => script.len() + miniscript.max_satisfaction_size(2),
+            //TODO signature are less in NofM where N<M
+            _ => script.len() + expected_signatures(script)? * 72, // using 72 as average signature size
+        };
         spending_weight += current * factor;
     }
 
@@ -122,6 +188,19 @@ pub fn extract_pub_keys(script: &Script) -> Result<Vec<key::PublicKey>> {
     Ok(result)
 }
 
+/// 20 bytes pushes of the script, which could be hashes of public keys as in `pk_h`
+pub fn extract_pub_key_hashes(script: &Script) -> Result<Vec<hash160::Hash>> {
+    let mut result = vec![];
+    for instruct in script.instructions() {
+        if let PushBytes(a) = instruct? {
+            if let Ok(hash) = hash160::Hash::from_slice(a) {
+                result.push(hash);
+            }
+        }
+    }
+    Ok(result)
+}
+
 pub fn map_json_error(result: Result<serde_json::Value>) -> Result<serde_json::Value> {
     match result {
         Ok(value) => match value.get("error") {
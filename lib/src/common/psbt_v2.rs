@@ -0,0 +1,964 @@
+//! PSBT version 2 (BIP370), where the unsigned transaction is not serialized in the global map but
+//! reconstructed from per-input and per-output fields, allowing inputs and outputs to be added by
+//! the participants of a collaborative transaction. Internally firma works with version 0, v2
+//! PSBTs are converted when read and can be exported for coordinators requiring them.
+
+use crate::*;
+use bitcoin::consensus::encode::{self, Decodable, Encodable, VarInt};
+use bitcoin::consensus::{deserialize, serialize};
+use bitcoin::util::psbt::{self, raw, Map};
+use bitcoin::{OutPoint, Script, Transaction, TxIn, TxOut, Txid};
+use std::collections::BTreeMap;
+use std::io::Cursor;
+
+const MAGIC: &[u8] = b"psbt\xff";
+
+const GLOBAL_UNSIGNED_TX: u8 = 0x00;
+const GLOBAL_TX_VERSION: u8 = 0x02;
+const GLOBAL_FALLBACK_LOCKTIME: u8 = 0x03;
+const GLOBAL_INPUT_COUNT: u8 = 0x04;
+const GLOBAL_OUTPUT_COUNT: u8 = 0x05;
+const GLOBAL_TX_MODIFIABLE: u8 = 0x06;
+const GLOBAL_VERSION: u8 = 0xfb;
+
+const IN_PARTIAL_SIG: u8 = 0x02;
+const IN_FINAL_SCRIPTSIG: u8 = 0x07;
+const IN_FINAL_SCRIPTWITNESS: u8 = 0x08;
+const IN_PREVIOUS_TXID: u8 = 0x0e;
+const IN_OUTPUT_INDEX: u8 = 0x0f;
+const IN_SEQUENCE: u8 = 0x10;
+const IN_REQUIRED_TIME_LOCKTIME: u8 = 0x11;
+const IN_REQUIRED_HEIGHT_LOCKTIME: u8 = 0x12;
+
+const OUT_AMOUNT: u8 = 0x03;
+const OUT_SCRIPT: u8 = 0x04;
+
+/// Fields introduced by BIP370, which must not be present in a version 0 PSBT
+const V2_GLOBAL_FIELDS: [u8; 5] = [
+    GLOBAL_TX_VERSION,
+    GLOBAL_FALLBACK_LOCKTIME,
+    GLOBAL_INPUT_COUNT,
+    GLOBAL_OUTPUT_COUNT,
+    GLOBAL_TX_MODIFIABLE,
+];
+const V2_IN_FIELDS: [u8; 5] = [
+    IN_PREVIOUS_TXID,
+    IN_OUTPUT_INDEX,
+    IN_SEQUENCE,
+    IN_REQUIRED_TIME_LOCKTIME,
+    IN_REQUIRED_HEIGHT_LOCKTIME,
+];
+const V2_OUT_FIELDS: [u8; 2] = [OUT_AMOUNT, OUT_SCRIPT];
+
+/// Locktimes below are block heights, the others are unix timestamps
+const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+
+/// Bit of `PSBT_GLOBAL_TX_MODIFIABLE` allowing to add or remove inputs
+pub const INPUTS_MODIFIABLE: u8 = 0x01;
+/// Bit of `PSBT_GLOBAL_TX_MODIFIABLE` allowing to add or remove outputs
+pub const OUTPUTS_MODIFIABLE: u8 = 0x02;
+/// Bit of `PSBT_GLOBAL_TX_MODIFIABLE` set when an input has a SIGHASH_SINGLE signature, inputs and
+/// outputs must then be added in pairs to keep every signed input paired with its output
+pub const HAS_SIGHASH_SINGLE: u8 = 0x04;
+
+const SIGHASH_ALL: u8 = 0x01;
+const SIGHASH_NONE: u8 = 0x02;
+const SIGHASH_SINGLE: u8 = 0x03;
+const SIGHASH_ANYONECANPAY: u8 = 0x80;
+
+/// Key-value pairs of a map, ordered by key as they are serialized
+pub type RawMap = BTreeMap<raw::Key, Vec<u8>>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PsbtV2 {
+    pub tx_version: u32,
+    pub fallback_locktime: Option<u32>,
+    pub tx_modifiable: Option<u8>,
+    /// Global pairs other than the ones describing the transaction
+    pub global: RawMap,
+    pub inputs: Vec<InputV2>,
+    pub outputs: Vec<OutputV2>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputV2 {
+    pub previous_output: OutPoint,
+    pub sequence: Option<u32>,
+    pub required_time_locktime: Option<u32>,
+    pub required_height_locktime: Option<u32>,
+    /// Input pairs other than the ones describing the transaction input, eg. utxos and signatures
+    pub pairs: RawMap,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputV2 {
+    pub amount: u64,
+    pub script_pubkey: Script,
+    /// Output pairs other than the ones describing the transaction output, eg. key derivations
+    pub pairs: RawMap,
+}
+
+/// Refuse a version 0 PSBT carrying fields defined only for version 2
+pub fn check_v0(psbt: &PSBT) -> Result<()> {
+    let unknowns = std::iter::once((&psbt.global.unknown, &V2_GLOBAL_FIELDS[..]))
+        .chain(psbt.inputs.iter().map(|i| (&i.unknown, &V2_IN_FIELDS[..])))
+        .chain(
+            psbt.outputs
+                .iter()
+                .map(|o| (&o.unknown, &V2_OUT_FIELDS[..])),
+        );
+    for (unknown, v2_fields) in unknowns {
+        if let Some(key) = unknown.keys().find(|k| v2_fields.contains(&k.type_value)) {
+            return Err(psbt::Error::InvalidKey(key.clone()).into());
+        }
+    }
+    Ok(())
+}
+
+/// PSBT version declared in the global map, 0 if not present
+pub fn psbt_version(bytes: &[u8]) -> Result<u32> {
+    let mut cursor = Cursor::new(bytes);
+    read_magic(&mut cursor)?;
+    let mut global = read_map(&mut cursor)?;
+    Ok(take_field(&mut global, GLOBAL_VERSION)?.unwrap_or(0))
+}
+
+impl PsbtV2 {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut cursor = Cursor::new(bytes);
+        read_magic(&mut cursor)?;
+        let mut global = read_map(&mut cursor)?;
+        let version: u32 = take_field(&mut global, GLOBAL_VERSION)?.unwrap_or(0);
+        if version != 2 {
+            return Err(Error::PsbtVersion(version));
+        }
+        if global.contains_key(&key(GLOBAL_UNSIGNED_TX)) {
+            return Err(psbt::Error::InvalidKey(key(GLOBAL_UNSIGNED_TX)).into());
+        }
+        let tx_version = required(take_field(&mut global, GLOBAL_TX_VERSION)?, "tx version")?;
+        check_tx_version(tx_version)?;
+        let fallback_locktime = take_field(&mut global, GLOBAL_FALLBACK_LOCKTIME)?;
+        let tx_modifiable = take_field(&mut global, GLOBAL_TX_MODIFIABLE)?;
+        let input_count: VarInt =
+            required(take_field(&mut global, GLOBAL_INPUT_COUNT)?, "input count")?;
+        let output_count: VarInt = required(
+            take_field(&mut global, GLOBAL_OUTPUT_COUNT)?,
+            "output count",
+        )?;
+
+        let mut inputs = vec![];
+        for _ in 0..input_count.0 {
+            let mut pairs = read_map(&mut cursor)?;
+            let txid: Txid = required(take_field(&mut pairs, IN_PREVIOUS_TXID)?, "previous txid")?;
+            let vout = required(take_field(&mut pairs, IN_OUTPUT_INDEX)?, "output index")?;
+            let input = InputV2 {
+                previous_output: OutPoint { txid, vout },
+                sequence: take_field(&mut pairs, IN_SEQUENCE)?,
+                required_time_locktime: take_field(&mut pairs, IN_REQUIRED_TIME_LOCKTIME)?,
+                required_height_locktime: take_field(&mut pairs, IN_REQUIRED_HEIGHT_LOCKTIME)?,
+                pairs,
+            };
+            input.check_locktimes()?;
+            inputs.push(input);
+        }
+        let mut outputs = vec![];
+        for _ in 0..output_count.0 {
+            let mut pairs = read_map(&mut cursor)?;
+            let amount = required(take_field(&mut pairs, OUT_AMOUNT)?, "amount")?;
+            let script = pairs
+                .remove(&key(OUT_SCRIPT))
+                .ok_or_else(|| Error::PsbtV2MissingField("script".to_string()))?;
+            outputs.push(OutputV2 {
+                amount,
+                script_pubkey: script.into(),
+                pairs,
+            });
+        }
+        check_consumed(&cursor)?;
+
+        Ok(PsbtV2 {
+            tx_version,
+            fallback_locktime,
+            tx_modifiable,
+            global,
+            inputs,
+            outputs,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut global = self.global.clone();
+        global.insert(key(GLOBAL_TX_VERSION), serialize(&self.tx_version));
+        if let Some(fallback_locktime) = self.fallback_locktime {
+            global.insert(key(GLOBAL_FALLBACK_LOCKTIME), serialize(&fallback_locktime));
+        }
+        let input_count = VarInt(self.inputs.len() as u64);
+        global.insert(key(GLOBAL_INPUT_COUNT), serialize(&input_count));
+        let output_count = VarInt(self.outputs.len() as u64);
+        global.insert(key(GLOBAL_OUTPUT_COUNT), serialize(&output_count));
+        if let Some(tx_modifiable) = self.tx_modifiable {
+            global.insert(key(GLOBAL_TX_MODIFIABLE), serialize(&tx_modifiable));
+        }
+        global.insert(key(GLOBAL_VERSION), serialize(&2u32));
+
+        let mut bytes = MAGIC.to_vec();
+        write_map(&mut bytes, &global)?;
+        for input in self.inputs.iter() {
+            let mut pairs = input.pairs.clone();
+            pairs.insert(
+                key(IN_PREVIOUS_TXID),
+                serialize(&input.previous_output.txid),
+            );
+            pairs.insert(key(IN_OUTPUT_INDEX), serialize(&input.previous_output.vout));
+            let optionals = [
+                (IN_SEQUENCE, input.sequence),
+                (IN_REQUIRED_TIME_LOCKTIME, input.required_time_locktime),
+                (IN_REQUIRED_HEIGHT_LOCKTIME, input.required_height_locktime),
+            ];
+            for (type_value, field) in optionals.iter() {
+                if let Some(field) = field {
+                    pairs.insert(key(*type_value), serialize(field));
+                }
+            }
+            write_map(&mut bytes, &pairs)?;
+        }
+        for output in self.outputs.iter() {
+            let mut pairs = output.pairs.clone();
+            pairs.insert(key(OUT_AMOUNT), serialize(&output.amount));
+            pairs.insert(key(OUT_SCRIPT), output.script_pubkey.to_bytes());
+            write_map(&mut bytes, &pairs)?;
+        }
+        Ok(bytes)
+    }
+
+    /// Convert a version 0 PSBT, moving the fields of the unsigned transaction in the input and
+    /// output maps. The resulting PSBT is not modifiable
+    pub fn from_v0(psbt: &PSBT) -> Result<Self> {
+        check_v0(psbt)?;
+        let bytes = serialize(psbt);
+        let mut cursor = Cursor::new(&bytes[..]);
+        read_magic(&mut cursor)?;
+        let mut global = read_map(&mut cursor)?;
+        global.remove(&key(GLOBAL_VERSION));
+        global.remove(&key(GLOBAL_UNSIGNED_TX));
+        let tx = &psbt.global.unsigned_tx;
+        check_tx_version(tx.version as u32)?;
+
+        let mut inputs = vec![];
+        for txin in tx.input.iter() {
+            inputs.push(InputV2 {
+                previous_output: txin.previous_output,
+                sequence: Some(txin.sequence),
+                required_time_locktime: None,
+                required_height_locktime: None,
+                pairs: read_map(&mut cursor)?,
+            });
+        }
+        let mut outputs = vec![];
+        for txout in tx.output.iter() {
+            outputs.push(OutputV2 {
+                amount: txout.value,
+                script_pubkey: txout.script_pubkey.clone(),
+                pairs: read_map(&mut cursor)?,
+            });
+        }
+        check_consumed(&cursor)?;
+
+        Ok(PsbtV2 {
+            tx_version: tx.version as u32,
+            fallback_locktime: Some(tx.lock_time),
+            tx_modifiable: None,
+            global,
+            inputs,
+            outputs,
+        })
+    }
+
+    /// Convert to a version 0 PSBT, building the unsigned transaction from the input and output
+    /// maps. `tx_modifiable` has no meaning in version 0 and it's dropped, converting back with
+    /// `from_v0` gives a PSBT which is not modifiable
+    pub fn to_v0(&self) -> Result<PSBT> {
+        let tx = self.unsigned_tx()?;
+        let mut global = self.global.clone();
+        global.insert(key(GLOBAL_UNSIGNED_TX), serialize(&tx));
+
+        let mut bytes = MAGIC.to_vec();
+        write_map(&mut bytes, &global)?;
+        for input in self.inputs.iter() {
+            write_map(&mut bytes, &input.pairs)?;
+        }
+        for output in self.outputs.iter() {
+            write_map(&mut bytes, &output.pairs)?;
+        }
+        Ok(deserialize(&bytes)?)
+    }
+
+    pub fn unsigned_tx(&self) -> Result<Transaction> {
+        Ok(Transaction {
+            version: self.tx_version as i32,
+            lock_time: self.locktime()?,
+            input: self
+                .inputs
+                .iter()
+                .map(|input| TxIn {
+                    previous_output: input.previous_output,
+                    script_sig: Script::new(),
+                    sequence: input.sequence.unwrap_or(0xffff_ffff),
+                    witness: vec![],
+                })
+                .collect(),
+            output: self
+                .outputs
+                .iter()
+                .map(|output| TxOut {
+                    value: output.amount,
+                    script_pubkey: output.script_pubkey.clone(),
+                })
+                .collect(),
+        })
+    }
+
+    /// Locktime of the transaction as specified by BIP370: the fallback locktime when no input
+    /// requires one, otherwise the maximum of the required ones, heights are preferred when every
+    /// input requiring a locktime accepts both
+    pub fn locktime(&self) -> Result<u32> {
+        let requiring: Vec<&InputV2> = self
+            .inputs
+            .iter()
+            .filter(|i| i.required_height_locktime.is_some() || i.required_time_locktime.is_some())
+            .collect();
+        if requiring.is_empty() {
+            return Ok(self.fallback_locktime.unwrap_or(0));
+        }
+        let heights: Option<Vec<u32>> = requiring
+            .iter()
+            .map(|i| i.required_height_locktime)
+            .collect();
+        let times: Option<Vec<u32>> = requiring.iter().map(|i| i.required_time_locktime).collect();
+        heights
+            .or(times)
+            .and_then(|locktimes| locktimes.into_iter().max())
+            .ok_or(Error::PsbtV2LocktimeConflict)
+    }
+
+    /// Add an input spending `previous_output` with the fields of `input` (eg. the utxo and the key
+    /// derivations) and the locktimes it requires, following the rules of BIP370: the PSBT must
+    /// allow adding inputs, existing signatures must be ANYONECANPAY and the locktime of the
+    /// transaction can't change once an input is signed
+    pub fn add_input(
+        &mut self,
+        previous_output: OutPoint,
+        sequence: u32,
+        required_height_locktime: Option<u32>,
+        required_time_locktime: Option<u32>,
+        input: &psbt::Input,
+    ) -> Result<()> {
+        self.check_modifiable(INPUTS_MODIFIABLE, "inputs")?;
+        if self
+            .inputs
+            .iter()
+            .any(|i| i.previous_output == previous_output)
+        {
+            return Err(format!("input {} already present", previous_output).into());
+        }
+        let sighashes = self.signature_sighashes()?;
+        if sighashes.iter().any(|s| s & SIGHASH_ANYONECANPAY == 0) {
+            return Err("can't add inputs, a signature commits to all the inputs".into());
+        }
+        if self.has_sighash_single(&sighashes) && self.inputs.len() != self.outputs.len() {
+            return Err(
+                "can't add inputs, with SIGHASH_SINGLE signatures inputs and outputs \
+                must be added in pairs, input first"
+                    .into(),
+            );
+        }
+
+        let input = InputV2 {
+            previous_output,
+            sequence: Some(sequence),
+            required_time_locktime,
+            required_height_locktime,
+            pairs: to_raw_map(input)?,
+        };
+        input.check_locktimes()?;
+        let mut added = self.clone();
+        added.inputs.push(input);
+        let locktime = added.locktime()?;
+        if !sighashes.is_empty() && locktime != self.locktime()? {
+            return Err("can't add an input changing the locktime of a signed transaction".into());
+        }
+        *self = added;
+        Ok(())
+    }
+
+    /// Add `txout` with the fields of `output` (eg. the key derivations of a change), following the
+    /// rules of BIP370: the PSBT must allow adding outputs and existing signatures must be
+    /// SIGHASH_NONE or SIGHASH_SINGLE
+    pub fn add_output(&mut self, txout: TxOut, output: &psbt::Output) -> Result<()> {
+        self.check_modifiable(OUTPUTS_MODIFIABLE, "outputs")?;
+        let sighashes = self.signature_sighashes()?;
+        if sighashes.iter().any(|s| {
+            let base = s & !SIGHASH_ANYONECANPAY;
+            base != SIGHASH_NONE && base != SIGHASH_SINGLE
+        }) {
+            return Err("can't add outputs, a signature commits to all the outputs".into());
+        }
+        if self.has_sighash_single(&sighashes) && self.inputs.len() != self.outputs.len() + 1 {
+            return Err(
+                "can't add outputs, with SIGHASH_SINGLE signatures inputs and outputs \
+                must be added in pairs, input first"
+                    .into(),
+            );
+        }
+        self.outputs.push(OutputV2 {
+            amount: txout.value,
+            script_pubkey: txout.script_pubkey,
+            pairs: to_raw_map(output)?,
+        });
+        Ok(())
+    }
+
+    /// Sighash types of the signatures of the inputs, finalized inputs are considered SIGHASH_ALL
+    /// since their signatures aren't parsed
+    fn signature_sighashes(&self) -> Result<Vec<u8>> {
+        let mut sighashes = vec![];
+        for (key, value) in self.inputs.iter().flat_map(|i| i.pairs.iter()) {
+            match key.type_value {
+                IN_PARTIAL_SIG => {
+                    let sighash = value.last().ok_or_else(|| {
+                        Error::PsbtV2MissingField("sighash of a partial signature".to_string())
+                    })?;
+                    sighashes.push(*sighash);
+                }
+                IN_FINAL_SCRIPTSIG | IN_FINAL_SCRIPTWITNESS => sighashes.push(SIGHASH_ALL),
+                _ => (),
+            }
+        }
+        Ok(sighashes)
+    }
+
+    fn has_sighash_single(&self, sighashes: &[u8]) -> bool {
+        self.tx_modifiable.unwrap_or(0) & HAS_SIGHASH_SINGLE != 0
+            || sighashes
+                .iter()
+                .any(|s| s & !SIGHASH_ANYONECANPAY == SIGHASH_SINGLE)
+    }
+
+    fn check_modifiable(&self, flag: u8, what: &str) -> Result<()> {
+        if self.tx_modifiable.unwrap_or(0) & flag == 0 {
+            return Err(Error::PsbtNotModifiable(what.to_string()));
+        }
+        Ok(())
+    }
+}
+
+impl InputV2 {
+    /// Required time locktimes must be timestamps and required height locktimes block heights
+    fn check_locktimes(&self) -> Result<()> {
+        if let Some(time) = self.required_time_locktime {
+            if time < LOCKTIME_THRESHOLD {
+                return Err(format!("required time locktime {} is a block height", time).into());
+            }
+        }
+        if let Some(height) = self.required_height_locktime {
+            if height == 0 || height >= LOCKTIME_THRESHOLD {
+                return Err(format!("required height locktime {} is not a height", height).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+fn key(type_value: u8) -> raw::Key {
+    raw::Key {
+        type_value,
+        key: vec![],
+    }
+}
+
+/// BIP370 requires transactions of version 2 or greater
+fn check_tx_version(tx_version: u32) -> Result<()> {
+    if tx_version < 2 {
+        return Err(format!(
+            "PSBTv2 requires tx version 2 or greater, found {}",
+            tx_version
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn required<T>(field: Option<T>, name: &str) -> Result<T> {
+    field.ok_or_else(|| Error::PsbtV2MissingField(name.to_string()))
+}
+
+/// Remove and decode the field of `type_value` without key data
+fn take_field<T: Decodable>(map: &mut RawMap, type_value: u8) -> Result<Option<T>> {
+    match map.remove(&key(type_value)) {
+        Some(value) => Ok(Some(deserialize(&value)?)),
+        None => Ok(None),
+    }
+}
+
+fn to_raw_map<M: Map>(map: &M) -> Result<RawMap> {
+    let mut raw_map = RawMap::new();
+    for pair in map.get_pairs()? {
+        if raw_map.insert(pair.key.clone(), pair.value).is_some() {
+            return Err(psbt::Error::DuplicateKey(pair.key).into());
+        }
+    }
+    Ok(raw_map)
+}
+
+fn read_magic(cursor: &mut Cursor<&[u8]>) -> Result<()> {
+    let mut magic = [0u8; 5];
+    std::io::Read::read_exact(cursor, &mut magic)
+        .map_err(|_| Error::from(psbt::Error::InvalidMagic))?;
+    if magic != MAGIC {
+        return Err(psbt::Error::InvalidMagic.into());
+    }
+    Ok(())
+}
+
+fn read_map(cursor: &mut Cursor<&[u8]>) -> Result<RawMap> {
+    let mut map = RawMap::new();
+    loop {
+        match raw::Key::consensus_decode(&mut *cursor) {
+            Ok(key) => {
+                let value: Vec<u8> = Decodable::consensus_decode(&mut *cursor)?;
+                if map.contains_key(&key) {
+                    return Err(psbt::Error::DuplicateKey(key).into());
+                }
+                map.insert(key, value);
+            }
+            Err(encode::Error::Psbt(psbt::Error::NoMorePairs)) => return Ok(map),
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+fn write_map(bytes: &mut Vec<u8>, map: &RawMap) -> Result<()> {
+    for (key, value) in map.iter() {
+        key.consensus_encode(&mut *bytes)?;
+        value.consensus_encode(&mut *bytes)?;
+    }
+    bytes.push(0x00);
+    Ok(())
+}
+
+fn check_consumed(cursor: &Cursor<&[u8]>) -> Result<()> {
+    if cursor.position() as usize != cursor.get_ref().len() {
+        return Err(encode::Error::ParseFailed("data not consumed entirely").into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::psbt_v2::*;
+    use bitcoin::util::psbt::raw;
+
+    fn psbt(bytes: &[u8]) -> PSBT {
+        let psbt_json: PsbtJson = serde_json::from_slice(bytes).unwrap();
+        psbt_from_base64(&psbt_json.psbt).unwrap().1
+    }
+
+    #[test]
+    fn test_psbt_v2() {
+        let psbt_v0 = psbt(include_bytes!(
+            "../../test_data/sign/psbt_testnet.1.signed.json"
+        ));
+        let v0_bytes = serialize(&psbt_v0);
+        assert_eq!(psbt_version(&v0_bytes).unwrap(), 0);
+
+        let psbt_v2 = PsbtV2::from_v0(&psbt_v0).unwrap();
+        assert_eq!(psbt_v2.inputs.len(), psbt_v0.inputs.len());
+        assert_eq!(psbt_v2.outputs.len(), psbt_v0.outputs.len());
+        assert_eq!(psbt_v2.unsigned_tx().unwrap(), psbt_v0.global.unsigned_tx);
+        let v2_bytes = psbt_v2.to_bytes().unwrap();
+        assert_eq!(psbt_version(&v2_bytes).unwrap(), 2);
+        assert!(
+            deserialize::<PSBT>(&v2_bytes).is_err(),
+            "v2 has no unsigned tx"
+        );
+        assert_eq!(PsbtV2::from_bytes(&v2_bytes).unwrap(), psbt_v2);
+        assert_eq!(psbt_v2.to_v0().unwrap(), psbt_v0);
+        assert!(PsbtV2::from_bytes(&v0_bytes).is_err());
+
+        // v2 is transparently converted when read
+        assert_eq!(psbt_from_bytes(&v2_bytes).unwrap(), psbt_v0);
+        let (bytes, psbt) = psbt_from_base64(&base64::encode(&v2_bytes)).unwrap();
+        assert_eq!(psbt, psbt_v0);
+        assert_eq!(bytes, v0_bytes);
+
+        let mut unsupported = PsbtV2::from_bytes(&v2_bytes).unwrap();
+        unsupported.global.insert(
+            raw::Key {
+                type_value: 0xfb,
+                key: vec![],
+            },
+            serialize(&3u32),
+        );
+        let mut bytes = MAGIC.to_vec();
+        write_map(&mut bytes, &unsupported.global).unwrap();
+        assert_eq!(psbt_version(&bytes).unwrap(), 3);
+        assert!(psbt_from_bytes(&bytes).is_err());
+        assert!(PsbtV2::from_bytes(&v2_bytes[..v2_bytes.len() - 1]).is_err());
+
+        for tx_version in &[0u32, 1] {
+            let mut old = psbt_v2.clone();
+            old.tx_version = *tx_version;
+            assert!(PsbtV2::from_bytes(&old.to_bytes().unwrap()).is_err());
+            let mut old_v0 = psbt_v0.clone();
+            old_v0.global.unsigned_tx.version = *tx_version as i32;
+            assert!(PsbtV2::from_v0(&old_v0).is_err());
+        }
+    }
+
+    #[test]
+    fn test_psbt_v2_locktime() {
+        let psbt_v0 = psbt(include_bytes!("../../test_data/sign/psbt_bip.json"));
+        let mut psbt_v2 = PsbtV2::from_v0(&psbt_v0).unwrap();
+        psbt_v2.fallback_locktime = None;
+        assert_eq!(psbt_v2.locktime().unwrap(), 0);
+        psbt_v2.fallback_locktime = Some(100);
+        assert_eq!(psbt_v2.locktime().unwrap(), 100);
+
+        psbt_v2.inputs[0].required_height_locktime = Some(200);
+        psbt_v2.inputs[0].required_time_locktime = Some(500_000_100);
+        assert_eq!(psbt_v2.locktime().unwrap(), 200, "height preferred");
+        psbt_v2.inputs[1].required_time_locktime = Some(500_000_200);
+        assert_eq!(psbt_v2.locktime().unwrap(), 500_000_200);
+        psbt_v2.inputs[0].required_time_locktime = None;
+        assert!(psbt_v2.locktime().is_err());
+        psbt_v2.inputs[1].required_height_locktime = Some(300);
+        assert_eq!(psbt_v2.locktime().unwrap(), 300);
+
+        let parsed = PsbtV2::from_bytes(&psbt_v2.to_bytes().unwrap()).unwrap();
+        assert_eq!(parsed, psbt_v2);
+        assert_eq!(parsed.to_v0().unwrap().global.unsigned_tx.lock_time, 300);
+    }
+
+    #[test]
+    fn test_psbt_v2_add() {
+        let first = psbt(include_bytes!("../../test_data/sign/psbt_bip.json"));
+        let second = psbt(include_bytes!(
+            "../../test_data/sign/psbt_testnet.1.signed.json"
+        ));
+        let mut psbt_v2 = PsbtV2::from_v0(&first).unwrap();
+        let input = unsigned_input(&second.inputs[0]);
+        let previous_output = second.global.unsigned_tx.input[0].previous_output;
+        let txout = second.global.unsigned_tx.output[0].clone();
+        assert!(psbt_v2
+            .add_input(previous_output, 0xffff_fffd, None, None, &input)
+            .is_err());
+        assert!(psbt_v2
+            .add_output(txout.clone(), &second.outputs[0])
+            .is_err());
+
+        psbt_v2.tx_modifiable = Some(INPUTS_MODIFIABLE | OUTPUTS_MODIFIABLE);
+        psbt_v2
+            .add_input(previous_output, 0xffff_fffd, None, None, &input)
+            .unwrap();
+        assert!(psbt_v2
+            .add_input(previous_output, 0xffff_fffd, None, None, &input)
+            .is_err());
+        psbt_v2
+            .add_output(txout.clone(), &second.outputs[0])
+            .unwrap();
+
+        let parsed = PsbtV2::from_bytes(&psbt_v2.to_bytes().unwrap()).unwrap();
+        assert_eq!(parsed.tx_modifiable, psbt_v2.tx_modifiable);
+        let psbt_v0 = parsed.to_v0().unwrap();
+        let tx = &psbt_v0.global.unsigned_tx;
+        assert_eq!(tx.input.len(), first.inputs.len() + 1);
+        assert_eq!(tx.input.last().unwrap().previous_output, previous_output);
+        assert_eq!(tx.input.last().unwrap().sequence, 0xffff_fffd);
+        assert_eq!(tx.output.last().unwrap(), &txout);
+        assert_eq!(psbt_v0.inputs.last().unwrap(), &input);
+        assert_eq!(psbt_v0.outputs.last().unwrap(), &second.outputs[0]);
+        assert_eq!(psbt_v0.inputs[0], first.inputs[0]);
+
+        // v0 has no modifiable flags, converting back gives a PSBT which is not modifiable
+        let back = PsbtV2::from_v0(&psbt_v0).unwrap();
+        assert_eq!(back.tx_modifiable, None);
+        assert!(back.clone().add_output(txout, &second.outputs[0]).is_err());
+    }
+
+    type Pairs = Vec<(u8, Vec<u8>)>;
+
+    /// Serialize maps of pairs without key data, sorted by type as BIP174 serializers do
+    fn raw_psbt(maps: &[&Pairs]) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        for pairs in maps {
+            let mut pairs = pairs.to_vec();
+            pairs.sort();
+            let map: RawMap = pairs.into_iter().map(|(t, v)| (key(t), v)).collect();
+            write_map(&mut bytes, &map).unwrap();
+        }
+        bytes
+    }
+
+    /// Without `removed` and with `added` fields
+    fn edit(pairs: &Pairs, removed: u8, added: &[(u8, Vec<u8>)]) -> Pairs {
+        let mut pairs: Pairs = pairs.iter().filter(|p| p.0 != removed).cloned().collect();
+        pairs.extend(added.iter().cloned());
+        pairs
+    }
+
+    /// The cases of the test vectors of BIP370, built from a PSBTv2 with 1 input and 2 outputs
+    #[test]
+    fn test_psbt_v2_bip370_vectors() {
+        let global: Pairs = vec![
+            (GLOBAL_TX_VERSION, serialize(&2u32)),
+            (GLOBAL_INPUT_COUNT, serialize(&VarInt(1))),
+            (GLOBAL_OUTPUT_COUNT, serialize(&VarInt(2))),
+            (GLOBAL_VERSION, serialize(&2u32)),
+        ];
+        let input: Pairs = vec![
+            (IN_PREVIOUS_TXID, vec![0x0b; 32]),
+            (IN_OUTPUT_INDEX, serialize(&1u32)),
+        ];
+        let script = hex::decode("0014d85c2b71d0060b09c9886aeb815e50991dda124d").unwrap();
+        let output: Pairs = vec![
+            (OUT_AMOUNT, serialize(&149_990_000u64)),
+            (OUT_SCRIPT, script.clone()),
+        ];
+        let change: Pairs = vec![
+            (OUT_AMOUNT, serialize(&100_000_000u64)),
+            (OUT_SCRIPT, script),
+        ];
+
+        // valid, parsed and serialized back to the same bytes
+        let time = serialize(&500_000_000u32);
+        let height = serialize(&10_000u32);
+        let valid_inputs = [
+            input.clone(),
+            edit(&input, 0, &[(IN_SEQUENCE, serialize(&0xffff_fffeu32))]),
+            edit(&input, 0, &[(IN_REQUIRED_TIME_LOCKTIME, time.clone())]),
+            edit(&input, 0, &[(IN_REQUIRED_HEIGHT_LOCKTIME, height.clone())]),
+            edit(
+                &input,
+                0,
+                &[
+                    (IN_REQUIRED_TIME_LOCKTIME, time),
+                    (IN_REQUIRED_HEIGHT_LOCKTIME, height),
+                ],
+            ),
+        ];
+        let valid_globals = [
+            global.clone(),
+            edit(&global, 0, &[(GLOBAL_FALLBACK_LOCKTIME, serialize(&0u32))]),
+            edit(
+                &global,
+                0,
+                &[(GLOBAL_TX_MODIFIABLE, vec![INPUTS_MODIFIABLE])],
+            ),
+            edit(
+                &global,
+                0,
+                &[(GLOBAL_TX_MODIFIABLE, vec![OUTPUTS_MODIFIABLE])],
+            ),
+            edit(
+                &global,
+                0,
+                &[(GLOBAL_TX_MODIFIABLE, vec![HAS_SIGHASH_SINGLE])],
+            ),
+            edit(&global, 0, &[(GLOBAL_TX_MODIFIABLE, vec![0x07])]),
+        ];
+        let mut valid = vec![];
+        for input in valid_inputs.iter() {
+            valid.push(raw_psbt(&[&global, input, &output, &change]));
+        }
+        for global in valid_globals.iter() {
+            valid.push(raw_psbt(&[global, &input, &output, &change]));
+        }
+        for bytes in valid {
+            let psbt_v2 = PsbtV2::from_bytes(&bytes).unwrap();
+            assert_eq!(psbt_v2.to_bytes().unwrap(), bytes);
+            assert_eq!(psbt_version(&bytes).unwrap(), 2);
+            let psbt_v0 = psbt_from_bytes(&bytes).unwrap();
+            assert_eq!(psbt_v0.global.unsigned_tx, psbt_v2.unsigned_tx().unwrap());
+        }
+
+        // invalid PSBTv2
+        let unsigned_tx = serialize(&Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![],
+            output: vec![],
+        });
+        let invalid = vec![
+            raw_psbt(&[
+                &edit(&global, GLOBAL_INPUT_COUNT, &[]),
+                &input,
+                &output,
+                &change,
+            ]),
+            raw_psbt(&[
+                &edit(&global, GLOBAL_OUTPUT_COUNT, &[]),
+                &input,
+                &output,
+                &change,
+            ]),
+            raw_psbt(&[
+                &edit(&global, 0xff, &[(GLOBAL_UNSIGNED_TX, unsigned_tx)]),
+                &input,
+                &output,
+                &change,
+            ]),
+            raw_psbt(&[
+                &global,
+                &edit(&input, IN_PREVIOUS_TXID, &[]),
+                &output,
+                &change,
+            ]),
+            raw_psbt(&[
+                &global,
+                &edit(&input, IN_OUTPUT_INDEX, &[]),
+                &output,
+                &change,
+            ]),
+            raw_psbt(&[&global, &input, &edit(&output, OUT_AMOUNT, &[]), &change]),
+            raw_psbt(&[&global, &input, &output, &edit(&change, OUT_SCRIPT, &[])]),
+            raw_psbt(&[
+                &global,
+                &edit(
+                    &input,
+                    0,
+                    &[(IN_REQUIRED_TIME_LOCKTIME, serialize(&499_999_999u32))],
+                ),
+                &output,
+                &change,
+            ]),
+            raw_psbt(&[
+                &global,
+                &edit(
+                    &input,
+                    0,
+                    &[(IN_REQUIRED_HEIGHT_LOCKTIME, serialize(&500_000_000u32))],
+                ),
+                &output,
+                &change,
+            ]),
+        ];
+        for bytes in invalid {
+            assert!(PsbtV2::from_bytes(&bytes).is_err());
+            assert!(psbt_from_bytes(&bytes).is_err());
+        }
+
+        // invalid PSBTv0 carrying fields of PSBTv2
+        let psbt_v0 = psbt(include_bytes!("../../test_data/sign/psbt_bip.json"));
+        assert!(psbt_from_bytes(&serialize(&psbt_v0)).is_ok());
+        let value = vec![0x01; 4];
+        for type_value in V2_GLOBAL_FIELDS.iter() {
+            let mut invalid = psbt_v0.clone();
+            invalid
+                .global
+                .unknown
+                .insert(key(*type_value), value.clone());
+            assert!(psbt_from_bytes(&serialize(&invalid)).is_err());
+        }
+        for type_value in V2_IN_FIELDS.iter() {
+            let mut invalid = psbt_v0.clone();
+            invalid.inputs[0]
+                .unknown
+                .insert(key(*type_value), value.clone());
+            assert!(psbt_from_bytes(&serialize(&invalid)).is_err());
+        }
+        for type_value in V2_OUT_FIELDS.iter() {
+            let mut invalid = psbt_v0.clone();
+            invalid.outputs[0]
+                .unknown
+                .insert(key(*type_value), value.clone());
+            assert!(psbt_from_bytes(&serialize(&invalid)).is_err());
+            assert!(PsbtV2::from_v0(&invalid).is_err());
+        }
+    }
+
+    fn unsigned_input(input: &psbt::Input) -> psbt::Input {
+        let mut input = input.clone();
+        input.partial_sigs.clear();
+        input.final_script_sig = None;
+        input.final_script_witness = None;
+        input
+    }
+
+    fn partial_sig(sighash: u8) -> (raw::Key, Vec<u8>) {
+        let key = raw::Key {
+            type_value: IN_PARTIAL_SIG,
+            key: vec![0x02; 33],
+        };
+        (key, vec![0x30, 0x00, sighash])
+    }
+
+    #[test]
+    fn test_psbt_v2_add_signed() {
+        let first = psbt(include_bytes!("../../test_data/sign/psbt_bip.json"));
+        let second = psbt(include_bytes!(
+            "../../test_data/sign/psbt_testnet.1.signed.json"
+        ));
+        let mut unsigned = PsbtV2::from_v0(&first).unwrap();
+        unsigned.tx_modifiable = Some(INPUTS_MODIFIABLE | OUTPUTS_MODIFIABLE);
+        for input in unsigned.inputs.iter_mut() {
+            input.pairs.retain(|k, _| {
+                k.type_value != IN_PARTIAL_SIG
+                    && k.type_value != IN_FINAL_SCRIPTSIG
+                    && k.type_value != IN_FINAL_SCRIPTWITNESS
+            });
+        }
+        let input = unsigned_input(&second.inputs[0]);
+        let previous_output = second.global.unsigned_tx.input[0].previous_output;
+        let txout = second.global.unsigned_tx.output[0].clone();
+        let add_input = |psbt: &mut PsbtV2, height: Option<u32>| {
+            psbt.add_input(previous_output, 0xffff_fffd, height, None, &input)
+        };
+        let add_output = |psbt: &mut PsbtV2| psbt.add_output(txout.clone(), &second.outputs[0]);
+
+        // SIGHASH_ALL commits to inputs and outputs, finalized inputs are considered SIGHASH_ALL
+        let mut signed = unsigned.clone();
+        let (sig_key, sig) = partial_sig(SIGHASH_ALL);
+        signed.inputs[0].pairs.insert(sig_key, sig);
+        assert!(add_input(&mut signed.clone(), None).is_err());
+        assert!(add_output(&mut signed).is_err());
+        let mut finalized = unsigned.clone();
+        finalized.inputs[0]
+            .pairs
+            .insert(key(IN_FINAL_SCRIPTWITNESS), vec![0x00]);
+        assert!(add_input(&mut finalized.clone(), None).is_err());
+        assert!(add_output(&mut finalized).is_err());
+
+        // ALL|ANYONECANPAY allows adding inputs not changing the locktime
+        let mut signed = unsigned.clone();
+        let (sig_key, sig) = partial_sig(SIGHASH_ALL | SIGHASH_ANYONECANPAY);
+        signed.inputs[0].pairs.insert(sig_key, sig);
+        assert!(add_output(&mut signed.clone()).is_err());
+        assert!(add_input(&mut signed.clone(), Some(100)).is_err());
+        let mut not_signed = unsigned.clone();
+        add_input(&mut not_signed, Some(100)).unwrap();
+        assert_eq!(not_signed.locktime().unwrap(), 100);
+        add_input(&mut signed, None).unwrap();
+        assert_eq!(signed.locktime().unwrap(), unsigned.locktime().unwrap());
+
+        // NONE allows adding outputs
+        let mut signed = unsigned.clone();
+        let (sig_key, sig) = partial_sig(SIGHASH_NONE);
+        signed.inputs[0].pairs.insert(sig_key, sig);
+        assert!(add_input(&mut signed.clone(), None).is_err());
+        add_output(&mut signed).unwrap();
+
+        // SINGLE|ANYONECANPAY requires adding input and output in pairs
+        let mut signed = unsigned.clone();
+        let (sig_key, sig) = partial_sig(SIGHASH_SINGLE | SIGHASH_ANYONECANPAY);
+        signed.inputs[0].pairs.insert(sig_key, sig);
+        assert_eq!(signed.inputs.len(), signed.outputs.len());
+        assert!(add_output(&mut signed).is_err(), "output before input");
+        add_input(&mut signed, None).unwrap();
+        assert!(add_input(&mut signed.clone(), None).is_err());
+        add_output(&mut signed).unwrap();
+        assert_eq!(signed.inputs.len(), signed.outputs.len());
+        let mut flagged = unsigned.clone();
+        flagged.tx_modifiable = Some(INPUTS_MODIFIABLE | OUTPUTS_MODIFIABLE | HAS_SIGHASH_SINGLE);
+        assert!(add_output(&mut flagged).is_err());
+    }
+}
@@ -1,29 +1,338 @@
+use crate::qr_render::{to_png, to_svg, Caption};
+use crate::ur::{Ur, UrEncoder};
 use crate::*;
+use bitcoin::hashes::{sha256, Hash};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
 use log::info;
 use qr_code::bits::{Bits, ExtendedMode};
 use qr_code::{bits, EcLevel, QrCode, Version};
+use serde::{Deserialize, Serialize};
 use std::convert::TryFrom;
+use std::fmt;
 use std::fs;
 use std::fs::File;
-use std::io::Write;
+use std::io;
+use std::io::{Read, Write};
 use std::path::PathBuf;
+use std::str::FromStr;
+use structopt::StructOpt;
 
 #[derive(Debug)]
 pub enum QrError {}
 
-/// path contains up to the filename (use dummy value) that will be replaced by qr file name
-pub fn save_qrs(bytes: Vec<u8>, qr_dir: PathBuf, version: i16) -> Result<Vec<PathBuf>> {
+/// Image format, rendering and encoding of the QR codes saved along with keys, wallets, PSBTs and
+/// addresses
+#[derive(StructOpt, Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct QrStyle {
+    /// Image format of the QR codes: bmp, png or svg
+    #[structopt(long, default_value = "bmp")]
+    pub qr_format: QrFormat,
+
+    /// Size in pixels of a module (the QR code "dot") of png and svg images
+    #[structopt(long, default_value = "8")]
+    pub qr_module_size: u32,
+
+    /// Width in modules of the light border around png and svg images
+    #[structopt(long, default_value = "4")]
+    pub qr_quiet_zone: u32,
+
+    /// Error correction level: L (7%), M (15%), Q (25%) or H (30%). Higher levels recover from
+    /// more damage but need more or bigger QR codes
+    #[structopt(long, default_value = "L")]
+    pub qr_ec_level: QrEcLevel,
+
+    /// Don't write the caption with name, part and checksum under png and svg images
+    #[structopt(long)]
+    pub qr_no_caption: bool,
+
+    /// Compress the content of the QR codes, JSON is also converted to CBOR, so that it needs fewer
+    /// codes. Compressed codes are readable by firma only, content fitting a single code is left
+    /// as is
+    #[structopt(long)]
+    pub qr_compress: bool,
+}
+
+impl Default for QrStyle {
+    fn default() -> Self {
+        QrStyle {
+            qr_format: QrFormat::Bmp,
+            qr_module_size: 8,
+            qr_quiet_zone: 4,
+            qr_ec_level: QrEcLevel::L,
+            qr_no_caption: false,
+            qr_compress: false,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum QrFormat {
+    Bmp,
+    Png,
+    Svg,
+}
+
+impl QrFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            QrFormat::Bmp => "bmp",
+            QrFormat::Png => "png",
+            QrFormat::Svg => "svg",
+        }
+    }
+}
+
+impl FromStr for QrFormat {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "bmp" => Ok(QrFormat::Bmp),
+            "png" => Ok(QrFormat::Png),
+            "svg" => Ok(QrFormat::Svg),
+            _ => Err(io_err("valid values are: bmp, png, svg")),
+        }
+    }
+}
+
+impl fmt::Display for QrFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum QrEcLevel {
+    L,
+    M,
+    Q,
+    H,
+}
+
+impl FromStr for QrEcLevel {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "L" | "l" => Ok(QrEcLevel::L),
+            "M" | "m" => Ok(QrEcLevel::M),
+            "Q" | "q" => Ok(QrEcLevel::Q),
+            "H" | "h" => Ok(QrEcLevel::H),
+            _ => Err(io_err("valid values are: L, M, Q, H")),
+        }
+    }
+}
+
+impl fmt::Display for QrEcLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl From<QrEcLevel> for EcLevel {
+    fn from(level: QrEcLevel) -> Self {
+        match level {
+            QrEcLevel::L => EcLevel::L,
+            QrEcLevel::M => EcLevel::M,
+            QrEcLevel::Q => EcLevel::Q,
+            QrEcLevel::H => EcLevel::H,
+        }
+    }
+}
+
+/// Save `bytes` in QR codes in `qr_dir`, split in structured append QR codes if they don't fit one
+/// QR code of `version`. Structured append is limited to 16 QR codes, bigger data is saved as the
+/// frames of an animated `bytes` UR
+pub fn save_qrs(
+    bytes: Vec<u8>,
+    qr_dir: PathBuf,
+    version: i16,
+    style: &QrStyle,
+) -> Result<Vec<PathBuf>> {
     match version {
         0 => return Ok(vec![]),
         5..=20 => info!("save_qrs data len:{} version:{}", bytes.len(), version),
         _ => return Err(format!("invalid qr version {}", version).into()),
     }
 
-    let mut wallet_qr_files = vec![];
-
-    let qrs = SplittedQr::new(bytes, version)?.split()?;
+    let checksum = checksum(&bytes);
+    let is_psbt = bytes.starts_with(b"psbt\xff");
+    // a plain single code stays readable as text by other readers, like the android app
+    let fits_one = bytes.len() < max_bytes(version, style.qr_ec_level.into())?;
+    let bytes = if style.qr_compress && !fits_one {
+        compress(bytes)?
+    } else {
+        bytes
+    };
+    let qrs = match SplittedQr::new(bytes.clone(), version, style.qr_ec_level.into()) {
+        Err(Error::QrSplitMax16(total_qr)) => {
+            info!("{} qr needed, saving as animated ur", total_qr);
+            // readers of crypto-psbt expect the PSBT as is, not in the compressed envelope
+            let ur = if is_psbt {
+                Ur::from_psbt(&decompress(bytes)?)
+            } else {
+                Ur::from_bytes(&bytes)
+            };
+            return save_ur_qrs(&ur, qr_dir, version, style);
+        }
+        splitted_qr => splitted_qr?.split()?,
+    };
     info!("splitted qr in {} pieces", qrs.len());
 
+    write_qrs(&qrs, qr_dir, style, &checksum)
+}
+
+/// Save `ur` in QR codes of `version` in `qr_dir`, as a single QR if it fits or as the frames of an
+/// animated QR otherwise. Frames after the first `seq_len` are fountain coded, allowing readers
+/// to recover frames missed while scanning. Parts are also saved in text in `ur.txt`
+pub fn save_ur_qrs(
+    ur: &Ur,
+    qr_dir: PathBuf,
+    version: i16,
+    style: &QrStyle,
+) -> Result<Vec<PathBuf>> {
+    match version {
+        0 => return Ok(vec![]),
+        5..=20 => info!(
+            "save_ur_qrs {} len:{} version:{}",
+            ur.ur_type,
+            ur.cbor.len(),
+            version
+        ),
+        _ => return Err(format!("invalid qr version {}", version).into()),
+    }
+
+    let level = style.qr_ec_level.into();
+    let mut encoder = UrEncoder::new(ur, ur_max_fragment_len(version, level)?)?;
+    let frames = match encoder.seq_len() {
+        1 => 1,
+        seq_len => seq_len * 2,
+    };
+    let parts: Vec<String> = (0..frames).map(|_| encoder.next_part()).collect();
+    info!("ur in {} parts", parts.len());
+    let qrs = parts
+        .iter()
+        .map(|part| {
+            // uppercase is encoded in the more compact alphanumeric mode
+            let part = part.to_uppercase();
+            Ok(QrCode::with_version(part, Version::Normal(version), level)?)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let qr_files = write_qrs(&qrs, qr_dir.clone(), style, &checksum(&ur.cbor))?;
+    let mut ur_file = qr_dir;
+    ur_file.push("ur.txt");
+    fs::write(&ur_file, parts.join("\n"))?;
+    Ok(qr_files)
+}
+
+/// Max payload bytes of a UR part fitting a QR code of `version` and `level`: max bytes in byte
+/// mode are about 16/11 chars in alphanumeric mode, minus the `ur:type/seq-len/` prefix, the CBOR
+/// header of the part and its checksum, two chars per byte
+fn ur_max_fragment_len(version: i16, level: EcLevel) -> Result<usize> {
+    let max_chars = max_bytes(version, level)? * 16 / 11;
+    match (max_chars.saturating_sub(32) / 2).saturating_sub(26) {
+        len if len < 10 => Err(format!(
+            "qr version {} is too small for UR parts at error correction level {:?}",
+            version, level
+        )
+        .into()),
+        len => Ok(len),
+    }
+}
+
+/// Max bytes encodable in a structured append qr code of `version` and `level`, `MAX_BYTES` are
+/// the ones of level L, higher levels use more codewords for error correction
+fn max_bytes(version: i16, level: EcLevel) -> Result<usize> {
+    let max_bytes = *MAX_BYTES
+        .get(version as usize)
+        .ok_or_else(|| Error::QrUnsupportedVersion(version))?;
+    let bits = Bits::new(Version::Normal(version));
+    let correction_bytes = (bits.max_len(EcLevel::L)? - bits.max_len(level)?) / 8;
+    Ok(max_bytes.saturating_sub(correction_bytes))
+}
+
+/// Compress `bytes` in an envelope starting with `ENVELOPE_MAGIC` and the flags of the encodings
+/// applied, JSON is converted to CBOR before zlib compression. Returns `bytes` unchanged if the
+/// envelope isn't smaller
+pub fn compress(bytes: Vec<u8>) -> Result<Vec<u8>> {
+    let mut flags = ENVELOPE_ZLIB;
+    let mut payload = &bytes[..];
+    let cbor;
+    if let Ok(json @ serde_json::Value::Object(_)) = serde_json::from_slice(&bytes) {
+        cbor = serde_cbor::to_vec(&json).map_err(|e| Error::QrEnvelope(e.to_string()))?;
+        payload = &cbor;
+        flags |= ENVELOPE_CBOR;
+    }
+    let mut envelope = ENVELOPE_MAGIC.to_vec();
+    envelope.push(flags);
+    let mut encoder = ZlibEncoder::new(envelope, Compression::best());
+    encoder.write_all(payload)?;
+    let envelope = encoder.finish()?;
+    info!("compressed {} bytes in {}", bytes.len(), envelope.len());
+    if envelope.len() < bytes.len() {
+        Ok(envelope)
+    } else {
+        Ok(bytes)
+    }
+}
+
+/// The content of the envelope created by `compress`, `bytes` unchanged if they aren't one
+pub fn decompress(bytes: Vec<u8>) -> Result<Vec<u8>> {
+    if !bytes.starts_with(ENVELOPE_MAGIC) || bytes.len() == ENVELOPE_MAGIC.len() {
+        return Ok(bytes);
+    }
+    let flags = bytes[ENVELOPE_MAGIC.len()];
+    if flags & !(ENVELOPE_ZLIB | ENVELOPE_CBOR) != 0 {
+        return Err(Error::QrEnvelope(format!("unknown flags {:#04x}", flags)));
+    }
+    let payload = &bytes[ENVELOPE_MAGIC.len() + 1..];
+    let mut decompressed = vec![];
+    if flags & ENVELOPE_ZLIB != 0 {
+        // limit the decompressed size, QR codes can't carry more than few KBs of useful data
+        ZlibDecoder::new(payload)
+            .take(ENVELOPE_MAX_LEN + 1)
+            .read_to_end(&mut decompressed)?;
+        if decompressed.len() as u64 > ENVELOPE_MAX_LEN {
+            return Err(Error::QrEnvelope(
+                "decompressed payload too large".to_string(),
+            ));
+        }
+    } else {
+        decompressed.extend(payload);
+    }
+    if flags & ENVELOPE_CBOR != 0 {
+        let json: serde_json::Value =
+            serde_cbor::from_slice(&decompressed).map_err(|e| Error::QrEnvelope(e.to_string()))?;
+        decompressed = serde_json::to_vec(&json)?;
+    }
+    Ok(decompressed)
+}
+
+/// First 4 bytes of the sha256 of `bytes`, printed in the caption of the QR codes to check the
+/// parts belong together
+fn checksum(bytes: &[u8]) -> String {
+    hex::encode(&sha256::Hash::hash(bytes)[..4])
+}
+
+fn write_qrs(
+    qrs: &[QrCode],
+    qr_dir: PathBuf,
+    style: &QrStyle,
+    checksum: &str,
+) -> Result<Vec<PathBuf>> {
+    if style.qr_format != QrFormat::Bmp && !(1..=64).contains(&style.qr_module_size) {
+        return Err(format!("invalid qr module size {}", style.qr_module_size).into());
+    }
+    let name = qr_dir
+        .parent()
+        .and_then(|p| p.file_name())
+        .map(|n| n.to_string_lossy().to_string());
+    let mut wallet_qr_files = vec![];
     let mut text_qr = vec![String::new(); 2];
     let single = qrs.len() == 1;
 
@@ -46,15 +355,30 @@ pub fn save_qrs(bytes: Vec<u8>, qr_dir: PathBuf, version: i16) -> Result<Vec<Pat
 
     let mut qr_file = qr_dir;
     qr_file.push("dummy");
+    let extension = style.qr_format.extension();
     for (i, qr) in qrs.iter().enumerate() {
         if single {
-            qr_file.set_file_name("qr.bmp");
+            qr_file.set_file_name(format!("qr.{}", extension));
         } else {
-            qr_file.set_file_name(&format!("qr-{}.bmp", i));
+            qr_file.set_file_name(format!("qr-{}.{}", i, extension));
         }
-        let qr_data = qr.to_bmp();
+        let caption = Caption {
+            name: name.clone(),
+            part: i + 1,
+            total: qrs.len(),
+            checksum: checksum.to_string(),
+        };
+        let caption = if style.qr_no_caption {
+            None
+        } else {
+            Some(caption.to_string())
+        };
         info!("Saving qr in {:?}", &qr_file);
-        qr_data.write(File::create(&qr_file)?)?;
+        match style.qr_format {
+            QrFormat::Bmp => qr.to_bmp().write(File::create(&qr_file)?)?,
+            QrFormat::Png => fs::write(&qr_file, to_png(qr, style, caption.as_deref())?)?,
+            QrFormat::Svg => fs::write(&qr_file, to_svg(qr, style, caption.as_deref()))?,
+        }
 
         wallet_qr_files.push(qr_file.clone());
 
@@ -80,7 +404,7 @@ pub fn merge_qrs(mut bytes: Vec<Vec<u8>>) -> Result<Vec<u8>> {
     bytes.sort();
     bytes.dedup();
 
-    if bytes.len() < 2 {
+    if bytes.len() < 2 && !is_single_structured(&bytes) {
         return Err(Error::QrAtLeast2Pieces);
     }
 
@@ -108,18 +432,32 @@ pub fn merge_qrs(mut bytes: Vec<Vec<u8>>) -> Result<Vec<u8>> {
         .flatten()
         .collect();
 
+    // parity is of the transmitted bytes, the envelope if compressed
     let final_parity = result.iter().fold(0u8, |acc, &x| acc ^ x);
     if vec_structured
         .iter()
         .map(|q| q.parity)
         .all(|p| p == final_parity)
     {
-        Ok(result)
+        decompress(result)
     } else {
         Err(crate::Error::QrParity)
     }
 }
 
+/// A structured append code of 1 part, as the envelopes of compressed content fitting one code
+fn is_single_structured(bytes: &[Vec<u8>]) -> bool {
+    use std::convert::TryInto;
+
+    match bytes {
+        [single] => {
+            let structured: Result<StructuredQr> = single.clone().try_into();
+            structured.map(|s| s.total == 0).unwrap_or(false)
+        }
+        _ => false,
+    }
+}
+
 struct StructuredQr {
     pub seq: u8,   // u4
     pub total: u8, // u4
@@ -149,18 +487,33 @@ impl TryFrom<Vec<u8>> for StructuredQr {
             return Err(Error::QrStructuredWrongEnc);
         }
 
-        let (length, from) = if value.len() < u8::max_value() as usize + 4 {
-            // 4 is header size, TODO recheck boundary
-            (value[3] as u16, 4usize)
-        } else {
-            (((value[3] as u16) << 8) + (value[4] as u16), 5usize)
-        };
-        let end = from + length as usize;
-        if value.len() < end {
-            return Err(crate::Error::QrLengthMismatch(end, value.len()));
+        // the length is 8 bits up to version 9, 16 bits from version 10, the version is the one
+        // having as many data codewords as `value`. When both are possible, like 154 codewords of
+        // version 8-M or 10-Q, the padding after the content decides
+        let (short, long) = length_forms(value.len());
+        let mut result = Err(Error::QrInvalidPayload);
+        for (from, possible) in [(5usize, long), (4usize, short)].iter() {
+            if !possible {
+                continue;
+            }
+            let length = if *from == 5 {
+                ((value[3] as usize) << 8) + value[4] as usize
+            } else {
+                value[3] as usize
+            };
+            let end = from + length;
+            result = if value.len() < end {
+                Err(crate::Error::QrLengthMismatch(end, value.len()))
+            } else if !is_padding(&value[end..]) {
+                Err(Error::QrInvalidPayload)
+            } else {
+                Ok(value[*from..end].to_vec())
+            };
+            if result.is_ok() {
+                break;
+            }
         }
-        let content = (&value[from..end]).to_vec();
-        //TODO check padding
+        let content = result?;
 
         Ok(StructuredQr {
             seq,
@@ -171,19 +524,52 @@ impl TryFrom<Vec<u8>> for StructuredQr {
     }
 }
 
+/// If the 8 bits and the 16 bits length are possible for a code with `data_len` data codewords,
+/// both are when no version has this number of codewords
+fn length_forms(data_len: usize) -> (bool, bool) {
+    let versions: Vec<i16> = (1..=40)
+        .filter(|v| {
+            [EcLevel::L, EcLevel::M, EcLevel::Q, EcLevel::H]
+                .iter()
+                .any(|l| Bits::new(Version::Normal(*v)).max_len(*l).ok() == Some(data_len * 8))
+        })
+        .collect();
+    if versions.is_empty() {
+        return (true, true);
+    }
+    (
+        versions.iter().any(|v| *v < 10),
+        versions.iter().any(|v| *v >= 10),
+    )
+}
+
+/// Bytes following the content: the terminator and the bits to the byte boundary, all zeros, then
+/// the pad codewords alternating 0xec and 0x11
+fn is_padding(bytes: &[u8]) -> bool {
+    match bytes.split_first() {
+        None => true,
+        Some((terminator, pad)) => {
+            *terminator == 0
+                && pad
+                    .iter()
+                    .zip([0xecu8, 0x11].iter().cycle())
+                    .all(|(a, b)| a == b)
+        }
+    }
+}
+
 pub struct SplittedQr {
     pub version: i16,
+    pub ec_level: EcLevel,
     pub parity: u8,
     pub total_qr: usize,
     pub bytes: Vec<u8>,
 }
 
 impl SplittedQr {
-    pub fn new(bytes: Vec<u8>, version: i16) -> Result<Self> {
+    pub fn new(bytes: Vec<u8>, version: i16, ec_level: EcLevel) -> Result<Self> {
         let parity = bytes.iter().fold(0u8, |acc, &x| acc ^ x);
-        let max_bytes = *MAX_BYTES
-            .get(version as usize)
-            .ok_or_else(|| Error::QrUnsupportedVersion(version))?;
+        let max_bytes = max_bytes(version, ec_level)?;
         let extra = if bytes.len() % max_bytes == 0 { 0 } else { 1 };
         let total_qr = bytes.len() / max_bytes + extra;
         if total_qr > 16 {
@@ -193,15 +579,17 @@ impl SplittedQr {
         Ok(SplittedQr {
             bytes,
             version,
+            ec_level,
             parity,
             total_qr,
         })
     }
 
     fn split_to_bits(&self) -> Result<Vec<Bits>> {
-        let max_bytes = MAX_BYTES[self.version as usize];
-        if self.bytes.len() < max_bytes {
-            let bits = bits::encode_auto(&self.bytes, LEVEL)?;
+        let max_bytes = max_bytes(self.version, self.ec_level)?;
+        // envelopes are always structured append, merged and decompressed by `merge_qrs`
+        if self.bytes.len() < max_bytes && !self.bytes.starts_with(ENVELOPE_MAGIC) {
+            let bits = bits::encode_auto(&self.bytes, self.ec_level)?;
             Ok(vec![bits])
         } else {
             let mut result = vec![];
@@ -216,7 +604,7 @@ impl SplittedQr {
     pub fn split(&self) -> Result<Vec<QrCode>> {
         self.split_to_bits()?
             .into_iter()
-            .map(|bits| Ok(QrCode::with_bits(bits, LEVEL)?))
+            .map(|bits| Ok(QrCode::with_bits(bits, self.ec_level)?))
             .collect()
     }
 
@@ -229,7 +617,7 @@ impl SplittedQr {
         bits.push_number_checked(4, self.total_qr - 1)?;
         bits.push_number_checked(8, self.parity as usize)?;
         bits.push_byte_data(chunk)?;
-        bits.push_terminator(LEVEL)?;
+        bits.push_terminator(self.ec_level)?;
 
         //println!("bits: {}\n", hex::encode(bits.clone().into_bytes()));
 
@@ -237,9 +625,14 @@ impl SplittedQr {
     }
 }
 
-const LEVEL: qr_code::types::EcLevel = EcLevel::L;
+/// Start of the compressed envelope, 0xfe can't start UTF-8 text nor a PSBT
+const ENVELOPE_MAGIC: &[u8] = b"\xfeFQR";
+const ENVELOPE_ZLIB: u8 = 1;
+const ENVELOPE_CBOR: u8 = 2;
+const ENVELOPE_MAX_LEN: u64 = 1_000_000;
 
-/// Max bytes encodable in a structured append qr code, given Qr code version as array index
+/// Max bytes encodable in a structured append qr code of level L, given Qr code version as array
+/// index
 const MAX_BYTES: [usize; 33] = [
     0, 15, 30, 51, 76, 104, 132, 152, 190, 228, 269, 319, 365, 423, 456, 518, 584, 642, 716, 790,
     856, 927, 1001, 1089, 1169, 1271, 1365, 1463, 1526, 1626, 1730, 1838, 1950,
@@ -247,12 +640,19 @@ const MAX_BYTES: [usize; 33] = [
 
 #[cfg(test)]
 mod tests {
-    use crate::common::qr::{merge_qrs, SplittedQr, StructuredQr, LEVEL};
-    use crate::Error;
+    use crate::common::qr::{
+        compress, decompress, merge_qrs, save_qrs, save_ur_qrs, ur_max_fragment_len, QrEcLevel,
+        QrFormat, QrStyle, SplittedQr, StructuredQr, ENVELOPE_CBOR, ENVELOPE_MAGIC,
+        ENVELOPE_MAX_LEN, ENVELOPE_ZLIB, MAX_BYTES,
+    };
+    use crate::qr_reader::read_qr;
+    use crate::ur::{self, Ur};
+    use crate::{Error, PsbtJson};
     use qr_code::bits::{Bits, ExtendedMode};
-    use qr_code::{QrCode, Version};
+    use qr_code::{EcLevel, QrCode, Version};
     use rand::Rng;
     use std::convert::TryInto;
+    use tempdir::TempDir;
 
     // from example https://segno.readthedocs.io/en/stable/structured-append.html#structured-append
     /*
@@ -294,6 +694,27 @@ mod tests {
         assert_eq!(structured_2.content, content);
     }
 
+    #[test]
+    fn test_structured_length_forms() {
+        let content = vec![0xabu8; 100];
+        let parse = |version: i16, level: EcLevel, content: &[u8]| {
+            let split_qr = SplittedQr::new(vec![0u8; 30], version, level).unwrap();
+            let bytes = split_qr.make_chunk(0, content).unwrap().into_bytes();
+            let structured: StructuredQr = bytes.clone().try_into().unwrap();
+            (bytes.len(), structured.content)
+        };
+        // 154 data codewords are both version 8-M, 8 bits length, and 10-Q, 16 bits length
+        assert_eq!(parse(8, EcLevel::M, &content), (154, content.clone()));
+        assert_eq!(parse(10, EcLevel::Q, &content), (154, content.clone()));
+        // an empty content would have a plausible 16 bits length
+        assert_eq!(parse(1, EcLevel::L, &[]), (19, vec![]));
+
+        let mut bytes = hex::decode(FIRST).unwrap();
+        bytes[18] = 0xec;
+        let result: Result<StructuredQr, _> = bytes.try_into();
+        assert!(result.is_err(), "invalid padding");
+    }
+
     #[test]
     fn test_merge() {
         let first = hex::decode(FIRST).unwrap();
@@ -354,7 +775,7 @@ mod tests {
         bits.push_number_checked(4, 1).unwrap(); // total length of the sequence (means 2)
         bits.push_number_checked(8, parity as usize).unwrap(); //parity of the complete data
         bits.push_byte_data(data_half).unwrap();
-        bits.push_terminator(LEVEL).unwrap();
+        bits.push_terminator(EcLevel::L).unwrap();
         assert_eq!(
             hex::encode(bits.into_bytes()),
             "3013940e49207265616420746865206e657700"
@@ -369,8 +790,12 @@ mod tests {
         for _ in 0..1_000 {
             let len = rng.gen_range(100, 4000);
             let ver = rng.gen_range(10, 20);
+            let level = [EcLevel::L, EcLevel::M, EcLevel::Q, EcLevel::H][rng.gen_range(0, 4)];
             let data = (&random_bytes[0..len]).to_vec();
-            let split_qr = SplittedQr::new(data.clone(), ver).unwrap();
+            let split_qr = match SplittedQr::new(data.clone(), ver, level) {
+                Err(Error::QrSplitMax16(_)) if level != EcLevel::L => continue,
+                split_qr => split_qr.unwrap(),
+            };
             let bits = split_qr.split_to_bits().unwrap();
             if bits.len() > 1 {
                 let bytes: Vec<Vec<u8>> = bits.into_iter().map(|b| b.into_bytes()).collect();
@@ -380,6 +805,198 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_save_ur_qrs() {
+        let temp_dir = TempDir::new("test_save_ur_qrs").unwrap();
+        let qr_dir = temp_dir.path().join("qr");
+        let psbt: Vec<u8> = (0..1500u32).map(|i| (i * 7 % 251) as u8).collect();
+        let ur = Ur::from_psbt(&psbt);
+        for version in 5..=20 {
+            assert!(ur_max_fragment_len(version, EcLevel::L).unwrap() >= 10);
+        }
+        assert!(ur_max_fragment_len(5, EcLevel::H).is_err());
+        let style = QrStyle::default();
+        for version in [5, 10, 14, 20].iter().cloned() {
+            let files = save_ur_qrs(&ur, qr_dir.clone(), version, &style).unwrap();
+            let parts: Vec<String> = std::fs::read_to_string(qr_dir.join("ur.txt"))
+                .unwrap()
+                .lines()
+                .map(|l| l.to_string())
+                .collect();
+            assert_eq!(files.len(), parts.len());
+            assert!(files.len() > 2);
+            assert_eq!(ur::decode(&parts).unwrap(), ur);
+            let missing_first = &parts[1..];
+            assert_eq!(
+                ur::decode(missing_first).unwrap(),
+                ur,
+                "recovered by fountain parts"
+            );
+        }
+
+        let small = Ur::from_psbt(&psbt[..10]);
+        let files = save_ur_qrs(&small, qr_dir.clone(), 14, &style).unwrap();
+        assert_eq!(files, vec![qr_dir.join("qr.bmp")]);
+        assert!(save_ur_qrs(&small, qr_dir, 0, &style).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_save_qrs_formats() {
+        let temp_dir = TempDir::new("test_save_qrs_formats").unwrap();
+        let qr_dir = temp_dir.path().join("psbt-name").join("qr");
+        std::fs::create_dir(temp_dir.path().join("psbt-name")).unwrap();
+        let data: Vec<u8> = (0..300u32).map(|i| (i * 7 % 251) as u8).collect();
+        let style = |qr_format, qr_ec_level| QrStyle {
+            qr_format,
+            qr_ec_level,
+            qr_module_size: 3,
+            ..Default::default()
+        };
+
+        let low = save_qrs(
+            data.clone(),
+            qr_dir.clone(),
+            5,
+            &style(QrFormat::Png, QrEcLevel::L),
+        );
+        let files = save_qrs(
+            data.clone(),
+            qr_dir.clone(),
+            5,
+            &style(QrFormat::Png, QrEcLevel::H),
+        );
+        let files = files.unwrap();
+        assert!(
+            files.len() > low.unwrap().len(),
+            "more codes with more correction"
+        );
+        assert_eq!(files[0], qr_dir.join("qr-0.png"));
+        let read: Vec<Vec<u8>> = files.iter().map(|f| read_qr(f).unwrap().data).collect();
+        assert_eq!(merge_qrs(read).unwrap(), data);
+
+        let files = save_qrs(data, qr_dir.clone(), 5, &style(QrFormat::Svg, QrEcLevel::M)).unwrap();
+        let svg = std::fs::read_to_string(&files[1]).unwrap();
+        assert_eq!(files[1], qr_dir.join("qr-1.svg"));
+        assert!(svg.contains(&format!("psbt-name 2/{} ", files.len())));
+        assert_eq!(std::fs::read_dir(&qr_dir).unwrap().count(), files.len() + 1);
+
+        let invalid = QrStyle {
+            qr_module_size: 0,
+            ..style(QrFormat::Svg, QrEcLevel::M)
+        };
+        assert!(save_qrs(vec![1], qr_dir, 5, &invalid).is_err());
+    }
+
+    #[test]
+    fn test_save_qrs_more_than_16() {
+        let temp_dir = TempDir::new("test_save_qrs_more_than_16").unwrap();
+        let qr_dir = temp_dir.path().join("qr");
+        let len = MAX_BYTES[5] as u32 * 16 + 1;
+        let data: Vec<u8> = (0..len).map(|i| (i * 7 % 251) as u8).collect();
+        assert_eq!(
+            SplittedQr::new(data.clone(), 5, EcLevel::L)
+                .err()
+                .unwrap()
+                .to_string(),
+            Error::QrSplitMax16(17).to_string()
+        );
+
+        let files = save_qrs(data.clone(), qr_dir.clone(), 5, &QrStyle::default()).unwrap();
+        assert!(files.len() > 16);
+        let content = read_qr(&files[3]).unwrap().content().unwrap();
+        assert!(content.starts_with(b"UR:BYTES/"));
+        let parts: Vec<String> = std::fs::read_to_string(qr_dir.join("ur.txt"))
+            .unwrap()
+            .lines()
+            .map(|l| l.to_string())
+            .collect();
+        assert_eq!(ur::decode(&parts).unwrap().to_bytes().unwrap(), data);
+
+        let mut psbt = b"psbt\xff".to_vec();
+        psbt.extend(&data);
+        let files = save_qrs(psbt.clone(), qr_dir.clone(), 5, &QrStyle::default()).unwrap();
+        let content = read_qr(&files[0]).unwrap().content().unwrap();
+        assert!(content.starts_with(b"UR:CRYPTO-PSBT/"));
+        let parts: Vec<String> = std::fs::read_to_string(qr_dir.join("ur.txt"))
+            .unwrap()
+            .lines()
+            .map(|l| l.to_string())
+            .collect();
+        assert_eq!(ur::decode(&parts).unwrap().to_psbt().unwrap(), psbt);
+    }
+
+    #[test]
+    fn test_compress() {
+        let psbt_json: PsbtJson =
+            serde_json::from_slice(include_bytes!("../../test_data/sign/psbt_bip.signed.json"))
+                .unwrap();
+        let psbt = base64::decode(&psbt_json.psbt).unwrap();
+        let compressed = compress(psbt.clone()).unwrap();
+        // same zlib stream of `test_compression` in sign.rs after the envelope header
+        assert_eq!(compressed.len(), ENVELOPE_MAGIC.len() + 1 + 1192);
+        assert_eq!(decompress(compressed).unwrap(), psbt);
+
+        let json = serde_json::to_vec(&psbt_json).unwrap();
+        let compressed = compress(json.clone()).unwrap();
+        assert_eq!(
+            compressed[ENVELOPE_MAGIC.len()],
+            ENVELOPE_ZLIB | ENVELOPE_CBOR
+        );
+        assert!(compressed.len() < psbt.len());
+        let decompressed: PsbtJson =
+            serde_json::from_slice(&decompress(compressed).unwrap()).unwrap();
+        assert_eq!(decompressed, psbt_json);
+
+        let short = b"tpubD6NzVbkrYhZ4XgiXtGrdW5XDAPFCL9h7we1vwNCpn8tGbBcgfVYj".to_vec();
+        assert_eq!(compress(short.clone()).unwrap(), short, "not smaller");
+        assert_eq!(decompress(short.clone()).unwrap(), short);
+        let mut unknown = ENVELOPE_MAGIC.to_vec();
+        unknown.extend(&[4, 1, 2, 3]);
+        assert!(decompress(unknown).is_err());
+
+        let temp_dir = TempDir::new("test_compress").unwrap();
+        let qr_dir = temp_dir.path().join("qr");
+        let style = QrStyle {
+            qr_compress: true,
+            ..Default::default()
+        };
+        let files = save_qrs(psbt.clone(), qr_dir.clone(), 5, &QrStyle::default()).unwrap();
+        let compressed_files = save_qrs(psbt.clone(), qr_dir.clone(), 5, &style).unwrap();
+        assert!(compressed_files.len() < files.len());
+        let read: Vec<Vec<u8>> = compressed_files
+            .iter()
+            .map(|f| read_qr(f).unwrap().data)
+            .collect();
+        assert_eq!(merge_qrs(read).unwrap(), psbt);
+
+        // fitting a single code once compressed, saved as a structured append code of 1 part
+        let text = vec![b'a'; 300];
+        let files = save_qrs(text.clone(), qr_dir.clone(), 5, &QrStyle::default()).unwrap();
+        assert_eq!(files.len(), 3);
+        let files = save_qrs(text.clone(), qr_dir.clone(), 5, &style).unwrap();
+        assert_eq!(files.len(), 1);
+        let read = read_qr(&files[0]).unwrap();
+        assert!(read.is_structured());
+        assert_eq!(merge_qrs(vec![read.data]).unwrap(), text);
+
+        // fitting a single code already, saved as is
+        let files = save_qrs(b"tpub".to_vec(), qr_dir, 5, &style).unwrap();
+        let read = read_qr(&files[0]).unwrap();
+        assert!(!read.is_structured());
+        assert_eq!(read.content().unwrap(), b"tpub");
+
+        // decompressing more than the limit is an error, not a truncated payload
+        let limit = ENVELOPE_MAX_LEN as usize;
+        let max = compress(vec![0u8; limit]).unwrap();
+        assert_eq!(decompress(max).unwrap().len(), limit);
+        let too_large = compress(vec![0u8; limit + 1]).unwrap();
+        assert!(too_large.starts_with(ENVELOPE_MAGIC));
+        assert_eq!(
+            decompress(too_large).unwrap_err().to_string(),
+            Error::QrEnvelope("decompressed payload too large".to_string()).to_string()
+        );
+    }
+
     #[test]
     fn test_print_qr() {
         let qr = QrCode::new(b"01234567").unwrap();
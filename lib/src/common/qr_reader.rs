@@ -0,0 +1,1256 @@
+//! Read QR codes from BMP or PNG images, like the ones saved by firma or snapshots of a code shown
+//! on another device. The image is binarized with a threshold adapting to uneven lighting, the code
+//! is located by its finder patterns and, from version 2, by its bottom right alignment pattern, so
+//! scaled, rotated, mirrored and perspective distorted images are supported.
+
+use crate::*;
+use log::debug;
+use std::fs;
+use std::path::Path;
+
+/// A QR code read from an image
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawQr {
+    /// QR version, from 1 to 40
+    pub version: usize,
+    /// The data codewords after error correction, the raw bytes expected by `merge_qrs`
+    pub data: Vec<u8>,
+}
+
+/// Read the QR code contained in the BMP or PNG image at `path`
+pub fn read_qr(path: &Path) -> Result<RawQr> {
+    let image = Image::load(path)?;
+    let mut finders = image.finders();
+    finders.sort_by_key(|f| std::cmp::Reverse(f.count));
+    finders.truncate(5);
+    debug!("{:?} finder patterns {:?}", path, finders);
+
+    // the most seen finder patterns are tried first, in case of false positives
+    for (i, a) in finders.iter().enumerate() {
+        for (j, b) in finders.iter().enumerate().skip(i + 1) {
+            for c in finders.iter().skip(j + 1) {
+                if let Some(qr) = image.decode(a, b, c) {
+                    return Ok(qr);
+                }
+            }
+        }
+    }
+    Err(Error::QrNotFound(path.to_path_buf()))
+}
+
+impl RawQr {
+    /// True if this is a part of a structured append sequence
+    pub fn is_structured(&self) -> bool {
+        self.data.first().map(|b| b >> 4) == Some(3)
+    }
+
+    /// The content of the code, concatenating its segments
+    pub fn content(&self) -> Result<Vec<u8>> {
+        let class = match self.version {
+            1..=9 => 0,
+            10..=26 => 1,
+            _ => 2,
+        };
+        let mut reader = BitReader {
+            data: &self.data,
+            pos: 0,
+        };
+        let mut content = vec![];
+        while reader.remaining() >= 4 {
+            match reader.read(4)? {
+                0 => break,
+                1 => {
+                    let mut count = reader.read([10, 12, 14][class])?;
+                    while count > 0 {
+                        let digits = count.min(3);
+                        let value = reader.read([4, 7, 10][digits - 1])?;
+                        content.extend(format!("{:0width$}", value, width = digits).bytes());
+                        count -= digits;
+                    }
+                }
+                2 => {
+                    let mut count = reader.read([9, 11, 13][class])?;
+                    while count > 0 {
+                        if count == 1 {
+                            content.push(alphanumeric(reader.read(6)?)?);
+                            count -= 1;
+                        } else {
+                            let value = reader.read(11)?;
+                            content.push(alphanumeric(value / 45)?);
+                            content.push(alphanumeric(value % 45)?);
+                            count -= 2;
+                        }
+                    }
+                }
+                4 => {
+                    let count = reader.read([8, 16, 16][class])?;
+                    for _ in 0..count {
+                        content.push(reader.read(8)? as u8);
+                    }
+                }
+                7 => {
+                    // ECI designator, the content is returned as is
+                    let first = reader.read(8)?;
+                    if first & 0x80 != 0 {
+                        reader.read(if first & 0x40 == 0 { 8 } else { 16 })?;
+                    }
+                }
+                mode => return Err(Error::QrUnsupportedMode(mode as u8)),
+            }
+        }
+        Ok(content)
+    }
+}
+
+fn alphanumeric(value: usize) -> Result<u8> {
+    const CHARS: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ $%*+-./:";
+    CHARS.get(value).cloned().ok_or(Error::QrInvalidPayload)
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn remaining(&self) -> usize {
+        self.data.len() * 8 - self.pos
+    }
+
+    fn read(&mut self, bits: usize) -> Result<usize> {
+        if bits > self.remaining() {
+            return Err(Error::QrInvalidPayload);
+        }
+        let mut value = 0;
+        for _ in 0..bits {
+            let bit = self.data[self.pos / 8] >> (7 - self.pos % 8) & 1;
+            value = value << 1 | bit as usize;
+            self.pos += 1;
+        }
+        Ok(value)
+    }
+}
+
+/// Monochrome image, `true` is dark
+struct Image {
+    width: usize,
+    height: usize,
+    dark: Vec<bool>,
+}
+
+#[derive(Debug, Clone)]
+struct Finder {
+    x: f64,
+    y: f64,
+    module: f64,
+    /// how many times the pattern has been found while scanning rows
+    count: usize,
+}
+
+impl Image {
+    fn load(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path)?;
+        let (width, height, luma) = if bytes.starts_with(b"BM") {
+            read_bmp(&bytes)?
+        } else if bytes.starts_with(b"\x89PNG") {
+            read_png(&bytes)?
+        } else {
+            return Err(Error::QrImage("expected a BMP or PNG image".to_string()));
+        };
+        Ok(Image {
+            width,
+            height,
+            dark: binarize(width, height, &luma),
+        })
+    }
+
+    fn get(&self, x: usize, y: usize) -> bool {
+        self.dark[y * self.width + x]
+    }
+
+    /// Pixel containing the point, outside of the image is light
+    fn sample(&self, x: f64, y: f64) -> bool {
+        x >= 0.0
+            && y >= 0.0
+            && (x as usize) < self.width
+            && (y as usize) < self.height
+            && self.get(x as usize, y as usize)
+    }
+
+    /// Centers of the finder patterns, the 7x7 squares at three corners of the code, with dark
+    /// and light modules in the ratio 1:1:3:1:1 along any line crossing the center
+    fn finders(&self) -> Vec<Finder> {
+        let mut finders: Vec<Finder> = vec![];
+        for y in 0..self.height {
+            let runs = runs(self.width, |x| self.get(x, y));
+            for window in runs.windows(5) {
+                if !window[0].2 {
+                    continue;
+                }
+                let counts = [
+                    window[0].1,
+                    window[1].1,
+                    window[2].1,
+                    window[3].1,
+                    window[4].1,
+                ];
+                if finder_module(&counts).is_none() {
+                    continue;
+                }
+                let x = window[2].0 + window[2].1 / 2;
+                let (cy, vertical) = match self.cross_check(x, y, true) {
+                    Some(found) => found,
+                    None => continue,
+                };
+                let (cx, horizontal) = match self.cross_check(x, cy as usize, false) {
+                    Some(found) => found,
+                    None => continue,
+                };
+                let module = (vertical + horizontal) / 2.0;
+                match finders.iter_mut().find(|f| {
+                    (f.x - cx).abs() <= f.module * 2.0
+                        && (f.y - cy).abs() <= f.module * 2.0
+                        && (f.module - module).abs() <= f.module.max(module) / 2.0
+                }) {
+                    Some(finder) => {
+                        let n = finder.count as f64;
+                        finder.x = (finder.x * n + cx) / (n + 1.0);
+                        finder.y = (finder.y * n + cy) / (n + 1.0);
+                        finder.module = (finder.module * n + module) / (n + 1.0);
+                        finder.count += 1;
+                    }
+                    None => finders.push(Finder {
+                        x: cx,
+                        y: cy,
+                        module,
+                        count: 1,
+                    }),
+                }
+            }
+        }
+        finders
+    }
+
+    /// Check the finder ratio on the column (or the row) crossing `x`,`y`, returning the center
+    /// on that line and the module size
+    fn cross_check(&self, x: usize, y: usize, vertical: bool) -> Option<(f64, f64)> {
+        let (len, start) = if vertical {
+            (self.height, y)
+        } else {
+            (self.width, x)
+        };
+        let pixel = |i: usize| {
+            if vertical {
+                self.get(x, i)
+            } else {
+                self.get(i, y)
+            }
+        };
+        if !pixel(start) {
+            return None;
+        }
+        let mut counts = [0usize; 5];
+        // from the center backward: dark, light, dark
+        let mut i = start as isize;
+        for (index, dark) in [(2, true), (1, false), (0, true)].iter() {
+            while i >= 0 && pixel(i as usize) == *dark {
+                counts[*index] += 1;
+                i -= 1;
+            }
+        }
+        let center_start = start + 1 - counts[2];
+        // from after the center forward: dark, light, dark
+        let mut i = start + 1;
+        for (index, dark) in [(2, true), (3, false), (4, true)].iter() {
+            while i < len && pixel(i) == *dark {
+                counts[*index] += 1;
+                i += 1;
+            }
+        }
+        let module = finder_module(&counts)?;
+        Some((center_start as f64 + counts[2] as f64 / 2.0, module))
+    }
+
+    /// Try to decode the code having the finder patterns `a`, `b` and `c`
+    fn decode(&self, a: &Finder, b: &Finder, c: &Finder) -> Option<RawQr> {
+        // the top left finder is the one opposite to the longest side
+        let (ab, bc, ca) = (distance(a, b), distance(b, c), distance(c, a));
+        let (top_left, b, c) = if bc >= ab && bc >= ca {
+            (a, b, c)
+        } else if ca >= ab {
+            (b, c, a)
+        } else {
+            (c, a, b)
+        };
+        let cross =
+            (b.x - top_left.x) * (c.y - top_left.y) - (b.y - top_left.y) * (c.x - top_left.x);
+        let (top_right, bottom_left) = if cross > 0.0 { (b, c) } else { (c, b) };
+
+        let module = (top_left.module + top_right.module + bottom_left.module) / 3.0;
+        let modules =
+            (distance(top_left, top_right) + distance(top_left, bottom_left)) / 2.0 / module + 7.0;
+        let version = ((modules - 17.0) / 4.0).round().max(1.0).min(40.0) as usize;
+        // perspective makes the estimate less precise
+        for delta in [0, 1, -1, 2, -2].iter() {
+            let version = version as isize + delta;
+            if !(1..=40).contains(&version) {
+                continue;
+            }
+            let version = version as usize;
+            let size = version * 4 + 17;
+            // the second orientation is the one of a mirrored image
+            for (top_right, bottom_left) in
+                [(top_right, bottom_left), (bottom_left, top_right)].iter()
+            {
+                // modules are addressed by their centers, the finder centers are 3 modules from
+                // the borders
+                let far = (size - 4) as f64;
+                let mut points = vec![
+                    ((3.0, 3.0), (top_left.x, top_left.y)),
+                    ((far, 3.0), (top_right.x, top_right.y)),
+                    ((3.0, far), (bottom_left.x, bottom_left.y)),
+                ];
+                let affine = Transform::affine(&points);
+                let mut transforms = vec![affine.clone()];
+                if let Some(alignment) = self.alignment(version, &affine, module) {
+                    let center = (size - 7) as f64;
+                    points.push(((center, center), alignment));
+                    transforms.extend(Transform::perspective(&points));
+                }
+                for transform in transforms {
+                    if let Some(data) = self.grid(version, &transform).decode() {
+                        return Some(RawQr { version, data });
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Center of the bottom right alignment pattern of a code of `version`, a dark module inside a
+    /// ring of light modules inside a ring of dark modules, searched around the position expected
+    /// without perspective
+    fn alignment(&self, version: usize, affine: &Transform, module: f64) -> Option<(f64, f64)> {
+        if version < 2 {
+            return None;
+        }
+        let center = ((version * 4 + 17) - 7) as f64;
+        let (ex, ey) = affine.map(center, center);
+        let (left, up) = (
+            affine.map(center - 1.0, center),
+            affine.map(center, center - 1.0),
+        );
+        let (u, v) = ((ex - left.0, ey - left.1), (ex - up.0, ey - up.1));
+        // the 5x5 modules of the pattern, as seen with the local orientation
+        let score = |x: f64, y: f64| {
+            let mut score = 0;
+            for j in -2i32..=2 {
+                for i in -2i32..=2 {
+                    let dark = i.abs().max(j.abs()) != 1;
+                    let (i, j) = (i as f64, j as f64);
+                    let (px, py) = (x + i * u.0 + j * v.0, y + i * u.1 + j * v.1);
+                    if self.sample(px, py) == dark {
+                        score += 1;
+                    }
+                }
+            }
+            score
+        };
+        let radius = module * 8.0;
+        let step = (module / 4.0).max(1.0);
+        let steps = (radius / step) as i32;
+        let mut found: Vec<(usize, f64, f64)> = vec![];
+        for j in -steps..=steps {
+            for i in -steps..=steps {
+                let (x, y) = (ex + i as f64 * step, ey + j as f64 * step);
+                found.push((score(x, y), x, y));
+            }
+        }
+        let best = found.iter().map(|f| f.0).max()?;
+        if best < 23 {
+            return None;
+        }
+        let nearest = found
+            .iter()
+            .filter(|f| f.0 == best)
+            .min_by(|a, b| {
+                let da = (a.1 - ex).powi(2) + (a.2 - ey).powi(2);
+                let db = (b.1 - ex).powi(2) + (b.2 - ey).powi(2);
+                da.partial_cmp(&db).expect("not NaN")
+            })
+            .cloned()?;
+        // the positions matching as well around the nearest one, their center is more precise
+        let around: Vec<&(usize, f64, f64)> = found
+            .iter()
+            .filter(|f| {
+                f.0 == best
+                    && (f.1 - nearest.1).abs() <= module
+                    && (f.2 - nearest.2).abs() <= module
+            })
+            .collect();
+        let n = around.len() as f64;
+        Some((
+            around.iter().map(|f| f.1).sum::<f64>() / n,
+            around.iter().map(|f| f.2).sum::<f64>() / n,
+        ))
+    }
+
+    /// Sample the modules of a code of `version`, `transform` maps module centers to the image
+    fn grid(&self, version: usize, transform: &Transform) -> Grid {
+        let size = version * 4 + 17;
+        let mut modules = Vec::with_capacity(size * size);
+        for y in 0..size {
+            for x in 0..size {
+                let (px, py) = transform.map(x as f64, y as f64);
+                modules.push(self.sample(px, py));
+            }
+        }
+        Grid {
+            version,
+            size,
+            modules,
+        }
+    }
+}
+
+/// Projective transformation of the plane, `x = (a*u + b*v + c) / (g*u + h*v + 1)` and
+/// `y = (d*u + e*v + f) / (g*u + h*v + 1)`, affine when `g` and `h` are 0
+#[derive(Debug, Clone)]
+struct Transform([f64; 8]);
+
+type PointPair = ((f64, f64), (f64, f64));
+
+impl Transform {
+    /// The affine transformation mapping the first point of the three pairs to the second
+    fn affine(points: &[PointPair]) -> Self {
+        let ((u0, v0), (x0, y0)) = points[0];
+        let ((u1, _), (x1, y1)) = points[1];
+        let ((_, v2), (x2, y2)) = points[2];
+        // the first two points differ only by u, the first and the last only by v
+        let (du, dv) = (u1 - u0, v2 - v0);
+        let (a, d) = ((x1 - x0) / du, (y1 - y0) / du);
+        let (b, e) = ((x2 - x0) / dv, (y2 - y0) / dv);
+        Transform([
+            a,
+            b,
+            x0 - a * u0 - b * v0,
+            d,
+            e,
+            y0 - d * u0 - e * v0,
+            0.0,
+            0.0,
+        ])
+    }
+
+    /// The projective transformation mapping the first point of the four pairs to the second,
+    /// `None` if three of the points are aligned
+    fn perspective(points: &[PointPair]) -> Option<Self> {
+        let mut rows = vec![];
+        for ((u, v), (x, y)) in points.iter().cloned() {
+            rows.push([u, v, 1.0, 0.0, 0.0, 0.0, -u * x, -v * x, x]);
+            rows.push([0.0, 0.0, 0.0, u, v, 1.0, -u * y, -v * y, y]);
+        }
+        // gaussian elimination with partial pivoting
+        for col in 0..8 {
+            let pivot = (col..8).max_by(|a, b| {
+                rows[*a][col]
+                    .abs()
+                    .partial_cmp(&rows[*b][col].abs())
+                    .expect("not NaN")
+            })?;
+            if rows[pivot][col].abs() < 1e-9 {
+                return None;
+            }
+            rows.swap(col, pivot);
+            let pivot_row = rows[col];
+            for (i, row) in rows.iter_mut().enumerate() {
+                if i != col {
+                    let factor = row[col] / pivot_row[col];
+                    for (cell, pivot_cell) in row.iter_mut().zip(pivot_row.iter()).skip(col) {
+                        *cell -= factor * pivot_cell;
+                    }
+                }
+            }
+        }
+        let mut coefficients = [0.0; 8];
+        for (i, c) in coefficients.iter_mut().enumerate() {
+            *c = rows[i][8] / rows[i][i];
+        }
+        Some(Transform(coefficients))
+    }
+
+    fn map(&self, u: f64, v: f64) -> (f64, f64) {
+        let [a, b, c, d, e, f, g, h] = self.0;
+        let w = g * u + h * v + 1.0;
+        ((a * u + b * v + c) / w, (d * u + e * v + f) / w)
+    }
+}
+
+/// Dark pixels by a threshold adapting to the lighting: in blocks with contrast the middle between
+/// the darkest and the lightest pixel around, in uniform blocks the threshold of the neighbours
+fn binarize(width: usize, height: usize, luma: &[u8]) -> Vec<bool> {
+    let (min, max) = match (luma.iter().min(), luma.iter().max()) {
+        (Some(min), Some(max)) if min != max => (*min, *max),
+        _ => return vec![false; luma.len()],
+    };
+    let block = (width.max(height) / 64).max(4);
+    let (blocks_x, blocks_y) = ((width - 1) / block + 1, (height - 1) / block + 1);
+    let mut ranges = vec![(255u8, 0u8); blocks_x * blocks_y];
+    for (i, l) in luma.iter().enumerate() {
+        let (x, y) = (i % width, i / width);
+        let range = &mut ranges[y / block * blocks_x + x / block];
+        range.0 = range.0.min(*l);
+        range.1 = range.1.max(*l);
+    }
+
+    // windows of 5x5 blocks with less contrast than this are considered uniform
+    let min_contrast = ((max - min) / 4).max(16);
+    let neighbours = |bx: usize, by: usize, radius: usize| {
+        let xs = bx.saturating_sub(radius)..(bx + radius + 1).min(blocks_x);
+        let ys = by.saturating_sub(radius)..(by + radius + 1).min(blocks_y);
+        ys.flat_map(move |y| xs.clone().map(move |x| y * blocks_x + x))
+    };
+    let mut thresholds: Vec<Option<u8>> = (0..blocks_x * blocks_y)
+        .map(|i| {
+            let (low, high) = neighbours(i % blocks_x, i / blocks_x, 2)
+                .map(|n| ranges[n])
+                .fold((255u8, 0), |(l, h), r| (l.min(r.0), h.max(r.1)));
+            if high - low >= min_contrast {
+                Some(((low as u16 + high as u16) / 2) as u8)
+            } else {
+                None
+            }
+        })
+        .collect();
+    if thresholds.iter().all(Option::is_none) {
+        let global = ((min as u16 + max as u16) / 2) as u8;
+        return luma.iter().map(|l| *l <= global).collect();
+    }
+    while thresholds.iter().any(Option::is_none) {
+        let previous = thresholds.clone();
+        for (i, threshold) in thresholds.iter_mut().enumerate() {
+            if threshold.is_some() {
+                continue;
+            }
+            let known: Vec<u16> = neighbours(i % blocks_x, i / blocks_x, 1)
+                .filter_map(|n| previous[n])
+                .map(u16::from)
+                .collect();
+            if !known.is_empty() {
+                *threshold = Some((known.iter().sum::<u16>() / known.len() as u16) as u8);
+            }
+        }
+    }
+
+    luma.iter()
+        .enumerate()
+        .map(|(i, l)| {
+            let (x, y) = (i % width, i / width);
+            *l <= thresholds[y / block * blocks_x + x / block].expect("filled")
+        })
+        .collect()
+}
+
+fn distance(a: &Finder, b: &Finder) -> f64 {
+    ((a.x - b.x).powi(2) + (a.y - b.y).powi(2)).sqrt()
+}
+
+/// Runs of equal pixels in a line of `len` pixels, as start, length and color
+fn runs<F: Fn(usize) -> bool>(len: usize, pixel: F) -> Vec<(usize, usize, bool)> {
+    let mut runs: Vec<(usize, usize, bool)> = vec![];
+    for i in 0..len {
+        let dark = pixel(i);
+        match runs.last_mut() {
+            Some(run) if run.2 == dark => run.1 += 1,
+            _ => runs.push((i, 1, dark)),
+        }
+    }
+    runs
+}
+
+/// The module size if `counts` are in the finder ratio 1:1:3:1:1, with 50% tolerance
+fn finder_module(counts: &[usize; 5]) -> Option<f64> {
+    let total: usize = counts.iter().sum();
+    if total < 7 {
+        return None;
+    }
+    let module = total as f64 / 7.0;
+    let ok = counts
+        .iter()
+        .zip([1.0, 1.0, 3.0, 1.0, 1.0].iter())
+        .all(|(count, ratio)| (*count as f64 - module * ratio).abs() < module * ratio / 2.0);
+    if ok {
+        Some(module)
+    } else {
+        None
+    }
+}
+
+fn read_bmp(bytes: &[u8]) -> Result<(usize, usize, Vec<u8>)> {
+    let u32_at = |pos: usize| -> Result<u32> {
+        let slice = bytes
+            .get(pos..pos + 4)
+            .ok_or_else(|| Error::QrImage("truncated BMP".to_string()))?;
+        Ok(u32::from_le_bytes([slice[0], slice[1], slice[2], slice[3]]))
+    };
+    let offset = u32_at(10)? as usize;
+    let header_size = u32_at(14)? as usize;
+    let width = u32_at(18)? as i32;
+    let height = u32_at(22)? as i32;
+    let bits = u32_at(28)? & 0xffff;
+    let compression = u32_at(30)?;
+    if width <= 0 || height == 0 || !(compression == 0 || (compression == 3 && bits == 32)) {
+        return Err(Error::QrImage("unsupported BMP".to_string()));
+    }
+    let (width, bottom_up) = (width as usize, height > 0);
+    let height = height
+        .checked_abs()
+        .ok_or_else(|| Error::QrImage("unsupported BMP".to_string()))? as usize;
+    let palette: Vec<u8> = if bits <= 8 {
+        let colors = match u32_at(46)? {
+            0 => 1 << bits,
+            n => n as usize,
+        };
+        (0..colors)
+            .map(|i| {
+                let pos = 14 + header_size + i * 4;
+                let bgr = bytes
+                    .get(pos..pos + 3)
+                    .ok_or_else(|| Error::QrImage("truncated BMP palette".to_string()))?;
+                Ok(luma(bgr[2], bgr[1], bgr[0]))
+            })
+            .collect::<Result<_>>()?
+    } else {
+        vec![]
+    };
+    let row_len = (bits as usize * width + 31) / 32 * 4;
+    if offset + row_len * height > bytes.len() {
+        return Err(Error::QrImage("truncated BMP".to_string()));
+    }
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let row = if bottom_up { height - 1 - y } else { y };
+        let row = &bytes[offset + row * row_len..offset + (row + 1) * row_len];
+        for x in 0..width {
+            let pixel = match bits {
+                1 | 4 | 8 => {
+                    let bit = x * bits as usize;
+                    let index = row[bit / 8] >> (8 - bits as usize - bit % 8) & ((1 << bits) - 1);
+                    *palette
+                        .get(index as usize)
+                        .ok_or_else(|| Error::QrImage("invalid BMP palette index".to_string()))?
+                }
+                24 | 32 => {
+                    let pos = x * bits as usize / 8;
+                    luma(row[pos + 2], row[pos + 1], row[pos])
+                }
+                _ => {
+                    return Err(Error::QrImage(format!(
+                        "unsupported BMP with {} bits",
+                        bits
+                    )))
+                }
+            };
+            pixels.push(pixel);
+        }
+    }
+    Ok((width, height, pixels))
+}
+
+fn read_png(bytes: &[u8]) -> Result<(usize, usize, Vec<u8>)> {
+    let mut decoder = png::Decoder::new(bytes);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let (info, mut reader) = decoder.read_info().map_err(png_error)?;
+    let mut buf = vec![0; info.buffer_size()];
+    reader.next_frame(&mut buf).map_err(png_error)?;
+    let channels = info.color_type.samples();
+    let pixels = buf
+        .chunks(channels)
+        .take(info.width as usize * info.height as usize)
+        .map(|p| {
+            let (color, alpha) = match p.len() {
+                1 => (p[0], 255),
+                2 => (p[0], p[1]),
+                3 => (luma(p[0], p[1], p[2]), 255),
+                _ => (luma(p[0], p[1], p[2]), p[3]),
+            };
+            // transparent pixels are light
+            ((color as u16 * alpha as u16 + 255 * (255 - alpha as u16)) / 255) as u8
+        })
+        .collect();
+    Ok((info.width as usize, info.height as usize, pixels))
+}
+
+fn png_error(e: png::DecodingError) -> Error {
+    Error::QrImage(e.to_string())
+}
+
+fn luma(r: u8, g: u8, b: u8) -> u8 {
+    ((r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000) as u8
+}
+
+/// Modules of a code, `true` is dark
+struct Grid {
+    version: usize,
+    size: usize,
+    modules: Vec<bool>,
+}
+
+/// Error correction codewords per block, by level (L, M, Q, H) and version
+const ECC_CODEWORDS_PER_BLOCK: [[u8; 41]; 4] = [
+    [
+        0, 7, 10, 15, 20, 26, 18, 20, 24, 30, 18, 20, 24, 26, 30, 22, 24, 28, 30, 28, 28, 28, 28,
+        30, 30, 26, 28, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30,
+    ],
+    [
+        0, 10, 16, 26, 18, 24, 16, 18, 22, 22, 26, 30, 22, 22, 24, 24, 28, 28, 26, 26, 26, 26, 28,
+        28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28,
+    ],
+    [
+        0, 13, 22, 18, 26, 18, 24, 18, 22, 20, 24, 28, 26, 24, 20, 30, 24, 28, 28, 26, 30, 28, 30,
+        30, 30, 30, 28, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30,
+    ],
+    [
+        0, 17, 28, 22, 16, 22, 28, 26, 26, 24, 28, 24, 28, 22, 24, 24, 30, 28, 28, 26, 28, 30, 24,
+        30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30,
+    ],
+];
+
+/// Error correction blocks, by level (L, M, Q, H) and version
+const NUM_ERROR_CORRECTION_BLOCKS: [[u8; 41]; 4] = [
+    [
+        0, 1, 1, 1, 1, 1, 2, 2, 2, 2, 4, 4, 4, 4, 4, 6, 6, 6, 6, 7, 8, 8, 9, 9, 10, 12, 12, 12, 13,
+        14, 15, 16, 17, 18, 19, 19, 20, 21, 22, 24, 25,
+    ],
+    [
+        0, 1, 1, 1, 2, 2, 4, 4, 4, 5, 5, 5, 8, 9, 9, 10, 10, 11, 13, 14, 16, 17, 17, 18, 20, 21,
+        23, 25, 26, 28, 29, 31, 33, 35, 37, 38, 40, 43, 45, 47, 49,
+    ],
+    [
+        0, 1, 1, 2, 2, 4, 4, 6, 6, 8, 8, 8, 10, 12, 16, 12, 17, 16, 18, 21, 20, 23, 23, 25, 27, 29,
+        34, 34, 35, 38, 40, 43, 45, 48, 51, 53, 56, 59, 62, 65, 68,
+    ],
+    [
+        0, 1, 1, 2, 4, 4, 4, 5, 6, 8, 8, 11, 11, 16, 16, 18, 16, 19, 21, 25, 25, 25, 34, 30, 32,
+        35, 37, 40, 42, 45, 48, 51, 54, 57, 60, 63, 66, 70, 74, 77, 81,
+    ],
+];
+
+impl Grid {
+    fn get(&self, x: usize, y: usize) -> bool {
+        self.modules[y * self.size + x]
+    }
+
+    /// Data codewords, `None` if the format is unreadable or there are too many errors
+    fn decode(&self) -> Option<Vec<u8>> {
+        let (level, mask) = self.format()?;
+        let function = self.function_modules();
+        let raw_codewords = raw_data_modules(self.version) / 8;
+        let mut codewords = vec![0u8; raw_codewords];
+        let mut bit = 0;
+        // codewords are placed in columns of two modules, zigzagging up and down from the right
+        let mut right = self.size - 1;
+        while right >= 1 {
+            if right == 6 {
+                right = 5;
+            }
+            for vert in 0..self.size {
+                for x in [right, right - 1].iter().cloned() {
+                    let upward = (right + 1) & 2 == 0;
+                    let y = if upward { self.size - 1 - vert } else { vert };
+                    if !function[y * self.size + x] && bit < raw_codewords * 8 {
+                        if self.get(x, y) != mask_bit(mask, x, y) {
+                            codewords[bit / 8] |= 0x80 >> (bit % 8);
+                        }
+                        bit += 1;
+                    }
+                }
+            }
+            if right < 2 {
+                break;
+            }
+            right -= 2;
+        }
+
+        let num_blocks = NUM_ERROR_CORRECTION_BLOCKS[level][self.version] as usize;
+        let ecc_len = ECC_CODEWORDS_PER_BLOCK[level][self.version] as usize;
+        let num_short_blocks = num_blocks - raw_codewords % num_blocks;
+        let short_block_len = raw_codewords / num_blocks;
+        // blocks are interleaved, short blocks lack the last data codeword
+        let mut blocks = vec![Vec::with_capacity(short_block_len + 1); num_blocks];
+        let mut codewords = codewords.into_iter();
+        for i in 0..=short_block_len {
+            for (j, block) in blocks.iter_mut().enumerate() {
+                if i != short_block_len - ecc_len || j >= num_short_blocks {
+                    block.push(codewords.next()?);
+                }
+            }
+        }
+        let gf = Gf::new();
+        let mut data = vec![];
+        for mut block in blocks {
+            gf.correct(&mut block, ecc_len)?;
+            data.extend(&block[..block.len() - ecc_len]);
+        }
+        Some(data)
+    }
+
+    /// Error correction level as table index and mask of the code, from the format bits
+    fn format(&self) -> Option<(usize, u8)> {
+        let size = self.size;
+        let bit = |x, y| self.get(x, y) as u16;
+        let mut first = 0;
+        for i in 0..6 {
+            first |= bit(8, i) << i;
+        }
+        first |= bit(8, 7) << 6 | bit(8, 8) << 7 | bit(7, 8) << 8;
+        for i in 9..15 {
+            first |= bit(14 - i, 8) << i;
+        }
+        let mut second = 0;
+        for i in 0..8 {
+            second |= bit(size - 1 - i, 8) << i;
+        }
+        for i in 8..15 {
+            second |= bit(8, size - 15 + i) << i;
+        }
+
+        // the format with the least different bits, up to 3
+        let (distance, data) = (0u16..32)
+            .map(|data| {
+                let code = format_code(data);
+                let distance = (code ^ first)
+                    .count_ones()
+                    .min((code ^ second).count_ones());
+                (distance, data)
+            })
+            .min()?;
+        if distance > 3 {
+            return None;
+        }
+        // format level bits are 1 for L, 0 for M, 3 for Q and 2 for H
+        let level = [1, 0, 3, 2][(data >> 3) as usize];
+        Some((level, (data & 7) as u8))
+    }
+
+    /// Modules of the finder, timing, alignment, format and version patterns
+    fn function_modules(&self) -> Vec<bool> {
+        let size = self.size;
+        let mut function = vec![false; size * size];
+        let mut set = |x: usize, y: usize| function[y * size + x] = true;
+        for y in 0..size {
+            for x in 0..size {
+                let top = y < 9;
+                let left = x < 9;
+                if (top && (left || x >= size - 8))
+                    || (left && y >= size - 8)
+                    || x == 6
+                    || y == 6
+                    || (self.version >= 7
+                        && ((x >= size - 11 && x < size - 8 && y < 6)
+                            || (y >= size - 11 && y < size - 8 && x < 6)))
+                {
+                    set(x, y);
+                }
+            }
+        }
+        let positions = alignment_positions(self.version);
+        let last = positions.len().saturating_sub(1);
+        for (i, ax) in positions.iter().enumerate() {
+            for (j, ay) in positions.iter().enumerate() {
+                // the alignment patterns overlapping the finder patterns are not drawn
+                if (i == 0 && (j == 0 || j == last)) || (i == last && j == 0) {
+                    continue;
+                }
+                for y in ay - 2..=ay + 2 {
+                    for x in ax - 2..=ax + 2 {
+                        set(x, y);
+                    }
+                }
+            }
+        }
+        function
+    }
+}
+
+/// The 15 bits of format `data`, with BCH error correction and masked
+fn format_code(data: u16) -> u16 {
+    let mut rem = data;
+    for _ in 0..10 {
+        rem = (rem << 1) ^ ((rem >> 9) * 0x537);
+    }
+    (data << 10 | rem) ^ 0x5412
+}
+
+fn mask_bit(mask: u8, x: usize, y: usize) -> bool {
+    match mask {
+        0 => (x + y) % 2 == 0,
+        1 => y % 2 == 0,
+        2 => x % 3 == 0,
+        3 => (x + y) % 3 == 0,
+        4 => (x / 3 + y / 2) % 2 == 0,
+        5 => x * y % 2 + x * y % 3 == 0,
+        6 => (x * y % 2 + x * y % 3) % 2 == 0,
+        _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+    }
+}
+
+fn alignment_positions(version: usize) -> Vec<usize> {
+    if version == 1 {
+        return vec![];
+    }
+    let size = version * 4 + 17;
+    let num_align = version / 7 + 2;
+    let step = if version == 32 {
+        26
+    } else {
+        (version * 4 + num_align * 2 + 1) / (num_align * 2 - 2) * 2
+    };
+    let mut positions: Vec<usize> = (0..num_align - 1).map(|i| size - 7 - i * step).collect();
+    positions.push(6);
+    positions.reverse();
+    positions
+}
+
+/// Modules available for codewords, excluding function patterns
+fn raw_data_modules(version: usize) -> usize {
+    let mut result = (16 * version + 128) * version + 64;
+    if version >= 2 {
+        let num_align = version / 7 + 2;
+        result -= (25 * num_align - 10) * num_align - 55;
+        if version >= 7 {
+            result -= 36;
+        }
+    }
+    result
+}
+
+/// GF(256) with the QR code polynomial x^8 + x^4 + x^3 + x^2 + 1
+struct Gf {
+    exp: [u8; 512],
+    log: [u8; 256],
+}
+
+impl Gf {
+    fn new() -> Self {
+        let mut gf = Gf {
+            exp: [0; 512],
+            log: [0; 256],
+        };
+        let mut x = 1u16;
+        for i in 0..255 {
+            gf.exp[i] = x as u8;
+            gf.log[x as usize] = i as u8;
+            x <<= 1;
+            if x & 0x100 != 0 {
+                x ^= 0x11d;
+            }
+        }
+        for i in 255..512 {
+            gf.exp[i] = gf.exp[i - 255];
+        }
+        gf
+    }
+
+    fn mul(&self, a: u8, b: u8) -> u8 {
+        if a == 0 || b == 0 {
+            0
+        } else {
+            self.exp[self.log[a as usize] as usize + self.log[b as usize] as usize]
+        }
+    }
+
+    fn div(&self, a: u8, b: u8) -> u8 {
+        if a == 0 {
+            0
+        } else {
+            self.exp[self.log[a as usize] as usize + 255 - self.log[b as usize] as usize]
+        }
+    }
+
+    /// Value of the polynomial with coefficients from the lowest degree
+    fn eval(&self, poly: &[u8], x: u8) -> u8 {
+        poly.iter().rev().fold(0, |acc, c| self.mul(acc, x) ^ c)
+    }
+
+    /// Reed-Solomon correction of `block`, whose last `ecc_len` bytes are error correction
+    /// codewords, `None` if there are too many errors
+    fn correct(&self, block: &mut [u8], ecc_len: usize) -> Option<()> {
+        let n = block.len();
+        let syndromes: Vec<u8> = (0..ecc_len)
+            .map(|i| {
+                block
+                    .iter()
+                    .fold(0, |acc, c| self.mul(acc, self.exp[i]) ^ c)
+            })
+            .collect();
+        if syndromes.iter().all(|s| *s == 0) {
+            return Some(());
+        }
+
+        // Berlekamp-Massey, error locator coefficients from the lowest degree
+        let mut locator = vec![1u8];
+        let mut previous = vec![1u8];
+        let (mut errors, mut shift, mut last) = (0, 1, 1u8);
+        for i in 0..ecc_len {
+            let discrepancy = (1..=errors).fold(syndromes[i], |acc, j| {
+                acc ^ self.mul(*locator.get(j).unwrap_or(&0), syndromes[i - j])
+            });
+            if discrepancy == 0 {
+                shift += 1;
+                continue;
+            }
+            let factor = self.div(discrepancy, last);
+            let mut updated = locator.clone();
+            updated.resize(updated.len().max(previous.len() + shift), 0);
+            for (j, c) in previous.iter().enumerate() {
+                updated[j + shift] ^= self.mul(factor, *c);
+            }
+            if 2 * errors <= i {
+                errors = i + 1 - errors;
+                previous = locator;
+                last = discrepancy;
+                shift = 1;
+            } else {
+                shift += 1;
+            }
+            locator = updated;
+        }
+        if 2 * errors > ecc_len {
+            return None;
+        }
+
+        // evaluator = syndromes * locator mod x^ecc_len
+        let mut evaluator = vec![0u8; ecc_len];
+        for (i, s) in syndromes.iter().enumerate() {
+            for (j, l) in locator.iter().enumerate().take(ecc_len - i) {
+                evaluator[i + j] ^= self.mul(*s, *l);
+            }
+        }
+        // formal derivative, in characteristic 2 only odd degrees remain
+        let derivative: Vec<u8> = locator
+            .iter()
+            .enumerate()
+            .skip(1)
+            .map(|(i, c)| if i % 2 == 1 { *c } else { 0 })
+            .collect();
+
+        let mut found = 0;
+        for (position, byte) in block.iter_mut().enumerate() {
+            let x = self.exp[n - 1 - position];
+            let x_inv = self.div(1, x);
+            if self.eval(&locator, x_inv) != 0 {
+                continue;
+            }
+            // Forney, the first root of the generator is 1
+            let denominator = self.eval(&derivative, x_inv);
+            if denominator == 0 {
+                return None;
+            }
+            *byte ^= self.mul(x, self.div(self.eval(&evaluator, x_inv), denominator));
+            found += 1;
+        }
+        if found != errors {
+            return None;
+        }
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::qr_reader::{read_qr, Gf, RawQr, Transform};
+    use qr_code::ec::create_error_correction_code;
+    use qr_code::{EcLevel, QrCode, Version};
+    use std::path::Path;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_read_qr() {
+        let temp_dir = TempDir::new("test_read_qr").unwrap();
+        let path = temp_dir.path().join("qr.bmp");
+        let content = b"chancellor 2009";
+        for (version, level) in [
+            (1, EcLevel::L),
+            (5, EcLevel::M),
+            (10, EcLevel::Q),
+            (20, EcLevel::H),
+            (40, EcLevel::L),
+        ]
+        .iter()
+        {
+            let qr = QrCode::with_version(&content[..], Version::Normal(*version), *level).unwrap();
+            qr.to_bmp()
+                .write(std::fs::File::create(&path).unwrap())
+                .unwrap();
+            let raw = read_qr(&path).unwrap();
+            assert_eq!(raw.version, *version as usize);
+            assert_eq!(raw.content().unwrap(), content.to_vec());
+
+            // scaled up, with a border
+            qr.to_bmp()
+                .mul(3)
+                .add_white_border(12)
+                .write(std::fs::File::create(&path).unwrap())
+                .unwrap();
+            assert_eq!(read_qr(&path).unwrap(), raw);
+        }
+
+        let qr = QrCode::new(b"UR:BYTES/HDCXLKAHSSQZWFVSLOFZOXWKRKAFIHF").unwrap();
+        qr.to_bmp()
+            .write(std::fs::File::create(&path).unwrap())
+            .unwrap();
+        let raw = read_qr(&path).unwrap();
+        assert_eq!(
+            raw.content().unwrap(),
+            b"UR:BYTES/HDCXLKAHSSQZWFVSLOFZOXWKRKAFIHF".to_vec()
+        );
+        assert!(!raw.is_structured());
+
+        // rotated by 90 degrees in a grayscale png, with some modules flipped
+        let width = qr.width();
+        let modules = qr.to_vec();
+        let (scale, border) = (4, 20);
+        let side = width * scale + border * 2;
+        let mut pixels = vec![255u8; side * side];
+        for y in 0..side {
+            for x in 0..side {
+                let (mx, my) = ((x / scale) as isize - 5, (y / scale) as isize - 5);
+                if mx >= 0 && my >= 0 && (mx as usize) < width && (my as usize) < width {
+                    let (mx, my) = (mx as usize, my as usize);
+                    let dark = modules[(width - 1 - mx) * width + my] ^ (mx == 12 && my < 3);
+                    pixels[y * side + x] = if dark { 30 } else { 220 };
+                }
+            }
+        }
+        let png_path = temp_dir.path().join("qr.png");
+        write_png(&png_path, side, &pixels);
+        assert_eq!(read_qr(&png_path).unwrap(), raw);
+
+        std::fs::write(&path, b"not an image").unwrap();
+        assert!(read_qr(&path).is_err());
+
+        // BMP declaring an height which can't be made positive
+        let mut bmp = vec![];
+        QrCode::new(b"x").unwrap().to_bmp().write(&mut bmp).unwrap();
+        bmp[22..26].copy_from_slice(&0x8000_0000u32.to_le_bytes());
+        std::fs::write(&path, &bmp).unwrap();
+        assert!(read_qr(&path).is_err());
+    }
+
+    fn write_png(path: &Path, side: usize, pixels: &[u8]) {
+        let mut encoder = png::Encoder::new(
+            std::fs::File::create(path).unwrap(),
+            side as u32,
+            side as u32,
+        );
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(pixels).unwrap();
+    }
+
+    #[test]
+    fn test_read_qr_photo() {
+        // like a snapshot taken at an angle of a screen lit from the right
+        let temp_dir = TempDir::new("test_read_qr_photo").unwrap();
+        let content = b"UR:CRYPTO-PSBT/HDCXLKAHSSQZWFVSLOFZOXWKRKAFIHFCHANCELLOR";
+        let qr = QrCode::with_version(&content[..], Version::Normal(5), EcLevel::M).unwrap();
+        let (width, modules) = (qr.width(), qr.to_vec());
+        let side = 640;
+        let (low, high) = (-4.5, width as f64 + 3.5);
+        let to_modules = |corners: [(f64, f64); 4]| {
+            let code = [(low, low), (high, low), (low, high), (high, high)];
+            let points: Vec<_> = corners.iter().cloned().zip(code.iter().cloned()).collect();
+            Transform::perspective(&points).unwrap()
+        };
+        let mut seed = 7u32;
+        let mut noise = || {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (seed >> 16) as f64 % 25.0 - 12.0
+        };
+        let render = |corners: [(f64, f64); 4], noise: &mut dyn FnMut() -> f64| {
+            let transform = to_modules(corners);
+            let mut pixels = vec![0u8; side * side];
+            for y in 0..side {
+                for x in 0..side {
+                    let light = 0.35 + 0.65 * x as f64 / side as f64;
+                    let (u, v) = transform.map(x as f64, y as f64);
+                    let (mx, my) = ((u + 0.5).floor(), (v + 0.5).floor());
+                    let inside = mx >= 0.0 && my >= 0.0 && mx < width as f64 && my < width as f64;
+                    let luma = if u < low || v < low || u > high || v > high {
+                        150.0 * light // the table
+                    } else if inside && modules[my as usize * width + mx as usize] {
+                        40.0 * light + 20.0
+                    } else {
+                        240.0 * light
+                    };
+                    // noise keeps the luma in the 0-255 range
+                    pixels[y * side + x] = (luma + noise()) as u8;
+                }
+            }
+            pixels
+        };
+
+        let path = temp_dir.path().join("photo.png");
+        for corners in [
+            [(60.0, 40.0), (540.0, 90.0), (30.0, 560.0), (580.0, 600.0)],
+            [
+                (100.0, 100.0),
+                (560.0, 40.0),
+                (120.0, 520.0),
+                (520.0, 600.0),
+            ],
+            [(560.0, 60.0), (600.0, 560.0), (40.0, 40.0), (90.0, 600.0)],
+        ]
+        .iter()
+        {
+            write_png(&path, side, &render(*corners, &mut noise));
+            let raw = read_qr(&path).unwrap();
+            assert_eq!(raw.version, 5);
+            assert_eq!(raw.content().unwrap(), content.to_vec());
+        }
+    }
+
+    #[test]
+    fn test_qr_content() {
+        // numeric "01234567" and alphanumeric "AC-42" segments from ISO 18004 examples
+        let raw = RawQr {
+            version: 1,
+            data: hex::decode("10200c56618000").unwrap(),
+        };
+        assert_eq!(raw.content().unwrap(), b"01234567".to_vec());
+        let raw = RawQr {
+            version: 1,
+            data: hex::decode("2029cee72100").unwrap(),
+        };
+        assert_eq!(raw.content().unwrap(), b"AC-42".to_vec());
+        let raw = RawQr {
+            version: 1,
+            data: hex::decode("42").unwrap(),
+        };
+        assert!(raw.content().is_err());
+    }
+
+    #[test]
+    fn test_reed_solomon() {
+        let gf = Gf::new();
+        let data = b"chancellor on brink".to_vec();
+        let mut block = data.clone();
+        block.extend(create_error_correction_code(&data, 10));
+        let mut corrupted = block.clone();
+        gf.correct(&mut corrupted, 10).unwrap();
+        assert_eq!(corrupted, block);
+
+        for (i, position) in [0, 7, 15, 20, 28].iter().enumerate() {
+            corrupted[*position] ^= 0x5a + i as u8;
+        }
+        gf.correct(&mut corrupted, 10).unwrap();
+        assert_eq!(corrupted, block);
+
+        for byte in corrupted.iter_mut().take(7).skip(1) {
+            *byte ^= 1;
+        }
+        assert!(gf.correct(&mut corrupted, 10).is_none() || corrupted != block);
+    }
+}
@@ -0,0 +1,288 @@
+//! Render QR codes in PNG and SVG images, with a quiet zone and an optional caption under the code
+
+use crate::qr::QrStyle;
+use crate::*;
+use qr_code::QrCode;
+use std::fmt;
+
+/// Text under a QR code identifying its content
+pub struct Caption {
+    /// Name of the key, wallet or PSBT, if any
+    pub name: Option<String>,
+    /// Part of the QR code, starting from 1
+    pub part: usize,
+    pub total: usize,
+    /// Checksum of the whole content, the same on every part
+    pub checksum: String,
+}
+
+impl fmt::Display for Caption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(name) = self.name.as_ref() {
+            write!(f, "{} ", name)?;
+        }
+        write!(f, "{}/{} {}", self.part, self.total, self.checksum)
+    }
+}
+
+/// Rows of the glyphs of the caption font, 5 pixels wide and 7 high, the most significant of the 5
+/// bits is the leftmost pixel. Letters are uppercase, unknown chars are rendered as `?`
+fn glyph(c: char) -> [u8; 7] {
+    match c.to_ascii_uppercase() {
+        '0' => [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e],
+        '1' => [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        '2' => [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f],
+        '3' => [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e],
+        '4' => [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02],
+        '5' => [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e],
+        '6' => [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e],
+        '7' => [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e],
+        '9' => [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c],
+        'A' => [0x0e, 0x11, 0x11, 0x11, 0x1f, 0x11, 0x11],
+        'B' => [0x1e, 0x11, 0x11, 0x1e, 0x11, 0x11, 0x1e],
+        'C' => [0x0e, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0e],
+        'D' => [0x1c, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1c],
+        'E' => [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x1f],
+        'F' => [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x10],
+        'G' => [0x0e, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0f],
+        'H' => [0x11, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11],
+        'I' => [0x0e, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0c],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1f],
+        'M' => [0x11, 0x1b, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0e, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e],
+        'P' => [0x1e, 0x11, 0x11, 0x1e, 0x10, 0x10, 0x10],
+        'Q' => [0x0e, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0d],
+        'R' => [0x1e, 0x11, 0x11, 0x1e, 0x14, 0x12, 0x11],
+        'S' => [0x0f, 0x10, 0x10, 0x0e, 0x01, 0x01, 0x1e],
+        'T' => [0x1f, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0a, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0a],
+        'X' => [0x11, 0x11, 0x0a, 0x04, 0x0a, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0a, 0x04, 0x04, 0x04],
+        'Z' => [0x1f, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1f],
+        ' ' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+        '-' => [0x00, 0x00, 0x00, 0x1f, 0x00, 0x00, 0x00],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1f],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c],
+        ':' => [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x0c, 0x00],
+        _ => [0x0e, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+    }
+}
+
+/// Side in pixels of the QR code with its quiet zone
+fn side(qr: &QrCode, style: &QrStyle) -> usize {
+    (qr.width() + 2 * style.qr_quiet_zone as usize) * style.qr_module_size as usize
+}
+
+/// Grayscale PNG of `qr`, black modules of `style.qr_module_size` pixels on a white background, with
+/// `caption` under the code
+pub fn to_png(qr: &QrCode, style: &QrStyle, caption: Option<&str>) -> Result<Vec<u8>> {
+    let module_size = style.qr_module_size as usize;
+    let quiet_zone = style.qr_quiet_zone as usize;
+    let side = side(qr, style);
+
+    // the caption font is scaled to a quarter of the module size, glyphs are separated by a pixel
+    // column, and reduced if the caption doesn't fit the width
+    let mut scale = (module_size / 4).max(1);
+    let chars: Vec<char> = caption.unwrap_or("").chars().collect();
+    while scale > 1 && (chars.len() * 6 + 2) * scale > side {
+        scale -= 1;
+    }
+    let chars = &chars[..chars.len().min(side.saturating_sub(2) / 6)];
+    let caption_height = if chars.is_empty() { 0 } else { 11 * scale };
+    let height = side + caption_height;
+
+    let mut pixels = vec![255u8; side * height];
+    let width = qr.width();
+    for (i, dark) in qr.to_vec().into_iter().enumerate() {
+        if !dark {
+            continue;
+        }
+        let x = (i % width + quiet_zone) * module_size;
+        let y = (i / width + quiet_zone) * module_size;
+        for row in y..y + module_size {
+            for pixel in &mut pixels[row * side + x..row * side + x + module_size] {
+                *pixel = 0;
+            }
+        }
+    }
+
+    let left = side.saturating_sub((chars.len() * 6).saturating_sub(1) * scale) / 2;
+    for (i, c) in chars.iter().enumerate() {
+        for (r, bits) in glyph(*c).iter().enumerate() {
+            for col in 0..5 {
+                if bits >> (4 - col) & 1 == 0 {
+                    continue;
+                }
+                let x = left + (i * 6 + col) * scale;
+                let y = side + (2 + r) * scale;
+                for row in y..y + scale {
+                    for pixel in &mut pixels[row * side + x..row * side + x + scale] {
+                        *pixel = 0;
+                    }
+                }
+            }
+        }
+    }
+
+    let mut png = vec![];
+    {
+        let mut encoder = png::Encoder::new(&mut png, side as u32, height as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder
+            .write_header()
+            .map_err(|e| Error::QrImage(e.to_string()))?;
+        writer
+            .write_image_data(&pixels)
+            .map_err(|e| Error::QrImage(e.to_string()))?;
+    }
+    Ok(png)
+}
+
+/// SVG of `qr`, sized `style.qr_module_size` pixels per module, with `caption` under the code.
+/// Coordinates are in modules, dark modules of a row are merged in horizontal bars
+pub fn to_svg(qr: &QrCode, style: &QrStyle, caption: Option<&str>) -> String {
+    let quiet_zone = style.qr_quiet_zone as usize;
+    let width = qr.width();
+    let side = width + 2 * quiet_zone;
+    let caption_height = if caption.is_some() { 3 } else { 0 };
+    let module_size = style.qr_module_size as usize;
+
+    let modules = qr.to_vec();
+    let mut path = String::new();
+    for (y, row) in modules.chunks(width).enumerate() {
+        let mut x = 0;
+        while x < width {
+            if !row[x] {
+                x += 1;
+                continue;
+            }
+            let len = row[x..].iter().take_while(|dark| **dark).count();
+            path.push_str(&format!(
+                "M{},{}h{}v1h-{}z",
+                x + quiet_zone,
+                y + quiet_zone,
+                len,
+                len
+            ));
+            x += len;
+        }
+    }
+
+    let mut svg = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<svg xmlns=\"http://www.w3.org/2000/svg\" \
+         version=\"1.1\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\" \
+         shape-rendering=\"crispEdges\">\n",
+        side * module_size,
+        (side + caption_height) * module_size,
+        side,
+        side + caption_height
+    );
+    svg.push_str(&format!(
+        "<rect width=\"{}\" height=\"{}\" fill=\"#ffffff\"/>\n",
+        side,
+        side + caption_height
+    ));
+    svg.push_str(&format!("<path d=\"{}\" fill=\"#000000\"/>\n", path));
+    if let Some(caption) = caption {
+        // monospace chars are about 0.6 of the font size wide, squeeze captions wider than the code
+        let length = if caption.chars().count() as f64 * 1.2 > (side - 2) as f64 {
+            format!(
+                " textLength=\"{}\" lengthAdjust=\"spacingAndGlyphs\"",
+                side - 2
+            )
+        } else {
+            String::new()
+        };
+        svg.push_str(&format!(
+            "<text x=\"{}\" y=\"{}\" font-family=\"monospace\" font-size=\"2\" \
+             text-anchor=\"middle\"{}>{}</text>\n",
+            side as f64 / 2.0,
+            side + 1,
+            length,
+            escape_xml(caption)
+        ));
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::qr::{QrEcLevel, QrStyle};
+    use crate::qr_reader::read_qr;
+    use crate::qr_render::*;
+    use qr_code::EcLevel;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_caption() {
+        let caption = Caption {
+            name: Some("wallet".to_string()),
+            part: 2,
+            total: 3,
+            checksum: "0a1b2c3d".to_string(),
+        };
+        assert_eq!(caption.to_string(), "wallet 2/3 0a1b2c3d");
+        let caption = Caption {
+            name: None,
+            ..caption
+        };
+        assert_eq!(caption.to_string(), "2/3 0a1b2c3d");
+    }
+
+    #[test]
+    fn test_render() {
+        let temp_dir = TempDir::new("test_render").unwrap();
+        let content = b"tpubD6NzVbkrYhZ4XgiXtGrdW5XDAPFCL9h7we1vwNCpn8tGbBcgfVYjXyhWo4E1xkh56hjod1RhGjxbaTLV3X4FyWuejifB9jusQ46QzG87VKp";
+        let qr = QrCode::with_error_correction_level(&content[..], EcLevel::Q).unwrap();
+        let style = QrStyle {
+            qr_module_size: 4,
+            qr_quiet_zone: 2,
+            qr_ec_level: QrEcLevel::Q,
+            ..Default::default()
+        };
+        let side = (qr.width() + 4) * 4;
+
+        for caption in &[
+            None,
+            Some("a long caption that doesn't fit the qr code width"),
+        ] {
+            let png = to_png(&qr, &style, *caption).unwrap();
+            let file = temp_dir.path().join("qr.png");
+            std::fs::write(&file, &png).unwrap();
+            let decoder = png::Decoder::new(&png[..]);
+            let (info, _) = decoder.read_info().unwrap();
+            assert_eq!(info.width as usize, side);
+            assert_eq!(info.height as usize > side, caption.is_some());
+            assert_eq!(read_qr(&file).unwrap().content().unwrap(), &content[..]);
+        }
+
+        let svg = to_svg(&qr, &style, Some("<name> 1/1 0a1b2c3d"));
+        assert!(svg.contains(&format!("width=\"{}\"", side)));
+        assert!(svg.contains(">&lt;name&gt; 1/1 0a1b2c3d</text>"));
+        let dark = qr.to_vec().iter().filter(|d| **d).count();
+        let path = svg.split("d=\"").nth(1).unwrap().split('"').next().unwrap();
+        let drawn: usize = path
+            .split('h')
+            .skip(1)
+            .step_by(2)
+            .map(|len| len.split('v').next().unwrap().parse::<usize>().unwrap())
+            .sum();
+        assert_eq!(drawn, dark);
+    }
+}
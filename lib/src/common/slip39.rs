@@ -0,0 +1,745 @@
+//!
+//! # SLIP-39 Shamir's secret sharing
+//!
+//! Split a master secret in groups of mnemonic shares and recombine them, as specified in
+//! <https://github.com/satoshilabs/slips/blob/master/slip-0039.md>
+//!
+use bitcoin::hashes::{sha256, Hash, HashEngine, Hmac, HmacEngine};
+use rand::{thread_rng, Rng, RngCore};
+use std::collections::BTreeMap;
+
+const RADIX_BITS: usize = 10;
+const ID_LENGTH_BITS: usize = 15;
+const CHECKSUM_LENGTH_WORDS: usize = 3;
+const DIGEST_LENGTH_BYTES: usize = 4;
+const METADATA_LENGTH_WORDS: usize = 4 + CHECKSUM_LENGTH_WORDS;
+const MIN_STRENGTH_BYTES: usize = 16;
+const MIN_MNEMONIC_LENGTH_WORDS: usize = METADATA_LENGTH_WORDS + (MIN_STRENGTH_BYTES * 8 + 9) / 10;
+const MAX_SHARE_COUNT: u8 = 16;
+const BASE_ITERATION_COUNT: u32 = 10_000;
+const ROUND_COUNT: u8 = 4;
+const DIGEST_INDEX: u8 = 254;
+const SECRET_INDEX: u8 = 255;
+const CUSTOMIZATION_STRING: &[u8] = b"shamir";
+const CUSTOMIZATION_STRING_EXTENDABLE: &[u8] = b"shamir_extendable";
+
+/// SLIP-39 possible errors
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// Mnemonic contains an unknown word
+    UnknownWord,
+
+    /// Mnemonic is too short or the padding of the share value is too long
+    InvalidLength,
+
+    /// Padding bits of the share value are not zero
+    InvalidPadding,
+
+    /// Checksum failed
+    ChecksumFailed,
+
+    /// Master secret must be at least 128 bits and have an even number of bytes
+    InvalidSecretLength,
+
+    /// Group or member threshold and counts are not valid
+    InvalidThreshold,
+
+    /// Shares belong to different secrets or have inconsistent parameters
+    InconsistentShares,
+
+    /// The same share index is given more than once
+    DuplicateShare,
+
+    /// Not enough groups or members to reach the thresholds
+    InsufficientShares,
+
+    /// The digest of the recovered secret doesn't match
+    InvalidDigest,
+}
+
+/// A single decoded share
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Share {
+    identifier: u16,
+    extendable: bool,
+    iteration_exponent: u8,
+    group_index: u8,
+    group_threshold: u8,
+    group_count: u8,
+    member_index: u8,
+    member_threshold: u8,
+    value: Vec<u8>,
+}
+
+/// Split `master_secret` in `groups.len()` groups, each described by (member threshold, member
+/// count), any `group_threshold` groups are required to recover the secret. Returns the mnemonic
+/// shares of every group.
+pub fn generate_mnemonics(
+    group_threshold: u8,
+    groups: &[(u8, u8)],
+    master_secret: &[u8],
+    passphrase: &[u8],
+    iteration_exponent: u8,
+) -> Result<Vec<Vec<String>>, Error> {
+    if master_secret.len() < MIN_STRENGTH_BYTES || master_secret.len() % 2 != 0 {
+        return Err(Error::InvalidSecretLength);
+    }
+    if groups.is_empty()
+        || groups.len() > MAX_SHARE_COUNT as usize
+        || group_threshold == 0
+        || group_threshold as usize > groups.len()
+        || iteration_exponent > 15
+    {
+        return Err(Error::InvalidThreshold);
+    }
+    for &(member_threshold, member_count) in groups {
+        if member_threshold == 0
+            || member_threshold > member_count
+            || member_count > MAX_SHARE_COUNT
+            || (member_threshold == 1 && member_count > 1)
+        {
+            return Err(Error::InvalidThreshold);
+        }
+    }
+
+    let identifier = thread_rng().gen::<u16>() & ((1 << ID_LENGTH_BITS) - 1);
+    let extendable = false;
+    let encrypted = encrypt(
+        master_secret,
+        passphrase,
+        iteration_exponent,
+        identifier,
+        extendable,
+    );
+    let group_shares = split_secret(group_threshold, groups.len() as u8, &encrypted)?;
+
+    let mut result = vec![];
+    for ((group_index, group_secret), &(member_threshold, member_count)) in
+        group_shares.into_iter().zip(groups)
+    {
+        let member_shares = split_secret(member_threshold, member_count, &group_secret)?;
+        let mnemonics = member_shares
+            .into_iter()
+            .map(|(member_index, value)| {
+                Share {
+                    identifier,
+                    extendable,
+                    iteration_exponent,
+                    group_index,
+                    group_threshold,
+                    group_count: groups.len() as u8,
+                    member_index,
+                    member_threshold,
+                    value,
+                }
+                .to_mnemonic()
+            })
+            .collect();
+        result.push(mnemonics);
+    }
+    Ok(result)
+}
+
+/// Recover the master secret from the given mnemonic shares, shares exceeding the thresholds and
+/// groups not reaching their member threshold are ignored
+pub fn combine_mnemonics(mnemonics: &[String], passphrase: &[u8]) -> Result<Vec<u8>, Error> {
+    let shares = mnemonics
+        .iter()
+        .map(|m| Share::from_mnemonic(m))
+        .collect::<Result<Vec<_>, _>>()?;
+    let first = shares.first().ok_or(Error::InsufficientShares)?;
+
+    let mut groups: BTreeMap<u8, Vec<&Share>> = BTreeMap::new();
+    for share in shares.iter() {
+        if share.identifier != first.identifier
+            || share.extendable != first.extendable
+            || share.iteration_exponent != first.iteration_exponent
+            || share.group_threshold != first.group_threshold
+            || share.group_count != first.group_count
+            || share.value.len() != first.value.len()
+        {
+            return Err(Error::InconsistentShares);
+        }
+        let group = groups.entry(share.group_index).or_default();
+        if group.iter().any(|s| s.member_index == share.member_index) {
+            return Err(Error::DuplicateShare);
+        }
+        if group
+            .iter()
+            .any(|s| s.member_threshold != share.member_threshold)
+        {
+            return Err(Error::InconsistentShares);
+        }
+        group.push(share);
+    }
+
+    let mut group_shares = vec![];
+    for (group_index, members) in groups.iter() {
+        let member_threshold = members[0].member_threshold;
+        if members.len() < member_threshold as usize {
+            continue;
+        }
+        let member_shares: Vec<_> = members
+            .iter()
+            .take(member_threshold as usize)
+            .map(|s| (s.member_index, s.value.clone()))
+            .collect();
+        let group_secret = recover_secret(member_threshold, &member_shares)?;
+        group_shares.push((*group_index, group_secret));
+        if group_shares.len() == first.group_threshold as usize {
+            break;
+        }
+    }
+    if group_shares.len() < first.group_threshold as usize {
+        return Err(Error::InsufficientShares);
+    }
+
+    let encrypted = recover_secret(first.group_threshold, &group_shares)?;
+    Ok(decrypt(
+        &encrypted,
+        passphrase,
+        first.iteration_exponent,
+        first.identifier,
+        first.extendable,
+    ))
+}
+
+impl Share {
+    fn to_mnemonic(&self) -> String {
+        let header = (self.identifier as u64) << 25
+            | (self.extendable as u64) << 24
+            | (self.iteration_exponent as u64) << 20
+            | (self.group_index as u64) << 16
+            | ((self.group_threshold - 1) as u64) << 12
+            | ((self.group_count - 1) as u64) << 8
+            | (self.member_index as u64) << 4
+            | (self.member_threshold - 1) as u64;
+        let mut data: Vec<u16> = (0..4)
+            .rev()
+            .map(|i| ((header >> (RADIX_BITS * i)) & 1023) as u16)
+            .collect();
+        data.extend(bytes_to_words(&self.value));
+        let checksum = create_checksum(customization(self.extendable), &data);
+        data.extend(checksum);
+        data.iter()
+            .map(|i| WORDS[*i as usize])
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn from_mnemonic(mnemonic: &str) -> Result<Share, Error> {
+        let data = mnemonic
+            .split_whitespace()
+            .map(|w| {
+                WORDS
+                    .binary_search(&w.to_lowercase().as_str())
+                    .map(|i| i as u16)
+                    .map_err(|_| Error::UnknownWord)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if data.len() < MIN_MNEMONIC_LENGTH_WORDS {
+            return Err(Error::InvalidLength);
+        }
+        let header = data[..4]
+            .iter()
+            .fold(0u64, |acc, w| acc << RADIX_BITS | *w as u64);
+        let extendable = (header >> 24) & 1 == 1;
+        if !verify_checksum(customization(extendable), &data) {
+            return Err(Error::ChecksumFailed);
+        }
+        let value_words = &data[4..data.len() - CHECKSUM_LENGTH_WORDS];
+        let share = Share {
+            identifier: (header >> 25) as u16,
+            extendable,
+            iteration_exponent: ((header >> 20) & 15) as u8,
+            group_index: ((header >> 16) & 15) as u8,
+            group_threshold: ((header >> 12) & 15) as u8 + 1,
+            group_count: ((header >> 8) & 15) as u8 + 1,
+            member_index: ((header >> 4) & 15) as u8,
+            member_threshold: (header & 15) as u8 + 1,
+            value: words_to_bytes(value_words)?,
+        };
+        if share.group_count < share.group_threshold {
+            return Err(Error::InvalidThreshold);
+        }
+        if share.value.len() < MIN_STRENGTH_BYTES || share.value.len() % 2 != 0 {
+            return Err(Error::InvalidSecretLength);
+        }
+        Ok(share)
+    }
+}
+
+fn customization(extendable: bool) -> &'static [u8] {
+    if extendable {
+        CUSTOMIZATION_STRING_EXTENDABLE
+    } else {
+        CUSTOMIZATION_STRING
+    }
+}
+
+/// Converts bytes to 10 bits words, the value is left padded with zero bits
+fn bytes_to_words(value: &[u8]) -> Vec<u16> {
+    let word_count = (value.len() * 8 + RADIX_BITS - 1) / RADIX_BITS;
+    let mut bits = word_count * RADIX_BITS - value.len() * 8;
+    let mut acc = 0u32;
+    let mut words = vec![];
+    for byte in value {
+        acc = acc << 8 | *byte as u32;
+        bits += 8;
+        while bits >= RADIX_BITS {
+            bits -= RADIX_BITS;
+            words.push((acc >> bits) as u16 & 1023);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    words
+}
+
+fn words_to_bytes(words: &[u16]) -> Result<Vec<u8>, Error> {
+    let padding = words.len() * RADIX_BITS % 16;
+    if padding > 8 {
+        return Err(Error::InvalidLength);
+    }
+    let mut bits = words
+        .iter()
+        .flat_map(|w| (0..RADIX_BITS).rev().map(move |i| (w >> i) & 1 == 1));
+    if bits.by_ref().take(padding).any(|b| b) {
+        return Err(Error::InvalidPadding);
+    }
+    let bits: Vec<bool> = bits.collect();
+    Ok(bits
+        .chunks(8)
+        .map(|c| c.iter().fold(0u8, |acc, b| acc << 1 | *b as u8))
+        .collect())
+}
+
+fn polymod(values: &[u32]) -> u32 {
+    const GEN: [u32; 10] = [
+        0x00E0_E040,
+        0x01C1_C080,
+        0x0383_8100,
+        0x0707_0200,
+        0x0E0E_0009,
+        0x1C0C_2412,
+        0x3808_6C24,
+        0x3090_FC48,
+        0x21B1_F890,
+        0x03F3_F120,
+    ];
+    let mut chk = 1u32;
+    for v in values {
+        let b = chk >> 20;
+        chk = (chk & 0xF_FFFF) << 10 ^ v;
+        for (i, g) in GEN.iter().enumerate() {
+            if (b >> i) & 1 == 1 {
+                chk ^= g;
+            }
+        }
+    }
+    chk
+}
+
+fn create_checksum(customization: &[u8], data: &[u16]) -> Vec<u16> {
+    let values: Vec<u32> = customization
+        .iter()
+        .map(|c| *c as u32)
+        .chain(data.iter().map(|w| *w as u32))
+        .chain(std::iter::repeat(0).take(CHECKSUM_LENGTH_WORDS))
+        .collect();
+    let polymod = polymod(&values) ^ 1;
+    (0..CHECKSUM_LENGTH_WORDS)
+        .rev()
+        .map(|i| (polymod >> (RADIX_BITS * i)) as u16 & 1023)
+        .collect()
+}
+
+fn verify_checksum(customization: &[u8], data: &[u16]) -> bool {
+    let values: Vec<u32> = customization
+        .iter()
+        .map(|c| *c as u32)
+        .chain(data.iter().map(|w| *w as u32))
+        .collect();
+    polymod(&values) == 1
+}
+
+/// Exp and log tables of GF(256) with the Rijndael polynomial x^8 + x^4 + x^3 + x + 1
+fn tables() -> ([u8; 255], [u8; 256]) {
+    let mut exp = [0u8; 255];
+    let mut log = [0u8; 256];
+    let mut poly = 1u16;
+    for (i, e) in exp.iter_mut().enumerate() {
+        *e = poly as u8;
+        log[poly as usize] = i as u8;
+        poly = (poly << 1) ^ poly;
+        if poly & 0x100 != 0 {
+            poly ^= 0x11B;
+        }
+    }
+    (exp, log)
+}
+
+/// Evaluate at `x` the polynomial passing through `shares` using Lagrange interpolation
+fn interpolate(shares: &[(u8, Vec<u8>)], x: u8) -> Result<Vec<u8>, Error> {
+    if let Some((_, value)) = shares.iter().find(|(i, _)| *i == x) {
+        return Ok(value.clone());
+    }
+    let (exp, log) = tables();
+    let len = shares.first().ok_or(Error::InsufficientShares)?.1.len();
+    let log_prod: u32 = shares
+        .iter()
+        .map(|(i, _)| log[(i ^ x) as usize] as u32)
+        .sum();
+
+    let mut result = vec![0u8; len];
+    for (i, value) in shares {
+        let others: u32 = shares
+            .iter()
+            .map(|(j, _)| log[(i ^ j) as usize] as u32)
+            .sum();
+        let log_basis = (log_prod + 255 * 16 - log[(i ^ x) as usize] as u32 - others) % 255;
+        for (r, v) in result.iter_mut().zip(value) {
+            if *v != 0 {
+                *r ^= exp[((log[*v as usize] as u32 + log_basis) % 255) as usize];
+            }
+        }
+    }
+    Ok(result)
+}
+
+fn create_digest(random: &[u8], secret: &[u8]) -> Vec<u8> {
+    let mut engine = HmacEngine::<sha256::Hash>::new(random);
+    engine.input(secret);
+    Hmac::from_engine(engine)[..DIGEST_LENGTH_BYTES].to_vec()
+}
+
+fn split_secret(threshold: u8, count: u8, secret: &[u8]) -> Result<Vec<(u8, Vec<u8>)>, Error> {
+    if threshold == 0 || threshold > count || count > MAX_SHARE_COUNT {
+        return Err(Error::InvalidThreshold);
+    }
+    if threshold == 1 {
+        return Ok((0..count).map(|i| (i, secret.to_vec())).collect());
+    }
+    let mut rng = thread_rng();
+    let random_count = threshold - 2;
+    let mut shares: Vec<(u8, Vec<u8>)> = (0..random_count)
+        .map(|i| {
+            let mut value = vec![0u8; secret.len()];
+            rng.fill_bytes(&mut value);
+            (i, value)
+        })
+        .collect();
+    let mut random_part = vec![0u8; secret.len() - DIGEST_LENGTH_BYTES];
+    rng.fill_bytes(&mut random_part);
+    let mut digest = create_digest(&random_part, secret);
+    digest.extend(random_part);
+
+    let mut base = shares.clone();
+    base.push((DIGEST_INDEX, digest));
+    base.push((SECRET_INDEX, secret.to_vec()));
+    for i in random_count..count {
+        shares.push((i, interpolate(&base, i)?));
+    }
+    Ok(shares)
+}
+
+fn recover_secret(threshold: u8, shares: &[(u8, Vec<u8>)]) -> Result<Vec<u8>, Error> {
+    if shares.len() < threshold as usize {
+        return Err(Error::InsufficientShares);
+    }
+    if threshold == 1 {
+        return Ok(shares[0].1.clone());
+    }
+    let secret = interpolate(shares, SECRET_INDEX)?;
+    let digest = interpolate(shares, DIGEST_INDEX)?;
+    let (digest, random_part) = digest.split_at(DIGEST_LENGTH_BYTES);
+    if digest != &create_digest(random_part, &secret)[..] {
+        return Err(Error::InvalidDigest);
+    }
+    Ok(secret)
+}
+
+/// PBKDF2 with HMAC-SHA256 producing a single block, enough for the feistel round function
+fn pbkdf2_sha256(password: &[u8], salt: &[u8], iterations: u32, len: usize) -> Vec<u8> {
+    let orig_engine = HmacEngine::<sha256::Hash>::new(password);
+    let mut result = vec![];
+    for block_index in 1..=((len + 31) / 32) as u32 {
+        let mut engine = orig_engine.clone();
+        engine.input(salt);
+        engine.input(&block_index.to_be_bytes());
+        let mut temp = Hmac::from_engine(engine).into_inner();
+        let mut block = temp;
+        for _ in 1..iterations {
+            let mut engine = orig_engine.clone();
+            engine.input(&temp);
+            temp = Hmac::from_engine(engine).into_inner();
+            for (output, input) in block.iter_mut().zip(temp.iter()) {
+                *output ^= input;
+            }
+        }
+        result.extend_from_slice(&block);
+    }
+    result.truncate(len);
+    result
+}
+
+fn round_function(round: u8, passphrase: &[u8], exponent: u8, salt: &[u8], r: &[u8]) -> Vec<u8> {
+    let mut password = vec![round];
+    password.extend_from_slice(passphrase);
+    let mut salt = salt.to_vec();
+    salt.extend_from_slice(r);
+    let iterations = (BASE_ITERATION_COUNT << exponent) / ROUND_COUNT as u32;
+    pbkdf2_sha256(&password, &salt, iterations, r.len())
+}
+
+fn salt(identifier: u16, extendable: bool) -> Vec<u8> {
+    if extendable {
+        vec![]
+    } else {
+        let mut salt = CUSTOMIZATION_STRING.to_vec();
+        salt.extend_from_slice(&identifier.to_be_bytes());
+        salt
+    }
+}
+
+fn feistel(
+    value: &[u8],
+    passphrase: &[u8],
+    exponent: u8,
+    identifier: u16,
+    extendable: bool,
+    rounds: &[u8],
+) -> Vec<u8> {
+    let (l, r) = value.split_at(value.len() / 2);
+    let (mut l, mut r) = (l.to_vec(), r.to_vec());
+    let salt = salt(identifier, extendable);
+    for round in rounds {
+        let f = round_function(*round, passphrase, exponent, &salt, &r);
+        let new_r: Vec<u8> = l.iter().zip(f.iter()).map(|(a, b)| a ^ b).collect();
+        l = std::mem::replace(&mut r, new_r);
+    }
+    r.extend(l);
+    r
+}
+
+fn encrypt(
+    secret: &[u8],
+    passphrase: &[u8],
+    exponent: u8,
+    identifier: u16,
+    extendable: bool,
+) -> Vec<u8> {
+    let rounds: Vec<u8> = (0..ROUND_COUNT).collect();
+    feistel(
+        secret, passphrase, exponent, identifier, extendable, &rounds,
+    )
+}
+
+fn decrypt(
+    encrypted: &[u8],
+    passphrase: &[u8],
+    exponent: u8,
+    identifier: u16,
+    extendable: bool,
+) -> Vec<u8> {
+    let rounds: Vec<u8> = (0..ROUND_COUNT).rev().collect();
+    feistel(
+        encrypted, passphrase, exponent, identifier, extendable, &rounds,
+    )
+}
+
+const WORDS: [&str; 1024] = [
+    "academic", "acid", "acne", "acquire", "acrobat", "activity", "actress", "adapt", "adequate",
+    "adjust", "admit", "adorn", "adult", "advance", "advocate", "afraid", "again", "agency",
+    "agree", "aide", "aircraft", "airline", "airport", "ajar", "alarm", "album", "alcohol",
+    "alien", "alive", "alpha", "already", "alto", "aluminum", "always", "amazing", "ambition",
+    "amount", "amuse", "analysis", "anatomy", "ancestor", "ancient", "angel", "angry", "animal",
+    "answer", "antenna", "anxiety", "apart", "aquatic", "arcade", "arena", "argue", "armed",
+    "artist", "artwork", "aspect", "auction", "august", "aunt", "average", "aviation", "avoid",
+    "award", "away", "axis", "axle", "beam", "beard", "beaver", "become", "bedroom", "behavior",
+    "being", "believe", "belong", "benefit", "best", "beyond", "bike", "biology", "birthday",
+    "bishop", "black", "blanket", "blessing", "blimp", "blind", "blue", "body", "bolt", "boring",
+    "born", "both", "boundary", "bracelet", "branch", "brave", "breathe", "briefing", "broken",
+    "brother", "browser", "bucket", "budget", "building", "bulb", "bulge", "bumpy", "bundle",
+    "burden", "burning", "busy", "buyer", "cage", "calcium", "camera", "campus", "canyon",
+    "capacity", "capital", "capture", "carbon", "cards", "careful", "cargo", "carpet", "carve",
+    "category", "cause", "ceiling", "center", "ceramic", "champion", "change", "charity", "check",
+    "chemical", "chest", "chew", "chubby", "cinema", "civil", "class", "clay", "cleanup", "client",
+    "climate", "clinic", "clock", "clogs", "closet", "clothes", "club", "cluster", "coal",
+    "coastal", "coding", "column", "company", "corner", "costume", "counter", "course", "cover",
+    "cowboy", "cradle", "craft", "crazy", "credit", "cricket", "criminal", "crisis", "critical",
+    "crowd", "crucial", "crunch", "crush", "crystal", "cubic", "cultural", "curious", "curly",
+    "custody", "cylinder", "daisy", "damage", "dance", "darkness", "database", "daughter",
+    "deadline", "deal", "debris", "debut", "decent", "decision", "declare", "decorate", "decrease",
+    "deliver", "demand", "density", "deny", "depart", "depend", "depict", "deploy", "describe",
+    "desert", "desire", "desktop", "destroy", "detailed", "detect", "device", "devote", "diagnose",
+    "dictate", "diet", "dilemma", "diminish", "dining", "diploma", "disaster", "discuss",
+    "disease", "dish", "dismiss", "display", "distance", "dive", "divorce", "document", "domain",
+    "domestic", "dominant", "dough", "downtown", "dragon", "dramatic", "dream", "dress", "drift",
+    "drink", "drove", "drug", "dryer", "duckling", "duke", "duration", "dwarf", "dynamic", "early",
+    "earth", "easel", "easy", "echo", "eclipse", "ecology", "edge", "editor", "educate", "either",
+    "elbow", "elder", "election", "elegant", "element", "elephant", "elevator", "elite", "else",
+    "email", "emerald", "emission", "emperor", "emphasis", "employer", "empty", "ending",
+    "endless", "endorse", "enemy", "energy", "enforce", "engage", "enjoy", "enlarge", "entrance",
+    "envelope", "envy", "epidemic", "episode", "equation", "equip", "eraser", "erode", "escape",
+    "estate", "estimate", "evaluate", "evening", "evidence", "evil", "evoke", "exact", "example",
+    "exceed", "exchange", "exclude", "excuse", "execute", "exercise", "exhaust", "exotic",
+    "expand", "expect", "explain", "express", "extend", "extra", "eyebrow", "facility", "fact",
+    "failure", "faint", "fake", "false", "family", "famous", "fancy", "fangs", "fantasy", "fatal",
+    "fatigue", "favorite", "fawn", "fiber", "fiction", "filter", "finance", "findings", "finger",
+    "firefly", "firm", "fiscal", "fishing", "fitness", "flame", "flash", "flavor", "flea",
+    "flexible", "flip", "float", "floral", "fluff", "focus", "forbid", "force", "forecast",
+    "forget", "formal", "fortune", "forward", "founder", "fraction", "fragment", "frequent",
+    "freshman", "friar", "fridge", "friendly", "frost", "froth", "frozen", "fumes", "funding",
+    "furl", "fused", "galaxy", "game", "garbage", "garden", "garlic", "gasoline", "gather",
+    "general", "genius", "genre", "genuine", "geology", "gesture", "glad", "glance", "glasses",
+    "glen", "glimpse", "goat", "golden", "graduate", "grant", "grasp", "gravity", "gray",
+    "greatest", "grief", "grill", "grin", "grocery", "gross", "group", "grownup", "grumpy",
+    "guard", "guest", "guilt", "guitar", "gums", "hairy", "hamster", "hand", "hanger", "harvest",
+    "have", "havoc", "hawk", "hazard", "headset", "health", "hearing", "heat", "helpful", "herald",
+    "herd", "hesitate", "hobo", "holiday", "holy", "home", "hormone", "hospital", "hour", "huge",
+    "human", "humidity", "hunting", "husband", "hush", "husky", "hybrid", "idea", "identify",
+    "idle", "image", "impact", "imply", "improve", "impulse", "include", "income", "increase",
+    "index", "indicate", "industry", "infant", "inform", "inherit", "injury", "inmate", "insect",
+    "inside", "install", "intend", "intimate", "invasion", "involve", "iris", "island", "isolate",
+    "item", "ivory", "jacket", "jerky", "jewelry", "join", "judicial", "juice", "jump", "junction",
+    "junior", "junk", "jury", "justice", "kernel", "keyboard", "kidney", "kind", "kitchen",
+    "knife", "knit", "laden", "ladle", "ladybug", "lair", "lamp", "language", "large", "laser",
+    "laundry", "lawsuit", "leader", "leaf", "learn", "leaves", "lecture", "legal", "legend",
+    "legs", "lend", "length", "level", "liberty", "library", "license", "lift", "likely", "lilac",
+    "lily", "lips", "liquid", "listen", "literary", "living", "lizard", "loan", "lobe", "location",
+    "losing", "loud", "loyalty", "luck", "lunar", "lunch", "lungs", "luxury", "lying", "lyrics",
+    "machine", "magazine", "maiden", "mailman", "main", "makeup", "making", "mama", "manager",
+    "mandate", "mansion", "manual", "marathon", "march", "market", "marvel", "mason", "material",
+    "math", "maximum", "mayor", "meaning", "medal", "medical", "member", "memory", "mental",
+    "merchant", "merit", "method", "metric", "midst", "mild", "military", "mineral", "minister",
+    "miracle", "mixed", "mixture", "mobile", "modern", "modify", "moisture", "moment", "morning",
+    "mortgage", "mother", "mountain", "mouse", "move", "much", "mule", "multiple", "muscle",
+    "museum", "music", "mustang", "nail", "national", "necklace", "negative", "nervous", "network",
+    "news", "nuclear", "numb", "numerous", "nylon", "oasis", "obesity", "object", "observe",
+    "obtain", "ocean", "often", "olympic", "omit", "oral", "orange", "orbit", "order", "ordinary",
+    "organize", "ounce", "oven", "overall", "owner", "paces", "pacific", "package", "paid",
+    "painting", "pajamas", "pancake", "pants", "papa", "paper", "parcel", "parking", "party",
+    "patent", "patrol", "payment", "payroll", "peaceful", "peanut", "peasant", "pecan", "penalty",
+    "pencil", "percent", "perfect", "permit", "petition", "phantom", "pharmacy", "photo", "phrase",
+    "physics", "pickup", "picture", "piece", "pile", "pink", "pipeline", "pistol", "pitch",
+    "plains", "plan", "plastic", "platform", "playoff", "pleasure", "plot", "plunge", "practice",
+    "prayer", "preach", "predator", "pregnant", "premium", "prepare", "presence", "prevent",
+    "priest", "primary", "priority", "prisoner", "privacy", "prize", "problem", "process",
+    "profile", "program", "promise", "prospect", "provide", "prune", "public", "pulse", "pumps",
+    "punish", "puny", "pupal", "purchase", "purple", "python", "quantity", "quarter", "quick",
+    "quiet", "race", "racism", "radar", "railroad", "rainbow", "raisin", "random", "ranked",
+    "rapids", "raspy", "reaction", "realize", "rebound", "rebuild", "recall", "receiver",
+    "recover", "regret", "regular", "reject", "relate", "remember", "remind", "remove", "render",
+    "repair", "repeat", "replace", "require", "rescue", "research", "resident", "response",
+    "result", "retailer", "retreat", "reunion", "revenue", "review", "reward", "rhyme", "rhythm",
+    "rich", "rival", "river", "robin", "rocky", "romantic", "romp", "roster", "round", "royal",
+    "ruin", "ruler", "rumor", "sack", "safari", "salary", "salon", "salt", "satisfy", "satoshi",
+    "saver", "says", "scandal", "scared", "scatter", "scene", "scholar", "science", "scout",
+    "scramble", "screw", "script", "scroll", "seafood", "season", "secret", "security", "segment",
+    "senior", "shadow", "shaft", "shame", "shaped", "sharp", "shelter", "sheriff", "short",
+    "should", "shrimp", "sidewalk", "silent", "silver", "similar", "simple", "single", "sister",
+    "skin", "skunk", "slap", "slavery", "sled", "slice", "slim", "slow", "slush", "smart", "smear",
+    "smell", "smirk", "smith", "smoking", "smug", "snake", "snapshot", "sniff", "society",
+    "software", "soldier", "solution", "soul", "source", "space", "spark", "speak", "species",
+    "spelling", "spend", "spew", "spider", "spill", "spine", "spirit", "spit", "spray", "sprinkle",
+    "square", "squeeze", "stadium", "staff", "standard", "starting", "station", "stay", "steady",
+    "step", "stick", "stilt", "story", "strategy", "strike", "style", "subject", "submit", "sugar",
+    "suitable", "sunlight", "superior", "surface", "surprise", "survive", "sweater", "swimming",
+    "swing", "switch", "symbolic", "sympathy", "syndrome", "system", "tackle", "tactics",
+    "tadpole", "talent", "task", "taste", "taught", "taxi", "teacher", "teammate", "teaspoon",
+    "temple", "tenant", "tendency", "tension", "terminal", "testify", "texture", "thank", "that",
+    "theater", "theory", "therapy", "thorn", "threaten", "thumb", "thunder", "ticket", "tidy",
+    "timber", "timely", "ting", "tofu", "together", "tolerate", "total", "toxic", "tracks",
+    "traffic", "training", "transfer", "trash", "traveler", "treat", "trend", "trial", "tricycle",
+    "trip", "triumph", "trouble", "true", "trust", "twice", "twin", "type", "typical", "ugly",
+    "ultimate", "umbrella", "uncover", "undergo", "unfair", "unfold", "unhappy", "union",
+    "universe", "unkind", "unknown", "unusual", "unwrap", "upgrade", "upstairs", "username",
+    "usher", "usual", "valid", "valuable", "vampire", "vanish", "various", "vegan", "velvet",
+    "venture", "verdict", "verify", "very", "veteran", "vexed", "victim", "video", "view",
+    "vintage", "violence", "viral", "visitor", "visual", "vitamins", "vocal", "voice", "volume",
+    "voter", "voting", "walnut", "warmth", "warn", "watch", "wavy", "wealthy", "weapon", "webcam",
+    "welcome", "welfare", "western", "width", "wildlife", "window", "wine", "wireless", "wisdom",
+    "withdraw", "wits", "wolf", "woman", "work", "worthy", "wrap", "wrist", "writing", "wrote",
+    "year", "yelp", "yield", "yoga", "zero",
+];
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bitcoin::hashes::hex::FromHex;
+    use serde_json::Value;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_slip39_vectors() {
+        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("test_data/SLIP39.json");
+        let data = std::fs::read_to_string(d).unwrap();
+        let json: Value = serde_json::from_str(&data).unwrap();
+
+        for t in json.as_array().unwrap() {
+            let values = t.as_array().unwrap();
+            let description = values[0].as_str().unwrap();
+            let mnemonics: Vec<String> = values[1]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|m| m.as_str().unwrap().to_string())
+                .collect();
+            let expected = values[2].as_str().unwrap();
+            let result = combine_mnemonics(&mnemonics, b"TREZOR");
+            if expected.is_empty() {
+                assert!(result.is_err(), "{}", description);
+            } else {
+                let expected = Vec::<u8>::from_hex(expected).unwrap();
+                assert_eq!(result.unwrap(), expected, "{}", description);
+                for m in mnemonics {
+                    assert_eq!(Share::from_mnemonic(&m).unwrap().to_mnemonic(), m);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_slip39_split_combine() {
+        let secret = [7u8; 32];
+        let groups = generate_mnemonics(2, &[(2, 3), (1, 1), (3, 5)], &secret, b"", 0).unwrap();
+        assert_eq!(
+            groups.iter().map(|g| g.len()).collect::<Vec<_>>(),
+            vec![3, 1, 5]
+        );
+        assert!(groups[0][0].split(' ').count() == 33);
+
+        let shares = vec![
+            groups[0][2].clone(),
+            groups[0][0].clone(),
+            groups[1][0].clone(),
+        ];
+        assert_eq!(combine_mnemonics(&shares, b"").unwrap(), secret);
+        assert_ne!(combine_mnemonics(&shares, b"other").unwrap(), secret);
+
+        // the incomplete group is ignored
+        let shares = vec![
+            groups[2][0].clone(),
+            groups[0][1].clone(),
+            groups[1][0].clone(),
+            groups[0][0].clone(),
+        ];
+        assert_eq!(combine_mnemonics(&shares, b"").unwrap(), secret);
+
+        let shares = vec![groups[0][2].clone(), groups[2][0].clone()];
+        assert_eq!(
+            combine_mnemonics(&shares, b""),
+            Err(Error::InsufficientShares)
+        );
+
+        assert_eq!(
+            generate_mnemonics(2, &[(1, 3)], &secret, b"", 0),
+            Err(Error::InvalidThreshold)
+        );
+        assert_eq!(
+            generate_mnemonics(1, &[(1, 1)], &secret[..15], b"", 0),
+            Err(Error::InvalidSecretLength)
+        );
+    }
+}
@@ -0,0 +1,100 @@
+//! Bytewords (BCR-2020-012) minimal encoding, each byte is encoded with the first and last letter
+//! of a four letters word, the data is followed by its CRC32 checksum
+
+use crate::*;
+
+/// The 256 words concatenated, the word of byte `b` is at `4 * b`
+const WORDS: &str = "\
+    ableacidalsoapexaquaarchatomauntawayaxisbackbaldbarnbeltbetabiasbluebodybragbrew\
+    bulbbuzzcalmcashcatschefcityclawcodecolacookcostcruxcurlcuspcyandarkdatadaysdeli\
+    dicedietdoordowndrawdropdrumdulldutyeacheasyechoedgeepicevenexamexiteyesfactfair\
+    fernfigsfilmfishfizzflapflewfluxfoxyfreefrogfuelfundgalagamegeargemsgiftgirlglow\
+    goodgraygrimgurugushgyrohalfhanghardhawkheathelphighhillholyhopehornhutsicedidea\
+    idleinchinkyintoirisironitemjadejazzjoinjoltjowljudojugsjumpjunkjurykeepkenokept\
+    keyskickkilnkingkitekiwiknoblamblavalazyleaflegsliarlimplionlistlogoloudloveluau\
+    lucklungmainmanymathmazememomenumeowmildmintmissmonknailnavyneednewsnextnoonnote\
+    numbobeyoboeomitonyxopenovalowlspaidpartpeckplaypluspoempoolposepuffpumapurrquad\
+    quizraceramprealredorichroadrockroofrubyruinrunsrustsafesagascarsetssilkskewslot\
+    soapsolosongstubsurfswantacotasktaxitenttiedtimetinytoiltombtoystriptunatwinugly\
+    undouniturgeuservastveryvetovialvibeviewvisavoidvowswallwandwarmwaspwavewaxywebs\
+    whatwhenwhizwolfworkyankyawnyellyogayurtzapszerozestzinczonezoom";
+
+/// CRC32 (ISO-HDLC) of `data`, used as checksum in bytewords and in multi-part UR
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/// Encode `data` followed by its checksum in lowercase minimal bytewords
+pub fn encode(data: &[u8]) -> String {
+    let checksum = crc32(data).to_be_bytes();
+    let mut result = String::with_capacity((data.len() + 4) * 2);
+    for byte in data.iter().chain(checksum.iter()) {
+        let word = &WORDS[*byte as usize * 4..*byte as usize * 4 + 4];
+        result.push_str(&word[0..1]);
+        result.push_str(&word[3..4]);
+    }
+    result
+}
+
+/// Decode minimal bytewords, case insensitive, checking and removing the checksum
+pub fn decode(s: &str) -> Result<Vec<u8>> {
+    let s = s.to_lowercase();
+    if !s.is_ascii() || s.len() % 2 != 0 {
+        return Err(Error::UrInvalid("bytewords length is odd".to_string()));
+    }
+    let mut bytes = Vec::with_capacity(s.len() / 2);
+    for pair in s.as_bytes().chunks(2) {
+        let byte = (0..256usize)
+            .find(|b| {
+                let word = &WORDS.as_bytes()[b * 4..b * 4 + 4];
+                word[0] == pair[0] && word[3] == pair[1]
+            })
+            .ok_or_else(|| Error::UrInvalid(format!("invalid byteword {}", s)))?;
+        bytes.push(byte as u8);
+    }
+    if bytes.len() < 4 {
+        return Err(Error::UrInvalid("bytewords too short".to_string()));
+    }
+    let checksum = bytes.split_off(bytes.len() - 4);
+    if checksum != crc32(&bytes).to_be_bytes() {
+        return Err(Error::UrChecksum);
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::ur::bytewords::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_bytewords() {
+        assert_eq!(WORDS.len(), 256 * 4);
+        let minimals: HashSet<String> =
+            (0..=255u8).map(|b| encode(&[b])[..2].to_string()).collect();
+        assert_eq!(minimals.len(), 256);
+
+        assert_eq!(crc32(b"Hello, world!"), 0xebe6_c6e6);
+        assert_eq!(crc32(b"Wolf"), 0x598c_84dc);
+
+        let data = [0u8, 1, 0x7f, 0xff];
+        let encoded = encode(&data);
+        assert!(encoded.starts_with("aeadlbzm"));
+        assert_eq!(encoded.len(), 16);
+        assert_eq!(decode(&encoded).unwrap(), data);
+        assert_eq!(decode(&encoded.to_uppercase()).unwrap(), data);
+        assert_eq!(decode(&encode(&[])).unwrap(), Vec::<u8>::new());
+
+        assert!(decode(&encoded[1..]).is_err());
+        assert!(decode("aeadlbzmaeaeaeae").is_err(), "wrong checksum");
+        assert!(decode("qqadlbzm").is_err(), "invalid word");
+    }
+}
@@ -0,0 +1,283 @@
+//! Minimal CBOR (RFC 7049) encoder and decoder, supporting only the data items used by UR types
+
+use crate::*;
+
+const UINT: u8 = 0;
+const BYTES: u8 = 2;
+const TEXT: u8 = 3;
+const ARRAY: u8 = 4;
+const MAP: u8 = 5;
+const TAG: u8 = 6;
+const SIMPLE: u8 = 7;
+
+const FALSE: u8 = 20;
+const TRUE: u8 = 21;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cbor {
+    Uint(u64),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Cbor>),
+    /// Map with unsigned integer keys, the only ones used by UR types
+    Map(Vec<(u64, Cbor)>),
+    Tag(u64, Box<Cbor>),
+    Bool(bool),
+}
+
+impl Cbor {
+    pub fn tag(tag: u64, value: Cbor) -> Cbor {
+        Cbor::Tag(tag, Box::new(value))
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        self.encode_to(&mut bytes);
+        bytes
+    }
+
+    fn encode_to(&self, bytes: &mut Vec<u8>) {
+        match self {
+            Cbor::Uint(value) => push_header(bytes, UINT, *value),
+            Cbor::Bytes(value) => {
+                push_header(bytes, BYTES, value.len() as u64);
+                bytes.extend(value);
+            }
+            Cbor::Text(value) => {
+                push_header(bytes, TEXT, value.len() as u64);
+                bytes.extend(value.as_bytes());
+            }
+            Cbor::Array(items) => {
+                push_header(bytes, ARRAY, items.len() as u64);
+                for item in items {
+                    item.encode_to(bytes);
+                }
+            }
+            Cbor::Map(entries) => {
+                push_header(bytes, MAP, entries.len() as u64);
+                for (key, value) in entries {
+                    push_header(bytes, UINT, *key);
+                    value.encode_to(bytes);
+                }
+            }
+            Cbor::Tag(tag, value) => {
+                push_header(bytes, TAG, *tag);
+                value.encode_to(bytes);
+            }
+            Cbor::Bool(value) => bytes.push(SIMPLE << 5 | if *value { TRUE } else { FALSE }),
+        }
+    }
+
+    /// Decode a data item, failing if `bytes` contains something else after it
+    pub fn decode(bytes: &[u8]) -> Result<Cbor> {
+        let mut decoder = Decoder { bytes, pos: 0 };
+        let value = decoder.item()?;
+        if decoder.pos != bytes.len() {
+            return Err(invalid("trailing bytes after cbor item"));
+        }
+        Ok(value)
+    }
+
+    pub fn as_uint(&self) -> Result<u64> {
+        match self {
+            Cbor::Uint(value) => Ok(*value),
+            _ => Err(invalid("expected unsigned integer")),
+        }
+    }
+
+    pub fn as_bytes(&self) -> Result<&[u8]> {
+        match self {
+            Cbor::Bytes(value) => Ok(value),
+            _ => Err(invalid("expected byte string")),
+        }
+    }
+
+    pub fn as_array(&self) -> Result<&[Cbor]> {
+        match self {
+            Cbor::Array(items) => Ok(items),
+            _ => Err(invalid("expected array")),
+        }
+    }
+
+    pub fn as_bool(&self) -> Result<bool> {
+        match self {
+            Cbor::Bool(value) => Ok(*value),
+            _ => Err(invalid("expected boolean")),
+        }
+    }
+
+    /// Value of `key` if this is a map containing it
+    pub fn get(&self, key: u64) -> Result<Option<&Cbor>> {
+        match self {
+            Cbor::Map(entries) => Ok(entries.iter().find(|(k, _)| *k == key).map(|(_, v)| v)),
+            _ => Err(invalid("expected map")),
+        }
+    }
+
+    /// Content of the item tagged with `tag`
+    pub fn untag(&self, tag: u64) -> Result<&Cbor> {
+        match self {
+            Cbor::Tag(t, value) if *t == tag => Ok(value),
+            _ => Err(invalid(&format!("expected tag {}", tag))),
+        }
+    }
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::UrInvalid(reason.to_string())
+}
+
+fn push_header(bytes: &mut Vec<u8>, major: u8, value: u64) {
+    let major = major << 5;
+    if value < 24 {
+        bytes.push(major | value as u8);
+    } else if value <= u8::max_value() as u64 {
+        bytes.push(major | 24);
+        bytes.push(value as u8);
+    } else if value <= u16::max_value() as u64 {
+        bytes.push(major | 25);
+        bytes.extend(&(value as u16).to_be_bytes());
+    } else if value <= u32::max_value() as u64 {
+        bytes.push(major | 26);
+        bytes.extend(&(value as u32).to_be_bytes());
+    } else {
+        bytes.push(major | 27);
+        bytes.extend(&value.to_be_bytes());
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| invalid("unexpected end of cbor"))?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn header(&mut self) -> Result<(u8, u8, u64)> {
+        let initial = self.take(1)?[0];
+        let (major, additional) = (initial >> 5, initial & 0x1f);
+        let len = match additional {
+            0..=23 => return Ok((major, additional, additional as u64)),
+            24 => 1,
+            25 => 2,
+            26 => 4,
+            27 => 8,
+            _ => return Err(invalid("indefinite lengths are not supported")),
+        };
+        let value = self
+            .take(len)?
+            .iter()
+            .fold(0u64, |acc, b| acc << 8 | *b as u64);
+        Ok((major, additional, value))
+    }
+
+    fn len(&self, value: u64) -> Result<usize> {
+        // any item takes at least one byte, refuse lengths greater than the remaining bytes to
+        // avoid huge allocations
+        if value > (self.bytes.len() - self.pos) as u64 {
+            return Err(invalid("cbor length exceeds the data"));
+        }
+        Ok(value as usize)
+    }
+
+    fn item(&mut self) -> Result<Cbor> {
+        let (major, additional, value) = self.header()?;
+        match major {
+            UINT => Ok(Cbor::Uint(value)),
+            BYTES => {
+                let len = self.len(value)?;
+                Ok(Cbor::Bytes(self.take(len)?.to_vec()))
+            }
+            TEXT => {
+                let len = self.len(value)?;
+                Ok(Cbor::Text(
+                    std::str::from_utf8(self.take(len)?)?.to_string(),
+                ))
+            }
+            ARRAY => {
+                let len = self.len(value)?;
+                let mut items = Vec::with_capacity(len);
+                for _ in 0..len {
+                    items.push(self.item()?);
+                }
+                Ok(Cbor::Array(items))
+            }
+            MAP => {
+                let len = self.len(value)?;
+                let mut entries = Vec::with_capacity(len);
+                for _ in 0..len {
+                    let key = self.item()?.as_uint()?;
+                    entries.push((key, self.item()?));
+                }
+                Ok(Cbor::Map(entries))
+            }
+            TAG => Ok(Cbor::tag(value, self.item()?)),
+            SIMPLE if additional == FALSE => Ok(Cbor::Bool(false)),
+            SIMPLE if additional == TRUE => Ok(Cbor::Bool(true)),
+            _ => Err(invalid("unsupported cbor item")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::ur::cbor::Cbor;
+
+    #[test]
+    fn test_cbor() {
+        // examples from RFC 7049 appendix A
+        let vectors = vec![
+            (Cbor::Uint(0), "00"),
+            (Cbor::Uint(23), "17"),
+            (Cbor::Uint(24), "1818"),
+            (Cbor::Uint(1000), "1903e8"),
+            (Cbor::Uint(1_000_000), "1a000f4240"),
+            (Cbor::Uint(1_000_000_000_000), "1b000000e8d4a51000"),
+            (Cbor::Bool(false), "f4"),
+            (Cbor::Bool(true), "f5"),
+            (Cbor::Bytes(vec![1, 2, 3, 4]), "4401020304"),
+            (Cbor::Text("IETF".to_string()), "6449455446"),
+            (Cbor::tag(1, Cbor::Uint(1_363_896_240)), "c11a514b67b0"),
+            (
+                Cbor::Array(vec![
+                    Cbor::Uint(1),
+                    Cbor::Array(vec![Cbor::Uint(2), Cbor::Uint(3)]),
+                ]),
+                "8201820203",
+            ),
+            (
+                Cbor::Map(vec![(1, Cbor::Uint(2)), (3, Cbor::Uint(4))]),
+                "a201020304",
+            ),
+        ];
+        for (value, expected) in vectors {
+            assert_eq!(hex::encode(value.encode()), expected);
+            assert_eq!(
+                Cbor::decode(&hex::decode(expected).unwrap()).unwrap(),
+                value
+            );
+        }
+
+        assert!(Cbor::decode(&hex::decode("0000").unwrap()).is_err());
+        assert!(Cbor::decode(&hex::decode("44010203").unwrap()).is_err());
+        assert!(Cbor::decode(&hex::decode("5bffffffffffffffff").unwrap()).is_err());
+        assert!(
+            Cbor::decode(&hex::decode("20").unwrap()).is_err(),
+            "negative"
+        );
+        assert!(
+            Cbor::decode(&hex::decode("9f").unwrap()).is_err(),
+            "indefinite"
+        );
+    }
+}
@@ -0,0 +1,558 @@
+//! Fountain codes of multi-part UR (BCR-2020-005): the message is split in fragments, the first
+//! parts contain each a fragment, the following ones the xor of a pseudo-random subset of fragments
+//! so that a receiver missing some frames of an animated QR can still recover the message
+
+use crate::common::ur::bytewords::crc32;
+use crate::common::ur::cbor::Cbor;
+use crate::*;
+use bitcoin::hashes::{sha256, Hash};
+use std::collections::{BTreeSet, HashMap};
+
+const MIN_FRAGMENT_LEN: usize = 10;
+/// Max length of a message, more than any payload exchanged with QR codes, received parts
+/// declaring more are refused before allocating for them
+const MAX_MESSAGE_LEN: usize = 1_000_000;
+/// Max number of fragments of a message, an animated QR with more frames isn't practical
+const MAX_SEQ_LEN: usize = 10_000;
+
+/// A part of a fountain encoded message
+#[derive(Debug, Clone, PartialEq)]
+pub struct Part {
+    pub seq_num: u32,
+    pub seq_len: usize,
+    pub message_len: usize,
+    pub checksum: u32,
+    pub data: Vec<u8>,
+}
+
+impl Part {
+    pub fn to_cbor(&self) -> Vec<u8> {
+        Cbor::Array(vec![
+            Cbor::Uint(self.seq_num as u64),
+            Cbor::Uint(self.seq_len as u64),
+            Cbor::Uint(self.message_len as u64),
+            Cbor::Uint(self.checksum as u64),
+            Cbor::Bytes(self.data.clone()),
+        ])
+        .encode()
+    }
+
+    pub fn from_cbor(bytes: &[u8]) -> Result<Self> {
+        let cbor = Cbor::decode(bytes)?;
+        let items = cbor.as_array()?;
+        if items.len() != 5 {
+            return Err(Error::UrInvalid(
+                "fountain part is not an array of 5".to_string(),
+            ));
+        }
+        let to_u32 = |value: u64| {
+            if value > u32::max_value() as u64 {
+                Err(Error::UrInvalid(
+                    "fountain part field overflows".to_string(),
+                ))
+            } else {
+                Ok(value as u32)
+            }
+        };
+        let part = Part {
+            seq_num: to_u32(items[0].as_uint()?)?,
+            seq_len: to_u32(items[1].as_uint()?)? as usize,
+            message_len: to_u32(items[2].as_uint()?)? as usize,
+            checksum: to_u32(items[3].as_uint()?)?,
+            data: items[4].as_bytes()?.to_vec(),
+        };
+        part.check()?;
+        Ok(part)
+    }
+
+    /// Refuse parts which can't be produced by an encoder: sequence numbers start from 1, the
+    /// message is split in `seq_len` fragments of the length of `data` and lengths are capped
+    fn check(&self) -> Result<()> {
+        let invalid = |reason: &str| Err(Error::UrInvalid(format!("fountain part {}", reason)));
+        if self.seq_num == 0 {
+            return invalid("sequence number is 0");
+        }
+        if self.seq_len == 0 || self.seq_len > MAX_SEQ_LEN {
+            return invalid("sequence length is 0 or too big");
+        }
+        if self.message_len == 0 || self.message_len > MAX_MESSAGE_LEN {
+            return invalid("message length is 0 or too big");
+        }
+        let fragment_len = self.data.len();
+        if fragment_len == 0 || fragment_count(self.message_len, fragment_len) != self.seq_len {
+            return invalid("data length is inconsistent with message and sequence lengths");
+        }
+        Ok(())
+    }
+
+    /// Indexes of the fragments xored in this part
+    pub fn indexes(&self) -> BTreeSet<usize> {
+        choose_fragments(self.seq_num, self.seq_len, self.checksum)
+    }
+}
+
+pub struct Encoder {
+    message_len: usize,
+    checksum: u32,
+    fragments: Vec<Vec<u8>>,
+    seq_num: u32,
+}
+
+impl Encoder {
+    /// Split `message` in fragments of at most `max_fragment_len` bytes
+    pub fn new(message: &[u8], max_fragment_len: usize) -> Result<Self> {
+        if message.is_empty() {
+            return Err(Error::UrInvalid("empty message".to_string()));
+        }
+        if max_fragment_len < MIN_FRAGMENT_LEN {
+            return Err(Error::UrInvalid(format!(
+                "max fragment length must be at least {}",
+                MIN_FRAGMENT_LEN
+            )));
+        }
+        if message.len() > MAX_MESSAGE_LEN {
+            return Err(Error::UrInvalid(format!(
+                "message longer than {} bytes",
+                MAX_MESSAGE_LEN
+            )));
+        }
+        let fragment_len = fragment_len(message.len(), max_fragment_len);
+        if fragment_count(message.len(), fragment_len) > MAX_SEQ_LEN {
+            return Err(Error::UrInvalid(format!(
+                "message split in more than {} fragments",
+                MAX_SEQ_LEN
+            )));
+        }
+        let fragments = message
+            .chunks(fragment_len)
+            .map(|chunk| {
+                let mut fragment = chunk.to_vec();
+                fragment.resize(fragment_len, 0);
+                fragment
+            })
+            .collect();
+        Ok(Encoder {
+            message_len: message.len(),
+            checksum: crc32(message),
+            fragments,
+            seq_num: 0,
+        })
+    }
+
+    pub fn seq_len(&self) -> usize {
+        self.fragments.len()
+    }
+
+    /// The next part, the first `seq_len` contain the fragments in order, the following are
+    /// mixed, there is no limit to the number of parts
+    pub fn next_part(&mut self) -> Part {
+        self.seq_num = self.seq_num.wrapping_add(1);
+        let indexes = choose_fragments(self.seq_num, self.seq_len(), self.checksum);
+        let mut data = vec![0u8; self.fragments[0].len()];
+        for index in indexes {
+            xor_into(&mut data, &self.fragments[index]);
+        }
+        Part {
+            seq_num: self.seq_num,
+            seq_len: self.seq_len(),
+            message_len: self.message_len,
+            checksum: self.checksum,
+            data,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Decoder {
+    /// seq_len, message_len, checksum and fragment length of the first part received
+    expected: Option<(usize, usize, u32, usize)>,
+    fragments: HashMap<usize, Vec<u8>>,
+    mixed: Vec<(BTreeSet<usize>, Vec<u8>)>,
+}
+
+impl Decoder {
+    /// Add a part, invalid parts and parts of other messages are refused
+    pub fn receive(&mut self, part: &Part) -> Result<()> {
+        part.check()?;
+        let params = (
+            part.seq_len,
+            part.message_len,
+            part.checksum,
+            part.data.len(),
+        );
+        match self.expected {
+            None => self.expected = Some(params),
+            Some(expected) if expected != params => {
+                return Err(Error::UrInvalid(
+                    "part belongs to a different message".to_string(),
+                ))
+            }
+            _ => (),
+        }
+        let mut queue = vec![(part.indexes(), part.data.clone())];
+        while let Some((mut indexes, mut data)) = queue.pop() {
+            self.reduce(&mut indexes, &mut data);
+            match indexes.len() {
+                0 => continue,
+                1 => {
+                    let index = *indexes.iter().next().expect("one index");
+                    self.fragments.insert(index, data);
+                    // a new fragment may reduce mixed parts to single fragments
+                    let mixed = std::mem::take(&mut self.mixed);
+                    queue.extend(mixed);
+                }
+                _ => {
+                    if !self.mixed.iter().any(|(i, _)| i == &indexes) {
+                        self.mixed.push((indexes, data));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the known fragments from a mixed part
+    fn reduce(&self, indexes: &mut BTreeSet<usize>, data: &mut [u8]) {
+        let known: Vec<usize> = indexes
+            .iter()
+            .filter(|i| self.fragments.contains_key(i))
+            .cloned()
+            .collect();
+        for index in known {
+            xor_into(data, &self.fragments[&index]);
+            indexes.remove(&index);
+        }
+    }
+
+    /// Number of fragments recovered and total number of fragments
+    pub fn progress(&self) -> (usize, usize) {
+        let total = self.expected.map(|e| e.0).unwrap_or(0);
+        (self.fragments.len(), total)
+    }
+
+    /// The message if all the fragments have been recovered
+    pub fn message(&self) -> Result<Option<Vec<u8>>> {
+        let (seq_len, message_len, checksum, _) = match self.expected {
+            Some(expected) => expected,
+            None => return Ok(None),
+        };
+        if self.fragments.len() < seq_len {
+            return Ok(None);
+        }
+        let mut message = Vec::with_capacity(message_len);
+        for i in 0..seq_len {
+            let fragment = self
+                .fragments
+                .get(&i)
+                .ok_or(Error::UrMissingParts(self.fragments.len(), seq_len))?;
+            message.extend(fragment);
+        }
+        message.truncate(message_len);
+        if crc32(&message) != checksum {
+            return Err(Error::UrChecksum);
+        }
+        Ok(Some(message))
+    }
+}
+
+fn xor_into(data: &mut [u8], other: &[u8]) {
+    for (a, b) in data.iter_mut().zip(other.iter()) {
+        *a ^= b;
+    }
+}
+
+/// Number of fragments of `fragment_len` needed for a message of `message_len`
+fn fragment_count(message_len: usize, fragment_len: usize) -> usize {
+    let full = message_len / fragment_len;
+    if full * fragment_len < message_len {
+        full + 1
+    } else {
+        full
+    }
+}
+
+/// Smallest fragment length not exceeding `max_fragment_len` splitting the message in equal parts
+fn fragment_len(message_len: usize, max_fragment_len: usize) -> usize {
+    let max_fragment_count = std::cmp::max(1, message_len / MIN_FRAGMENT_LEN);
+    let mut fragment_len = message_len;
+    for fragment_count in 1..=max_fragment_count {
+        fragment_len = (message_len + fragment_count - 1) / fragment_count;
+        if fragment_len <= max_fragment_len {
+            break;
+        }
+    }
+    fragment_len
+}
+
+/// Indexes of the fragments of the part `seq_num`, starting from 1
+fn choose_fragments(seq_num: u32, seq_len: usize, checksum: u32) -> BTreeSet<usize> {
+    if (seq_num as usize) <= seq_len {
+        return std::iter::once((seq_num as usize).saturating_sub(1)).collect();
+    }
+    let mut seed = seq_num.to_be_bytes().to_vec();
+    seed.extend(&checksum.to_be_bytes());
+    let mut rng = Xoshiro256::new(&seed);
+    let degree = choose_degree(seq_len, &mut rng);
+    let indexes: Vec<usize> = (0..seq_len).collect();
+    shuffle(indexes, &mut rng)
+        .into_iter()
+        .take(degree)
+        .collect()
+}
+
+/// Number of fragments to mix, with probability inversely proportional to the number
+fn choose_degree(seq_len: usize, rng: &mut Xoshiro256) -> usize {
+    let probabilities: Vec<f64> = (1..=seq_len).map(|i| 1.0 / i as f64).collect();
+    RandomSampler::new(&probabilities).next(rng) + 1
+}
+
+fn shuffle<T>(mut remaining: Vec<T>, rng: &mut Xoshiro256) -> Vec<T> {
+    let mut result = Vec::with_capacity(remaining.len());
+    while !remaining.is_empty() {
+        let index = rng.next_int(0, remaining.len() as u64 - 1) as usize;
+        result.push(remaining.remove(index));
+    }
+    result
+}
+
+/// Xoshiro256** pseudo random generator seeded with the SHA256 of the given bytes, as required to
+/// produce the same mixed parts of the reference implementation
+struct Xoshiro256 {
+    s: [u64; 4],
+}
+
+impl Xoshiro256 {
+    fn new(seed: &[u8]) -> Self {
+        let digest = sha256::Hash::hash(seed).into_inner();
+        let mut s = [0u64; 4];
+        for (i, chunk) in digest.chunks(8).enumerate() {
+            s[i] = chunk.iter().fold(0u64, |acc, b| acc << 8 | *b as u64);
+        }
+        Xoshiro256 { s }
+    }
+
+    fn next(&mut self) -> u64 {
+        let s = &mut self.s;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        result
+    }
+
+    fn next_double(&mut self) -> f64 {
+        self.next() as f64 / (u64::max_value() as f64 + 1.0)
+    }
+
+    fn next_int(&mut self, low: u64, high: u64) -> u64 {
+        (self.next_double() * (high - low + 1) as f64) as u64 + low
+    }
+}
+
+/// Walker-Vose alias method to sample indexes with the given probabilities
+struct RandomSampler {
+    probs: Vec<f64>,
+    aliases: Vec<usize>,
+}
+
+impl RandomSampler {
+    fn new(probabilities: &[f64]) -> Self {
+        let n = probabilities.len();
+        let sum: f64 = probabilities.iter().sum();
+        let mut p: Vec<f64> = probabilities.iter().map(|p| p * n as f64 / sum).collect();
+        let mut small = vec![];
+        let mut large = vec![];
+        // reverse order, as in the reference implementation
+        for i in (0..n).rev() {
+            if p[i] < 1.0 {
+                small.push(i);
+            } else {
+                large.push(i);
+            }
+        }
+        let mut probs = vec![0.0; n];
+        let mut aliases = vec![0; n];
+        while !small.is_empty() && !large.is_empty() {
+            let a = small.pop().expect("not empty");
+            let g = large.pop().expect("not empty");
+            probs[a] = p[a];
+            aliases[a] = g;
+            p[g] += p[a] - 1.0;
+            if p[g] < 1.0 {
+                small.push(g);
+            } else {
+                large.push(g);
+            }
+        }
+        for i in large.into_iter().chain(small) {
+            probs[i] = 1.0;
+        }
+        RandomSampler { probs, aliases }
+    }
+
+    fn next(&self, rng: &mut Xoshiro256) -> usize {
+        let r1 = rng.next_double();
+        let r2 = rng.next_double();
+        let i = (self.probs.len() as f64 * r1) as usize;
+        if r2 < self.probs[i] {
+            i
+        } else {
+            self.aliases[i]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::ur::fountain::*;
+    use crate::common::ur::{bytewords, decode, Ur, UrDecoder, UrEncoder, BYTES};
+
+    #[test]
+    fn test_xoshiro() {
+        // vectors of the reference implementation
+        let mut rng = Xoshiro256::new(b"Wolf");
+        let numbers: Vec<u64> = (0..10).map(|_| rng.next() % 100).collect();
+        assert_eq!(numbers, vec![42, 81, 85, 8, 82, 84, 76, 73, 70, 88]);
+
+        let mut rng = Xoshiro256::new(b"Wolf");
+        let values: Vec<u32> = (1..=10).collect();
+        assert_eq!(
+            shuffle(values.clone(), &mut rng),
+            vec![6, 4, 9, 3, 10, 5, 7, 8, 1, 2]
+        );
+        assert_eq!(
+            shuffle(values, &mut rng),
+            vec![10, 8, 6, 5, 1, 2, 3, 9, 7, 4]
+        );
+    }
+
+    #[test]
+    fn test_fountain() {
+        assert_eq!(fragment_len(12345, 1955), 1764);
+        assert_eq!(fragment_len(12345, 30000), 12345);
+        assert_eq!(fragment_len(5, 10), 5);
+
+        let message: Vec<u8> = (0..1000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut encoder = Encoder::new(&message, 100).unwrap();
+        assert_eq!(encoder.seq_len(), 10);
+        let parts: Vec<Part> = (0..40).map(|_| encoder.next_part()).collect();
+        assert_eq!(parts[0].data, message[..100].to_vec());
+        assert_eq!(parts[9].indexes().into_iter().collect::<Vec<_>>(), vec![9]);
+        assert!(parts[10..].iter().any(|p| p.indexes().len() > 1));
+        for part in parts.iter() {
+            assert_eq!(&Part::from_cbor(&part.to_cbor()).unwrap(), part);
+        }
+
+        // the simple parts in any order
+        let mut decoder = Decoder::default();
+        for part in parts[..10].iter().rev() {
+            assert!(decoder.message().unwrap().is_none());
+            decoder.receive(part).unwrap();
+        }
+        assert_eq!(decoder.message().unwrap().unwrap(), message);
+
+        // skipping some simple parts, recovered from the mixed ones
+        let mut decoder = Decoder::default();
+        for part in parts.iter().skip(3).step_by(2).chain(parts[10..].iter()) {
+            decoder.receive(part).unwrap();
+        }
+        assert_eq!(decoder.progress(), (10, 10));
+        assert_eq!(decoder.message().unwrap().unwrap(), message);
+
+        let mut other = Encoder::new(&message[1..], 100).unwrap();
+        assert!(decoder.receive(&other.next_part()).is_err());
+        let mut corrupted = Decoder::default();
+        for part in parts[..10].iter() {
+            let mut part = part.clone();
+            part.data[0] ^= 1;
+            corrupted.receive(&part).unwrap();
+        }
+        assert!(corrupted.message().is_err());
+
+        let invalid = |f: &dyn Fn(&mut Part)| {
+            let mut part = parts[0].clone();
+            f(&mut part);
+            assert!(Part::from_cbor(&part.to_cbor()).is_err());
+            assert!(Decoder::default().receive(&part).is_err());
+        };
+        invalid(&|p| p.seq_num = 0);
+        invalid(&|p| p.seq_len = 0);
+        invalid(&|p| p.seq_len = MAX_SEQ_LEN + 1);
+        invalid(&|p| p.seq_len = 11);
+        invalid(&|p| p.message_len = MAX_MESSAGE_LEN + 1);
+        invalid(&|p| p.message_len = 1001);
+        invalid(&|p| p.data.clear());
+        invalid(&|p| {
+            // consistent lengths, but too many fragments
+            p.seq_len = MAX_SEQ_LEN + 1;
+            p.message_len = MAX_SEQ_LEN + 1;
+            p.data = vec![0];
+        });
+        assert!(Encoder::new(&vec![0u8; MAX_MESSAGE_LEN + 1], 1000).is_err());
+        assert!(Encoder::new(
+            &vec![0u8; (MAX_SEQ_LEN + 1) * MIN_FRAGMENT_LEN],
+            MIN_FRAGMENT_LEN
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_fountain_reference_vectors() {
+        // `make_message_ur(256)` encoded with fragments of 30 bytes, from the reference
+        // implementation tests
+        let expected = [
+            "ur:bytes/1-9/lpadascfadaxcywenbpljkhdcahkadaemejtswhhylkepmykhhtsytsnoyoyaxaedsuttydmmhhpktpmsrjtdkgslpgh",
+            "ur:bytes/2-9/lpaoascfadaxcywenbpljkhdcagwdpfnsboxgwlbaawzuefywkdplrsrjynbvygabwjldapfcsgmghhkhstlrdcxaefz",
+            "ur:bytes/3-9/lpaxascfadaxcywenbpljkhdcahelbknlkuejnbadmssfhfrdpsbiegecpasvssovlgeykssjykklronvsjksopdzmol",
+            "ur:bytes/4-9/lpaaascfadaxcywenbpljkhdcasotkhemthydawydtaxneurlkosgwcekonertkbrlwmplssjtammdplolsbrdzcrtas",
+            "ur:bytes/5-9/lpahascfadaxcywenbpljkhdcatbbdfmssrkzmcwnezelennjpfzbgmuktrhtejscktelgfpdlrkfyfwdajldejokbwf",
+            "ur:bytes/6-9/lpamascfadaxcywenbpljkhdcackjlhkhybssklbwefectpfnbbectrljectpavyrolkzczcpkmwidmwoxkilghdsowp",
+            "ur:bytes/7-9/lpatascfadaxcywenbpljkhdcavszmwnjkwtclrtvaynhpahrtoxmwvwatmedibkaegdosftvandiodagdhthtrlnnhy",
+            "ur:bytes/8-9/lpayascfadaxcywenbpljkhdcadmsponkkbbhgsoltjntegepmttmoonftnbuoiyrehfrtsabzsttorodklubbuyaetk",
+            "ur:bytes/9-9/lpasascfadaxcywenbpljkhdcajskecpmdckihdyhphfotjojtfmlnwmadspaxrkytbztpbauotbgtgtaeaevtgavtny",
+            "ur:bytes/10-9/lpbkascfadaxcywenbpljkhdcahkadaemejtswhhylkepmykhhtsytsnoyoyaxaedsuttydmmhhpktpmsrjtwdkiplzs",
+            "ur:bytes/11-9/lpbdascfadaxcywenbpljkhdcahelbknlkuejnbadmssfhfrdpsbiegecpasvssovlgeykssjykklronvsjkvetiiapk",
+            "ur:bytes/12-9/lpbnascfadaxcywenbpljkhdcarllaluzmdmgstospeyiefmwejlwtpedamktksrvlcygmzemovovllarodtmtbnptrs",
+            "ur:bytes/13-9/lpbtascfadaxcywenbpljkhdcamtkgtpknghchchyketwsvwgwfdhpgmgtylctotzopdrpayoschcmhplffziachrfgd",
+            "ur:bytes/14-9/lpbaascfadaxcywenbpljkhdcapazewnvonnvdnsbyleynwtnsjkjndeoldydkbkdslgjkbbkortbelomueekgvstegt",
+            "ur:bytes/15-9/lpbsascfadaxcywenbpljkhdcaynmhpddpzmversbdqdfyrehnqzlugmjzmnmtwmrouohtstgsbsahpawkditkckynwt",
+            "ur:bytes/16-9/lpbeascfadaxcywenbpljkhdcawygekobamwtlihsnpalnsghenskkiynthdzotsimtojetprsttmukirlrsbtamjtpd",
+            "ur:bytes/17-9/lpbyascfadaxcywenbpljkhdcamklgftaxykpewyrtqzhydntpnytyisincxmhtbceaykolduortotiaiaiafhiaoyce",
+            "ur:bytes/18-9/lpbgascfadaxcywenbpljkhdcahkadaemejtswhhylkepmykhhtsytsnoyoyaxaedsuttydmmhhpktpmsrjtntwkbkwy",
+            "ur:bytes/19-9/lpbwascfadaxcywenbpljkhdcadekicpaajootjzpsdrbalpeywllbdsnbinaerkurspbncxgslgftvtsrjtksplcpeo",
+            "ur:bytes/20-9/lpbbascfadaxcywenbpljkhdcayapmrleeleaxpasfrtrdkncffwjyjzgyetdmlewtkpktgllepfrltataztksmhkbot",
+        ];
+        let mut rng = Xoshiro256::new(b"Wolf");
+        let message: Vec<u8> = (0..256).map(|_| rng.next_int(0, 255) as u8).collect();
+        let ur = Ur {
+            ur_type: BYTES.to_string(),
+            cbor: Cbor::Bytes(message).encode(),
+        };
+        let mut encoder = UrEncoder::new(&ur, 30).unwrap();
+        let parts: Vec<String> = (0..expected.len()).map(|_| encoder.next_part()).collect();
+        assert_eq!(parts, expected.to_vec());
+
+        assert_eq!(decode(&parts[..9]).unwrap(), ur);
+        assert_eq!(
+            decode(&parts[1..]).unwrap(),
+            ur,
+            "recovered from the mixed parts"
+        );
+        assert!(decode(&parts[1..9]).is_err());
+
+        // seq_num 0 is refused instead of underflowing the fragment index
+        let mut part = Part::from_cbor(&bytewords::decode(&parts[0][13..]).unwrap()).unwrap();
+        part.seq_num = 0;
+        let zero = format!("ur:bytes/0-9/{}", bytewords::encode(&part.to_cbor()));
+        let mut decoder = UrDecoder::default();
+        assert!(decoder.receive(&zero).is_err());
+        for part in parts[1..9].iter() {
+            decoder.receive(part).unwrap();
+        }
+        assert!(decoder.result().unwrap().is_none());
+    }
+}
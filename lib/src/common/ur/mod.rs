@@ -0,0 +1,534 @@
+//! Blockchain Commons Uniform Resources (BCR-2020-005), `ur:type/...` strings of CBOR payloads
+//! encoded in bytewords, multi-part with fountain codes when the payload doesn't fit in one QR code.
+//! Supported types are `crypto-psbt`, `crypto-account` and `crypto-output` (BCR-2020-006, 010,
+//! 015), understood by Sparrow, Keystone, Passport and other UR wallets, and `bytes` used for data
+//! too big for structured append QR codes
+
+use crate::common::ur::cbor::Cbor;
+use crate::common::ur::fountain::Part;
+use crate::offline::descriptor::{multisig, with_checksum};
+use crate::*;
+use bitcoin::util::bip32::{ChainCode, ChildNumber, DerivationPath, ExtendedPubKey, Fingerprint};
+use bitcoin::Network;
+use miniscript::descriptor::{DescriptorPublicKey, DescriptorXPub};
+
+pub mod bytewords;
+pub mod cbor;
+pub mod fountain;
+
+pub const CRYPTO_PSBT: &str = "crypto-psbt";
+pub const CRYPTO_ACCOUNT: &str = "crypto-account";
+pub const CRYPTO_OUTPUT: &str = "crypto-output";
+pub const BYTES: &str = "bytes";
+
+const TAG_HDKEY: u64 = 303;
+const TAG_KEYPATH: u64 = 304;
+const TAG_COIN_INFO: u64 = 305;
+const TAG_ECKEY: u64 = 306;
+const TAG_SH: u64 = 400;
+const TAG_WSH: u64 = 401;
+const TAG_PKH: u64 = 403;
+const TAG_WPKH: u64 = 404;
+const TAG_MULTI: u64 = 406;
+const TAG_SORTED_MULTI: u64 = 407;
+const TAG_COSIGNER: u64 = 410;
+
+/// A decoded UR, its type and CBOR payload
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ur {
+    pub ur_type: String,
+    pub cbor: Vec<u8>,
+}
+
+/// Encodes a UR in parts of at most `max_fragment_len` payload bytes, a single `ur:type/payload`
+/// if it fits, otherwise `ur:type/seq-len/part` parts
+pub struct UrEncoder {
+    ur_type: String,
+    encoder: fountain::Encoder,
+}
+
+impl UrEncoder {
+    pub fn new(ur: &Ur, max_fragment_len: usize) -> Result<Self> {
+        check_type(&ur.ur_type)?;
+        Ok(UrEncoder {
+            ur_type: ur.ur_type.clone(),
+            encoder: fountain::Encoder::new(&ur.cbor, max_fragment_len)?,
+        })
+    }
+
+    pub fn seq_len(&self) -> usize {
+        self.encoder.seq_len()
+    }
+
+    pub fn next_part(&mut self) -> String {
+        let part = self.encoder.next_part();
+        if part.seq_len == 1 {
+            format!("ur:{}/{}", self.ur_type, bytewords::encode(&part.data))
+        } else {
+            format!(
+                "ur:{}/{}-{}/{}",
+                self.ur_type,
+                part.seq_num,
+                part.seq_len,
+                bytewords::encode(&part.to_cbor())
+            )
+        }
+    }
+}
+
+/// Collects the parts of a UR, in any order and possibly with duplicates and missing frames
+#[derive(Default)]
+pub struct UrDecoder {
+    ur_type: Option<String>,
+    decoder: fountain::Decoder,
+    single: Option<Vec<u8>>,
+}
+
+impl UrDecoder {
+    /// Add a part, case insensitive since QR codes use uppercase to be encoded alphanumeric
+    pub fn receive(&mut self, part: &str) -> Result<()> {
+        let part = part.trim().to_lowercase();
+        if !part.starts_with("ur:") {
+            return Err(Error::UrInvalid("missing ur: prefix".to_string()));
+        }
+        let rest = &part[3..];
+        let components: Vec<&str> = rest.split('/').collect();
+        let ur_type = components[0];
+        check_type(ur_type)?;
+        match &self.ur_type {
+            Some(t) if t != ur_type => {
+                return Err(Error::UrUnexpectedType(t.clone(), ur_type.to_string()))
+            }
+            _ => self.ur_type = Some(ur_type.to_string()),
+        }
+        match components.len() {
+            2 => self.single = Some(bytewords::decode(components[1])?),
+            3 => {
+                let fountain_part = Part::from_cbor(&bytewords::decode(components[2])?)?;
+                let expected = format!("{}-{}", fountain_part.seq_num, fountain_part.seq_len);
+                if components[1] != expected {
+                    return Err(Error::UrInvalid(format!(
+                        "sequence {} differs from the encoded {}",
+                        components[1], expected
+                    )));
+                }
+                self.decoder.receive(&fountain_part)?;
+            }
+            _ => return Err(Error::UrInvalid(format!("invalid ur {}", part))),
+        }
+        Ok(())
+    }
+
+    /// Fragments recovered and total fragments of a multi-part UR
+    pub fn progress(&self) -> (usize, usize) {
+        self.decoder.progress()
+    }
+
+    /// The UR if all the parts needed have been received
+    pub fn result(&self) -> Result<Option<Ur>> {
+        let cbor = match &self.single {
+            Some(cbor) => Some(cbor.clone()),
+            None => self.decoder.message()?,
+        };
+        Ok(cbor.map(|cbor| Ur {
+            ur_type: self.ur_type.clone().unwrap_or_default(),
+            cbor,
+        }))
+    }
+}
+
+/// Decode a UR from all of its parts
+pub fn decode(parts: &[String]) -> Result<Ur> {
+    let mut decoder = UrDecoder::default();
+    for part in parts {
+        decoder.receive(part)?;
+    }
+    decoder.result()?.ok_or_else(|| {
+        let (received, total) = decoder.progress();
+        Error::UrMissingParts(received, total)
+    })
+}
+
+fn check_type(ur_type: &str) -> Result<()> {
+    let valid = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-';
+    if ur_type.is_empty() || !ur_type.chars().all(valid) {
+        return Err(Error::UrInvalid(format!("invalid ur type {}", ur_type)));
+    }
+    Ok(())
+}
+
+impl Ur {
+    fn check_type(&self, expected: &str) -> Result<()> {
+        if self.ur_type != expected {
+            return Err(Error::UrUnexpectedType(
+                expected.to_string(),
+                self.ur_type.clone(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// `crypto-psbt` of the serialized `psbt`
+    pub fn from_psbt(psbt: &[u8]) -> Self {
+        Ur {
+            ur_type: CRYPTO_PSBT.to_string(),
+            cbor: Cbor::Bytes(psbt.to_vec()).encode(),
+        }
+    }
+
+    /// The serialized PSBT of a `crypto-psbt`
+    pub fn to_psbt(&self) -> Result<Vec<u8>> {
+        self.check_type(CRYPTO_PSBT)?;
+        Ok(Cbor::decode(&self.cbor)?.as_bytes()?.to_vec())
+    }
+
+    /// `bytes` UR of arbitrary data
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Ur {
+            ur_type: BYTES.to_string(),
+            cbor: Cbor::Bytes(bytes.to_vec()).encode(),
+        }
+    }
+
+    /// The data of a `bytes` UR
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        self.check_type(BYTES)?;
+        Ok(Cbor::decode(&self.cbor)?.as_bytes()?.to_vec())
+    }
+
+    /// `crypto-account` of a BIP48 native segwit multisig account, as exported by hardware
+    /// signers for multisig coordinators
+    pub fn from_account(key: &PublicMasterKey) -> Result<Self> {
+        let origin = key.key_origin();
+        let hdkey = hdkey(key, None)?;
+        let account = Cbor::Map(vec![
+            (
+                1,
+                Cbor::Uint(u32::from_be_bytes(origin.fingerprint.to_bytes()) as u64),
+            ),
+            (
+                2,
+                Cbor::Array(vec![Cbor::tag(
+                    TAG_WSH,
+                    Cbor::tag(TAG_COSIGNER, Cbor::tag(TAG_HDKEY, hdkey)),
+                )]),
+            ),
+        ]);
+        Ok(Ur {
+            ur_type: CRYPTO_ACCOUNT.to_string(),
+            cbor: account.encode(),
+        })
+    }
+
+    /// The master fingerprint and the output descriptors of a `crypto-account`, keys used in
+    /// multisig are in `cosigner()` expressions
+    pub fn to_account(&self) -> Result<(Fingerprint, Vec<String>)> {
+        self.check_type(CRYPTO_ACCOUNT)?;
+        let cbor = Cbor::decode(&self.cbor)?;
+        let fingerprint = fingerprint(required(cbor.get(1)?)?)?;
+        let descriptors = required(cbor.get(2)?)?
+            .as_array()?
+            .iter()
+            .map(script_expression)
+            .collect::<Result<_>>()?;
+        Ok((fingerprint, descriptors))
+    }
+
+    /// `crypto-output` of the main descriptor of a multisig wallet
+    pub fn from_wallet(wallet: &WalletJson) -> Result<Self> {
+        let multisig = multisig(&wallet.descriptor_main)?
+            .ok_or("crypto-output supports only multisig wallets")?;
+        let keys = multisig
+            .keys
+            .iter()
+            .map(|key| Ok(Cbor::tag(TAG_HDKEY, hdkey(key, Some(0))?)))
+            .collect::<Result<_>>()?;
+        let tag = if multisig.sorted {
+            TAG_SORTED_MULTI
+        } else {
+            TAG_MULTI
+        };
+        let multi = Cbor::Map(vec![
+            (1, Cbor::Uint(multisig.required_sig as u64)),
+            (2, Cbor::Array(keys)),
+        ]);
+        Ok(Ur {
+            ur_type: CRYPTO_OUTPUT.to_string(),
+            cbor: Cbor::tag(TAG_WSH, Cbor::tag(tag, multi)).encode(),
+        })
+    }
+
+    /// The output descriptor, with checksum, of a `crypto-output`
+    pub fn to_descriptor(&self) -> Result<String> {
+        self.check_type(CRYPTO_OUTPUT)?;
+        with_checksum(&script_expression(&Cbor::decode(&self.cbor)?)?)
+    }
+}
+
+fn required(value: Option<&Cbor>) -> Result<&Cbor> {
+    value.ok_or_else(|| Error::UrInvalid("missing required field".to_string()))
+}
+
+fn fingerprint(value: &Cbor) -> Result<Fingerprint> {
+    let value = value.as_uint()?;
+    if value > u32::max_value() as u64 {
+        return Err(Error::UrInvalid("fingerprint overflows".to_string()));
+    }
+    Ok(Fingerprint::from(&(value as u32).to_be_bytes()[..]))
+}
+
+/// `crypto-hdkey` of `key` with its origin, when `int_or_ext` is given the children are
+/// `int_or_ext/*`
+fn hdkey(key: &PublicMasterKey, int_or_ext: Option<u32>) -> Result<Cbor> {
+    let origin = key.key_origin();
+    let xpub = &key.xpub;
+    let network = match xpub.network {
+        Network::Bitcoin => 0,
+        _ => 1,
+    };
+    let mut origin_map = vec![(1, keypath_components(origin.path.as_ref(), false))];
+    // the source fingerprint is omitted for keys without origin, so that they round trip as such
+    if key.origin.is_some() {
+        origin_map.push((
+            2,
+            Cbor::Uint(u32::from_be_bytes(origin.fingerprint.to_bytes()) as u64),
+        ));
+    }
+    origin_map.push((3, Cbor::Uint(xpub.depth as u64)));
+    let mut map = vec![
+        (3, Cbor::Bytes(xpub.public_key.to_bytes())),
+        (4, Cbor::Bytes(xpub.chain_code.as_bytes().to_vec())),
+        (
+            5,
+            Cbor::tag(TAG_COIN_INFO, Cbor::Map(vec![(2, Cbor::Uint(network))])),
+        ),
+        (6, Cbor::tag(TAG_KEYPATH, Cbor::Map(origin_map))),
+    ];
+    if let Some(int_or_ext) = int_or_ext {
+        let children = [ChildNumber::from_normal_idx(int_or_ext)?];
+        map.push((
+            7,
+            Cbor::tag(
+                TAG_KEYPATH,
+                Cbor::Map(vec![(1, keypath_components(&children, true))]),
+            ),
+        ));
+    }
+    map.push((
+        8,
+        Cbor::Uint(u32::from_be_bytes(xpub.parent_fingerprint.to_bytes()) as u64),
+    ));
+    Ok(Cbor::Map(map))
+}
+
+/// keypath components as pairs of index and hardened flag, an empty array is a wildcard
+fn keypath_components(path: &[ChildNumber], wildcard: bool) -> Cbor {
+    let mut components = vec![];
+    for child in path {
+        let (index, hardened) = match *child {
+            ChildNumber::Normal { index } => (index, false),
+            ChildNumber::Hardened { index } => (index, true),
+        };
+        components.push(Cbor::Uint(index as u64));
+        components.push(Cbor::Bool(hardened));
+    }
+    if wildcard {
+        components.push(Cbor::Array(vec![]));
+        components.push(Cbor::Bool(false));
+    }
+    Cbor::Array(components)
+}
+
+/// Derivation path and wildcard flag of a `crypto-keypath`
+fn keypath(value: &Cbor) -> Result<(DerivationPath, bool)> {
+    let components = required(value.untag(TAG_KEYPATH)?.get(1)?)?.as_array()?;
+    if components.len() % 2 != 0 {
+        return Err(Error::UrInvalid(
+            "keypath components are not pairs".to_string(),
+        ));
+    }
+    let mut path = vec![];
+    let mut wildcard = false;
+    for pair in components.chunks(2) {
+        if wildcard {
+            return Err(Error::UrInvalid("wildcard must be the last".to_string()));
+        }
+        let hardened = pair[1].as_bool()?;
+        match &pair[0] {
+            Cbor::Array(a) if a.is_empty() && !hardened => wildcard = true,
+            index => {
+                let index = index.as_uint()?;
+                if index >= 1 << 31 {
+                    return Err(Error::UrInvalid("keypath index overflows".to_string()));
+                }
+                path.push(if hardened {
+                    ChildNumber::from_hardened_idx(index as u32)?
+                } else {
+                    ChildNumber::from_normal_idx(index as u32)?
+                });
+            }
+        }
+    }
+    Ok((path.into(), wildcard))
+}
+
+/// Descriptor key of a `crypto-hdkey` or `crypto-eckey`
+fn descriptor_key(value: &Cbor) -> Result<String> {
+    if let Ok(eckey) = value.untag(TAG_ECKEY) {
+        return Ok(hex::encode(required(eckey.get(3)?)?.as_bytes()?));
+    }
+    let hdkey = value.untag(TAG_HDKEY)?;
+    if hdkey.get(2)?.map(Cbor::as_bool).transpose()? == Some(true) {
+        return Err(Error::UrInvalid(
+            "private keys are not supported".to_string(),
+        ));
+    }
+    let network = match hdkey.get(5)? {
+        Some(info) => match info.untag(TAG_COIN_INFO)?.get(2)? {
+            Some(network) if network.as_uint()? != 0 => Network::Testnet,
+            _ => Network::Bitcoin,
+        },
+        None => Network::Bitcoin,
+    };
+    let (origin_path, origin) = match hdkey.get(6)? {
+        Some(origin) => {
+            let (path, _) = keypath(origin)?;
+            let source = origin.untag(TAG_KEYPATH)?.get(2)?;
+            let source = source.map(fingerprint).transpose()?;
+            (path.clone(), source.map(|fingerprint| (fingerprint, path)))
+        }
+        None => (vec![].into(), None),
+    };
+    let (derivation_path, is_wildcard) = match hdkey.get(7)? {
+        Some(children) => keypath(children)?,
+        None => (vec![].into(), false),
+    };
+    let key_data = required(hdkey.get(3)?)?.as_bytes()?;
+    let chain_code = required(hdkey.get(4)?)?.as_bytes()?;
+    if chain_code.len() != 32 {
+        return Err(Error::UrInvalid("chain code must be 32 bytes".to_string()));
+    }
+    let parent_fingerprint = match hdkey.get(8)? {
+        Some(fp) => fingerprint(fp)?,
+        None => Fingerprint::default(),
+    };
+    let origin_path: &[ChildNumber] = origin_path.as_ref();
+    let xpub = ExtendedPubKey {
+        network,
+        depth: origin_path.len() as u8,
+        parent_fingerprint,
+        child_number: origin_path
+            .last()
+            .cloned()
+            .unwrap_or(ChildNumber::Normal { index: 0 }),
+        public_key: bitcoin::PublicKey::from_slice(key_data)?,
+        chain_code: ChainCode::from(chain_code),
+    };
+    let key = DescriptorPublicKey::XPub(DescriptorXPub {
+        origin,
+        xpub,
+        derivation_path,
+        is_wildcard,
+    });
+    Ok(key.to_string())
+}
+
+/// Descriptor of a script expression of a `crypto-output`
+fn script_expression(value: &Cbor) -> Result<String> {
+    let (tag, inner) = match value {
+        Cbor::Tag(tag, inner) => (*tag, inner.as_ref()),
+        _ => return Err(Error::UrInvalid("expected script expression".to_string())),
+    };
+    let wrap = |name: &str| Ok(format!("{}({})", name, script_expression(inner)?));
+    let key = |name: &str| Ok(format!("{}({})", name, descriptor_key(inner)?));
+    match tag {
+        TAG_SH => wrap("sh"),
+        TAG_WSH => wrap("wsh"),
+        TAG_PKH => key("pkh"),
+        TAG_WPKH => key("wpkh"),
+        TAG_COSIGNER => key("cosigner"),
+        TAG_MULTI | TAG_SORTED_MULTI => {
+            let threshold = required(inner.get(1)?)?.as_uint()?;
+            let keys = required(inner.get(2)?)?
+                .as_array()?
+                .iter()
+                .map(descriptor_key)
+                .collect::<Result<Vec<_>>>()?;
+            let name = if tag == TAG_MULTI {
+                "multi"
+            } else {
+                "sortedmulti"
+            };
+            Ok(format!("{}({},{})", name, threshold, keys.join(",")))
+        }
+        _ => Err(Error::UrInvalid(format!(
+            "unsupported script expression tag {}",
+            tag
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::mnemonic::Mnemonic;
+    use crate::common::ur::*;
+
+    fn account(entropy: u8) -> PublicMasterKey {
+        let mnemonic = Mnemonic::new(&[entropy; 16]).unwrap();
+        let key = PrivateMasterKey::new(Network::Testnet, &mnemonic, None, "ur").unwrap();
+        key.bip48_account(0).unwrap()
+    }
+
+    #[test]
+    fn test_ur() {
+        let ur = Ur {
+            ur_type: "bytes".to_string(),
+            cbor: Cbor::Bytes(vec![1, 2, 3]).encode(),
+        };
+        let mut encoder = UrEncoder::new(&ur, 100).unwrap();
+        let single = encoder.next_part();
+        assert_eq!(single, format!("ur:bytes/{}", bytewords::encode(&ur.cbor)));
+        assert_eq!(decode(&[single.to_uppercase()]).unwrap(), ur);
+
+        let ur = Ur::from_psbt(&[7u8; 500]);
+        let mut encoder = UrEncoder::new(&ur, 100).unwrap();
+        assert_eq!(encoder.seq_len(), 6);
+        let parts: Vec<String> = (0..12).map(|_| encoder.next_part()).collect();
+        assert!(parts[0].starts_with("ur:crypto-psbt/1-6/"));
+        assert!(parts[11].starts_with("ur:crypto-psbt/12-6/"));
+        assert_eq!(decode(&parts).unwrap().to_psbt().unwrap(), vec![7u8; 500]);
+        assert_eq!(
+            decode(&parts[1..]).unwrap(),
+            ur,
+            "recovered from a mixed part"
+        );
+        assert!(decode(&parts[..5]).is_err());
+        assert!(decode(&[parts[0].replace("/1-6/", "/2-6/")]).is_err());
+        assert!(decode(&[parts[0].clone(), "ur:bytes/aeadaolazmjendeoti".to_string()]).is_err());
+        assert!(ur.to_descriptor().is_err());
+    }
+
+    #[test]
+    fn test_ur_wallet_and_account() {
+        let (first, second) = (account(1), account(2));
+        let descriptor = format!("wsh(sortedmulti(2,{}/0/*,{}/0/*))", first, second);
+        let wallet = WalletJson {
+            name: "wallet".to_string(),
+            descriptor_main: with_checksum(&descriptor).unwrap(),
+            descriptor_change: with_checksum(&descriptor.replace("/0/*", "/1/*")).unwrap(),
+            fingerprints: Default::default(),
+            required_sig: 2,
+            policy: None,
+            daemon_opts: None,
+            created_at_height: 0,
+        };
+        let ur = Ur::from_wallet(&wallet).unwrap();
+        assert_eq!(ur.to_descriptor().unwrap(), wallet.descriptor_main);
+        assert!(ur.to_account().is_err());
+
+        let ur = Ur::from_account(&first).unwrap();
+        let (fingerprint, descriptors) = ur.to_account().unwrap();
+        assert_eq!(fingerprint, first.key_origin().fingerprint);
+        assert_eq!(descriptors, vec![format!("wsh(cosigner({}))", first)]);
+    }
+}
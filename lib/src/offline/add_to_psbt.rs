@@ -0,0 +1,183 @@
+use crate::online::create_tx::AddressAmount;
+use crate::psbt_v2::{psbt_version, PsbtV2};
+use crate::*;
+use bitcoin::consensus::deserialize;
+use bitcoin::util::psbt;
+use bitcoin::{Network, OutPoint, Transaction, TxOut};
+use log::info;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+/// Add an input and/or an output to a version 2 PSBT (BIP370), for collaborative transactions
+#[derive(StructOpt, Debug)]
+#[structopt(name = "add_to_psbt")]
+pub struct AddToPsbtOptions {
+    /// PSBT json file containing a version 2 PSBT, it's updated with the added input and output
+    #[structopt(parse(from_os_str))]
+    pub psbt_file: PathBuf,
+
+    /// Coin to add as input, specified as txid:vout
+    #[structopt(long, requires = "previous-tx")]
+    pub coin: Option<OutPoint>,
+
+    /// Hex of the transaction creating the coin, saved in the input for the signers
+    #[structopt(long, requires = "coin")]
+    pub previous_tx: Option<String>,
+
+    /// nSequence of the added input
+    #[structopt(long, default_value = "4294967293")]
+    pub sequence: u32,
+
+    /// Block height the transaction locktime must be at least, required by the added input
+    #[structopt(long, requires = "coin")]
+    pub required_height_locktime: Option<u32>,
+
+    /// Timestamp the transaction locktime must be at least, required by the added input
+    #[structopt(long, requires = "coin")]
+    pub required_time_locktime: Option<u32>,
+
+    /// Output to add, with address and amount in satoshi specified as address:amount
+    #[structopt(long)]
+    pub recipient: Option<AddressAmount>,
+}
+
+pub fn start(network: Network, opt: &AddToPsbtOptions) -> Result<ConvertPsbtOutput> {
+    if opt.coin.is_none() && opt.recipient.is_none() {
+        return Err("Nothing to add, specify --coin and/or --recipient".into());
+    }
+    let mut psbt_json = read_psbt_json(&opt.psbt_file)?;
+    let bytes = base64::decode(&psbt_json.psbt)?;
+    let version = psbt_version(&bytes)?;
+    if version != 2 {
+        return Err(format!(
+            "Inputs and outputs can be added only to version 2 PSBTs, found version {}",
+            version
+        )
+        .into());
+    }
+    let mut psbt_v2 = PsbtV2::from_bytes(&bytes)?;
+
+    // input first, with SIGHASH_SINGLE signatures inputs and outputs are added in pairs
+    if let (Some(coin), Some(previous_tx)) = (opt.coin, &opt.previous_tx) {
+        let tx: Transaction = deserialize(&hex::decode(previous_tx)?)?;
+        if tx.txid() != coin.txid {
+            return Err(
+                format!("Previous tx {} is not the one of coin {}", tx.txid(), coin).into(),
+            );
+        }
+        let utxo = tx
+            .output
+            .get(coin.vout as usize)
+            .ok_or_else(|| Error::Generic(format!("Previous tx has no output {}", coin.vout)))?;
+        let input = psbt::Input {
+            witness_utxo: Some(utxo.clone()).filter(|u| u.script_pubkey.is_witness_program()),
+            non_witness_utxo: Some(tx.clone()),
+            ..Default::default()
+        };
+        psbt_v2.add_input(
+            coin,
+            opt.sequence,
+            opt.required_height_locktime,
+            opt.required_time_locktime,
+            &input,
+        )?;
+        info!("added input {}", coin);
+    }
+    if let Some(recipient) = &opt.recipient {
+        if recipient.address.network != network {
+            return Err(format!("Address {} is not of {}", recipient.address, network).into());
+        }
+        let txout = TxOut {
+            value: recipient.amount.as_sat(),
+            script_pubkey: recipient.address.script_pubkey(),
+        };
+        psbt_v2.add_output(txout, &psbt::Output::default())?;
+        info!("added output to {}", recipient.address);
+    }
+
+    let bytes = psbt_v2.to_bytes()?;
+    psbt_json.psbt = base64::encode(&bytes);
+    std::fs::write(&opt.psbt_file, serde_json::to_string_pretty(&psbt_json)?)?;
+    info!("Saving {:?}", opt.psbt_file);
+
+    Ok(ConvertPsbtOutput {
+        psbt: psbt_json.psbt,
+        version,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::offline::add_to_psbt::*;
+    use crate::psbt_v2::{INPUTS_MODIFIABLE, OUTPUTS_MODIFIABLE};
+    use bitcoin::consensus::serialize;
+    use bitcoin::Address;
+    use std::str::FromStr;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_add_to_psbt() {
+        let temp_dir = TempDir::new("test_add_to_psbt").unwrap();
+        let bytes = include_bytes!("../../test_data/sign/psbt_bip.json");
+        let psbt_file = temp_dir.path().join("psbt.json");
+        std::fs::write(&psbt_file, &bytes[..]).unwrap();
+        let psbt = read_psbt(&psbt_file).unwrap();
+        let mut psbt_json = read_psbt_json(&psbt_file).unwrap();
+
+        // a coin of the transaction spent by the first input, other than the spent one
+        let previous_tx = psbt.inputs[0].non_witness_utxo.clone().unwrap();
+        let spent = psbt.global.unsigned_tx.input[0].previous_output;
+        let vout = (0..previous_tx.output.len() as u32)
+            .find(|vout| *vout != spent.vout)
+            .unwrap();
+        let coin = OutPoint::new(previous_tx.txid(), vout);
+        let script = &psbt.global.unsigned_tx.output[0].script_pubkey;
+        let address = Address::from_script(script, Network::Testnet).unwrap();
+        let recipient = AddressAmount::from_str(&format!("{}:1000", address)).unwrap();
+        let mut opt = AddToPsbtOptions {
+            psbt_file: psbt_file.clone(),
+            coin: Some(coin),
+            previous_tx: Some(hex::encode(serialize(&previous_tx))),
+            sequence: 0xffff_fffd,
+            required_height_locktime: None,
+            required_time_locktime: None,
+            recipient: Some(recipient),
+        };
+        assert!(start(Network::Testnet, &opt).is_err(), "version 0");
+
+        let mut psbt_v2 = PsbtV2::from_v0(&psbt).unwrap();
+        psbt_json.psbt = base64::encode(psbt_v2.to_bytes().unwrap());
+        std::fs::write(&psbt_file, serde_json::to_vec(&psbt_json).unwrap()).unwrap();
+        assert!(start(Network::Testnet, &opt).is_err(), "not modifiable");
+
+        psbt_v2.tx_modifiable = Some(INPUTS_MODIFIABLE | OUTPUTS_MODIFIABLE);
+        psbt_json.psbt = base64::encode(psbt_v2.to_bytes().unwrap());
+        std::fs::write(&psbt_file, serde_json::to_vec(&psbt_json).unwrap()).unwrap();
+        assert!(
+            start(Network::Bitcoin, &opt).is_err(),
+            "address of another network"
+        );
+        let output = start(Network::Testnet, &opt).unwrap();
+        assert_eq!(output.version, 2);
+        let added = PsbtV2::from_bytes(&base64::decode(&output.psbt).unwrap()).unwrap();
+        assert_eq!(added.inputs.len(), psbt_v2.inputs.len() + 1);
+        assert_eq!(added.outputs.len(), psbt_v2.outputs.len() + 1);
+        assert_eq!(read_psbt_json(&psbt_file).unwrap().psbt, output.psbt);
+
+        let tx = read_psbt(&psbt_file).unwrap().global.unsigned_tx;
+        assert_eq!(tx.input.last().unwrap().previous_output, coin);
+        assert_eq!(tx.output.last().unwrap().value, 1000);
+        assert!(
+            start(Network::Testnet, &opt).is_err(),
+            "coin already present"
+        );
+
+        opt.coin = Some(spent);
+        assert!(start(Network::Testnet, &opt).is_err(), "wrong previous tx");
+        opt.coin = None;
+        opt.previous_tx = None;
+        start(Network::Testnet, &opt).unwrap();
+        opt.recipient = None;
+        assert!(start(Network::Testnet, &opt).is_err(), "nothing to add");
+    }
+}
@@ -0,0 +1,70 @@
+use crate::psbt_v2::PsbtV2;
+use crate::*;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+/// Convert a PSBT to the given version, for coordinators supporting only one of them
+#[derive(StructOpt, Debug, Serialize, Deserialize)]
+#[structopt(name = "convert_psbt")]
+pub struct ConvertPsbtOptions {
+    /// PSBT json file
+    #[structopt(parse(from_os_str))]
+    pub psbt_file: PathBuf,
+
+    /// Version of the resulting PSBT, 0 or 2 (BIP370)
+    #[structopt(long, default_value = "2")]
+    pub psbt_version: u32,
+}
+
+pub fn start(opt: &ConvertPsbtOptions) -> Result<ConvertPsbtOutput> {
+    let psbt = read_psbt(&opt.psbt_file)?;
+    let bytes = match opt.psbt_version {
+        0 => psbt_to_base64(&psbt).0,
+        2 => PsbtV2::from_v0(&psbt)?.to_bytes()?,
+        version => return Err(Error::PsbtVersion(version)),
+    };
+    Ok(ConvertPsbtOutput {
+        psbt: base64::encode(&bytes),
+        version: opt.psbt_version,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::offline::convert_psbt::*;
+    use crate::psbt_v2::psbt_version;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_convert_psbt() {
+        let temp_dir = TempDir::new("test_convert_psbt").unwrap();
+        let bytes = include_bytes!("../../test_data/sign/psbt_testnet.1.signed.json");
+        let psbt_file = temp_dir.path().join("psbt.json");
+        std::fs::write(&psbt_file, &bytes[..]).unwrap();
+        let psbt = read_psbt(&psbt_file).unwrap();
+
+        let mut opt = ConvertPsbtOptions {
+            psbt_file: psbt_file.clone(),
+            psbt_version: 2,
+        };
+        let output = start(&opt).unwrap();
+        let (v0_bytes, converted) = psbt_from_base64(&output.psbt).unwrap();
+        assert_eq!(converted, psbt);
+        assert_eq!(
+            psbt_version(&base64::decode(&output.psbt).unwrap()).unwrap(),
+            2
+        );
+
+        // a v2 PSBT file is read and converted back
+        let mut psbt_json = read_psbt_json(&psbt_file).unwrap();
+        psbt_json.psbt = output.psbt;
+        std::fs::write(&psbt_file, serde_json::to_vec(&psbt_json).unwrap()).unwrap();
+        opt.psbt_version = 0;
+        let output = start(&opt).unwrap();
+        assert_eq!(base64::decode(&output.psbt).unwrap(), v0_bytes);
+
+        opt.psbt_version = 1;
+        assert!(start(&opt).is_err());
+    }
+}
@@ -0,0 +1,358 @@
+use crate::offline::descriptor::with_checksum;
+use crate::offline::import_wallet::import_wallet;
+use crate::qr::QrStyle;
+use crate::*;
+use bitcoin::util::bip32::ExtendedPubKey;
+use bitcoin::Network;
+use log::debug;
+use miniscript::descriptor::DescriptorPublicKey;
+use miniscript::policy::{Concrete, Liftable};
+use miniscript::{Descriptor, Miniscript, Segwitv0};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use structopt::StructOpt;
+
+/// Create a wallet descriptor from the xpubs of the signers, without a bitcoin node
+#[derive(StructOpt, Debug, Serialize, Deserialize)]
+#[structopt(name = "create_wallet")]
+pub struct CreateWalletOptions {
+    /// Name of the wallet
+    #[structopt(short, long)]
+    pub wallet_name: String,
+
+    #[structopt(flatten)]
+    pub descriptor: WalletDescriptorOptions,
+
+    /// Height of the blockchain when the wallet is created, used as starting point of rescans
+    #[structopt(long, default_value = "0")]
+    pub created_at_height: u64,
+
+    /// QR code max version to use (max size)
+    #[structopt(long, default_value = "14")]
+    pub qr_version: i16,
+
+    #[structopt(flatten)]
+    #[serde(flatten)]
+    pub qr_style: QrStyle,
+}
+
+/// Keys and spending conditions of a wallet, shared by the offline and online `create-wallet`
+#[derive(StructOpt, Debug, Serialize, Deserialize, Clone)]
+pub struct WalletDescriptorOptions {
+    /// number of signatures required, creates a `wsh(multi(r,...))` wallet
+    #[structopt(short, required_unless = "policy")]
+    pub r: Option<usize>,
+
+    /// creates a `wsh(sortedmulti(r,...))` wallet, so that addresses don't depend on the order of
+    /// the xpubs
+    #[structopt(long, requires = "r")]
+    pub sortedmulti: bool,
+
+    /// Miniscript policy of the wallet, alternative to `-r`, keys are referenced by fingerprint or
+    /// xpub, eg. `or(thresh(2,pk(a),pk(b),pk(c)),and(pk(d),older(52560)))` means 2 of 3 now or
+    /// the recovery key d after 52560 blocks
+    #[structopt(long, conflicts_with = "r")]
+    pub policy: Option<String>,
+
+    /// Extended Public Keys (xpub) that are composing the wallet, given as String (xpub... or
+    /// with origin [d34db33f/48'/0'/0'/2']xpub...)
+    #[structopt(long = "xpub")]
+    pub xpubs: Vec<PublicMasterKey>,
+
+    /// Extended Public Keys (xpub) that are composing the wallet, given as a json file
+    #[structopt(long = "xpub-file")]
+    pub xpub_files: Vec<PathBuf>,
+}
+
+impl WalletDescriptorOptions {
+    fn validate(&self, network: Network) -> Result<()> {
+        match (self.r, &self.policy) {
+            (Some(r), None) => {
+                if r == 0 {
+                    return Err("required signatures cannot be 0".into());
+                }
+
+                if r > 15 {
+                    return Err("required signatures cannot be greater than 15".into());
+                }
+
+                if r > (self.xpubs.len() + self.xpub_files.len()) {
+                    return Err(
+                        "required signatures cannot be greater than the number of xpubs".into(),
+                    );
+                }
+            }
+            (None, Some(_)) => (),
+            _ => return Err("one of -r or --policy is required".into()),
+        }
+
+        let mut keys = read_xpubs_files(&self.xpub_files)?;
+        keys.extend(self.xpubs.iter().cloned());
+        let xpubs: Vec<ExtendedPubKey> = keys.iter().map(|k| k.xpub).collect();
+
+        for xpub in xpubs.iter() {
+            if !(network == xpub.network
+                || (network == Network::Regtest && xpub.network == Network::Testnet))
+            {
+                return Err(format!(
+                    "detected xpub of another network (cmd:{}) (xpub:{})",
+                    network, xpub.network
+                )
+                .into());
+            }
+
+            if xpubs.iter().filter(|xpub2| *xpub2 == xpub).count() > 1 {
+                return Err("Cannot use same xpub twice".into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Build the wallet with descriptors, checksums, fingerprints and required signatures
+    pub fn wallet(
+        &self,
+        name: &str,
+        network: Network,
+        created_at_height: u64,
+    ) -> Result<WalletJson> {
+        self.validate(network)?;
+        debug!("building wallet {}", name);
+
+        let mut xpubs = read_xpubs_files(&self.xpub_files)?;
+        xpubs.extend(self.xpubs.iter().cloned());
+
+        let (descriptors, required_sig) = match &self.policy {
+            Some(policy) => policy_descriptors(policy, &xpubs)?,
+            None => {
+                let r = self.r.ok_or("-r is required without --policy")?;
+                (multi_descriptors(r, &xpubs, self.sortedmulti), r)
+            }
+        };
+
+        Ok(WalletJson {
+            name: name.to_string(),
+            descriptor_main: with_checksum(&descriptors[0])?,
+            descriptor_change: with_checksum(&descriptors[1])?,
+            fingerprints: xpubs.iter().map(|x| x.key_origin().fingerprint).collect(),
+            required_sig,
+            policy: self.policy.clone(),
+            daemon_opts: None,
+            created_at_height,
+        })
+    }
+}
+
+pub fn start(
+    datadir: &str,
+    network: Network,
+    opt: &CreateWalletOptions,
+) -> Result<CreateWalletOutput> {
+    let wallet = opt
+        .descriptor
+        .wallet(&opt.wallet_name, network, opt.created_at_height)?;
+    import_wallet(datadir, network, &wallet, opt.qr_version, &opt.qr_style)
+}
+
+fn read_xpubs_files(paths: &[PathBuf]) -> Result<Vec<PublicMasterKey>> {
+    let mut xpubs = vec![];
+    for xpub_path in paths.iter() {
+        let content = fs::read(xpub_path)?;
+        let json: PublicMasterKey = serde_json::from_slice(&content)?;
+        xpubs.push(json.wallet_key()?);
+    }
+    Ok(xpubs)
+}
+
+/// main and change descriptors in the form `wsh(multi(r,[fingerprint/path]xpub/0/*,...))` or
+/// `wsh(sortedmulti(r,[fingerprint/path]xpub/0/*,...))`
+pub fn multi_descriptors(r: usize, xpubs: &[PublicMasterKey], sorted: bool) -> Vec<String> {
+    let multi = if sorted { "sortedmulti" } else { "multi" };
+    let mut descriptors = vec![];
+    for i in 0..=1 {
+        let mut xpub_paths = vec![];
+        for xpub in xpubs.iter() {
+            xpub_paths.push(xpub.descriptor_key(i).to_string())
+        }
+        let descriptor = format!("wsh({}({},{}))", multi, r, xpub_paths.join(","));
+        descriptors.push(descriptor);
+    }
+    descriptors
+}
+
+/// compile `policy` and returns main and change descriptors with the minimum number of
+/// signatures needed to spend
+pub fn policy_descriptors(policy: &str, xpubs: &[PublicMasterKey]) -> Result<(Vec<String>, usize)> {
+    let policy = Concrete::<String>::from_str(policy)?;
+    let compiled: Miniscript<String, Segwitv0> = policy
+        .compile()
+        .map_err(|e| Error::Generic(format!("policy compilation failed: {}", e)))?;
+
+    let find_xpub = |key: &String| -> Result<PublicMasterKey> {
+        xpubs
+            .iter()
+            .find(|k| {
+                [k.key_origin().fingerprint, k.xpub.fingerprint()]
+                    .iter()
+                    .any(|f| &f.to_string() == key)
+                    || &k.xpub.to_string() == key
+                    || &k.to_string() == key
+            })
+            .cloned()
+            .ok_or_else(|| Error::Generic(format!("policy key {} not in the given xpubs", key)))
+    };
+    let used: Vec<PublicMasterKey> = compiled
+        .iter_pkh()
+        .map(|k| find_xpub(&k))
+        .collect::<Result<_>>()?;
+    if let Some(unused) = xpubs.iter().find(|x| !used.contains(x)) {
+        return Err(format!("xpub {} not used in the policy", unused).into());
+    }
+
+    let mut descriptors = vec![];
+    for i in 0..=1 {
+        let mut translate =
+            |key: &String| -> Result<DescriptorPublicKey> { Ok(find_xpub(key)?.descriptor_key(i)) };
+        let mut translate_hash = translate;
+        let miniscript = compiled.translate_pk(&mut translate, &mut translate_hash)?;
+        descriptors.push(Descriptor::Wsh(miniscript).to_string());
+    }
+    let required_sig = compiled.lift()?.minimum_n_keys();
+
+    Ok((descriptors, required_sig))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::offline::create_wallet::*;
+    use crate::offline::descriptor::{
+        extract_keys, extract_xpubs, lift_witness_script, strip_checksum,
+    };
+    use crate::PublicMasterKey;
+    use bitcoin::util::bip32::ChildNumber;
+    use miniscript::descriptor::DescriptorPublicKey;
+    use miniscript::Descriptor;
+    use std::str::FromStr;
+    use tempdir::TempDir;
+
+    const XPUBS: [&str; 4] = [
+        "tpubD6NzVbkrYhZ4XuzR59W1JHQpXcufQVj64NDa4eiALMJxC2xAwpY7wy2J9RVQ7BHDYK3eWrVRsuMUcdwGn9xVBmC9wfpVawpNGLyrdgAhehd",
+        "tpubD6NzVbkrYhZ4WarEBpY5okLrjRQ8sgfoEsxZfprQDEbAjWM585LhNeT9GuSeFRGL7yLheiRgtCQCBb73y21EsLzRfwdrRmfaAT4yUTEKtu7",
+        "tpubD6NzVbkrYhZ4WRwbTYgdGDMxPUzq5WwX8HwnAR6PYB291uUH63pCU1WFV6RRWGyA2Xy8okiFAqfAXEErx1SVh7mKSVQa34hFaa8GcmuEeds",
+        "tpubD6NzVbkrYhZ4YkVm13NDmMPEHEWXHoqGXBPCrtHbB1hE6GoTjdvXEKrtRBMtSe4gQQUSyvU78jgyrK5AfwLewr1cTkkojQbYTuyNtgQFEDb",
+    ];
+
+    #[test]
+    fn test_multi_descriptors() {
+        let with_origin = format!("[d34db33f/48'/1'/0'/2']{}", XPUBS[1]);
+        let keys: Vec<PublicMasterKey> = [XPUBS[0], &with_origin]
+            .iter()
+            .map(|x| PublicMasterKey::from_str(x).unwrap())
+            .collect();
+        let fingerprint = keys[0].xpub.fingerprint();
+        let descriptors = multi_descriptors(2, &keys, false);
+        assert_eq!(
+            descriptors[0],
+            format!(
+                "wsh(multi(2,[{}]{}/0/*,{}/0/*))",
+                fingerprint, XPUBS[0], with_origin
+            )
+        );
+        let descriptors = multi_descriptors(2, &keys, true);
+        assert_eq!(
+            descriptors[1],
+            format!(
+                "wsh(sortedmulti(2,[{}]{}/1/*,{}/1/*))",
+                fingerprint, XPUBS[0], with_origin
+            )
+        );
+        let extracted = extract_keys(&descriptors[1]).unwrap();
+        assert_eq!(extracted[0].key_origin(), keys[0].key_origin());
+        assert_eq!(extracted[1], keys[1]);
+    }
+
+    #[test]
+    fn test_policy_descriptors() {
+        let keys: Vec<PublicMasterKey> = XPUBS
+            .iter()
+            .map(|x| PublicMasterKey::from_str(x).unwrap())
+            .collect();
+        let xpubs: Vec<ExtendedPubKey> = keys.iter().map(|k| k.xpub).collect();
+        let f: Vec<String> = xpubs.iter().map(|x| x.fingerprint().to_string()).collect();
+        let policy = format!(
+            "or(99@thresh(2,pk({}),pk({}),pk({})),and(pk({}),older(52560)))",
+            f[0], f[1], f[2], XPUBS[3]
+        );
+        let (descriptors, required_sig) = policy_descriptors(&policy, &keys).unwrap();
+        assert_eq!(required_sig, 1);
+        assert!(descriptors[0].starts_with("wsh("));
+        assert!(descriptors[0].contains(&format!("[{}]", f[0])));
+        assert!(descriptors[0].contains("/0/*"));
+        assert!(descriptors[1].contains("/1/*"));
+        assert!(descriptors[0].contains("older(52560)"));
+        assert_eq!(extract_xpubs(&descriptors[0]).unwrap(), xpubs);
+
+        let descriptor = Descriptor::<DescriptorPublicKey>::from_str(&descriptors[0]).unwrap();
+        let witness_script = descriptor
+            .derive(ChildNumber::from_normal_idx(0).unwrap())
+            .witness_script();
+        let policy = lift_witness_script(&witness_script).unwrap();
+        assert_eq!(policy.relative_timelocks(), vec![52560]);
+
+        let policy = format!("thresh(2,pk({}),pk({}),pk({}))", f[0], f[1], f[2]);
+        assert!(policy_descriptors(&policy, &keys).is_err()); // xpub not used
+        assert!(policy_descriptors(&policy, &keys[..3]).is_ok());
+        let policy = "thresh(2,pk(a),pk(b))";
+        assert!(policy_descriptors(policy, &keys).is_err()); // unknown keys
+    }
+
+    #[test]
+    fn test_create_wallet() {
+        let temp_dir = TempDir::new("test_create_wallet").unwrap();
+        let temp_dir_str = format!("{}/", temp_dir.path().display());
+        let xpub_file = temp_dir.path().join("public.json");
+        let key = PublicMasterKey::from_str(XPUBS[2]).unwrap();
+        fs::write(&xpub_file, serde_json::to_vec(&key).unwrap()).unwrap();
+
+        let mut opt = CreateWalletOptions {
+            wallet_name: "offline".to_string(),
+            descriptor: WalletDescriptorOptions {
+                r: Some(2),
+                sortedmulti: true,
+                policy: None,
+                xpubs: XPUBS[..2]
+                    .iter()
+                    .map(|x| PublicMasterKey::from_str(x).unwrap())
+                    .collect(),
+                xpub_files: vec![xpub_file],
+            },
+            created_at_height: 0,
+            qr_version: 14,
+            qr_style: QrStyle::default(),
+        };
+        let created = start(&temp_dir_str, Network::Testnet, &opt).unwrap();
+        let wallet = &created.wallet;
+        assert_eq!(wallet.required_sig, 2);
+        assert_eq!(wallet.fingerprints.len(), 3);
+        assert!(wallet.daemon_opts.is_none());
+        assert!(wallet.descriptor_main.starts_with("wsh(sortedmulti(2,"));
+        assert!(wallet.descriptor_main.contains('#'));
+        assert!(strip_checksum(&wallet.descriptor_change).is_ok());
+        // keys from files come first
+        assert_eq!(
+            extract_keys(&wallet.descriptor_main).unwrap()[0].xpub,
+            key.xpub
+        );
+        assert_eq!(read_wallet(&created.wallet_file).unwrap(), *wallet);
+        assert!(!created.qr_files.is_empty());
+
+        // the same name can't be used twice
+        assert!(start(&temp_dir_str, Network::Testnet, &opt).is_err());
+        opt.wallet_name = "mainnet".to_string();
+        assert!(start(&temp_dir_str, Network::Bitcoin, &opt).is_err());
+        opt.descriptor.r = Some(4);
+        assert!(start(&temp_dir_str, Network::Testnet, &opt).is_err());
+    }
+}
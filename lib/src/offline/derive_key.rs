@@ -1,5 +1,7 @@
+use crate::common::encryption;
 use crate::file::save_keys;
 use crate::offline::sign::read_key;
+use crate::qr::QrStyle;
 use crate::{MasterKeyOutput, PrivateMasterKey};
 use bitcoin::secp256k1::Secp256k1;
 use bitcoin::util::bip32::ChildNumber;
@@ -22,6 +24,25 @@ pub struct DeriveKeyOptions {
     /// QR code max version to use (max size)
     #[structopt(long, default_value = "14")]
     pub qr_version: i16,
+
+    #[structopt(flatten)]
+    pub qr_style: QrStyle,
+
+    /// File containing the passphrase to decrypt the master^2 key, if given it is also used to encrypt
+    /// the generated key
+    #[structopt(
+        long = "encryption-passphrase-file",
+        parse(try_from_os_str = encryption::read_passphrase_file)
+    )]
+    pub encryption_passphrase: Option<String>,
+
+    /// File containing the BIP39 passphrase of the master^2 key, required if it has been created
+    /// with a passphrase
+    #[structopt(
+        long = "bip39-passphrase-file",
+        parse(try_from_os_str = encryption::read_passphrase_file)
+    )]
+    pub bip39_passphrase: Option<String>,
 }
 
 pub fn start(
@@ -33,7 +54,12 @@ pub fn start(
         return Err("--to-key-name must have 1 or more characters".into());
     }
     let secp = Secp256k1::signing_only();
-    let from_key_json = read_key(&opt.from_key_file)?;
+    let passphrase = opt.encryption_passphrase.as_deref();
+    let from_key_json = read_key(
+        &opt.from_key_file,
+        passphrase,
+        opt.bip39_passphrase.as_deref(),
+    )?;
     let mut child_key = from_key_json.xprv;
     let bytes = opt.to_key_name.as_bytes();
     for byte in bytes {
@@ -48,6 +74,8 @@ pub fn start(
         &opt.to_key_name,
         child_key_json,
         opt.qr_version,
+        &opt.qr_style,
+        passphrase,
     )?;
 
     Ok(output)
@@ -57,6 +85,7 @@ pub fn start(
 mod tests {
     use crate::offline::derive_key::DeriveKeyOptions;
     use crate::offline::random::RandomOptions;
+    use crate::qr::QrStyle;
     use bitcoin::Network;
     use tempdir::TempDir;
 
@@ -75,6 +104,9 @@ mod tests {
             from_key_file: key.private_file.clone(),
             to_key_name,
             qr_version: 14,
+            qr_style: QrStyle::default(),
+            encryption_passphrase: None,
+            bip39_passphrase: None,
         };
         let derived =
             crate::offline::derive_key::start(&temp_dir_str, Network::Testnet, &der_opts.clone())
@@ -1,11 +1,15 @@
 use crate::*;
-use bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPubKey};
-use bitcoin::Network;
+use bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPubKey, Fingerprint};
+use bitcoin::{Network, PublicKey, Script, Transaction};
 use miniscript::descriptor::DescriptorPublicKey;
-use miniscript::Descriptor;
+use miniscript::policy::{Liftable, Semantic};
+use miniscript::{Descriptor, Miniscript, MiniscriptKey, Segwitv0, Terminal, ToPublicKey};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::str::FromStr;
 
+pub type HDKeypaths = BTreeMap<PublicKey, (Fingerprint, DerivationPath)>;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DeriveAddressOpts {
     pub descriptor: String,
@@ -13,41 +17,273 @@ pub struct DeriveAddressOpts {
 }
 
 /// derive address from descriptor in the form "wsh(multi({n},{x}/{c}/*,{y}/{c}/*,...))#5wstxmwd"
+/// or "wsh(sortedmulti(...))"
 pub fn derive_address(
     network: Network,
     opt: &DeriveAddressOpts,
     int_or_ext: u32,
 ) -> Result<GetAddressOutput> {
-    // checksum not supported at the moment, stripping out
-    let end = opt
-        .descriptor
-        .find('#')
-        .unwrap_or_else(|| opt.descriptor.len());
-    let descriptor: miniscript::Descriptor<DescriptorPublicKey> = opt.descriptor[..end].parse()?;
-
-    let address = descriptor
-        .derive(ChildNumber::from_normal_idx(opt.index)?)
+    let address = derive_descriptor(&opt.descriptor, opt.index)?
         .address(network)
         .ok_or_else(|| Error::AddressFromDescriptorFails)?;
     let path = DerivationPath::from_str(&format!("m/{}/{}", int_or_ext, opt.index))?;
 
-    Ok(GetAddressOutput { address, path })
+    Ok(GetAddressOutput {
+        address,
+        path,
+        warning: None,
+    })
 }
 
 /// extract the xpubs from a descriptor in the form "wsh(multi({n},{x}/0/*,{y}/0/*,...))#5wstxmwd"
+/// or any other wsh miniscript, in order of appearance and without duplicates
 pub fn extract_xpubs(descriptor: &str) -> Result<Vec<ExtendedPubKey>> {
-    let mut xpubs = vec![];
-    let end = descriptor.find('#').unwrap_or_else(|| descriptor.len());
-    let descriptor: miniscript::Descriptor<DescriptorPublicKey> =
-        descriptor[..end].parse().unwrap();
+    Ok(extract_keys(descriptor)?
+        .into_iter()
+        .map(|k| k.xpub)
+        .collect())
+}
+
+/// extract the xpubs with their origin, see `extract_xpubs`
+pub fn extract_keys(descriptor: &str) -> Result<Vec<PublicMasterKey>> {
+    let mut keys: Vec<PublicMasterKey> = vec![];
+    let (descriptor, _) = parse_descriptor(descriptor)?;
     if let Descriptor::Wsh(miniscript) = descriptor {
-        for el in miniscript.get_leaf_pk() {
+        for el in miniscript.iter_pkh() {
             if let DescriptorPublicKey::XPub(desc_xpub) = el {
-                xpubs.push(desc_xpub.xpub);
+                if !keys.iter().any(|k| k.xpub == desc_xpub.xpub) {
+                    keys.push(PublicMasterKey {
+                        xpub: desc_xpub.xpub,
+                        origin: desc_xpub
+                            .origin
+                            .map(|(fingerprint, path)| KeyOrigin { fingerprint, path }),
+                        account_xpub: None,
+                    });
+                }
             }
         }
     }
-    Ok(xpubs)
+    Ok(keys)
+}
+
+/// A `wsh(multi(...))` or `wsh(sortedmulti(...))` descriptor
+#[derive(Debug, Clone, PartialEq)]
+pub struct Multisig {
+    pub required_sig: usize,
+    pub keys: Vec<PublicMasterKey>,
+    pub sorted: bool,
+}
+
+/// the multisig of `descriptor`, None if the descriptor is not a `multi` or `sortedmulti`
+pub fn multisig(descriptor: &str) -> Result<Option<Multisig>> {
+    let (parsed, sorted) = parse_descriptor(descriptor)?;
+    match parsed {
+        Descriptor::Wsh(Miniscript {
+            node: Terminal::Multi(required_sig, _),
+            ..
+        }) => Ok(Some(Multisig {
+            required_sig,
+            keys: extract_keys(descriptor)?,
+            sorted,
+        })),
+        _ => Ok(None),
+    }
+}
+
+/// the minimum number of signatures needed to spend from `descriptor`
+pub fn required_sig(descriptor: &str) -> Result<usize> {
+    let (parsed, _) = parse_descriptor(descriptor)?;
+    Ok(parsed.lift()?.minimum_n_keys())
+}
+
+/// derive the descriptor at `index`, keys of a `sortedmulti` are sorted after derivation as in BIP67
+pub fn derive_descriptor(descriptor: &str, index: u32) -> Result<Descriptor<DescriptorPublicKey>> {
+    let (descriptor, sorted) = parse_descriptor(descriptor)?;
+    let derived = descriptor.derive(ChildNumber::from_normal_idx(index)?);
+    if !sorted {
+        return Ok(derived);
+    }
+    match derived {
+        Descriptor::Wsh(Miniscript {
+            node: Terminal::Multi(k, mut keys),
+            ..
+        }) => {
+            keys.sort_by_key(|key| key.to_public_key().key.serialize().to_vec());
+            Ok(Descriptor::Wsh(Miniscript::from_ast(Terminal::Multi(
+                k, keys,
+            ))?))
+        }
+        _ => Err(Error::AddressFromDescriptorFails),
+    }
+}
+
+/// parse a descriptor validating and stripping out the checksum, miniscript doesn't support
+/// `sortedmulti` so it's parsed as a `multi`, the returned bool is true if keys must be sorted
+/// after derivation
+fn parse_descriptor(descriptor: &str) -> Result<(Descriptor<DescriptorPublicKey>, bool)> {
+    let descriptor = strip_checksum(descriptor)?;
+    if !descriptor.contains("sortedmulti(") {
+        return Ok((descriptor.parse()?, false));
+    }
+    if !descriptor.starts_with("wsh(sortedmulti(") {
+        return Err("sortedmulti is supported only in the form wsh(sortedmulti(...))".into());
+    }
+    let descriptor = descriptor.replacen("sortedmulti(", "multi(", 1);
+    Ok((descriptor.parse()?, true))
+}
+
+const INPUT_CHARSET: &str = "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+fn poly_mod(mut c: u64, val: u64) -> u64 {
+    let c0 = c >> 35;
+    c = ((c & 0x7_ffff_ffff) << 5) ^ val;
+    let generators = [
+        0xf5_dee5_1989,
+        0xa9_fdca_3312,
+        0x1b_ab10_e32d,
+        0x37_06b1_677a,
+        0x64_4d62_6ffd,
+    ];
+    for (i, generator) in generators.iter().enumerate() {
+        if c0 & (1 << i) != 0 {
+            c ^= generator;
+        }
+    }
+    c
+}
+
+/// BIP380 checksum of a descriptor without the `#checksum` suffix
+pub fn descriptor_checksum(descriptor: &str) -> Result<String> {
+    let mut c = 1u64;
+    let mut cls = 0u64;
+    let mut cls_count = 0;
+    for ch in descriptor.chars() {
+        let pos = INPUT_CHARSET
+            .find(ch)
+            .ok_or(Error::InvalidDescriptorChar(ch))? as u64;
+        c = poly_mod(c, pos & 31);
+        cls = cls * 3 + (pos >> 5);
+        cls_count += 1;
+        if cls_count == 3 {
+            c = poly_mod(c, cls);
+            cls = 0;
+            cls_count = 0;
+        }
+    }
+    if cls_count > 0 {
+        c = poly_mod(c, cls);
+    }
+    for _ in 0..8 {
+        c = poly_mod(c, 0);
+    }
+    c ^= 1;
+    Ok((0..8)
+        .map(|j| CHECKSUM_CHARSET[((c >> (5 * (7 - j))) & 31) as usize] as char)
+        .collect())
+}
+
+/// the descriptor without the checksum, returns an error if the checksum is present and wrong
+pub fn strip_checksum(descriptor: &str) -> Result<&str> {
+    let mut parts = descriptor.splitn(2, '#');
+    let without = parts.next().unwrap_or_default();
+    let expected = descriptor_checksum(without)?;
+    match parts.next() {
+        Some(found) if found != expected => {
+            Err(Error::WrongDescriptorChecksum(found.to_string(), expected))
+        }
+        _ => Ok(without),
+    }
+}
+
+/// the descriptor with the checksum appended, an existing checksum is validated
+pub fn with_checksum(descriptor: &str) -> Result<String> {
+    let without = strip_checksum(descriptor)?;
+    Ok(format!("{}#{}", without, descriptor_checksum(without)?))
+}
+
+/// Semantic policy of a segwit v0 witness script, None if the script is not miniscript
+pub fn lift_witness_script(witness_script: &Script) -> Option<Semantic<PublicKey>> {
+    let miniscript = Miniscript::<PublicKey, Segwitv0>::parse(witness_script).ok()?;
+    miniscript.lift().ok()
+}
+
+/// Restrict `policy` to the spending paths available to the input `input_index` of `tx`,
+/// timelocks satisfied by nSequence and nLockTime are considered trivial and the others
+/// unsatisfiable
+pub fn at_timelocks(
+    policy: Semantic<PublicKey>,
+    tx: &Transaction,
+    input_index: usize,
+) -> Semantic<PublicKey> {
+    let sequence = tx.input[input_index].sequence;
+    let restricted = match policy {
+        Semantic::Older(n) if older_satisfied(n, tx.version, sequence) => Semantic::Trivial,
+        Semantic::After(n) if after_satisfied(n, tx.lock_time, sequence) => Semantic::Trivial,
+        Semantic::Older(_) | Semantic::After(_) => Semantic::Unsatisfiable,
+        Semantic::Threshold(k, subs) => Semantic::Threshold(
+            k,
+            subs.into_iter()
+                .map(|sub| at_timelocks(sub, tx, input_index))
+                .collect(),
+        ),
+        x => x,
+    };
+    restricted.normalized()
+}
+
+/// Timelocks of `policy` not satisfied by the input `input_index` of `tx`, in miniscript notation
+pub fn unsatisfied_timelocks(
+    policy: &Semantic<PublicKey>,
+    tx: &Transaction,
+    input_index: usize,
+) -> Vec<String> {
+    let sequence = tx.input[input_index].sequence;
+    match policy {
+        Semantic::Older(n) if !older_satisfied(*n, tx.version, sequence) => {
+            vec![format!("older({})", n)]
+        }
+        Semantic::After(n) if !after_satisfied(*n, tx.lock_time, sequence) => {
+            vec![format!("after({})", n)]
+        }
+        Semantic::Threshold(_, subs) => subs
+            .iter()
+            .flat_map(|sub| unsatisfied_timelocks(sub, tx, input_index))
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// true if a signature of `key` could contribute to satisfy `policy`
+pub fn policy_has_key(policy: &Semantic<PublicKey>, key: &PublicKey) -> bool {
+    match policy {
+        Semantic::KeyHash(hash) => *hash == key.to_pubkeyhash(),
+        Semantic::Threshold(_, subs) => subs.iter().any(|sub| policy_has_key(sub, key)),
+        _ => false,
+    }
+}
+
+/// relative timelock semantic as in BIP68 and BIP112
+fn older_satisfied(n: u32, version: i32, sequence: u32) -> bool {
+    const DISABLE_FLAG: u32 = 1 << 31;
+    const TYPE_FLAG: u32 = 1 << 22;
+    const MASK: u32 = 0x0000_ffff;
+    if n & DISABLE_FLAG != 0 {
+        return true;
+    }
+    if version < 2 || sequence & DISABLE_FLAG != 0 || n & TYPE_FLAG != sequence & TYPE_FLAG {
+        return false;
+    }
+    n & MASK <= sequence & MASK
+}
+
+/// absolute timelock semantic, heights and timestamps are not comparable
+fn after_satisfied(n: u32, lock_time: u32, sequence: u32) -> bool {
+    const THRESHOLD: u32 = 500_000_000;
+    if sequence == 0xffff_ffff || (n < THRESHOLD) != (lock_time < THRESHOLD) {
+        return false;
+    }
+    n <= lock_time
 }
 
 #[cfg(test)]
@@ -84,6 +320,125 @@ mod tests {
         );
     }
 
+    #[test]
+    fn checksum_test() {
+        assert_eq!(descriptor_checksum("raw(deadbeef)").unwrap(), "89f8spxm");
+        let (without, checksum) = DESCRIPTOR.split_at(DESCRIPTOR.len() - 9);
+        assert_eq!(descriptor_checksum(without).unwrap(), &checksum[1..]);
+        assert_eq!(strip_checksum(DESCRIPTOR).unwrap(), without);
+        assert_eq!(strip_checksum(without).unwrap(), without);
+        assert_eq!(with_checksum(without).unwrap(), DESCRIPTOR);
+        assert_eq!(with_checksum(DESCRIPTOR).unwrap(), DESCRIPTOR);
+
+        let wrong_checksum = format!("{}#5wstxmwe", without);
+        assert!(strip_checksum(&wrong_checksum).is_err());
+        assert!(extract_xpubs(&wrong_checksum).is_err());
+        // a corrupted char in a key is detected
+        let corrupted = DESCRIPTOR.replacen("tpubD6NzVbkrYhZ4Y", "tpubD6NzVbkrYhZ4Z", 1);
+        let opts = DeriveAddressOpts {
+            descriptor: corrupted,
+            index: 0,
+        };
+        let err = derive_address(Network::Testnet, &opts, 0).unwrap_err();
+        assert!(err.to_string().starts_with("Wrong descriptor checksum"));
+        assert!(descriptor_checksum("wsh(\u{e8})").is_err());
+    }
+
+    #[test]
+    fn derive_address_sortedmulti_test() {
+        let xpubs = extract_xpubs(DESCRIPTOR).unwrap();
+        let sorted = |a: &ExtendedPubKey, b: &ExtendedPubKey| DeriveAddressOpts {
+            descriptor: format!("wsh(sortedmulti(2,{}/0/*,{}/0/*))", a, b),
+            index: 0,
+        };
+        let sorted_ab = sorted(&xpubs[0], &xpubs[1]);
+        let sorted_ba = sorted(&xpubs[1], &xpubs[0]);
+        assert_eq!(extract_xpubs(&sorted_ab.descriptor).unwrap(), xpubs);
+
+        let mut addresses = vec![];
+        for index in 0..10 {
+            let derive = |descriptor: &str| {
+                let opts = DeriveAddressOpts {
+                    descriptor: descriptor.to_string(),
+                    index,
+                };
+                derive_address(Network::Testnet, &opts, 0).unwrap().address
+            };
+            let address = derive(&sorted_ab.descriptor);
+            assert_eq!(address, derive(&sorted_ba.descriptor));
+            let multi_ab = derive(&sorted_ab.descriptor.replace("sortedmulti", "multi"));
+            let multi_ba = derive(&sorted_ba.descriptor.replace("sortedmulti", "multi"));
+            assert_ne!(multi_ab, multi_ba);
+            assert!(address == multi_ab || address == multi_ba);
+            addresses.push(address == multi_ab);
+        }
+        // with random keys both orders happen in the first 10 derivations
+        assert!(addresses.contains(&true) && addresses.contains(&false));
+
+        let nested = sorted_ab.descriptor.replace("wsh(", "sh(wsh(") + ")";
+        assert!(extract_xpubs(&nested).is_err());
+    }
+
+    #[test]
+    fn timelocks_test() {
+        use bitcoin::{OutPoint, TxIn};
+
+        let keys: Vec<PublicKey> = (2..5u8)
+            .map(|i| {
+                let secp = bitcoin::secp256k1::Secp256k1::signing_only();
+                let sk = bitcoin::secp256k1::SecretKey::from_slice(&[i; 32]).unwrap();
+                PublicKey {
+                    compressed: true,
+                    key: bitcoin::secp256k1::PublicKey::from_secret_key(&secp, &sk),
+                }
+            })
+            .collect();
+        let ms = format!(
+            "or_d(multi(2,{},{}),and_v(v:pk({}),older(144)))",
+            keys[0], keys[1], keys[2]
+        );
+        let ms = Miniscript::<PublicKey, Segwitv0>::from_str(&ms).unwrap();
+        let policy = lift_witness_script(&ms.encode()).unwrap();
+        let recovery = &keys[2];
+
+        let mut tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::default(),
+                script_sig: Script::new(),
+                sequence: 0xffff_fffe,
+                witness: vec![],
+            }],
+            output: vec![],
+        };
+        let available = at_timelocks(policy.clone(), &tx, 0);
+        assert!(policy_has_key(&policy, recovery));
+        assert!(!policy_has_key(&available, recovery));
+        assert!(policy_has_key(&available, &keys[0]));
+        assert_eq!(unsatisfied_timelocks(&policy, &tx, 0), vec!["older(144)"]);
+
+        tx.input[0].sequence = 144;
+        assert!(policy_has_key(
+            &at_timelocks(policy.clone(), &tx, 0),
+            recovery
+        ));
+        assert!(unsatisfied_timelocks(&policy, &tx, 0).is_empty());
+
+        tx.input[0].sequence = 144 | 1 << 22; // time based, not comparable with blocks
+        assert!(!policy_has_key(
+            &at_timelocks(policy.clone(), &tx, 0),
+            recovery
+        ));
+        tx.version = 1;
+        tx.input[0].sequence = 144;
+        assert!(!policy_has_key(&at_timelocks(policy, &tx, 0), recovery));
+
+        assert!(after_satisfied(100, 100, 0));
+        assert!(!after_satisfied(100, 100, 0xffff_ffff));
+        assert!(!after_satisfied(100, 500_000_100, 0));
+    }
+
     #[test]
     fn extract_xpubs_test() {
         let a = ExtendedPubKey::from_str("tpubD6NzVbkrYhZ4YfG9CySHqKHFbaLcD7hSDyqRUtCmMKNim5fkiJtTnFeqKsRHMHSK5ddFrhqRr3Ghv1JtuWkBzikuBqKu1xCpjQ9YxoPGgqU").unwrap();
@@ -1,4 +1,6 @@
+use crate::common::encryption;
 use crate::mnemonic::Mnemonic;
+use crate::qr::QrStyle;
 use crate::*;
 use bitcoin::Network;
 use num_bigint::BigUint;
@@ -30,6 +32,26 @@ pub struct DiceOptions {
     /// QR code max version to use (max size)
     #[structopt(long, default_value = "14")]
     pub qr_version: i16,
+
+    #[structopt(flatten)]
+    #[serde(flatten)]
+    pub qr_style: QrStyle,
+
+    /// File containing the passphrase to encrypt the private key file, if omitted the key is saved
+    /// unencrypted
+    #[structopt(
+        long = "encryption-passphrase-file",
+        parse(try_from_os_str = encryption::read_passphrase_file)
+    )]
+    pub encryption_passphrase: Option<String>,
+
+    /// File containing the BIP39 passphrase used to derive the seed from the mnemonic, the
+    /// passphrase is not saved and it's required again at sign time
+    #[structopt(
+        long = "bip39-passphrase-file",
+        parse(try_from_os_str = encryption::read_passphrase_file)
+    )]
+    pub bip39_passphrase: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -79,8 +101,22 @@ impl DiceOptions {
 pub fn roll(datadir: &str, network: Network, opt: &DiceOptions) -> Result<MasterKeyOutput> {
     opt.validate()?;
 
-    let master_key = calculate_key(&opt.launches, opt.faces as u32, network, &opt.key_name)?;
-    let output = save_keys(datadir, network, &opt.key_name, master_key, opt.qr_version)?;
+    let master_key = calculate_key(
+        &opt.launches,
+        opt.faces as u32,
+        network,
+        opt.bip39_passphrase.as_deref(),
+        &opt.key_name,
+    )?;
+    let output = save_keys(
+        datadir,
+        network,
+        &opt.key_name,
+        master_key,
+        opt.qr_version,
+        &opt.qr_style,
+        opt.encryption_passphrase.as_deref(),
+    )?;
 
     Ok(output)
 }
@@ -111,6 +147,7 @@ fn calculate_key(
     launches: &[u32],
     faces: u32,
     network: Network,
+    bip39_passphrase: Option<&str>,
     name: &str,
 ) -> Result<PrivateMasterKey> {
     let acc = multiply_dice_launches(&launches, faces);
@@ -118,7 +155,7 @@ fn calculate_key(
     let sec = acc.to_bytes_be();
     let mnemonic = Mnemonic::new(&sec)?;
 
-    let mut key = PrivateMasterKey::new(network, &mnemonic, name)?;
+    let mut key = PrivateMasterKey::new(network, &mnemonic, bip39_passphrase, name)?;
     let dice = Dice {
         faces,
         launches: format!("{:?}", launches),
@@ -194,6 +231,9 @@ mod tests {
             key_name: "a".to_string(),
             launches,
             qr_version: 14,
+            qr_style: QrStyle::default(),
+            encryption_passphrase: None,
+            bip39_passphrase: None,
         };
 
         roll(&temp_dir_str, Network::Testnet, &opt).unwrap();
@@ -278,15 +318,21 @@ mod tests {
         /*
         let bytes = include_bytes!("../../test_data/dice/priv1.key");
         let expected: PrivateMasterKey = serde_json::from_slice(bytes).unwrap();
-        let calculated = calculate_key(&vec![2], 2, Network::Bitcoin, "name").unwrap();
+        let calculated = calculate_key(&vec![2], 2, Network::Bitcoin, None, "name").unwrap();
 
         assert_eq!(calculated, expected);
         */
 
         let bytes = include_bytes!("../../test_data/dice/priv2.key");
         let expected: PrivateMasterKey = serde_json::from_slice(bytes).unwrap();
-        let calculated =
-            calculate_key(&vec![2, 3, 4, 5, 6, 7, 8, 9], 256, Network::Bitcoin, "name").unwrap();
+        let calculated = calculate_key(
+            &vec![2, 3, 4, 5, 6, 7, 8, 9],
+            256,
+            Network::Bitcoin,
+            None,
+            "name",
+        )
+        .unwrap();
         assert_eq!(
             calculated.fingerprint.to_string(),
             expected.fingerprint.to_string()
@@ -0,0 +1,100 @@
+use crate::common::encryption::{self, KdfParams};
+use crate::offline::sign::read_key_file;
+use crate::*;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+/// Encrypt an existing unencrypted key file
+#[derive(StructOpt, Debug, Serialize, Deserialize)]
+#[structopt(name = "encrypt_key")]
+pub struct EncryptKeyOptions {
+    /// File containing the master key (PRIVATE.json)
+    #[structopt(short, long, parse(from_os_str))]
+    pub key: PathBuf,
+
+    /// File containing the passphrase used to encrypt the key file
+    #[structopt(
+        long = "encryption-passphrase-file",
+        parse(try_from_os_str = encryption::read_passphrase_file)
+    )]
+    pub encryption_passphrase: String,
+}
+
+pub fn start(opt: &EncryptKeyOptions) -> Result<EncryptedKeyOutput> {
+    encrypt_key_file(
+        &opt.key,
+        &opt.encryption_passphrase,
+        KdfParams::new_random(),
+    )
+}
+
+fn encrypt_key_file(
+    path: &PathBuf,
+    passphrase: &str,
+    kdf: KdfParams,
+) -> Result<EncryptedKeyOutput> {
+    let key_file = read_key_file(path)?;
+    let encrypted = encryption::encrypt_key_file(&key_file, passphrase, kdf)?;
+
+    // write in a temporary file first, so that the plain key is replaced only if writing succeeds
+    let mut temp_file = path.clone();
+    temp_file.set_file_name("PRIVATE.json.tmp");
+    fs::write(&temp_file, serde_json::to_string_pretty(&encrypted)?)?;
+    fs::rename(&temp_file, path)?;
+    info!("Encrypted {:?}", path);
+
+    Ok(EncryptedKeyOutput {
+        name: encrypted.name,
+        fingerprint: encrypted.fingerprint,
+        xpub: encrypted.xpub,
+        private_file: path.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::encryption::KdfParams;
+    use crate::common::list::{list, ListOptions};
+    use crate::offline::encrypt_key::encrypt_key_file;
+    use crate::offline::random::{self, RandomOptions};
+    use crate::offline::sign::read_key;
+    use crate::{Error, Kind};
+    use bitcoin::Network;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_encrypt_key() {
+        let temp_dir = TempDir::new("test_encrypt_key").unwrap();
+        let temp_dir_str = format!("{}/", temp_dir.path().display());
+
+        let rand_opts = RandomOptions::new("plain".to_string());
+        let key = random::create_key(&temp_dir_str, Network::Testnet, &rand_opts).unwrap();
+
+        let kdf = KdfParams::with_cost(4, 8, 1);
+        let path = &key.private_file;
+        let encrypted = encrypt_key_file(path, "passphrase", kdf.clone()).unwrap();
+        assert_eq!(encrypted.fingerprint, key.key.fingerprint);
+
+        assert_eq!(
+            read_key(path, None, None).unwrap_err().to_string(),
+            Error::MissingPassphrase.to_string()
+        );
+        let decrypted = read_key(path, Some("passphrase"), None).unwrap();
+        assert_eq!(decrypted, key.key);
+
+        assert_eq!(
+            encrypt_key_file(path, "passphrase", kdf)
+                .unwrap_err()
+                .to_string(),
+            Error::KeyAlreadyEncrypted.to_string()
+        );
+
+        let opt = ListOptions { kind: Kind::Key };
+        let list = list(&temp_dir_str, Network::Testnet, &opt).unwrap();
+        assert!(list.keys.is_empty());
+        assert_eq!(list.encrypted_keys, vec![encrypted]);
+    }
+}
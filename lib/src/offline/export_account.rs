@@ -0,0 +1,155 @@
+use crate::common::encryption;
+use crate::file::save_public;
+use crate::offline::sign::read_key;
+use crate::qr::{save_qrs, save_ur_qrs, QrStyle};
+use crate::ur::Ur;
+use crate::*;
+use bitcoin::Network;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+/// Export the xpub of a BIP48 native segwit multisig account of a key
+#[derive(StructOpt, Debug, Serialize, Deserialize)]
+#[structopt(name = "export_account")]
+pub struct ExportAccountOptions {
+    /// File containing the master key (PRIVATE.json)
+    #[structopt(short, long, parse(from_os_str))]
+    pub key: PathBuf,
+
+    /// Account number, the xpub is derived at m/48'/coin'/account'/2', the account 0 is exported
+    /// in public.json when the key is created
+    #[structopt(short, long)]
+    pub account: u32,
+
+    /// QR code max version to use (max size)
+    #[structopt(long, default_value = "14")]
+    pub qr_version: i16,
+
+    #[structopt(flatten)]
+    #[serde(flatten)]
+    pub qr_style: QrStyle,
+
+    /// File containing the passphrase to decrypt the key file, required if the key has been saved
+    /// encrypted
+    #[structopt(
+        long = "encryption-passphrase-file",
+        parse(try_from_os_str = encryption::read_passphrase_file)
+    )]
+    pub encryption_passphrase: Option<String>,
+
+    /// File containing the BIP39 passphrase of the key, required if it has been created with a
+    /// passphrase
+    #[structopt(
+        long = "bip39-passphrase-file",
+        parse(try_from_os_str = encryption::read_passphrase_file)
+    )]
+    pub bip39_passphrase: Option<String>,
+}
+
+pub fn start(
+    datadir: &str,
+    network: Network,
+    opt: &ExportAccountOptions,
+) -> Result<ExportAccountOutput> {
+    let key = read_key(
+        &opt.key,
+        opt.encryption_passphrase.as_deref(),
+        opt.bip39_passphrase.as_deref(),
+    )?;
+    check_compatibility(network, key.xprv.network)?;
+    let account_key = key.bip48_account(opt.account)?;
+    debug!("exporting account {} of {}", opt.account, key.name);
+
+    let path_builder = PathBuilder::new(datadir, network, Kind::Key, Some(key.name.clone()));
+    let public_file = path_builder.file(&format!("account-{}.json", opt.account))?;
+    // exporting the same account twice gives the same xpub
+    if !public_file.exists() {
+        save_public(&account_key, &public_file)?;
+    }
+    let qr_dir = path_builder.file(&format!("qr-account-{}", opt.account))?;
+    let public_qr_files = save_qrs(
+        account_key.to_string().as_bytes().to_vec(),
+        qr_dir,
+        opt.qr_version,
+        &opt.qr_style,
+    )?;
+    let ur_qr_dir = path_builder.file(&format!("qr-account-{}-ur", opt.account))?;
+    let ur_qr_files = save_ur_qrs(
+        &Ur::from_account(&account_key)?,
+        ur_qr_dir,
+        opt.qr_version,
+        &opt.qr_style,
+    )?;
+
+    Ok(ExportAccountOutput {
+        name: key.name,
+        account: opt.account,
+        key: account_key,
+        public_file,
+        public_qr_files,
+        ur_qr_files,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::offline::export_account::{self, ExportAccountOptions};
+    use crate::offline::random::{self, RandomOptions};
+    use crate::qr::QrStyle;
+    use crate::PublicMasterKey;
+    use bitcoin::util::bip32::DerivationPath;
+    use bitcoin::Network;
+    use std::str::FromStr;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_export_account() {
+        let temp_dir = TempDir::new("test_export_account").unwrap();
+        let temp_dir_str = format!("{}/", temp_dir.path().display());
+
+        let rand_opts = RandomOptions::new("key".to_string());
+        let key = random::create_key(&temp_dir_str, Network::Testnet, &rand_opts).unwrap();
+        let content = std::fs::read(key.public_file.as_ref().unwrap()).unwrap();
+        let public: PublicMasterKey = serde_json::from_slice(&content).unwrap();
+        assert_eq!(public.xpub, key.key.xpub);
+        assert!(public.origin.is_none());
+        let account_0 = public.wallet_key().unwrap();
+        assert_eq!(Some(account_0.xpub), public.account_xpub);
+        let origin = account_0.origin.clone().unwrap();
+        assert_eq!(origin.fingerprint, key.key.fingerprint);
+        assert_eq!(
+            origin.path,
+            DerivationPath::from_str("m/48'/1'/0'/2'").unwrap()
+        );
+
+        let mut opt = ExportAccountOptions {
+            key: key.private_file.clone(),
+            account: 0,
+            qr_version: 14,
+            qr_style: QrStyle::default(),
+            encryption_passphrase: None,
+            bip39_passphrase: None,
+        };
+        let exported = export_account::start(&temp_dir_str, Network::Testnet, &opt).unwrap();
+        assert_eq!(exported.key, account_0);
+        let ur_file = exported.ur_qr_files[0].with_file_name("ur.txt");
+        let ur = crate::ur::decode(&[std::fs::read_to_string(ur_file).unwrap()]).unwrap();
+        let (fingerprint, descriptors) = ur.to_account().unwrap();
+        assert_eq!(fingerprint, key.key.fingerprint);
+        assert_eq!(descriptors, vec![format!("wsh(cosigner({}))", account_0)]);
+
+        opt.account = 7;
+        let exported = export_account::start(&temp_dir_str, Network::Testnet, &opt).unwrap();
+        let path = exported.key.origin.unwrap().path;
+        assert_eq!(path, DerivationPath::from_str("m/48'/1'/7'/2'").unwrap());
+        assert_ne!(exported.key.xpub, account_0.xpub);
+        assert!(exported.public_file.exists());
+        assert!(export_account::start(&temp_dir_str, Network::Testnet, &opt).is_ok());
+
+        assert!(export_account::start(&temp_dir_str, Network::Bitcoin, &opt).is_err());
+        opt.account = 1 << 31;
+        assert!(export_account::start(&temp_dir_str, Network::Testnet, &opt).is_err());
+    }
+}
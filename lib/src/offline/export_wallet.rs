@@ -0,0 +1,439 @@
+use crate::offline::descriptor::{multisig, with_checksum, Multisig};
+use crate::qr::{save_qrs, save_ur_qrs, QrStyle};
+use crate::ur::{Ur, UrEncoder};
+use crate::*;
+use bitcoin::util::base58;
+use bitcoin::util::bip32::ExtendedPubKey;
+use bitcoin::Network;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use structopt::StructOpt;
+
+/// Export a wallet in the format of another wallet or coordinator
+#[derive(StructOpt, Debug, Serialize, Deserialize)]
+#[structopt(name = "export_wallet")]
+pub struct ExportWalletOptions {
+    /// File containing the wallet descriptor (descriptor.json), firma-online uses --wallet-name
+    /// instead
+    #[structopt(long, parse(from_os_str))]
+    pub wallet_descriptor_file: Option<PathBuf>,
+
+    /// Format of the export: coldcard (also for passport), specter (also for sparrow), electrum,
+    /// core (importdescriptors payload), ur (crypto-output, animated QR for UR wallets)
+    #[structopt(long)]
+    pub format: ExportFormat,
+
+    /// QR code max version to use (max size)
+    #[structopt(long, default_value = "14")]
+    pub qr_version: i16,
+
+    #[structopt(flatten)]
+    #[serde(flatten)]
+    pub qr_style: QrStyle,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Coldcard,
+    Specter,
+    Electrum,
+    Core,
+    Ur,
+}
+
+impl FromStr for ExportFormat {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "coldcard" | "passport" => Ok(ExportFormat::Coldcard),
+            "specter" | "sparrow" => Ok(ExportFormat::Specter),
+            "electrum" => Ok(ExportFormat::Electrum),
+            "core" => Ok(ExportFormat::Core),
+            "ur" | "crypto-output" => Ok(ExportFormat::Ur),
+            _ => Err(io_err(
+                "valid values are: coldcard, passport, specter, sparrow, electrum, core, ur",
+            )),
+        }
+    }
+}
+
+impl ExportFormat {
+    fn file_name(self) -> &'static str {
+        match self {
+            ExportFormat::Coldcard => "coldcard.txt",
+            ExportFormat::Specter => "specter.json",
+            ExportFormat::Electrum => "electrum.json",
+            ExportFormat::Core => "importdescriptors.json",
+            ExportFormat::Ur => "crypto-output.txt",
+        }
+    }
+
+    fn qr_dir_name(self) -> String {
+        format!(
+            "qr-{}",
+            self.file_name().split('.').next().unwrap_or_default()
+        )
+    }
+}
+
+/// SLIP-132 version bytes of extended public keys for native segwit multisig (Zpub, Vpub)
+const ZPUB_VERSION: [u8; 4] = [0x02, 0xaa, 0x7e, 0xd3];
+const VPUB_VERSION: [u8; 4] = [0x02, 0x57, 0x54, 0x83];
+const XPUB_VERSION: [u8; 4] = [0x04, 0x88, 0xb2, 0x1e];
+const TPUB_VERSION: [u8; 4] = [0x04, 0x35, 0x87, 0xcf];
+
+/// Encode `xpub` with the SLIP-132 version of native segwit multisig, as used by Electrum
+pub fn to_slip132(xpub: &ExtendedPubKey) -> Result<String> {
+    let mut data = base58::from_check(&xpub.to_string())?;
+    let version = match xpub.network {
+        Network::Bitcoin => ZPUB_VERSION,
+        _ => VPUB_VERSION,
+    };
+    data[..4].copy_from_slice(&version);
+    Ok(base58::check_encode_slice(&data))
+}
+
+/// Decode an extended public key encoded as xpub/tpub or with the SLIP-132 version of native
+/// segwit multisig (Zpub/Vpub), other versions are refused since they imply a different script
+pub fn from_slip132(s: &str) -> Result<ExtendedPubKey> {
+    let mut data = base58::from_check(s)?;
+    if data.len() != 78 {
+        return Err(format!("{} is not an extended public key", s).into());
+    }
+    let version = [data[0], data[1], data[2], data[3]];
+    if version == ZPUB_VERSION {
+        data[..4].copy_from_slice(&XPUB_VERSION);
+    } else if version == VPUB_VERSION {
+        data[..4].copy_from_slice(&TPUB_VERSION);
+    } else if version != XPUB_VERSION && version != TPUB_VERSION {
+        return Err(format!("{} is not a native segwit multisig extended public key", s).into());
+    }
+    Ok(ExtendedPubKey::from_str(&base58::check_encode_slice(
+        &data,
+    ))?)
+}
+
+pub fn start(
+    datadir: &str,
+    network: Network,
+    opt: &ExportWalletOptions,
+) -> Result<ExportWalletOutput> {
+    let wallet_file = opt
+        .wallet_descriptor_file
+        .as_ref()
+        .ok_or("--wallet-descriptor-file is required")?;
+    let wallet = read_wallet(wallet_file)?;
+    export_wallet(
+        datadir,
+        network,
+        &wallet,
+        opt.format,
+        opt.qr_version,
+        &opt.qr_style,
+    )
+}
+
+/// Render `wallet` in `format`, saving it with its QR codes in the wallet directory
+pub fn export_wallet(
+    datadir: &str,
+    network: Network,
+    wallet: &WalletJson,
+    format: ExportFormat,
+    qr_version: i16,
+    qr_style: &QrStyle,
+) -> Result<ExportWalletOutput> {
+    let content = match format {
+        ExportFormat::Coldcard => to_coldcard(wallet)?,
+        ExportFormat::Specter => to_specter(wallet)?,
+        ExportFormat::Electrum => to_electrum(wallet)?,
+        ExportFormat::Core => to_core(wallet)?,
+        ExportFormat::Ur => to_ur(wallet)?,
+    };
+
+    let path_builder = PathBuilder::new(datadir, network, Kind::Wallet, Some(wallet.name.clone()));
+    let file = path_builder.file(format.file_name())?;
+    info!("Saving {:?}", file);
+    fs::write(&file, &content)?;
+    let qr_dir = path_builder.file(&format.qr_dir_name())?;
+    let qr_files = match format {
+        ExportFormat::Ur => save_ur_qrs(&Ur::from_wallet(wallet)?, qr_dir, qr_version, qr_style)?,
+        _ => save_qrs(content.as_bytes().to_vec(), qr_dir, qr_version, qr_style)?,
+    };
+
+    Ok(ExportWalletOutput {
+        name: wallet.name.clone(),
+        format,
+        content,
+        file,
+        qr_files,
+    })
+}
+
+fn sorted_multisig(wallet: &WalletJson, format: &str) -> Result<Multisig> {
+    match multisig(&wallet.descriptor_main)? {
+        Some(multisig) if multisig.sorted => Ok(multisig),
+        _ => Err(format!("{} supports only sortedmulti wallets", format).into()),
+    }
+}
+
+/// Coldcard multisig setup file, the same format is used by Passport
+fn to_coldcard(wallet: &WalletJson) -> Result<String> {
+    let multisig = sorted_multisig(wallet, "Coldcard")?;
+    let name: String = wallet.name.chars().take(20).collect(); // max len supported by Coldcard
+    let mut lines = vec![
+        "# Coldcard Multisig setup file (exported from Firma)".to_string(),
+        "#".to_string(),
+        format!("Name: {}", name),
+        format!(
+            "Policy: {} of {}",
+            multisig.required_sig,
+            multisig.keys.len()
+        ),
+        "Format: P2WSH".to_string(),
+    ];
+    for key in multisig.keys.iter() {
+        let origin = key.key_origin();
+        lines.push(String::new());
+        lines.push(format!("Derivation: {}", origin.path));
+        lines.push(format!(
+            "{}: {}",
+            origin.fingerprint.to_string().to_uppercase(),
+            key.xpub
+        ));
+    }
+    lines.push(String::new());
+    Ok(lines.join("\n"))
+}
+
+#[derive(Serialize)]
+struct SpecterWallet<'a> {
+    label: &'a str,
+    blockheight: u64,
+    descriptor: &'a str,
+    devices: Vec<SpecterDevice>,
+}
+
+#[derive(Serialize)]
+struct SpecterDevice {
+    #[serde(rename = "type")]
+    kind: String,
+    label: String,
+}
+
+/// Specter wallet json, imported also by Sparrow
+fn to_specter(wallet: &WalletJson) -> Result<String> {
+    let multisig =
+        multisig(&wallet.descriptor_main)?.ok_or("Specter supports only multisig wallets")?;
+    let specter = SpecterWallet {
+        label: &wallet.name,
+        blockheight: wallet.created_at_height,
+        descriptor: &wallet.descriptor_main,
+        devices: multisig
+            .keys
+            .iter()
+            .map(|k| SpecterDevice {
+                kind: "other".to_string(),
+                label: k.key_origin().fingerprint.to_string(),
+            })
+            .collect(),
+    };
+    Ok(serde_json::to_string_pretty(&specter)?)
+}
+
+#[derive(Serialize)]
+struct ElectrumKeystore {
+    #[serde(rename = "type")]
+    kind: String,
+    xpub: String,
+    derivation: String,
+    root_fingerprint: String,
+    label: String,
+}
+
+#[derive(Serialize)]
+struct ElectrumWallet {
+    wallet_type: String,
+    use_encryption: bool,
+    seed_version: u32,
+    #[serde(flatten)]
+    keystores: BTreeMap<String, ElectrumKeystore>,
+}
+
+/// Electrum multisig wallet file, keys are encoded as Zpub/Vpub
+fn to_electrum(wallet: &WalletJson) -> Result<String> {
+    let multisig = sorted_multisig(wallet, "Electrum")?;
+    let mut keystores = BTreeMap::new();
+    for (i, key) in multisig.keys.iter().enumerate() {
+        let origin = key.key_origin();
+        let keystore = ElectrumKeystore {
+            kind: "bip32".to_string(),
+            xpub: to_slip132(&key.xpub)?,
+            derivation: origin.path.to_string(),
+            root_fingerprint: origin.fingerprint.to_string(),
+            label: String::new(),
+        };
+        keystores.insert(format!("x{}/", i + 1), keystore);
+    }
+    let electrum = ElectrumWallet {
+        wallet_type: format!("{}of{}", multisig.required_sig, multisig.keys.len()),
+        use_encryption: false,
+        seed_version: 17,
+        keystores,
+    };
+    Ok(serde_json::to_string_pretty(&electrum)?)
+}
+
+#[derive(Serialize)]
+struct ImportDescriptorsRequest<'a> {
+    desc: &'a str,
+    active: bool,
+    internal: bool,
+    range: [u32; 2],
+    timestamp: u64,
+}
+
+/// Payload of the bitcoin core `importdescriptors` rpc call, timestamp is 0 because the creation
+/// time of the wallet is unknown offline, so the node rescans the whole chain
+fn to_core(wallet: &WalletJson) -> Result<String> {
+    let request = |desc, internal| ImportDescriptorsRequest {
+        desc,
+        active: true,
+        internal,
+        range: [0, DESCRIPTOR_RANGE],
+        timestamp: 0,
+    };
+    // importdescriptors requires checksums, wallets created by older versions may lack them
+    let main = with_checksum(&wallet.descriptor_main)?;
+    let change = with_checksum(&wallet.descriptor_change)?;
+    let requests = [request(&main, false), request(&change, true)];
+    Ok(serde_json::to_string_pretty(&requests)?)
+}
+
+/// `ur:crypto-output` of the main descriptor in a single part, QR codes are animated if needed
+fn to_ur(wallet: &WalletJson) -> Result<String> {
+    let ur = Ur::from_wallet(wallet)?;
+    Ok(UrEncoder::new(&ur, ur.cbor.len())?.next_part())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::offline::export_wallet::*;
+    use bitcoin::util::bip32::ExtendedPubKey;
+    use tempdir::TempDir;
+
+    const XPUBS: [&str; 2] = [
+        "tpubD6NzVbkrYhZ4YfG9CySHqKHFbaLcD7hSDyqRUtCmMKNim5fkiJtTnFeqKsRHMHSK5ddFrhqRr3Ghv1JtuWkBzikuBqKu1xCpjQ9YxoPGgqU",
+        "tpubD6NzVbkrYhZ4WpudNKLizFbGzpsG3jkLF7mc8Vfh1fTDbbBPjDP29My6TaLncaS8VeDPcaNMdUkybucr8Kz9CHSdAtvxnaXyBxPRocefdXN",
+    ];
+
+    fn wallet(multi: &str) -> WalletJson {
+        let descriptor = |c: u32| {
+            format!(
+                "wsh({}(2,[d34db33f/48'/1'/0'/2']{}/{}/*,{}/{}/*))",
+                multi, XPUBS[0], c, XPUBS[1], c
+            )
+        };
+        WalletJson {
+            name: "a-wallet-with-a-very-long-name".to_string(),
+            descriptor_main: descriptor(0),
+            descriptor_change: descriptor(1),
+            fingerprints: Default::default(),
+            required_sig: 2,
+            policy: None,
+            daemon_opts: None,
+            created_at_height: 1_800_000,
+        }
+    }
+
+    #[test]
+    fn test_slip132() {
+        let xpub = ExtendedPubKey::from_str(XPUBS[0]).unwrap();
+        let vpub = to_slip132(&xpub).unwrap();
+        assert!(vpub.starts_with("Vpub"));
+        assert_eq!(from_slip132(&vpub).unwrap(), xpub);
+        assert_eq!(from_slip132(XPUBS[0]).unwrap(), xpub);
+        let mut data = base58::from_check(&vpub).unwrap();
+        data[..4].copy_from_slice(&[0x04, 0x5f, 0x1c, 0xf6]); // vpub, single sig
+        assert!(from_slip132(&base58::check_encode_slice(&data)).is_err());
+    }
+
+    #[test]
+    fn test_export_wallet() {
+        let temp_dir = TempDir::new("test_export_wallet").unwrap();
+        let temp_dir_str = format!("{}/", temp_dir.path().display());
+        let sorted = wallet("sortedmulti");
+        let fingerprint_1 = ExtendedPubKey::from_str(XPUBS[1]).unwrap().fingerprint();
+        let export = |wallet: &WalletJson, format: &str| {
+            let format = ExportFormat::from_str(format).unwrap();
+            export_wallet(
+                &temp_dir_str,
+                Network::Testnet,
+                wallet,
+                format,
+                14,
+                &QrStyle::default(),
+            )
+        };
+
+        let coldcard = export(&sorted, "passport").unwrap();
+        assert!(coldcard.content.contains("Name: a-wallet-with-a-very\n"));
+        assert!(coldcard.content.contains("Policy: 2 of 2\n"));
+        assert!(coldcard.content.contains(&format!(
+            "Derivation: m/48'/1'/0'/2'\nD34DB33F: {}\n",
+            XPUBS[0]
+        )));
+        let expected = format!(
+            "Derivation: m\n{}: {}\n",
+            fingerprint_1.to_string().to_uppercase(),
+            XPUBS[1]
+        );
+        assert!(coldcard.content.contains(&expected));
+        assert_eq!(
+            fs::read_to_string(&coldcard.file).unwrap(),
+            coldcard.content
+        );
+        assert!(!coldcard.qr_files.is_empty());
+        assert!(export(&wallet("multi"), "coldcard").is_err());
+
+        let specter = export(&sorted, "sparrow").unwrap();
+        let value: serde_json::Value = serde_json::from_str(&specter.content).unwrap();
+        assert_eq!(value["descriptor"], sorted.descriptor_main);
+        assert_eq!(value["blockheight"], 1_800_000);
+        assert_eq!(value["devices"][0]["label"], "d34db33f");
+        assert!(export(&wallet("multi"), "specter").is_ok());
+
+        let electrum = export(&sorted, "electrum").unwrap();
+        let value: serde_json::Value = serde_json::from_str(&electrum.content).unwrap();
+        assert_eq!(value["wallet_type"], "2of2");
+        assert_eq!(value["x1/"]["derivation"], "m/48'/1'/0'/2'");
+        assert_eq!(value["x1/"]["root_fingerprint"], "d34db33f");
+        assert!(value["x2/"]["xpub"].as_str().unwrap().starts_with("Vpub"));
+        assert!(export(&wallet("multi"), "electrum").is_err());
+
+        let core = export(&wallet("multi"), "core").unwrap();
+        let value: serde_json::Value = serde_json::from_str(&core.content).unwrap();
+        let change = with_checksum(&wallet("multi").descriptor_change).unwrap();
+        assert_eq!(value[1]["desc"], change);
+        assert_eq!(value[1]["internal"], true);
+        assert_eq!(core.format, ExportFormat::Core);
+
+        let ur = export(&wallet("multi"), "ur").unwrap();
+        assert!(ur.content.starts_with("ur:crypto-output/"));
+        let decoded = crate::ur::decode(std::slice::from_ref(&ur.content)).unwrap();
+        let descriptor = decoded.to_descriptor().unwrap();
+        // the fixture xpubs have depth and child number not matching their origin, which
+        // crypto-hdkey derives from the origin path, so only origins and keys are compared
+        assert!(descriptor.starts_with("wsh(multi(2,[d34db33f/48'/1'/0'/2']tpub"));
+        assert!(descriptor.contains(",tpubD6NzVbkrYhZ4WpudNKLizFbGzpsG3jkLF7mc8Vfh1fTDbbBPjDP29My6TaLncaS8VeDPcaNMdUkybucr8Kz9CHSdAtvxnaXyBxPRocefdXN/0/*))#"));
+        assert!(!ur.qr_files.is_empty());
+
+        assert!(ExportFormat::from_str("trezor").is_err());
+    }
+}
@@ -0,0 +1,138 @@
+use crate::qr::{save_qrs, QrStyle};
+use crate::*;
+use bitcoin::consensus::serialize;
+use bitcoin::Transaction;
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+/// Combine PSBTs signed by different keys and finalize them in a transaction ready to broadcast
+#[derive(StructOpt, Debug, Serialize, Deserialize)]
+#[structopt(name = "finalize")]
+pub struct FinalizeOptions {
+    /// PSBT json files to combine, they must spend the same unsigned transaction. The transaction is
+    /// saved in the directory of the first one
+    #[structopt(parse(from_os_str), required = true, min_values = 1)]
+    pub psbt_files: Vec<PathBuf>,
+
+    /// QR code max version to use (max size)
+    #[structopt(long, default_value = "14")]
+    pub qr_version: i16,
+
+    #[structopt(flatten)]
+    #[serde(flatten)]
+    pub qr_style: QrStyle,
+}
+
+pub fn start(opt: &FinalizeOptions) -> Result<FinalizeOutput> {
+    let first_file = opt.psbt_files.first().ok_or(Error::NeedAtLeastOne)?;
+    let psbts = opt
+        .psbt_files
+        .iter()
+        .map(|path| read_psbt(path))
+        .collect::<Result<Vec<_>>>()?;
+    let mut psbt = combine(psbts)?;
+    let tx = finalize(&mut psbt)?;
+    let bytes = serialize(&tx);
+    let hex = hex::encode(&bytes);
+    info!("finalized {}", hex);
+
+    let tx_file = first_file.with_file_name("tx.hex");
+    std::fs::write(&tx_file, &hex)?;
+    let qr_files = save_qrs(
+        bytes,
+        first_file.with_file_name("qr-tx"),
+        opt.qr_version,
+        &opt.qr_style,
+    )?;
+
+    Ok(FinalizeOutput {
+        hex,
+        txid: tx.txid(),
+        tx_file,
+        qr_files,
+    })
+}
+
+/// Merge the partial signatures of `psbts` in the first one, like the `combinepsbt` RPC
+pub fn combine(psbts: Vec<PSBT>) -> Result<PSBT> {
+    let mut iter = psbts.into_iter();
+    let mut combined = iter.next().ok_or(Error::NeedAtLeastOne)?;
+    for psbt in iter {
+        combined.merge(psbt)?;
+    }
+    debug!("combined {:?}", combined);
+    Ok(combined)
+}
+
+/// Build the final witnesses and script sigs of the inputs from their partial signatures using
+/// miniscript satisfaction, then extract the transaction checking the signatures are valid
+pub fn finalize(psbt: &mut PSBT) -> Result<Transaction> {
+    miniscript::psbt::finalize(psbt)?;
+    Ok(miniscript::psbt::extract(psbt)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::offline::finalize::*;
+    use std::fs;
+    use tempdir::TempDir;
+
+    fn psbt(bytes: &[u8]) -> PSBT {
+        let psbt_json: PsbtJson = serde_json::from_slice(bytes).unwrap();
+        psbt_from_base64(&psbt_json.psbt).unwrap().1
+    }
+
+    #[test]
+    fn test_finalize() {
+        let psbt_1 = psbt(include_bytes!(
+            "../../test_data/sign/psbt_testnet.1.signed.json"
+        ));
+        let psbt_2 = psbt(include_bytes!(
+            "../../test_data/sign/psbt_testnet.2.signed.json"
+        ));
+        let complete = psbt(include_bytes!(
+            "../../test_data/sign/psbt_testnet.signed.json"
+        ));
+        let unsigned_txid = psbt_1.global.unsigned_tx.txid();
+
+        assert!(
+            finalize(&mut psbt_1.clone()).is_err(),
+            "missing a signature"
+        );
+        let mut combined = combine(vec![psbt_1.clone(), psbt_2.clone()]).unwrap();
+        assert_eq!(combined, complete);
+        let tx = finalize(&mut combined).unwrap();
+        assert_eq!(tx.txid(), unsigned_txid);
+        assert!(tx.input.iter().all(|i| !i.witness.is_empty()));
+
+        let mut other = psbt(include_bytes!("../../test_data/sign/psbt_bip.json"));
+        assert!(combine(vec![psbt_1.clone(), other.clone()]).is_err());
+        assert!(finalize(&mut other).is_err());
+
+        let temp_dir = TempDir::new("test_finalize").unwrap();
+        let mut psbt_files = vec![];
+        for (i, bytes) in [
+            &include_bytes!("../../test_data/sign/psbt_testnet.1.signed.json")[..],
+            &include_bytes!("../../test_data/sign/psbt_testnet.2.signed.json")[..],
+        ]
+        .iter()
+        .enumerate()
+        {
+            let path = temp_dir.path().join(format!("psbt-{}.json", i));
+            fs::write(&path, bytes).unwrap();
+            psbt_files.push(path);
+        }
+        let opt = FinalizeOptions {
+            psbt_files,
+            qr_version: 14,
+            qr_style: QrStyle::default(),
+        };
+        let output = start(&opt).unwrap();
+        assert_eq!(output.txid, unsigned_txid);
+        assert_eq!(output.hex, hex::encode(serialize(&tx)));
+        assert_eq!(fs::read_to_string(&output.tx_file).unwrap(), output.hex);
+        assert!(!output.qr_files.is_empty());
+    }
+}
@@ -0,0 +1,351 @@
+use crate::file::save_public;
+use crate::offline::import_wallet::{import_wallet, parse_wallet};
+use crate::offline::sign::save_psbt_opt;
+use crate::qr::{decompress, merge_qrs, save_qrs, QrStyle};
+use crate::qr_reader::{read_qr, RawQr};
+use crate::ur::{self, BYTES, CRYPTO_ACCOUNT, CRYPTO_OUTPUT, CRYPTO_PSBT};
+use crate::*;
+use bitcoin::Network;
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use structopt::StructOpt;
+
+/// Import a PSBT, a key or a wallet from images of QR codes, like snapshots of the codes shown by
+/// another device
+#[derive(StructOpt, Debug, Serialize, Deserialize)]
+#[structopt(name = "import_qr")]
+pub struct ImportQrOptions {
+    /// BMP or PNG images of the QR codes, or directories containing them. The parts of a split
+    /// QR code and the frames of an animated UR are merged
+    #[structopt(parse(from_os_str), required = true)]
+    pub files: Vec<PathBuf>,
+
+    /// Name of the imported key, by default its fingerprint, or of the imported wallet if it
+    /// doesn't contain one
+    #[structopt(short, long)]
+    pub name: Option<String>,
+
+    /// QR code max version to use (max size)
+    #[structopt(long, default_value = "14")]
+    pub qr_version: i16,
+
+    #[structopt(flatten)]
+    #[serde(flatten)]
+    pub qr_style: QrStyle,
+}
+
+/// Content of the QR codes
+enum QrContent {
+    Psbt(Vec<u8>),
+    Text(String),
+}
+
+pub fn start(datadir: &str, network: Network, opt: &ImportQrOptions) -> Result<ImportQrOutput> {
+    let mut qrs = vec![];
+    for path in image_files(&opt.files)? {
+        info!("reading qr in {:?}", path);
+        qrs.push(read_qr(&path)?);
+    }
+
+    let text = match merge(&qrs)? {
+        QrContent::Psbt(bytes) => return import_psbt(datadir, network, &bytes, opt),
+        QrContent::Text(text) => text,
+    };
+    let text = text.trim();
+    if text.starts_with("cHNidP") {
+        let bytes = base64::decode(text)?;
+        import_psbt(datadir, network, &bytes, opt)
+    } else if let Ok(key) = PublicMasterKey::from_str(text) {
+        import_key(datadir, network, &key, opt)
+    } else {
+        let wallet = parse_wallet(text, opt.name.as_deref())?;
+        let output = import_wallet(datadir, network, &wallet, opt.qr_version, &opt.qr_style)?;
+        Ok(ImportQrOutput {
+            kind: Kind::Wallet,
+            file: output.wallet_file,
+            qr_files: output.qr_files,
+        })
+    }
+}
+
+/// The images in `paths`, directories are expanded to the BMP and PNG files they contain
+fn image_files(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for path in paths {
+        if path.is_dir() {
+            let mut images = vec![];
+            for entry in fs::read_dir(path)? {
+                let entry = entry?.path();
+                let extension = entry
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .map(str::to_lowercase);
+                if let Some("bmp") | Some("png") = extension.as_deref() {
+                    images.push(entry);
+                }
+            }
+            images.sort();
+            files.extend(images);
+        } else {
+            files.push(path.clone());
+        }
+    }
+    if files.is_empty() {
+        return Err("No BMP or PNG images found".into());
+    }
+    Ok(files)
+}
+
+/// Merge the parts of a structured append QR code or the frames of an animated UR
+fn merge(qrs: &[RawQr]) -> Result<QrContent> {
+    if qrs.iter().any(RawQr::is_structured) {
+        let bytes = merge_qrs(qrs.iter().map(|qr| qr.data.clone()).collect())?;
+        return content(bytes);
+    }
+    let mut contents = qrs.iter().map(RawQr::content).collect::<Result<Vec<_>>>()?;
+    contents.sort();
+    contents.dedup();
+    let is_ur = |c: &Vec<u8>| c.len() > 3 && c[..3].eq_ignore_ascii_case(b"ur:");
+    if contents.iter().all(is_ur) {
+        let parts = contents
+            .into_iter()
+            .map(|c| Ok(std::str::from_utf8(&c)?.to_string()))
+            .collect::<Result<Vec<_>>>()?;
+        return ur_content(&parts);
+    }
+    match contents.len() {
+        1 => content(contents.remove(0)),
+        n => Err(format!(
+            "Expected one QR code or the parts of one, found {} codes",
+            n
+        )
+        .into()),
+    }
+}
+
+/// Decode the content of the parts of a UR
+fn ur_content(parts: &[String]) -> Result<QrContent> {
+    let parts: Vec<String> = parts.iter().map(|p| p.to_lowercase()).collect();
+    let ur = ur::decode(&parts)?;
+    debug!("decoded UR {}", ur.ur_type);
+    match ur.ur_type.as_str() {
+        CRYPTO_PSBT => Ok(QrContent::Psbt(ur.to_psbt()?)),
+        // data too big for structured append QR codes
+        BYTES => content(ur.to_bytes()?),
+        CRYPTO_OUTPUT => Ok(QrContent::Text(ur.to_descriptor()?)),
+        CRYPTO_ACCOUNT => {
+            // keys for multisig are in the `wsh(cosigner(KEY))` descriptor
+            let (prefix, suffix) = ("wsh(cosigner(", "))");
+            let (_, descriptors) = ur.to_account()?;
+            let key = descriptors
+                .iter()
+                .find(|d| d.starts_with(prefix) && d.ends_with(suffix))
+                .map(|d| d[prefix.len()..d.len() - suffix.len()].to_string())
+                .ok_or_else(|| Error::UrInvalid("missing cosigner key".to_string()))?;
+            Ok(QrContent::Text(key))
+        }
+        other => Err(Error::UrUnexpectedType(
+            format!(
+                "{}, {}, {} or {}",
+                CRYPTO_PSBT, CRYPTO_OUTPUT, CRYPTO_ACCOUNT, BYTES
+            ),
+            other.to_string(),
+        )),
+    }
+}
+
+/// The content of the parts of a UR, the PSBT bytes or the text, for readers of QR codes other
+/// than the ones of `import-qr`
+pub fn merge_urs(parts: &[String]) -> Result<Vec<u8>> {
+    match ur_content(parts)? {
+        QrContent::Psbt(bytes) => Ok(bytes),
+        QrContent::Text(text) => Ok(text.into_bytes()),
+    }
+}
+
+fn content(bytes: Vec<u8>) -> Result<QrContent> {
+    let bytes = decompress(bytes)?;
+    if bytes.starts_with(b"psbt\xff") {
+        Ok(QrContent::Psbt(bytes))
+    } else {
+        Ok(QrContent::Text(std::str::from_utf8(&bytes)?.to_string()))
+    }
+}
+
+fn import_psbt(
+    datadir: &str,
+    network: Network,
+    bytes: &[u8],
+    opt: &ImportQrOptions,
+) -> Result<ImportQrOutput> {
+    let opt = SavePSBTOptions {
+        psbt: StringEncoding::Base64(base64::encode(bytes)),
+        qr_version: opt.qr_version,
+        qr_style: opt.qr_style.clone(),
+    };
+    let (file, qr_files) = save_psbt_opt(datadir, network, &opt)?;
+    Ok(ImportQrOutput {
+        kind: Kind::PSBT,
+        file,
+        qr_files,
+    })
+}
+
+/// Save the public key in the keys directory, like the `public.json` of the keys created here
+fn import_key(
+    datadir: &str,
+    network: Network,
+    key: &PublicMasterKey,
+    opt: &ImportQrOptions,
+) -> Result<ImportQrOutput> {
+    check_compatibility(network, key.xpub.network)?;
+    let name = opt
+        .name
+        .clone()
+        .unwrap_or_else(|| key.key_origin().fingerprint.to_string());
+    let path_builder = PathBuilder::new(datadir, network, Kind::Key, Some(name));
+    let file = path_builder.file("public.json")?;
+    save_public(key, &file)?;
+    let qr_files = save_qrs(
+        key.to_string().as_bytes().to_vec(),
+        path_builder.file("qr")?,
+        opt.qr_version,
+        &opt.qr_style,
+    )?;
+    Ok(ImportQrOutput {
+        kind: Kind::Key,
+        file,
+        qr_files,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::offline::import_qr::*;
+    use crate::offline::import_wallet::ImportWalletOptions;
+    use crate::offline::random::{self, RandomOptions};
+    use crate::offline::sign::{get_psbt_name, save_psbt};
+    use crate::qr::{save_ur_qrs, QrEcLevel};
+    use crate::ur::Ur;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_import_qr() {
+        let temp_dir = TempDir::new("test_import_qr").unwrap();
+        let datadir = format!("{}/", temp_dir.path().display());
+        let bytes = include_bytes!("../../test_data/sign/psbt_testnet.1.signed.json");
+        let psbt_file = temp_dir.path().join("psbt.json");
+        std::fs::write(&psbt_file, &bytes[..]).unwrap();
+        let psbt = read_psbt(&psbt_file).unwrap();
+        let (psbt_bytes, _) = psbt_to_base64(&psbt);
+        let opt = |files: Vec<PathBuf>| ImportQrOptions {
+            files,
+            name: None,
+            qr_version: 14,
+            qr_style: QrStyle::default(),
+        };
+
+        // split in structured append QR codes
+        let qr_dir = temp_dir.path().join("qr");
+        let qr_files =
+            save_qrs(psbt_bytes.clone(), qr_dir.clone(), 5, &QrStyle::default()).unwrap();
+        assert!(qr_files.len() > 1);
+        let imported = start(&datadir, Network::Testnet, &opt(vec![qr_dir])).unwrap();
+        assert_eq!(imported.kind, Kind::PSBT);
+        let imported_psbt = read_psbt(&imported.file).unwrap();
+        // a name is given to PSBTs without one
+        assert_eq!(get_psbt_name(&imported_psbt), Some("psbt-0".to_string()));
+        assert_eq!(imported_psbt.global.unsigned_tx, psbt.global.unsigned_tx);
+        assert_eq!(imported_psbt.inputs, psbt.inputs);
+        assert!(!imported.qr_files.is_empty());
+        let partial = opt(qr_files[1..].to_vec());
+        assert!(start(&datadir, Network::Testnet, &partial).is_err());
+
+        // animated UR, missing the first frame
+        let ur_dir = temp_dir.path().join("qr-ur");
+        let ur_files = save_ur_qrs(
+            &Ur::from_psbt(&psbt_bytes),
+            ur_dir.clone(),
+            5,
+            &QrStyle::default(),
+        )
+        .unwrap();
+        let imported = start(&datadir, Network::Testnet, &opt(ur_files[1..].to_vec())).unwrap();
+        assert_eq!(imported.kind, Kind::PSBT);
+        assert_eq!(read_psbt(&imported.file).unwrap(), imported_psbt);
+
+        // UR parts scanned by other readers, as in the android app
+        let parts: Vec<String> = std::fs::read_to_string(ur_dir.join("ur.txt"))
+            .unwrap()
+            .lines()
+            .map(|l| l.to_uppercase())
+            .collect();
+        assert_eq!(merge_urs(&parts[1..]).unwrap(), psbt_bytes);
+        assert!(merge_urs(&parts[..1]).is_err());
+
+        // PSBT needing more than 16 structured append QR codes, saved as an animated UR
+        let bytes = include_bytes!("../../test_data/sign/psbt_bip.signed.json");
+        std::fs::write(&psbt_file, &bytes[..]).unwrap();
+        let mut big_psbt = read_psbt(&psbt_file).unwrap();
+        let style = QrStyle {
+            qr_ec_level: QrEcLevel::M,
+            ..Default::default()
+        };
+        let mut psbts_dir = temp_dir.path().join("big");
+        std::fs::create_dir(&psbts_dir).unwrap();
+        let (_, qr_files) = save_psbt(&mut big_psbt, &mut psbts_dir, 5, &style).unwrap();
+        assert!(qr_files.len() > 16);
+        // another data dir, the name given to the PSBT is already used in `datadir`
+        let big_dir = TempDir::new("test_import_qr_big").unwrap();
+        let big_datadir = format!("{}/", big_dir.path().display());
+        let imported = start(&big_datadir, Network::Testnet, &opt(qr_files)).unwrap();
+        assert_eq!(imported.kind, Kind::PSBT);
+        let imported_psbt = read_psbt(&imported.file).unwrap();
+        assert_eq!(imported_psbt.global, big_psbt.global);
+        assert_eq!(imported_psbt.inputs, big_psbt.inputs);
+
+        // public key of a key created in another data dir
+        let other_dir = TempDir::new("test_import_qr_other").unwrap();
+        let other_datadir = format!("{}/", other_dir.path().display());
+        let rand_opts = RandomOptions::new("cosigner".to_string());
+        let key = random::create_key(&other_datadir, Network::Testnet, &rand_opts).unwrap();
+        let imported = start(&datadir, Network::Testnet, &opt(key.public_qr_files)).unwrap();
+        assert_eq!(imported.kind, Kind::Key);
+        // the key QR contains the bare xpub of the public.json
+        let read_public = |path: &PathBuf| -> PublicMasterKey {
+            serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap()
+        };
+        assert_eq!(
+            read_public(&imported.file).xpub,
+            read_public(&key.public_file.clone().unwrap()).xpub
+        );
+        let expected_name = read_public(&key.public_file.clone().unwrap())
+            .xpub
+            .fingerprint()
+            .to_string();
+        assert!(imported.file.to_str().unwrap().contains(&expected_name));
+        assert!(start(&datadir, Network::Bitcoin, &opt(imported.qr_files.clone())).is_err());
+
+        // wallet QR of a wallet imported in another data dir
+        let wallet_opt = ImportWalletOptions {
+            file: None,
+            descriptor: Some(format!(
+                "wsh(multi(1,{}/0/*,{}/0/*))",
+                key.key.bip48_account(0).unwrap(),
+                key.key.bip48_account(1).unwrap()
+            )),
+            wallet_name: Some("qr-wallet".to_string()),
+            qr_version: 14,
+            qr_style: QrStyle::default(),
+        };
+        let wallet =
+            crate::offline::import_wallet::start(&other_datadir, Network::Testnet, &wallet_opt)
+                .unwrap();
+        let imported = start(&datadir, Network::Testnet, &opt(wallet.qr_files)).unwrap();
+        assert_eq!(imported.kind, Kind::Wallet);
+        assert_eq!(read_wallet(&imported.file).unwrap(), wallet.wallet);
+    }
+}
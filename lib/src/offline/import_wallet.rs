@@ -0,0 +1,434 @@
+use crate::offline::create_wallet::multi_descriptors;
+use crate::offline::descriptor::{extract_keys, required_sig, strip_checksum, with_checksum};
+use crate::offline::export_wallet::from_slip132;
+use crate::qr::{save_qrs, QrStyle};
+use crate::*;
+use bitcoin::util::bip32::{DerivationPath, Fingerprint};
+use bitcoin::Network;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use structopt::StructOpt;
+
+/// Import a wallet created with firma or another wallet or coordinator
+#[derive(StructOpt, Debug, Serialize, Deserialize)]
+#[structopt(name = "import_wallet")]
+pub struct ImportWalletOptions {
+    /// File containing the wallet: firma descriptor.json, Coldcard/Passport multisig setup,
+    /// Specter/Sparrow json, Electrum wallet file, bitcoin core descriptors or raw descriptors,
+    /// one per line, the change one is optional
+    #[structopt(
+        long,
+        parse(from_os_str),
+        required_unless = "descriptor",
+        conflicts_with = "descriptor"
+    )]
+    pub file: Option<PathBuf>,
+
+    /// Main descriptor of the wallet, the change descriptor is obtained replacing `/0/*` with `/1/*`
+    #[structopt(long)]
+    pub descriptor: Option<String>,
+
+    /// Name of the imported wallet, required if the format doesn't contain one
+    #[structopt(short, long)]
+    pub wallet_name: Option<String>,
+
+    /// QR code max version to use (max size)
+    #[structopt(long, default_value = "14")]
+    pub qr_version: i16,
+
+    #[structopt(flatten)]
+    #[serde(flatten)]
+    pub qr_style: QrStyle,
+}
+
+pub fn start(
+    datadir: &str,
+    network: Network,
+    opt: &ImportWalletOptions,
+) -> Result<CreateWalletOutput> {
+    let content = match (&opt.file, &opt.descriptor) {
+        (Some(file), _) => fs::read_to_string(file)?,
+        (None, Some(descriptor)) => descriptor.clone(),
+        (None, None) => return Err("--file or --descriptor is required".into()),
+    };
+    let wallet = parse_wallet(&content, opt.wallet_name.as_deref())?;
+    import_wallet(datadir, network, &wallet, opt.qr_version, &opt.qr_style)
+}
+
+/// Save `wallet` in the data dir after checking its keys are for `network`
+pub fn import_wallet(
+    datadir: &str,
+    network: Network,
+    wallet: &WalletJson,
+    qr_version: i16,
+    qr_style: &QrStyle,
+) -> Result<CreateWalletOutput> {
+    for descriptor in [&wallet.descriptor_main, &wallet.descriptor_change].iter() {
+        for key in extract_keys(descriptor)? {
+            check_compatibility(network, key.xpub.network)?;
+        }
+    }
+    let context = Context {
+        firma_datadir: datadir.to_string(),
+        network,
+        wallet_name: wallet.name.clone(),
+    };
+    let wallet_file = context.save_wallet(wallet)?;
+    let mut wallet_for_qr = wallet.clone();
+    wallet_for_qr.daemon_opts = None; // no need of this info in the qr code
+    let qr_bytes = serde_json::to_vec(&wallet_for_qr)?;
+    let qr_files = save_qrs(
+        qr_bytes,
+        context.path_for_wallet_qr()?,
+        qr_version,
+        qr_style,
+    )?;
+
+    Ok(CreateWalletOutput {
+        qr_files,
+        wallet_file,
+        wallet: wallet_for_qr,
+    })
+}
+
+/// Parse a wallet in one of the supported formats, the format is detected from the content.
+/// `name`, if present, overrides the name contained in the wallet
+pub fn parse_wallet(content: &str, name: Option<&str>) -> Result<WalletJson> {
+    let content = content.trim();
+    let (wallet_name, descriptors, created_at_height, policy) =
+        if content.starts_with('{') || content.starts_with('[') {
+            let value: Value = serde_json::from_str(content)?;
+            if value.get("descriptor_main").is_some() {
+                let wallet: WalletJson = serde_json::from_value(value)?;
+                debug!("firma wallet {}", wallet.name);
+                let descriptors = (wallet.descriptor_main, Some(wallet.descriptor_change));
+                (
+                    Some(wallet.name),
+                    descriptors,
+                    wallet.created_at_height,
+                    wallet.policy,
+                )
+            } else if value.get("wallet_type").is_some() {
+                (None, from_electrum(&value)?, 0, None)
+            } else if let Some(descriptor) = value.get("descriptor").and_then(Value::as_str) {
+                debug!("specter wallet");
+                let label = value.get("label").and_then(Value::as_str).map(String::from);
+                let height = value.get("blockheight").and_then(Value::as_u64);
+                let descriptors = (descriptor.to_string(), None);
+                (label, descriptors, height.unwrap_or(0), None)
+            } else if let Some(descriptors) = value.get("descriptors") {
+                debug!("bitcoin core listdescriptors");
+                let name = value.get("wallet_name").and_then(Value::as_str);
+                (name.map(String::from), from_core(descriptors)?, 0, None)
+            } else if value.is_array() {
+                debug!("bitcoin core importdescriptors");
+                (None, from_core(&value)?, 0, None)
+            } else {
+                return Err("Unrecognized wallet format".into());
+            }
+        } else if content.starts_with("wsh(") {
+            debug!("raw descriptors");
+            let mut lines = content.lines().map(str::trim).filter(|l| !l.is_empty());
+            let main = lines.next().unwrap_or_default().to_string();
+            let change = lines.next().map(String::from);
+            (None, (main, change), 0, None)
+        } else {
+            let (name, descriptor) = from_coldcard(content)?;
+            (Some(name), (descriptor, None), 0, None)
+        };
+
+    let name = name
+        .map(String::from)
+        .or(wallet_name)
+        .ok_or("The wallet doesn't contain a name, --wallet-name is required")?;
+    let (descriptor_main, descriptor_change) = match descriptors {
+        (main, Some(change)) => (main, change),
+        (main, None) => change_descriptor(&main)?,
+    };
+    let descriptor_main = with_checksum(&descriptor_main)?;
+    let descriptor_change = with_checksum(&descriptor_change)?;
+
+    let keys = extract_keys(&descriptor_main)?;
+    if keys.is_empty() || keys != extract_keys(&descriptor_change)? {
+        return Err("Main and change descriptors must contain the same extended keys".into());
+    }
+    let (main_sig, change_sig) = (
+        required_sig(&descriptor_main)?,
+        required_sig(&descriptor_change)?,
+    );
+    if main_sig != change_sig {
+        return Err("Main and change descriptors must require the same signatures".into());
+    }
+
+    Ok(WalletJson {
+        name,
+        descriptor_main,
+        descriptor_change,
+        fingerprints: keys.iter().map(|k| k.key_origin().fingerprint).collect(),
+        required_sig: main_sig,
+        policy,
+        daemon_opts: None,
+        created_at_height,
+    })
+}
+
+/// main and change descriptors from a descriptor with `/0/*` or `/<0;1>/*` derivations, the
+/// checksum is validated and dropped since it doesn't match the derived descriptors
+fn change_descriptor(descriptor: &str) -> Result<(String, String)> {
+    let descriptor = strip_checksum(descriptor)?;
+    if descriptor.contains("/<0;1>/*") {
+        return Ok((
+            descriptor.replace("/<0;1>/*", "/0/*"),
+            descriptor.replace("/<0;1>/*", "/1/*"),
+        ));
+    }
+    if !descriptor.contains("/0/*") {
+        return Err("Cannot derive the change descriptor, keys must end with /0/*".into());
+    }
+    Ok((descriptor.to_string(), descriptor.replace("/0/*", "/1/*")))
+}
+
+/// Coldcard multisig setup file, returns the name and the main descriptor
+fn from_coldcard(content: &str) -> Result<(String, String)> {
+    let mut name = None;
+    let mut policy = None;
+    let mut derivation = None;
+    let mut keys = vec![];
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut parts = line.splitn(2, ':').map(str::trim);
+        let (label, value) = match (parts.next(), parts.next()) {
+            (Some(label), Some(value)) => (label, value),
+            _ => return Err(format!("Invalid line in Coldcard file: {}", line).into()),
+        };
+        match label.to_lowercase().as_str() {
+            "name" => name = Some(value.to_string()),
+            "policy" => policy = Some(value.to_string()),
+            "derivation" => derivation = Some(DerivationPath::from_str(value)?),
+            "format" if value.eq_ignore_ascii_case("p2wsh") => (),
+            "format" => return Err(format!("Format {} not supported, only P2WSH", value).into()),
+            _ => {
+                let fingerprint = Fingerprint::from_str(label)
+                    .map_err(|_| format!("Invalid line in Coldcard file: {}", line))?;
+                let path = derivation
+                    .clone()
+                    .ok_or("Derivation is required before the keys")?;
+                keys.push(PublicMasterKey {
+                    xpub: from_slip132(value)?,
+                    origin: Some(KeyOrigin { fingerprint, path }),
+                    account_xpub: None,
+                });
+            }
+        }
+    }
+    let name = name.ok_or("Name missing in Coldcard file")?;
+    let policy = policy.ok_or("Policy missing in Coldcard file")?;
+    let mut parts = policy
+        .split(|c| c == '/' || c == ' ')
+        .filter(|s| *s != "of");
+    let (required_sig, total) = match (parts.next(), parts.next()) {
+        (Some(m), Some(n)) => (m.parse::<usize>(), n.parse::<usize>()),
+        _ => return Err(format!("Invalid policy {}", policy).into()),
+    };
+    let (required_sig, total) = match (required_sig, total) {
+        (Ok(m), Ok(n)) if m > 0 && m <= n => (m, n),
+        _ => return Err(format!("Invalid policy {}", policy).into()),
+    };
+    if total != keys.len() {
+        return Err(format!("Policy {} but {} keys", policy, keys.len()).into());
+    }
+    debug!("coldcard wallet {}", name);
+    let descriptors = multi_descriptors(required_sig, &keys, true);
+    Ok((name, descriptors[0].clone()))
+}
+
+/// Electrum multisig wallet file, returns main and change descriptors
+fn from_electrum(value: &Value) -> Result<(String, Option<String>)> {
+    let wallet_type = value["wallet_type"].as_str().unwrap_or_default();
+    let mut parts = wallet_type.split("of").map(str::parse::<usize>);
+    let (required_sig, total) = match (parts.next(), parts.next()) {
+        (Some(Ok(m)), Some(Ok(n))) if m > 0 && m <= n => (m, n),
+        _ => return Err("Only multisig Electrum wallets are supported".into()),
+    };
+    let mut keys = vec![];
+    for i in 1..=total {
+        let keystore = &value[format!("x{}/", i)];
+        let xpub = keystore["xpub"]
+            .as_str()
+            .ok_or_else(|| format!("Electrum keystore x{}/ missing", i))?;
+        let xpub = from_slip132(xpub)?;
+        let origin = match (
+            keystore["root_fingerprint"].as_str(),
+            keystore["derivation"].as_str(),
+        ) {
+            (Some(fingerprint), Some(path)) => Some(KeyOrigin {
+                fingerprint: Fingerprint::from_str(fingerprint)
+                    .map_err(|e| Error::Generic(e.to_string()))?,
+                path: DerivationPath::from_str(path)?,
+            }),
+            _ => None,
+        };
+        keys.push(PublicMasterKey {
+            xpub,
+            origin,
+            account_xpub: None,
+        });
+    }
+    debug!("electrum wallet {}", wallet_type);
+    let mut descriptors = multi_descriptors(required_sig, &keys, true).into_iter();
+    Ok((descriptors.next().unwrap_or_default(), descriptors.next()))
+}
+
+/// Bitcoin core descriptors as in `importdescriptors` or `listdescriptors`, returns main and
+/// change descriptors
+fn from_core(value: &Value) -> Result<(String, Option<String>)> {
+    let requests = value.as_array().ok_or("Descriptors must be an array")?;
+    let find = |internal: bool| {
+        requests
+            .iter()
+            .filter(|r| r["internal"].as_bool().unwrap_or(false) == internal)
+            .filter_map(|r| r["desc"].as_str())
+            .find(|d| d.starts_with("wsh("))
+            .map(String::from)
+    };
+    let main = find(false).ok_or("No external wsh descriptor found")?;
+    Ok((main, find(true)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::offline::descriptor::with_checksum;
+    use crate::offline::export_wallet::{export_wallet, ExportFormat};
+    use crate::offline::import_wallet::{self, parse_wallet, ImportWalletOptions};
+    use crate::qr::QrStyle;
+    use crate::*;
+    use bitcoin::Network;
+    use std::str::FromStr;
+    use tempdir::TempDir;
+
+    const XPUBS: [&str; 2] = [
+        "tpubD6NzVbkrYhZ4YfG9CySHqKHFbaLcD7hSDyqRUtCmMKNim5fkiJtTnFeqKsRHMHSK5ddFrhqRr3Ghv1JtuWkBzikuBqKu1xCpjQ9YxoPGgqU",
+        "tpubD6NzVbkrYhZ4WpudNKLizFbGzpsG3jkLF7mc8Vfh1fTDbbBPjDP29My6TaLncaS8VeDPcaNMdUkybucr8Kz9CHSdAtvxnaXyBxPRocefdXN",
+    ];
+
+    fn descriptor(c: &str) -> String {
+        format!(
+            "wsh(sortedmulti(2,[d34db33f/48'/1'/0'/2']{}/{}/*,[a0b1c2d3/48'/1'/0'/2']{}/{}/*))",
+            XPUBS[0], c, XPUBS[1], c
+        )
+    }
+
+    #[test]
+    fn test_parse_wallet() {
+        let wallet = parse_wallet(&descriptor("0"), Some("raw")).unwrap();
+        assert_eq!(
+            wallet.descriptor_change,
+            with_checksum(&descriptor("1")).unwrap()
+        );
+        assert_eq!(wallet.required_sig, 2);
+        assert!(wallet.fingerprints.contains(&"a0b1c2d3".parse().unwrap()));
+        assert_eq!(
+            parse_wallet(&descriptor("<0;1>"), Some("raw")).unwrap(),
+            wallet
+        );
+        let both = format!("{}\n{}\n", descriptor("0"), descriptor("1"));
+        assert_eq!(parse_wallet(&both, Some("raw")).unwrap(), wallet);
+        assert!(parse_wallet(&descriptor("0"), None).is_err());
+        assert!(parse_wallet(&descriptor("2"), Some("raw")).is_err());
+        let other_keys = format!("{}\nwsh(pk({}/1/*))", descriptor("0"), XPUBS[0]);
+        assert!(parse_wallet(&other_keys, Some("raw")).is_err());
+
+        let json = serde_json::to_string(&wallet).unwrap();
+        assert_eq!(parse_wallet(&json, None).unwrap(), wallet);
+        let renamed = parse_wallet(&json, Some("renamed")).unwrap();
+        assert_eq!(renamed.name, "renamed");
+
+        let specter = format!(
+            r#"{{"label": "specter", "blockheight": 1000, "descriptor": "{}"}}"#,
+            with_checksum(&descriptor("0")).unwrap()
+        );
+        let wrong_checksum = specter.replace("\"}", "x\"}");
+        assert!(parse_wallet(&wrong_checksum, None).is_err());
+        let specter = parse_wallet(&specter, None).unwrap();
+        assert_eq!(specter.name, "specter");
+        assert_eq!(specter.created_at_height, 1000);
+        assert_eq!(specter.descriptor_main, wallet.descriptor_main);
+
+        let core = format!(
+            r#"{{"wallet_name": "core", "descriptors": [{{"desc": "{}", "internal": true}},
+            {{"desc": "{}", "internal": false}}]}}"#,
+            descriptor("1"),
+            descriptor("0")
+        );
+        let core = parse_wallet(&core, None).unwrap();
+        assert_eq!(core.descriptor_main, wallet.descriptor_main);
+        assert_eq!(core.descriptor_change, wallet.descriptor_change);
+
+        let coldcard = format!(
+            "# comment\nName: cc\nPolicy: 2 of 2\nFormat: P2WSH\nDerivation: m/48'/1'/0'/2'\n\n\
+            D34DB33F: {}\nA0B1C2D3: {}\n",
+            XPUBS[0], XPUBS[1]
+        );
+        let cc = parse_wallet(&coldcard, None).unwrap();
+        assert_eq!(cc.name, "cc");
+        assert_eq!(cc.descriptor_main, wallet.descriptor_main);
+        let p2sh = coldcard.replace("Format: P2WSH", "Format: P2SH-P2WSH");
+        assert!(parse_wallet(&p2sh, None).is_err());
+        let wrong_policy = coldcard.replace("2 of 2", "2 of 3");
+        assert!(parse_wallet(&wrong_policy, None).is_err());
+
+        assert!(parse_wallet(r#"{"some": "json"}"#, Some("name")).is_err());
+        assert!(parse_wallet("not a wallet", Some("name")).is_err());
+    }
+
+    #[test]
+    fn test_import_wallet() {
+        let temp_dir = TempDir::new("test_import_wallet").unwrap();
+        let temp_dir_str = format!("{}/", temp_dir.path().display());
+        let wallet = parse_wallet(&descriptor("0"), Some("orig")).unwrap();
+
+        let formats = ["coldcard", "specter", "electrum", "core"];
+        for (i, format) in formats.iter().enumerate() {
+            let format = ExportFormat::from_str(format).unwrap();
+            let export = export_wallet(
+                &temp_dir_str,
+                Network::Testnet,
+                &wallet,
+                format,
+                14,
+                &QrStyle::default(),
+            );
+            let opt = ImportWalletOptions {
+                file: Some(export.unwrap().file),
+                descriptor: None,
+                wallet_name: Some(format!("imported-{}", i)),
+                qr_version: 14,
+                qr_style: QrStyle::default(),
+            };
+            let imported = import_wallet::start(&temp_dir_str, Network::Testnet, &opt).unwrap();
+            assert_eq!(imported.wallet.descriptor_main, wallet.descriptor_main);
+            assert_eq!(imported.wallet.descriptor_change, wallet.descriptor_change);
+            assert_eq!(imported.wallet.fingerprints, wallet.fingerprints);
+            assert_eq!(read_wallet(&imported.wallet_file).unwrap(), imported.wallet);
+            assert!(!imported.qr_files.is_empty());
+            // importing twice with the same name fails
+            assert!(import_wallet::start(&temp_dir_str, Network::Testnet, &opt).is_err());
+        }
+
+        let opt = ImportWalletOptions {
+            file: None,
+            descriptor: Some(descriptor("0")),
+            wallet_name: Some("mainnet".to_string()),
+            qr_version: 14,
+            qr_style: QrStyle::default(),
+        };
+        assert!(import_wallet::start(&temp_dir_str, Network::Bitcoin, &opt).is_err());
+        assert!(import_wallet::start(&temp_dir_str, Network::Testnet, &opt).is_ok());
+    }
+}
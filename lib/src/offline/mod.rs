@@ -1,7 +1,19 @@
+pub mod add_to_psbt;
+pub mod convert_psbt;
+pub mod create_wallet;
 pub mod derive_key;
 pub mod descriptor;
 pub mod dice;
+pub mod encrypt_key;
+pub mod export_account;
+pub mod export_wallet;
+pub mod finalize;
+pub mod import_qr;
+pub mod import_wallet;
 pub mod print;
 pub mod random;
 pub mod restore;
 pub mod sign;
+pub mod spending_policy;
+pub mod split_key;
+pub mod verify_address;
@@ -1,17 +1,18 @@
-use crate::list::ListOptions;
-use crate::offline::descriptor::{derive_address, DeriveAddressOpts};
+use crate::offline::descriptor::{
+    at_timelocks, derive_address, lift_witness_script, unsatisfied_timelocks, DeriveAddressOpts,
+    HDKeypaths,
+};
+use crate::offline::sign::invalid_signatures;
 use crate::*;
 use bitcoin::consensus::serialize;
 use bitcoin::util::bip32::{ChildNumber, DerivationPath, Fingerprint};
-use bitcoin::util::key;
 use bitcoin::{Address, Amount, Network, OutPoint, Script, SignedAmount, TxOut};
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{HashMap, HashSet};
+use std::fs;
 use std::path::PathBuf;
 use structopt::StructOpt;
 
-type HDKeypaths = BTreeMap<key::PublicKey, (Fingerprint, DerivationPath)>;
-
 /// Sign a Partially Signed Bitcoin Transaction (PSBT) with a key.
 #[derive(StructOpt, Debug, Serialize, Deserialize)]
 #[structopt(name = "firma")]
@@ -22,39 +23,36 @@ pub struct PrintOptions {
 
 pub fn start(datadir: &str, network: Network, opt: &PrintOptions) -> Result<PsbtPrettyPrint> {
     let psbt = read_psbt(&opt.psbt_file)?;
-    let kind = Kind::Wallet;
-    let opt = ListOptions { kind };
-    let result = common::list::list(datadir, network, &opt)?;
-    let wallets: Vec<WalletJson> = result.wallets.iter().map(|w| w.wallet.clone()).collect();
+    let wallets = read_wallets(datadir, network)?;
     let output = pretty_print(&psbt, network, &wallets)?;
     Ok(output)
 }
 
+/// the wallets in the data dir, unlike listing them a wallet that can't be read is an error, so
+/// that the outputs of a corrupted wallet are not shown as foreign
+fn read_wallets(datadir: &str, network: Network) -> Result<Vec<WalletJson>> {
+    let path = PathBuilder::new(datadir, network, Kind::Wallet, None).type_path()?;
+    let mut wallets = vec![];
+    if path.is_dir() {
+        for entry in fs::read_dir(path)? {
+            let wallet_file = entry?.path().join("descriptor.json");
+            if wallet_file.exists() {
+                wallets.push(read_wallet(&wallet_file)?);
+            }
+        }
+    }
+    Ok(wallets)
+}
+
 pub fn pretty_print(
     psbt: &PSBT,
     network: Network,
     wallets: &[WalletJson],
 ) -> Result<PsbtPrettyPrint> {
     let mut result = PsbtPrettyPrint::default();
-    let mut previous_outputs: Vec<TxOut> = vec![];
     let mut output_values: Vec<u64> = vec![];
     let tx = &psbt.global.unsigned_tx;
-    let vouts: Vec<OutPoint> = tx.input.iter().map(|el| el.previous_output).collect();
-    for (i, input) in psbt.inputs.iter().enumerate() {
-        let previous_output = match (&input.non_witness_utxo, &input.witness_utxo) {
-            (_, Some(val)) => val,
-            (Some(prev_tx), None) => {
-                let outpoint = *vouts.get(i).ok_or_else(|| Error::MissingOutpoint)?;
-                assert_eq!(prev_tx.txid(), outpoint.txid);
-                prev_tx
-                    .output
-                    .get(outpoint.vout as usize)
-                    .ok_or_else(|| Error::MissingTxout)?
-            }
-            _ => return Err("witness_utxo and non_witness_utxo are both None".into()),
-        };
-        previous_outputs.push(previous_output.clone());
-    }
+    let previous_outputs = previous_outputs(psbt)?;
     let input_values: Vec<u64> = previous_outputs.iter().map(|o| o.value).collect();
     let mut balances = HashMap::new();
 
@@ -62,11 +60,19 @@ pub fn pretty_print(
         let addr = Address::from_script(&previous_outputs[i].script_pubkey, network)
             .ok_or_else(|| Error::NonDefaultScript)?;
         let keypaths = &psbt.inputs[i].hd_keypaths;
+        let invalid_signatures = invalid_signatures(psbt, i)?;
         let signatures: HashSet<Fingerprint> = psbt.inputs[i]
             .partial_sigs
             .iter()
+            .filter(|(k, _)| !invalid_signatures.contains(k))
             .filter_map(|(k, _)| keypaths.get(k).map(|v| v.0))
             .collect();
+        if !invalid_signatures.is_empty() {
+            result.info.push(format!(
+                "Input #{} has invalid signatures, the PSBT must not be trusted",
+                i
+            ));
+        }
         let wallet_if_any = wallet_with_path(keypaths, &wallets, &addr);
         if let Some((wallet, _)) = &wallet_if_any {
             *balances.entry(wallet.clone()).or_insert(0i64) -= previous_outputs[i].value as i64
@@ -74,6 +80,8 @@ pub fn pretty_print(
         let txin = json::TxIn {
             outpoint: input.previous_output.to_string(),
             signatures,
+            invalid_signatures,
+            sighash: psbt.inputs[i].sighash_type.map(Sighash::from),
             common: TxCommonInOut {
                 value: Amount::from_sat(previous_outputs[i].value).to_string(),
                 wallet_with_path: wallet_if_any.map(|(w, p)| format!("[{}]{}", w, p)),
@@ -100,6 +108,21 @@ pub fn pretty_print(
         result.outputs.push(txout);
         output_values.push(output.value);
     }
+    // Detect inputs with timelocks not satisfied by nSequence and nLockTime
+    for (i, input) in psbt.inputs.iter().enumerate() {
+        let witness_script = input.witness_script.as_ref();
+        if let Some(policy) = witness_script.and_then(lift_witness_script) {
+            let unsatisfied = unsatisfied_timelocks(&policy, tx, i);
+            if at_timelocks(policy, tx, i).is_unsatisfiable() {
+                result.info.push(format!(
+                    "Input #{} can't be spent until {} is satisfied by nSequence or nLockTime",
+                    i,
+                    unsatisfied.join(" or ")
+                ));
+            }
+        }
+    }
+
     let balances_vec: Vec<String> = balances
         .iter()
         .map(|(k, v)| format!("{}: {}", k, SignedAmount::from_sat(*v).to_string()))
@@ -171,6 +194,83 @@ pub fn pretty_print(
     Ok(result)
 }
 
+/// the outputs spent by the inputs of `psbt`
+fn previous_outputs(psbt: &PSBT) -> Result<Vec<TxOut>> {
+    let mut previous_outputs: Vec<TxOut> = vec![];
+    let vouts: Vec<OutPoint> = psbt
+        .global
+        .unsigned_tx
+        .input
+        .iter()
+        .map(|el| el.previous_output)
+        .collect();
+    for (i, input) in psbt.inputs.iter().enumerate() {
+        let previous_output = match (&input.non_witness_utxo, &input.witness_utxo) {
+            (_, Some(val)) => val,
+            (Some(prev_tx), None) => {
+                let outpoint = *vouts.get(i).ok_or_else(|| Error::MissingOutpoint)?;
+                assert_eq!(prev_tx.txid(), outpoint.txid);
+                prev_tx
+                    .output
+                    .get(outpoint.vout as usize)
+                    .ok_or_else(|| Error::MissingTxout)?
+            }
+            _ => return Err("witness_utxo and non_witness_utxo are both None".into()),
+        };
+        previous_outputs.push(previous_output.clone());
+    }
+    Ok(previous_outputs)
+}
+
+/// net balance of `wallet` in `psbt`, the value of its outputs minus the value of its inputs,
+/// it's the same value shown in `PsbtPrettyPrint.balances`
+pub fn wallet_balance(psbt: &PSBT, network: Network, wallet: &WalletJson) -> Result<SignedAmount> {
+    let wallets = std::slice::from_ref(wallet);
+    let tx = &psbt.global.unsigned_tx;
+    let mut balance = 0i64;
+    for (i, previous_output) in previous_outputs(psbt)?.iter().enumerate() {
+        let addr = Address::from_script(&previous_output.script_pubkey, network)
+            .ok_or_else(|| Error::NonDefaultScript)?;
+        if wallet_with_path(&psbt.inputs[i].hd_keypaths, wallets, &addr).is_some() {
+            balance -= previous_output.value as i64;
+        }
+    }
+    for (i, output) in tx.output.iter().enumerate() {
+        let addr = Address::from_script(&output.script_pubkey, network)
+            .ok_or_else(|| Error::NonDefaultScript)?;
+        if wallet_with_path(&psbt.outputs[i].hd_keypaths, wallets, &addr).is_some() {
+            balance += output.value as i64;
+        }
+    }
+    Ok(SignedAmount::from_sat(balance))
+}
+
+/// indexes of the outputs with key derivations of `wallet` whose address is not derived from the
+/// wallet descriptors, a malicious coordinator could present them as change
+pub fn foreign_change_outputs(
+    psbt: &PSBT,
+    network: Network,
+    wallet: &WalletJson,
+) -> Result<Vec<usize>> {
+    let wallets = std::slice::from_ref(wallet);
+    let mut result = vec![];
+    for (i, output) in psbt.global.unsigned_tx.output.iter().enumerate() {
+        let keypaths = &psbt.outputs[i].hd_keypaths;
+        if !keypaths
+            .values()
+            .any(|(finger, _)| wallet.fingerprints.contains(finger))
+        {
+            continue;
+        }
+        let addr = Address::from_script(&output.script_pubkey, network)
+            .ok_or_else(|| Error::NonDefaultScript)?;
+        if wallet_with_path(keypaths, wallets, &addr).is_none() {
+            result.push(i);
+        }
+    }
+    Ok(result)
+}
+
 fn biggest_dividing_pow(num: u64) -> u8 {
     let mut start = 10u64;
     let mut count = 0u8;
@@ -203,22 +303,27 @@ fn wallet_with_path(
     for wallet in wallets {
         for (_, (finger, path)) in hd_keypaths.iter() {
             if wallet.fingerprints.contains(finger) {
+                // the last two levels are the descriptor branch and the address index, the others
+                // are the origin path of the key
                 let path_vec: Vec<ChildNumber> = path.clone().into();
-                if let ChildNumber::Normal { index } = path_vec.first()? {
-                    let descriptor = match index {
+                let mut levels = path_vec.iter().rev();
+                if let (
+                    Some(ChildNumber::Normal { index }),
+                    Some(ChildNumber::Normal { index: int_or_ext }),
+                ) = (levels.next(), levels.next())
+                {
+                    let descriptor = match int_or_ext {
                         0 => &wallet.descriptor_main,
                         1 => &wallet.descriptor_change,
                         _ => return None,
                     };
-                    if let ChildNumber::Normal { index } = path_vec.last()? {
-                        let opts = DeriveAddressOpts {
-                            descriptor: descriptor.to_string(),
-                            index: *index,
-                        };
-                        if let Ok(derived) = derive_address(address.network, &opts, *index) {
-                            if &derived.address == address {
-                                return Some((wallet.name.clone(), path.clone()));
-                            }
+                    let opts = DeriveAddressOpts {
+                        descriptor: descriptor.to_string(),
+                        index: *index,
+                    };
+                    if let Ok(derived) = derive_address(address.network, &opts, *int_or_ext) {
+                        if &derived.address == address {
+                            return Some((wallet.name.clone(), path.clone()));
                         }
                     }
                 };
@@ -230,7 +335,13 @@ fn wallet_with_path(
 
 #[cfg(test)]
 mod tests {
-    use crate::offline::print::{biggest_dividing_pow, script_type};
+    use crate::offline::descriptor::with_checksum;
+    use crate::offline::print::{biggest_dividing_pow, script_type, start, PrintOptions};
+    use crate::{psbt_from_base64, psbt_to_base64, Error, PsbtJson, WalletJson};
+    use bitcoin::util::bip32::{DerivationPath, Fingerprint};
+    use bitcoin::{Address, Network, PublicKey};
+    use std::str::FromStr;
+    use tempdir::TempDir;
 
     #[test]
     fn test_biggest_dividing_pow() {
@@ -262,4 +373,84 @@ mod tests {
         let s = hex_script!("00201775ead41acefa14d2d534d6272da610cc35855d0de4cab0f5c1a3f894921989");
         assert_eq!(script_type(&s), Some(4usize));
     }
+
+    #[test]
+    fn test_wrong_descriptor_checksum() {
+        let temp_dir = TempDir::new("test_print_checksum").unwrap();
+        let datadir = format!("{}/", temp_dir.path().display());
+        let descriptor = "wsh(multi(2,tpubD6NzVbkrYhZ4YfG9CySHqKHFbaLcD7hSDyqRUtCmMKNim5fkiJtTnFeqKsRHMHSK5ddFrhqRr3Ghv1JtuWkBzikuBqKu1xCpjQ9YxoPGgqU/0/*,tpubD6NzVbkrYhZ4WpudNKLizFbGzpsG3jkLF7mc8Vfh1fTDbbBPjDP29My6TaLncaS8VeDPcaNMdUkybucr8Kz9CHSdAtvxnaXyBxPRocefdXN/0/*))";
+        let finger = Fingerprint::from(&[1u8, 2, 3, 4][..]);
+        let mut wallet = WalletJson {
+            name: "wallet".to_string(),
+            descriptor_main: with_checksum(descriptor).unwrap(),
+            descriptor_change: with_checksum(&descriptor.replace("/0/*", "/1/*")).unwrap(),
+            fingerprints: vec![finger].into_iter().collect(),
+            required_sig: 2,
+            policy: None,
+            daemon_opts: None,
+            created_at_height: 0,
+        };
+
+        // output #1 to the first main address of the wallet
+        let bytes = include_bytes!("../../test_data/sign/psbt_testnet.json");
+        let psbt_json: PsbtJson = serde_json::from_slice(bytes).unwrap();
+        let (_, mut psbt) = psbt_from_base64(&psbt_json.psbt).unwrap();
+        let address =
+            Address::from_str("tb1q5nrregep899vnvaa5vdpxcwg8794jqy38nu304kl4d7wm4e92yeqz4jfmk")
+                .unwrap();
+        psbt.global.unsigned_tx.output[1].script_pubkey = address.script_pubkey();
+        let pubkey = PublicKey::from_str(
+            "020ca0e815748c41087075f3840c1edd9400f4db031dbe948b1929b6a93c72386a",
+        )
+        .unwrap();
+        let path = DerivationPath::from_str("m/0/0").unwrap();
+        psbt.outputs[1].hd_keypaths.insert(pubkey, (finger, path));
+        let psbt_file = temp_dir.path().join("psbt.json");
+        let psbt_json = PsbtJson {
+            name: "psbt".to_string(),
+            psbt: psbt_to_base64(&psbt).1,
+        };
+        std::fs::write(&psbt_file, serde_json::to_vec(&psbt_json).unwrap()).unwrap();
+        let opt = PrintOptions { psbt_file };
+
+        let wallet_dir = temp_dir
+            .path()
+            .join("testnet")
+            .join("wallets")
+            .join("wallet");
+        std::fs::create_dir_all(&wallet_dir).unwrap();
+        let wallet_file = wallet_dir.join("descriptor.json");
+        std::fs::write(&wallet_file, serde_json::to_vec(&wallet).unwrap()).unwrap();
+        let output = start(&datadir, Network::Testnet, &opt).unwrap();
+        assert_eq!(
+            output.outputs[1].common.wallet_with_path,
+            Some("[wallet]m/0/0".to_string())
+        );
+
+        // the last checksum char replaced with another valid one
+        let checksum = wallet.descriptor_main.pop().unwrap();
+        let tampered = if checksum == 'q' { 'p' } else { 'q' };
+        wallet.descriptor_main.push(tampered);
+        std::fs::write(&wallet_file, serde_json::to_vec(&wallet).unwrap()).unwrap();
+        let expected = Error::WrongDescriptorChecksum(
+            wallet
+                .descriptor_main
+                .split('#')
+                .nth(1)
+                .unwrap()
+                .to_string(),
+            with_checksum(descriptor)
+                .unwrap()
+                .split('#')
+                .nth(1)
+                .unwrap()
+                .to_string(),
+        );
+        assert_eq!(
+            start(&datadir, Network::Testnet, &opt)
+                .unwrap_err()
+                .to_string(),
+            expected.to_string()
+        );
+    }
 }
@@ -1,3 +1,5 @@
+use crate::common::encryption;
+use crate::qr::QrStyle;
 use crate::*;
 use bitcoin::Network;
 use common::mnemonic::Mnemonic;
@@ -17,6 +19,26 @@ pub struct RandomOptions {
     #[structopt(long, default_value = "14")]
     #[serde(default)]
     pub qr_version: i16,
+
+    #[structopt(flatten)]
+    #[serde(flatten)]
+    pub qr_style: QrStyle,
+
+    /// File containing the passphrase to encrypt the private key file, if omitted the key is saved
+    /// unencrypted
+    #[structopt(
+        long = "encryption-passphrase-file",
+        parse(try_from_os_str = encryption::read_passphrase_file)
+    )]
+    pub encryption_passphrase: Option<String>,
+
+    /// File containing the BIP39 passphrase used to derive the seed from the mnemonic, the
+    /// passphrase is not saved and it's required again at sign time
+    #[structopt(
+        long = "bip39-passphrase-file",
+        parse(try_from_os_str = encryption::read_passphrase_file)
+    )]
+    pub bip39_passphrase: Option<String>,
 }
 
 impl RandomOptions {
@@ -24,6 +46,9 @@ impl RandomOptions {
         RandomOptions {
             key_name,
             qr_version: 20,
+            qr_style: QrStyle::default(),
+            encryption_passphrase: None,
+            bip39_passphrase: None,
         }
     }
 }
@@ -31,8 +56,21 @@ impl RandomOptions {
 pub fn create_key(datadir: &str, network: Network, opt: &RandomOptions) -> Result<MasterKeyOutput> {
     let sec = rand::thread_rng().gen::<[u8; 32]>();
     let mnemonic = Mnemonic::new(&sec)?;
-    let master_key = PrivateMasterKey::new(network, &mnemonic, &opt.key_name)?;
-    let output = save_keys(datadir, network, &opt.key_name, master_key, opt.qr_version)?;
+    let master_key = PrivateMasterKey::new(
+        network,
+        &mnemonic,
+        opt.bip39_passphrase.as_deref(),
+        &opt.key_name,
+    )?;
+    let output = save_keys(
+        datadir,
+        network,
+        &opt.key_name,
+        master_key,
+        opt.qr_version,
+        &opt.qr_style,
+        opt.encryption_passphrase.as_deref(),
+    )?;
 
     Ok(output)
 }
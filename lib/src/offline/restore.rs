@@ -1,4 +1,7 @@
+use crate::common::encryption;
 use crate::mnemonic::Mnemonic;
+use crate::qr::QrStyle;
+use crate::slip39;
 use crate::{check_compatibility, Result};
 use crate::{save_keys, MasterKeyOutput, PrivateMasterKey};
 use bitcoin::util::bip32::ExtendedPrivKey;
@@ -15,24 +18,46 @@ use structopt::StructOpt;
 pub struct RestoreOptions {
     /// Name of the key
     #[structopt(short, long)]
-    key_name: String,
+    pub key_name: String,
 
     /// Kind of the secret material
     #[structopt(short, long)]
-    nature: Nature,
+    pub nature: Nature,
 
     /// QR code max version to use (max size)
     #[structopt(long, default_value = "14")]
     pub qr_version: i16,
 
-    /// value of the secret component, could be xprv or seed in hex or bech32
-    value: String,
+    #[structopt(flatten)]
+    #[serde(flatten)]
+    pub qr_style: QrStyle,
+
+    /// File containing the passphrase to encrypt the private key file, if omitted the key is saved
+    /// unencrypted
+    #[structopt(
+        long = "encryption-passphrase-file",
+        parse(try_from_os_str = encryption::read_passphrase_file)
+    )]
+    pub encryption_passphrase: Option<String>,
+
+    /// File containing the BIP39 passphrase used to derive the seed from the mnemonic, the
+    /// passphrase is not saved and it's required again at sign time
+    #[structopt(
+        long = "bip39-passphrase-file",
+        parse(try_from_os_str = encryption::read_passphrase_file)
+    )]
+    pub bip39_passphrase: Option<String>,
+
+    /// value of the secret component, could be xprv or seed in hex or bech32, for slip39 the
+    /// mnemonic shares separated by comma
+    pub value: String,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
-enum Nature {
+pub enum Nature {
     Xprv,
     Mnemonic,
+    Slip39,
 }
 
 impl FromStr for Nature {
@@ -42,9 +67,10 @@ impl FromStr for Nature {
         match s {
             "xprv" => Ok(Nature::Xprv),
             "mnemonic" => Ok(Nature::Mnemonic),
+            "slip39" => Ok(Nature::Slip39),
             _ => Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
-                format!("({}) valid values are: xprv, mnemonic", s),
+                format!("({}) valid values are: xprv, mnemonic, slip39", s),
             )),
         }
     }
@@ -54,17 +80,49 @@ pub fn start(datadir: &str, network: Network, opt: &RestoreOptions) -> Result<Ma
     debug!("restore {:?}", &opt);
     let master_key = match opt.nature {
         Nature::Xprv => {
+            if opt.bip39_passphrase.is_some() {
+                return Err("BIP39 passphrase can be used only with mnemonic".into());
+            }
             let key = ExtendedPrivKey::from_str(&opt.value)?;
             check_compatibility(key.network, network)?;
             PrivateMasterKey::from_xprv(key, &opt.key_name)
         }
+        Nature::Slip39 => {
+            let shares: Vec<String> = opt
+                .value
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect();
+            let entropy = slip39::combine_mnemonics(&shares, b"")?;
+            let mnemonic = Mnemonic::new(&entropy)?;
+            PrivateMasterKey::new(
+                network,
+                &mnemonic,
+                opt.bip39_passphrase.as_deref(),
+                &opt.key_name,
+            )?
+        }
         Nature::Mnemonic => {
             let mnemonic = Mnemonic::from_str(&opt.value)?;
-            PrivateMasterKey::new(network, &mnemonic, &opt.key_name)?
+            PrivateMasterKey::new(
+                network,
+                &mnemonic,
+                opt.bip39_passphrase.as_deref(),
+                &opt.key_name,
+            )?
         }
     };
 
-    let output = save_keys(datadir, network, &opt.key_name, master_key, opt.qr_version)?;
+    let output = save_keys(
+        datadir,
+        network,
+        &opt.key_name,
+        master_key,
+        opt.qr_version,
+        &opt.qr_style,
+        opt.encryption_passphrase.as_deref(),
+    )?;
     Ok(output)
 }
 
@@ -72,6 +130,8 @@ pub fn start(datadir: &str, network: Network, opt: &RestoreOptions) -> Result<Ma
 mod tests {
     use crate::offline::random::RandomOptions;
     use crate::offline::restore::{Nature, RestoreOptions};
+    use crate::offline::sign::read_key;
+    use crate::qr::QrStyle;
     use bitcoin::Network;
     use tempdir::TempDir;
 
@@ -93,6 +153,9 @@ mod tests {
             nature: Nature::Xprv,
             value: key_orig.key.xprv.to_string(),
             qr_version: 14,
+            qr_style: QrStyle::default(),
+            encryption_passphrase: None,
+            bip39_passphrase: None,
         };
         let key_restored =
             crate::offline::restore::start(&temp_dir_str, Network::Testnet, &restore_opts).unwrap();
@@ -106,6 +169,9 @@ mod tests {
             nature: Nature::Mnemonic,
             value: key_orig.key.mnemonic.as_ref().unwrap().to_string(),
             qr_version: 14,
+            qr_style: QrStyle::default(),
+            encryption_passphrase: None,
+            bip39_passphrase: None,
         };
         let key_restored =
             crate::offline::restore::start(&temp_dir_str, Network::Testnet, &restore_opts).unwrap();
@@ -121,18 +187,43 @@ mod tests {
             nature: Nature::Xprv,
             value: "X".to_string(),
             qr_version: 14,
+            qr_style: QrStyle::default(),
+            encryption_passphrase: None,
+            bip39_passphrase: None,
         };
         let result = crate::offline::restore::start(&temp_dir_str, Network::Testnet, &restore_opts);
         assert!(result.is_err());
 
-        let (key_name, _name_counter) = (format!("{}", name_counter), name_counter + 1);
+        let (key_name, name_counter) = (format!("{}", name_counter), name_counter + 1);
         let restore_opts = RestoreOptions {
             key_name,
             nature: Nature::Xprv,
             value: key_orig.key.xpub.to_string(),
             qr_version: 14,
+            qr_style: QrStyle::default(),
+            encryption_passphrase: None,
+            bip39_passphrase: None,
         };
         let result = crate::offline::restore::start(&temp_dir_str, Network::Testnet, &restore_opts);
         assert!(result.is_err());
+
+        let (key_name, _name_counter) = (format!("{}", name_counter), name_counter + 1);
+        let restore_opts = RestoreOptions {
+            key_name,
+            nature: Nature::Mnemonic,
+            value: key_orig.key.mnemonic.as_ref().unwrap().to_string(),
+            qr_version: 14,
+            qr_style: QrStyle::default(),
+            encryption_passphrase: None,
+            bip39_passphrase: Some("25th".to_string()),
+        };
+        let key_restored =
+            crate::offline::restore::start(&temp_dir_str, Network::Testnet, &restore_opts).unwrap();
+        assert_ne!(key_orig.key.fingerprint, key_restored.key.fingerprint);
+        let content = std::fs::read_to_string(&key_restored.private_file).unwrap();
+        assert!(!content.contains(&key_restored.key.xprv.to_string()));
+        assert!(read_key(&key_restored.private_file, None, None).is_err());
+        let key_read = read_key(&key_restored.private_file, None, Some("25th")).unwrap();
+        assert_eq!(key_read, key_restored.key);
     }
 }
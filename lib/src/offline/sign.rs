@@ -1,16 +1,22 @@
-use crate::offline::print::pretty_print;
-use crate::qr::save_qrs;
+use crate::common::encryption;
+use crate::offline::descriptor::{
+    at_timelocks, lift_witness_script, policy_has_key, unsatisfied_timelocks,
+};
+use crate::offline::print::{foreign_change_outputs, pretty_print, wallet_balance};
+use crate::offline::spending_policy::{self, PolicyCheck, PolicyViolation};
+use crate::qr::{save_qrs, save_ur_qrs, QrStyle};
+use crate::ur::Ur;
 use crate::*;
 use bitcoin::blockdata::opcodes;
 use bitcoin::blockdata::script::Builder;
-use bitcoin::consensus::deserialize;
 use bitcoin::hashes::Hash;
 use bitcoin::secp256k1::{self, Message, Secp256k1, SignOnly};
 use bitcoin::util::bip143::SigHashCache;
-use bitcoin::util::bip32::{DerivationPath, ExtendedPrivKey, ExtendedPubKey};
+use bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPrivKey, ExtendedPubKey};
 use bitcoin::util::psbt::{raw, Map};
-use bitcoin::{Network, Script, SigHashType, Txid};
+use bitcoin::{Network, Script, SigHashType, SignedAmount, Txid};
 use log::{debug, info};
+use miniscript::MiniscriptKey;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
@@ -37,17 +43,59 @@ pub struct SignOptions {
     #[structopt(long, default_value = "14")]
     pub qr_version: i16,
 
+    #[structopt(flatten)]
+    #[serde(flatten)]
+    pub qr_style: QrStyle,
+
     /// PSBT json file
     psbt_file: PathBuf,
 
     /// Allow any derivations (to avoid ramson attacks, by default only 2 levels are allowed, and the first level must be 0 or 1)
     #[structopt(long)]
     allow_any_derivations: bool,
+
+    /// File containing the passphrase to decrypt the key file, required if the key has been saved
+    /// encrypted
+    #[structopt(
+        long = "encryption-passphrase-file",
+        parse(try_from_os_str = encryption::read_passphrase_file)
+    )]
+    pub encryption_passphrase: Option<String>,
+
+    /// File containing the BIP39 passphrase, required if the key has been created with a passphrase
+    #[structopt(
+        long = "bip39-passphrase-file",
+        parse(try_from_os_str = encryption::read_passphrase_file)
+    )]
+    pub bip39_passphrase: Option<String>,
+
+    /// Refuse to sign if outputs with key derivations of the wallet aren't wallet addresses or if
+    /// the wallet balance differs from `--confirmed-balance`, by default true only on mainnet
+    #[structopt(long)]
+    pub strict: Option<bool>,
+
+    /// Net balance of the wallet in the transaction as shown by `print`, eg. "-0.00001581 BTC",
+    /// required in strict mode
+    #[structopt(long, allow_hyphen_values = true)]
+    #[serde(default, with = "bitcoin::util::amount::serde::as_sat::opt")]
+    pub confirmed_balance: Option<SignedAmount>,
+
+    /// Sighash types allowed to be requested by the PSBT inputs, other types are refused, can be
+    /// repeated, eg. `--allow-sighash ALL --allow-sighash "SINGLE|ANYONECANPAY"`
+    #[structopt(long = "allow-sighash", default_value = "ALL", number_of_values = 1)]
+    #[serde(default = "default_allowed_sighash")]
+    pub allowed_sighash: Vec<Sighash>,
+}
+
+fn default_allowed_sighash() -> Vec<Sighash> {
+    vec![Sighash::All]
 }
 
+#[derive(Debug)]
 pub struct SignResult {
     signed: bool,
     added_paths: bool,
+    refusals: Vec<PolicyViolation>,
 }
 
 #[derive(Debug)]
@@ -59,6 +107,10 @@ struct PSBTSigner {
     network: Network, // even if network is included in xprv, regtest is equal to testnet there, so we need this
     derivations: u32,
     allow_any_derivations: bool,
+    policy_check: Option<PolicyCheck>,
+    /// wallet to check and the balance confirmed by the user, when in strict mode
+    strict: Option<(WalletJson, Option<SignedAmount>)>,
+    allowed_sighash: Vec<Sighash>,
 }
 
 /// extract field name in the PSBT extra field if present
@@ -70,15 +122,21 @@ pub fn get_psbt_name(psbt: &PSBT) -> Option<String> {
     }) // TODO remove expect
 }
 
-pub fn save_psbt_opt(datadir: &str, network: Network, opt: &SavePSBTOptions) -> Result<()> {
+/// Save the PSBT in the data dir, returning the PSBT file and its QR codes
+pub fn save_psbt_opt(
+    datadir: &str,
+    network: Network,
+    opt: &SavePSBTOptions,
+) -> Result<(PathBuf, Vec<PathBuf>)> {
     info!("save_psbt_opt {:?}", opt);
     let bytes = opt.psbt.as_bytes()?;
-    let mut psbt: PSBT = deserialize(&bytes)?;
+    let mut psbt = psbt_from_bytes(&bytes)?;
     let mut psbts_dir: PathBuf = datadir.into();
     psbts_dir.push(format!("{}", network));
     psbts_dir.push("psbts");
-    save_psbt(&mut psbt, &mut psbts_dir, opt.qr_version)?;
-    Ok(())
+    // needed to give a name to PSBTs without one
+    fs::create_dir_all(&psbts_dir)?;
+    save_psbt(&mut psbt, &mut psbts_dir, opt.qr_version, &opt.qr_style)
 }
 
 /// Search existing psbt, if one matches the txid, return that name, otherwise it gives a new unused name
@@ -114,6 +172,7 @@ pub fn save_psbt(
     psbt: &mut PSBT,
     psbts_dir: &mut PathBuf,
     qr_version: i16,
+    qr_style: &QrStyle,
 ) -> Result<(PathBuf, Vec<PathBuf>)> {
     let name = get_psbt_name(psbt).unwrap_or_else(|| {
         let new_name = get_name(&psbts_dir, &psbt.global.unsigned_tx.txid()).unwrap(); // TODO remove unwrap
@@ -126,6 +185,7 @@ pub fn save_psbt(
         new_name
     });
 
+    check_signatures(psbt)?;
     psbts_dir.push(&name);
     if psbts_dir.exists() {
         let mut old_psbt = psbts_dir.clone();
@@ -150,8 +210,15 @@ pub fn save_psbt(
     let contents = serde_json::to_string_pretty(&psbt_json)?;
     fs::write(&psbts_dir, contents.as_bytes())?;
 
+    psbts_dir.set_file_name("qr-ur");
+    save_ur_qrs(
+        &Ur::from_psbt(&psbt_bytes),
+        psbts_dir.clone(),
+        qr_version,
+        qr_style,
+    )?;
     psbts_dir.set_file_name("qr");
-    let qrs = save_qrs(psbt_bytes, psbts_dir.clone(), qr_version)?;
+    let qrs = save_qrs(psbt_bytes, psbts_dir.clone(), qr_version, qr_style)?;
 
     Ok((psbt_file, qrs))
 }
@@ -177,17 +244,24 @@ impl PSBTSigner {
             derivations,
             network,
             allow_any_derivations,
+            policy_check: None,
+            strict: None,
+            allowed_sighash: default_allowed_sighash(),
         })
     }
 
-    fn from_opt(opt: &SignOptions, network: Network) -> Result<Self> {
+    fn from_opt(opt: &SignOptions, network: Network, wallet: &WalletJson) -> Result<Self> {
         let psbt = read_psbt(&opt.psbt_file)?;
         let psbt_file = opt.psbt_file.clone();
         let psbts_dir = psbt_file.parent().unwrap().parent().unwrap().to_path_buf(); //TODO remove unwrap
 
-        let xprv_json = read_key(&opt.key)?;
+        let xprv_json = read_key(
+            &opt.key,
+            opt.encryption_passphrase.as_deref(),
+            opt.bip39_passphrase.as_deref(),
+        )?;
 
-        let signer = PSBTSigner::new(
+        let mut signer = PSBTSigner::new(
             &psbt,
             &xprv_json.xprv,
             network,
@@ -195,112 +269,114 @@ impl PSBTSigner {
             psbts_dir,
             opt.allow_any_derivations,
         )?;
+        signer.allowed_sighash = opt.allowed_sighash.clone();
+        signer.policy_check = PolicyCheck::load(&opt.key, &opt.wallet_descriptor_file, wallet)?;
+        if opt.strict.unwrap_or(network == Network::Bitcoin) {
+            signer.strict = Some((wallet.clone(), opt.confirmed_balance));
+        }
         Ok(signer)
     }
 
     pub fn sign(&mut self) -> Result<SignResult> {
         let initial_inputs = self.psbt.inputs.clone();
         let added_paths = self.init_hd_keypath_if_absent()?;
+        if let Some((wallet, confirmed_balance)) = self.strict.as_ref() {
+            self.check_strict(wallet, *confirmed_balance)?;
+        }
+        if let Some(policy_check) = self.policy_check.as_ref() {
+            let refusals =
+                policy_check.violations(&self.psbt, self.network, spending_policy::now()?)?;
+            if !refusals.is_empty() {
+                return Ok(SignResult {
+                    added_paths,
+                    signed: false,
+                    refusals,
+                });
+            }
+        }
 
+        check_signatures(&self.psbt)?;
         for (i, input) in self.psbt.inputs.clone().iter().enumerate() {
             debug!("{} {:?}", i, input);
-            let is_segwit = input.witness_utxo.is_some();
-
-            match input.non_witness_utxo.as_ref() {
-                None => {
-                    return Err(Error::MissingPrevoutTx);
-                }
-                Some(non_witness_utxo) => {
-                    let prevout = self.psbt.global.unsigned_tx.input[i].previous_output;
-                    if non_witness_utxo.txid() != prevout.txid {
-                        return Err(Error::MismatchPrevoutHash);
-                    }
-                    if is_segwit {
-                        let witness_utxo = input
-                            .clone()
-                            .witness_utxo
-                            .expect("both witness_utxo and non_witness_utxo are none");
-                        let script = match input.clone().redeem_script {
-                            Some(script) => {
-                                if witness_utxo.script_pubkey != script.to_p2sh() {
-                                    return Err("witness_utxo script_pubkey doesn't match the redeem script converted to p2sh".into());
-                                }
-                                script
-                            }
-                            None => witness_utxo.script_pubkey,
-                        };
-                        if script.is_v0_p2wpkh() {
-                            let script = to_p2pkh(&script.as_bytes()[2..]);
-                            if !script.is_p2pkh() {
-                                return Err("it is not a p2pkh script".into());
-                            }
-                            self.sign_input(&script, i)?;
-                        } else {
-                            let wit_script = input
-                                .clone()
-                                .witness_script
-                                .expect("witness_script is none");
-                            if script != wit_script.to_v0_p2wsh() {
-                                return Err(
-                                    "script and witness script to v0 p2wsh doesn't match".into()
-                                );
-                            }
-                            self.sign_input(&wit_script, i)?;
-                        }
-                    } else {
-                        let script_pubkey = non_witness_utxo.output[prevout.vout as usize]
-                            .clone()
-                            .script_pubkey;
-                        match input.redeem_script.clone() {
-                            Some(redeem_script) => {
-                                if script_pubkey != redeem_script.to_p2sh() {
-                                    return Err("script_pubkey does not match the redeem script converted to p2sh".into());
-                                }
-                                self.sign_input(&redeem_script, i)?;
-                            }
-                            None => {
-                                self.sign_input(&script_pubkey, i)?;
-                            }
-                        };
-                    }
-                }
+            let non_witness_utxo = input
+                .non_witness_utxo
+                .as_ref()
+                .ok_or_else(|| Error::MissingPrevoutTx)?;
+            let prevout = self.psbt.global.unsigned_tx.input[i].previous_output;
+            if non_witness_utxo.txid() != prevout.txid {
+                return Err(Error::MismatchPrevoutHash);
             }
+            let script = script_code(&self.psbt, i)?;
+            self.sign_input(&script, i)?;
         }
         let signed = self.psbt.inputs != initial_inputs;
         Ok(SignResult {
             added_paths,
             signed,
+            refusals: vec![],
         })
     }
 
+    /// outputs with key derivations of the wallet must be wallet addresses and the net balance of
+    /// the wallet must be the one confirmed by the user
+    fn check_strict(
+        &self,
+        wallet: &WalletJson,
+        confirmed_balance: Option<SignedAmount>,
+    ) -> Result<()> {
+        if let Some(i) = foreign_change_outputs(&self.psbt, self.network, wallet)?.first() {
+            return Err(Error::ChangeNotInWallet(*i, wallet.name.clone()));
+        }
+        let balance = wallet_balance(&self.psbt, self.network, wallet)?;
+        match confirmed_balance {
+            None => Err(Error::BalanceNotConfirmed(balance.to_string())),
+            Some(confirmed) if confirmed != balance => Err(Error::BalanceMismatch(
+                balance.to_string(),
+                confirmed.to_string(),
+            )),
+            Some(_) => Ok(()),
+        }
+    }
+
     fn init_hd_keypath_if_absent(&mut self) -> Result<bool> {
-        // temp code for handling psbt generated from core without hd paths
-        let outputs_empty = self.psbt.inputs.iter().any(|i| i.hd_keypaths.is_empty());
-        let inputs_empty = self.psbt.outputs.iter().any(|o| o.hd_keypaths.is_empty());
+        // fallback for PSBTs without hd paths, created without key origins in the descriptors,
+        // outputs without witness script are not ours and have no hd paths
+        let inputs_empty = self.psbt.inputs.iter().any(|i| i.hd_keypaths.is_empty());
+        let outputs_empty = self
+            .psbt
+            .outputs
+            .iter()
+            .any(|o| o.witness_script.is_some() && o.hd_keypaths.is_empty());
 
         let mut added = false;
         if outputs_empty || inputs_empty {
             info!("Provided PSBT does not contain all HD key paths, trying to deduce them...");
             let mut keys = HashMap::new();
-            for i in 0..=1 {
-                let derivation_path = DerivationPath::from_str(&format!("m/{}", i))?;
-                let first = self.xprv.derive_priv(&self.secp, &derivation_path)?;
-                for j in 0..=self.derivations {
-                    let derivation_path = DerivationPath::from_str(&format!("m/{}", j))?;
-                    let derived = first.derive_priv(&self.secp, &derivation_path)?;
-                    let derived_pubkey = ExtendedPubKey::from_private(&self.secp, &derived);
-                    let complete_derivation_path =
-                        DerivationPath::from_str(&format!("m/{}/{}", i, j))?;
-                    keys.insert(
-                        derived_pubkey.public_key,
-                        (self.xprv.fingerprint(&self.secp), complete_derivation_path),
-                    );
+            // keys derived directly from the master key and from the first BIP48 account
+            let accounts = [DerivationPath::from(vec![]), bip48_path(self.network, 0)?];
+            for account in accounts.iter() {
+                let account_xprv = self.xprv.derive_priv(&self.secp, account)?;
+                for i in 0..=1 {
+                    let derivation_path = DerivationPath::from_str(&format!("m/{}", i))?;
+                    let first = account_xprv.derive_priv(&self.secp, &derivation_path)?;
+                    for j in 0..=self.derivations {
+                        let derivation_path = DerivationPath::from_str(&format!("m/{}", j))?;
+                        let derived = first.derive_priv(&self.secp, &derivation_path)?;
+                        let derived_pubkey = ExtendedPubKey::from_private(&self.secp, &derived);
+                        let complete_derivation_path = account
+                            .child(ChildNumber::from(i))
+                            .into_child(ChildNumber::from(j));
+                        keys.insert(
+                            derived_pubkey.public_key,
+                            (self.xprv.fingerprint(&self.secp), complete_derivation_path),
+                        );
+                    }
                 }
             }
 
             for input in self.psbt.inputs.iter_mut() {
                 if let Some(ref witness_script) = input.witness_script {
-                    let script_keys = extract_pub_keys(&witness_script)?;
+                    let script_keys = extract_keys_and_hashes(witness_script, &keys)?;
                     for key in script_keys {
                         if keys.contains_key(&key) {
                             input.hd_keypaths.insert(
@@ -315,7 +391,7 @@ impl PSBTSigner {
 
             for output in self.psbt.outputs.iter_mut() {
                 if let Some(ref witness_script) = output.witness_script {
-                    let script_keys = extract_pub_keys(&witness_script)?;
+                    let script_keys = extract_keys_and_hashes(witness_script, &keys)?;
                     for key in script_keys {
                         if keys.contains_key(&key) {
                             output.hd_keypaths.insert(
@@ -335,11 +411,11 @@ impl PSBTSigner {
     }
 
     fn sign_input(&mut self, script: &Script, input_index: usize) -> Result<()> {
-        let input = &mut self.psbt.inputs[input_index];
+        let input = &self.psbt.inputs[input_index];
         let tx = &self.psbt.global.unsigned_tx;
         let is_segwit = input.witness_utxo.is_some();
         let my_fing = self.xprv.fingerprint(&self.secp);
-        let mut sig_hash_cache = SigHashCache::new(tx);
+        let mut signatures = vec![];
 
         for (pubkey, (fing, child)) in input.hd_keypaths.iter() {
             if fing != &my_fing {
@@ -351,12 +427,7 @@ impl PSBTSigner {
                 );
             }
             if !self.allow_any_derivations {
-                let path_slice = child.as_ref();
-                if path_slice.len() != 2 {
-                    return Err(format!("{} only two derivation paths allowed", child).into());
-                } else if !(path_slice[0] == 0.into() || path_slice[0] == 1.into()) {
-                    return Err(format!("{} first derivation must be Soft 0 or 1", child).into());
-                }
+                check_derivation(child, self.network)?;
             }
             let privkey = self.xprv.derive_priv(&self.secp, &child)?;
             let derived_pubkey =
@@ -366,28 +437,49 @@ impl PSBTSigner {
                     "pubkey derived and expected differs even if fingerprint matches!".into(),
                 );
             }
-            let (hash, sighash);
-            if is_segwit {
-                let wutxo = input.witness_utxo.as_ref();
-                let value = wutxo.ok_or_else(|| Error::MissingWitnessUtxo)?.value;
-                sighash = input.sighash_type.unwrap_or(SigHashType::All);
-                hash = sig_hash_cache.signature_hash(input_index, script, value, sighash);
+            if let Some(policy) = lift_witness_script(script) {
+                if policy_has_key(&policy, pubkey)
+                    && !policy_has_key(&at_timelocks(policy.clone(), tx, input_index), pubkey)
+                {
+                    let locks = unsatisfied_timelocks(&policy, tx, input_index).join(" or ");
+                    return Err(Error::TimelockNotSatisfied(input_index, locks));
+                }
+            }
+            let sighash = if is_segwit {
+                input.sighash_type.unwrap_or(SigHashType::All)
             } else {
-                sighash = input.sighash_type.ok_or_else(|| Error::MissingSighash)?;
-                hash = tx.signature_hash(input_index, &script, sighash.as_u32());
+                input.sighash_type.ok_or_else(|| Error::MissingSighash)?
             };
-            let msg = &Message::from_slice(&hash.into_inner()[..])?;
+            if !self.allowed_sighash.contains(&sighash.into()) {
+                return Err(Error::SighashNotAllowed(
+                    input_index,
+                    Sighash::from(sighash).to_string(),
+                ));
+            }
+            let msg = signature_hash(&self.psbt, input_index, script, sighash)?;
             let key = &privkey.private_key.key;
-            let signature = self.secp.sign(msg, key);
+            let signature = self.secp.sign(&msg, key);
             let mut signature = signature.serialize_der().to_vec();
             signature.push(sighash.as_u32() as u8); // TODO how to properly do this?
-            input.partial_sigs.insert(*pubkey, signature);
+            signatures.push((*pubkey, signature));
         }
+        self.psbt.inputs[input_index]
+            .partial_sigs
+            .extend(signatures);
         Ok(())
     }
 
-    fn save_signed_psbt_file(&mut self, qr_version: i16) -> Result<(PathBuf, Vec<PathBuf>)> {
-        save_psbt(&mut self.psbt, &mut self.psbts_dir.clone(), qr_version)
+    fn save_signed_psbt_file(
+        &mut self,
+        qr_version: i16,
+        qr_style: &QrStyle,
+    ) -> Result<(PathBuf, Vec<PathBuf>)> {
+        save_psbt(
+            &mut self.psbt,
+            &mut self.psbts_dir.clone(),
+            qr_version,
+            qr_style,
+        )
     }
 
     fn pretty_print(&self, wallets: &[WalletJson]) -> Result<PsbtPrettyPrint> {
@@ -395,9 +487,165 @@ impl PSBTSigner {
     }
 }
 
+/// the script signed by input `i`: the witness script for p2wsh, the p2pkh script of the key
+/// for p2wpkh, the redeem script for p2sh or the script pubkey
+pub fn script_code(psbt: &PSBT, i: usize) -> Result<Script> {
+    let input = &psbt.inputs[i];
+    match input.witness_utxo.as_ref() {
+        Some(witness_utxo) => {
+            let script = match input.redeem_script.clone() {
+                Some(script) => {
+                    if witness_utxo.script_pubkey != script.to_p2sh() {
+                        return Err("witness_utxo script_pubkey doesn't match the redeem script converted to p2sh".into());
+                    }
+                    script
+                }
+                None => witness_utxo.script_pubkey.clone(),
+            };
+            if script.is_v0_p2wpkh() {
+                let script = to_p2pkh(&script.as_bytes()[2..]);
+                if !script.is_p2pkh() {
+                    return Err("it is not a p2pkh script".into());
+                }
+                Ok(script)
+            } else {
+                let wit_script = input
+                    .witness_script
+                    .clone()
+                    .ok_or("witness_script is none")?;
+                if script != wit_script.to_v0_p2wsh() {
+                    return Err("script and witness script to v0 p2wsh doesn't match".into());
+                }
+                Ok(wit_script)
+            }
+        }
+        None => {
+            let non_witness_utxo = input
+                .non_witness_utxo
+                .as_ref()
+                .ok_or_else(|| Error::MissingPrevoutTx)?;
+            let prevout = psbt.global.unsigned_tx.input[i].previous_output;
+            if non_witness_utxo.txid() != prevout.txid {
+                return Err(Error::MismatchPrevoutHash);
+            }
+            let script_pubkey = non_witness_utxo
+                .output
+                .get(prevout.vout as usize)
+                .ok_or_else(|| Error::MissingTxout)?
+                .script_pubkey
+                .clone();
+            match input.redeem_script.clone() {
+                Some(redeem_script) => {
+                    if script_pubkey != redeem_script.to_p2sh() {
+                        return Err(
+                            "script_pubkey does not match the redeem script converted to p2sh"
+                                .into(),
+                        );
+                    }
+                    Ok(redeem_script)
+                }
+                None => Ok(script_pubkey),
+            }
+        }
+    }
+}
+
+/// message signed by input `i` with `sighash`, BIP143 for segwit inputs
+pub fn signature_hash(
+    psbt: &PSBT,
+    i: usize,
+    script: &Script,
+    sighash: SigHashType,
+) -> Result<Message> {
+    let input = &psbt.inputs[i];
+    let tx = &psbt.global.unsigned_tx;
+    let hash = match input.witness_utxo.as_ref() {
+        Some(witness_utxo) => {
+            SigHashCache::new(tx).signature_hash(i, script, witness_utxo.value, sighash)
+        }
+        None => tx.signature_hash(i, script, sighash.as_u32()),
+    };
+    Ok(Message::from_slice(&hash.into_inner()[..])?)
+}
+
+/// public keys of the partial signatures of input `i` which are not valid ECDSA signatures of the
+/// input with their sighash, or whose sighash differs from the one requested by the input
+pub fn invalid_signatures(psbt: &PSBT, i: usize) -> Result<Vec<bitcoin::PublicKey>> {
+    let input = &psbt.inputs[i];
+    if input.partial_sigs.is_empty() {
+        return Ok(vec![]);
+    }
+    let script = script_code(psbt, i)?;
+    let secp = Secp256k1::verification_only();
+    let mut result = vec![];
+    for (pubkey, signature) in input.partial_sigs.iter() {
+        let valid = match signature.split_last() {
+            Some((flag, der)) => {
+                let sighash = SigHashType::from_u32(*flag as u32);
+                let requested = input.sighash_type.unwrap_or(sighash);
+                let msg = signature_hash(psbt, i, &script, sighash)?;
+                sighash.as_u32() == *flag as u32
+                    && sighash == requested
+                    && secp256k1::Signature::from_der(der)
+                        .map(|sig| secp.verify(&msg, &sig, &pubkey.key).is_ok())
+                        .unwrap_or(false)
+            }
+            None => false,
+        };
+        if !valid {
+            result.push(*pubkey);
+        }
+    }
+    Ok(result)
+}
+
+/// error if any partial signature of `psbt` is not valid
+pub fn check_signatures(psbt: &PSBT) -> Result<()> {
+    for i in 0..psbt.inputs.len() {
+        if let Some(pubkey) = invalid_signatures(psbt, i)?.first() {
+            return Err(Error::InvalidSignature(i, pubkey.to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// allowed derivations are `m/{0,1}/i` and `m/48'/coin'/account'/2'/{0,1}/i` of BIP48 accounts
+fn check_derivation(path: &DerivationPath, network: Network) -> Result<()> {
+    let path_slice = path.as_ref();
+    if path_slice.len() < 2 {
+        return Err(format!("{} at least two derivation paths required", path).into());
+    }
+    let (account, relative) = path_slice.split_at(path_slice.len() - 2);
+    if !(account.is_empty() || is_bip48_account(account, network)) {
+        return Err(format!(
+            "{} is not derived from a BIP48 account of this network",
+            path
+        )
+        .into());
+    }
+    if !(relative[0] == 0.into() || relative[0] == 1.into()) {
+        return Err(format!("{} derivation after the account must be Soft 0 or 1", path).into());
+    }
+    Ok(())
+}
+
+/// public keys in the script, plus the ones in `keys` whose hash is in the script as in `pk_h`
+fn extract_keys_and_hashes<V>(
+    script: &Script,
+    keys: &HashMap<bitcoin::PublicKey, V>,
+) -> Result<Vec<bitcoin::PublicKey>> {
+    let mut result = extract_pub_keys(script)?;
+    for hash in extract_pub_key_hashes(script)? {
+        if let Some(key) = keys.keys().find(|k| k.to_pubkeyhash() == hash) {
+            result.push(*key);
+        }
+    }
+    Ok(result)
+}
+
 pub fn start(opt: &SignOptions, network: Network) -> Result<PsbtPrettyPrint> {
     let wallet = read_wallet(&opt.wallet_descriptor_file)?;
-    let mut psbt_signer = PSBTSigner::from_opt(opt, network)?;
+    let mut psbt_signer = PSBTSigner::from_opt(opt, network, &wallet)?;
     debug!("{:?}", psbt_signer);
     //TODO refuse to sign if my address has first level different from 0/1 and more than one level?
     let sign_result = psbt_signer.sign()?;
@@ -407,17 +655,32 @@ pub fn start(opt: &SignOptions, network: Network) -> Result<PsbtPrettyPrint> {
         psbt_print.info.push("Added paths".to_string());
     }
     if sign_result.signed {
-        let (psbt_file, _) = psbt_signer.save_signed_psbt_file(opt.qr_version)?;
+        let (psbt_file, _) = psbt_signer.save_signed_psbt_file(opt.qr_version, &opt.qr_style)?;
         psbt_print.psbt_file = psbt_file;
         psbt_print.info.push("Added signatures".to_string());
+        if let Some(policy_check) = psbt_signer.policy_check.as_ref() {
+            policy_check.record(&psbt_signer.psbt, network, spending_policy::now()?)?;
+        }
     } else {
+        psbt_print
+            .info
+            .extend(sign_result.refusals.iter().map(|r| r.to_string()));
         psbt_print.info.push("No signature added".to_string());
     }
 
     Ok(psbt_print)
 }
 
-pub fn read_key(path: &PathBuf) -> Result<PrivateMasterKey> {
+pub fn read_key(
+    path: &PathBuf,
+    encryption_passphrase: Option<&str>,
+    bip39_passphrase: Option<&str>,
+) -> Result<PrivateMasterKey> {
+    read_key_file(path)?.unlock(encryption_passphrase, bip39_passphrase)
+}
+
+/// read the key file without decrypting it, the content could be encrypted or not
+pub fn read_key_file(path: &PathBuf) -> Result<PrivateMasterKeyFile> {
     let is_key = path
         .file_name()
         .ok_or_else(|| Error::WrongKeyFileName)?
@@ -443,8 +706,10 @@ fn to_p2pkh(pubkey_hash: &[u8]) -> Script {
 
 #[cfg(test)]
 mod tests {
+    use crate::offline::descriptor::{descriptor_checksum, with_checksum};
     use crate::offline::sign::*;
     use crate::{psbt_from_base64, psbt_to_base64, Error, PsbtJson, PSBT};
+    use bitcoin::consensus::deserialize;
     use bitcoin::util::bip32::ExtendedPubKey;
     use bitcoin::Transaction;
     use flate2::write::ZlibEncoder;
@@ -482,6 +747,184 @@ mod tests {
         psbt_from_base64(&psbt_json.psbt).unwrap()
     }
 
+    #[test]
+    fn test_check_derivation() {
+        let check = |path: &str, network: Network| {
+            check_derivation(&DerivationPath::from_str(path).unwrap(), network).is_ok()
+        };
+        assert!(check("m/0/1", Network::Testnet));
+        assert!(check("m/1/1", Network::Bitcoin));
+        assert!(check("m/48'/1'/0'/2'/0/5", Network::Testnet));
+        assert!(check("m/48'/1'/3'/2'/1/5", Network::Regtest));
+        assert!(check("m/48'/0'/3'/2'/1/5", Network::Bitcoin));
+        assert!(!check("m/48'/0'/3'/2'/1/5", Network::Testnet));
+        assert!(!check("m/48'/1'/0'/2'/2/5", Network::Testnet));
+        assert!(!check("m/48'/1'/0/2'/0/5", Network::Testnet));
+        assert!(!check("m/48'/1'/0'/1'/0/5", Network::Testnet));
+        assert!(!check("m/0/1/1", Network::Testnet));
+        assert!(!check("m/2/1", Network::Testnet));
+        assert!(!check("m/0", Network::Testnet));
+    }
+
+    #[test]
+    fn test_check_strict() {
+        let descriptor = "wsh(multi(2,tpubD6NzVbkrYhZ4YfG9CySHqKHFbaLcD7hSDyqRUtCmMKNim5fkiJtTnFeqKsRHMHSK5ddFrhqRr3Ghv1JtuWkBzikuBqKu1xCpjQ9YxoPGgqU/0/*,tpubD6NzVbkrYhZ4WpudNKLizFbGzpsG3jkLF7mc8Vfh1fTDbbBPjDP29My6TaLncaS8VeDPcaNMdUkybucr8Kz9CHSdAtvxnaXyBxPRocefdXN/0/*))";
+        let finger = bitcoin::util::bip32::Fingerprint::from(&[1u8, 2, 3, 4][..]);
+        let wallet = WalletJson {
+            name: "wallet".to_string(),
+            descriptor_main: descriptor.to_string(),
+            descriptor_change: descriptor.replace("/0/*", "/1/*"),
+            fingerprints: vec![finger].into_iter().collect(),
+            required_sig: 2,
+            policy: None,
+            daemon_opts: None,
+            created_at_height: 0,
+        };
+        let bytes = include_bytes!("../../test_data/sign/psbt_testnet.json");
+        let (_, mut psbt) = extract_psbt(bytes);
+        // output #1 to the first main address of the wallet
+        let address = bitcoin::Address::from_str(
+            "tb1q5nrregep899vnvaa5vdpxcwg8794jqy38nu304kl4d7wm4e92yeqz4jfmk",
+        )
+        .unwrap();
+        psbt.global.unsigned_tx.output[1].script_pubkey = address.script_pubkey();
+        let pubkey = bitcoin::PublicKey::from_str(
+            "020ca0e815748c41087075f3840c1edd9400f4db031dbe948b1929b6a93c72386a",
+        )
+        .unwrap();
+        let path = DerivationPath::from_str("m/0/0").unwrap();
+        psbt.outputs[1].hd_keypaths.insert(pubkey, (finger, path));
+
+        let xprv = ExtendedPrivKey::new_master(Network::Testnet, &[0u8; 32]).unwrap();
+        let temp_dir = TempDir::new("test_check_strict").unwrap().into_path();
+        let signer = PSBTSigner::new(&psbt, &xprv, Network::Testnet, 10, temp_dir, false).unwrap();
+        let balance = SignedAmount::from_sat(187_498);
+        signer.check_strict(&wallet, Some(balance)).unwrap();
+        assert_eq!(
+            signer.check_strict(&wallet, None).unwrap_err().to_string(),
+            Error::BalanceNotConfirmed(balance.to_string()).to_string()
+        );
+        let confirmed = SignedAmount::from_sat(-212_502);
+        assert_eq!(
+            signer
+                .check_strict(&wallet, Some(confirmed))
+                .unwrap_err()
+                .to_string(),
+            Error::BalanceMismatch(balance.to_string(), confirmed.to_string()).to_string()
+        );
+
+        let mut signer = signer;
+        let path = DerivationPath::from_str("m/1/3").unwrap();
+        signer.psbt.outputs[0]
+            .hd_keypaths
+            .insert(pubkey, (finger, path));
+        assert_eq!(
+            signer
+                .check_strict(&wallet, Some(balance))
+                .unwrap_err()
+                .to_string(),
+            Error::ChangeNotInWallet(0, "wallet".to_string()).to_string()
+        );
+    }
+
+    #[test]
+    fn test_wrong_descriptor_checksum() {
+        let temp_dir = TempDir::new("test_sign_checksum").unwrap();
+        let descriptor = "wsh(multi(2,tpubD6NzVbkrYhZ4YfG9CySHqKHFbaLcD7hSDyqRUtCmMKNim5fkiJtTnFeqKsRHMHSK5ddFrhqRr3Ghv1JtuWkBzikuBqKu1xCpjQ9YxoPGgqU/0/*,tpubD6NzVbkrYhZ4WpudNKLizFbGzpsG3jkLF7mc8Vfh1fTDbbBPjDP29My6TaLncaS8VeDPcaNMdUkybucr8Kz9CHSdAtvxnaXyBxPRocefdXN/0/*))";
+        let change = with_checksum(&descriptor.replace("/0/*", "/1/*")).unwrap();
+        // the checksum of the change descriptor with the main one
+        let change_checksum = change.split('#').nth(1).unwrap();
+        let tampered = format!("{}#{}", descriptor, change_checksum);
+        let wallet = WalletJson {
+            name: "wallet".to_string(),
+            descriptor_main: tampered,
+            descriptor_change: change.clone(),
+            fingerprints: Default::default(),
+            required_sig: 2,
+            policy: None,
+            daemon_opts: None,
+            created_at_height: 0,
+        };
+        let wallet_descriptor_file = temp_dir.path().join("descriptor.json");
+        std::fs::write(
+            &wallet_descriptor_file,
+            serde_json::to_vec(&wallet).unwrap(),
+        )
+        .unwrap();
+        let opt = SignOptions {
+            key: PathBuf::from("test_data/sign/psbt_testnet.1.key"),
+            total_derivations: 10,
+            wallet_descriptor_file,
+            qr_version: 14,
+            qr_style: QrStyle::default(),
+            psbt_file: PathBuf::from("test_data/sign/psbt_testnet.json"),
+            allow_any_derivations: false,
+            encryption_passphrase: None,
+            bip39_passphrase: None,
+            strict: Some(false),
+            confirmed_balance: None,
+            allowed_sighash: vec![Sighash::All],
+        };
+        let expected = Error::WrongDescriptorChecksum(
+            change_checksum.to_string(),
+            descriptor_checksum(descriptor).unwrap(),
+        );
+        assert_eq!(
+            start(&opt, Network::Testnet).unwrap_err().to_string(),
+            expected.to_string()
+        );
+    }
+
+    #[test]
+    fn test_invalid_signatures() {
+        let bytes = include_bytes!("../../test_data/sign/psbt_bip.signed.json");
+        let (_, psbt_bip) = extract_psbt(bytes);
+        check_signatures(&psbt_bip).unwrap();
+
+        let bytes = include_bytes!("../../test_data/sign/psbt_testnet.1.signed.json");
+        let (_, psbt) = extract_psbt(bytes);
+        check_signatures(&psbt).unwrap();
+        let printed = pretty_print(&psbt, Network::Testnet, &[]).unwrap();
+        assert!(printed.inputs[0].invalid_signatures.is_empty());
+        assert_eq!(printed.inputs[0].signatures.len(), 1);
+
+        let (pubkey, signature) = psbt.inputs[0].partial_sigs.iter().next().unwrap();
+        let (pubkey, signature) = (*pubkey, signature.clone());
+        let mut corrupted = psbt.clone();
+        let mut wrong_signature = signature.clone();
+        wrong_signature[10] ^= 1;
+        corrupted.inputs[0]
+            .partial_sigs
+            .insert(pubkey, wrong_signature);
+        assert_eq!(invalid_signatures(&corrupted, 0).unwrap(), vec![pubkey]);
+        let printed = pretty_print(&corrupted, Network::Testnet, &[]).unwrap();
+        assert_eq!(printed.inputs[0].invalid_signatures, vec![pubkey]);
+        assert!(printed.inputs[0].signatures.is_empty());
+        assert!(printed
+            .info
+            .iter()
+            .any(|i| i.contains("invalid signatures")));
+        let expected = Error::InvalidSignature(0, pubkey.to_string()).to_string();
+        assert_eq!(
+            check_signatures(&corrupted).unwrap_err().to_string(),
+            expected
+        );
+        let temp_dir = TempDir::new("test_invalid_signatures").unwrap();
+        let mut psbts_dir = temp_dir.path().to_path_buf();
+        assert_eq!(
+            save_psbt(&mut corrupted, &mut psbts_dir, 0, &QrStyle::default())
+                .unwrap_err()
+                .to_string(),
+            expected
+        );
+
+        // valid signature of a different sighash
+        let mut wrong_flag = signature;
+        *wrong_flag.last_mut().unwrap() = SigHashType::None.as_u32() as u8;
+        corrupted.inputs[0].partial_sigs.insert(pubkey, wrong_flag);
+        assert_eq!(invalid_signatures(&corrupted, 0).unwrap(), vec![pubkey]);
+    }
+
     #[test]
     fn test_compression() {
         let bytes = include_bytes!("../../test_data/sign/psbt_bip.json");
@@ -608,6 +1051,24 @@ mod tests {
         let tx_in = "020000000001019e60071916a88cf0f5b9c6f015b7f8eef3ab1ef6ca4929b7236ec74e693f36210000000023220020c3af1472a85b23206da9be4fbef18d0ce5fd965671110d722a816e892d2e5f33fdffffff02801a0600000000002200201148e93e9315e37dbed2121be5239257af35adc03ffdfc5d914b083afa44dab80e07a1010000000017a9142aaba9f43085c5a6f28b0d01a8ed4dbcc0e5ec4f87040047304402203fdaeafde5fc1d1838d4c431abf6672f4cfee996f932187b31a4e3dad04d7b9f0220247d2cee5aabceb029ee6a1809a821fd95aa3ff02627977cbac8d00ff5a4628901473044022026879e4c65462161e2805ca26d392b0aace13906ec5b4776cac99f5e2bfd49f4022072500f1e2818a6738c37b6cedb2fd0a16375df34ce145e3f8fbfef7b7bec99d401475221020ca0e815748c41087075f3840c1edd9400f4db031dbe948b1929b6a93c72386a21026471f666489f80aed63bbbdee4f09ffcd69b40900435633cef5f5a35bf00932752ae4ff21700";
         let tx_in: Transaction = deserialize(&hex::decode(tx_in).unwrap()).unwrap();
         psbt_to_sign.inputs[0].non_witness_utxo = Some(tx_in.clone());
+        let mut sighash_none = psbt_to_sign.clone();
+        sighash_none.inputs[0].sighash_type = Some(SigHashType::None);
+        let temp_dir = TempDir::new("test_sighash").unwrap().into_path();
+        let mut signer = PSBTSigner::new(
+            &sighash_none,
+            &key.xprv,
+            key.xprv.network,
+            10,
+            temp_dir,
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            signer.sign().unwrap_err().to_string(),
+            Error::SighashNotAllowed(0, "NONE".to_string()).to_string()
+        );
+        signer.allowed_sighash.push(Sighash::None);
+        assert!(signer.sign().unwrap().signed);
         test_sign(&mut psbt_to_sign, &psbt_1, &key.xprv).unwrap();
         assert!(perc_diff_with_core(&psbt_to_sign, 192).unwrap());
 
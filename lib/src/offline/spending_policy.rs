@@ -0,0 +1,616 @@
+use crate::offline::print::{foreign_change_outputs, pretty_print};
+use crate::offline::verify_address::find_address;
+use crate::*;
+use bitcoin::{Address, Amount, Network, Txid};
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Name of the spending policy file, searched in the directory of the key and of the wallet
+pub const POLICY_FILE: &str = "spending_policy.json";
+
+/// Name of the file recording transactions signed under a policy, next to the policy file
+pub const LEDGER_FILE: &str = "spending_ledger.json";
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+/// Main and change addresses of the allowed wallets are searched up to this index
+const ALLOWED_WALLETS_MAX_INDEX: u32 = 1000;
+
+/// Limits checked before signing, missing fields are not checked, amounts are in satoshi
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(deny_unknown_fields)]
+pub struct SpendingPolicy {
+    /// Max amount sent outside the wallet in one transaction
+    pub max_amount: Option<u64>,
+
+    /// Max absolute fee
+    pub max_fee: Option<u64>,
+
+    /// Max fee rate in sat/vbyte, computed on the estimated size of the signed transaction
+    pub max_fee_rate: Option<f64>,
+
+    /// When this or `allowed_wallets` is not empty, outputs outside the wallet must pay one of
+    /// these addresses or an address of one of the `allowed_wallets`
+    #[serde(default)]
+    pub allowed_addresses: Vec<Address>,
+
+    /// Names of the wallets in the same data directory of the signing wallet
+    #[serde(default)]
+    pub allowed_wallets: Vec<String>,
+
+    /// Outputs containing key derivations of the wallet must be addresses of the wallet
+    #[serde(default)]
+    pub change_to_same_wallet: bool,
+
+    /// Hours of the day (UTC) in which signing is allowed
+    pub allowed_hours: Option<HoursRange>,
+
+    /// Max amount sent outside the wallet in the last 24 hours, including this transaction
+    pub max_amount_per_day: Option<u64>,
+
+    /// Max number of transactions signed in the last 24 hours, including this transaction
+    pub max_tx_per_day: Option<u32>,
+}
+
+/// Hours from `from` included to `to` excluded, wraps around midnight if `from` is greater than `to`,
+/// `from` equal to `to` is an empty range and it's rejected
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct HoursRange {
+    pub from: u8,
+    pub to: u8,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct SpendingLedger {
+    pub entries: Vec<LedgerEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LedgerEntry {
+    pub txid: Txid,
+    /// unix time in seconds
+    pub timestamp: u64,
+    /// amount sent outside the wallet in satoshi
+    pub amount: u64,
+}
+
+/// A rule of a spending policy not respected by the transaction
+#[derive(Debug, Clone, PartialEq)]
+pub struct PolicyViolation {
+    pub policy_file: PathBuf,
+    pub rule: &'static str,
+    pub reason: String,
+}
+
+/// The spending policies applying to a wallet, evaluated before signing
+#[derive(Debug)]
+pub struct PolicyCheck {
+    wallet: WalletJson,
+    policies: Vec<LoadedPolicy>,
+}
+
+#[derive(Debug)]
+struct LoadedPolicy {
+    path: PathBuf,
+    policy: SpendingPolicy,
+    allowed_wallets: Vec<WalletJson>,
+}
+
+/// What the transaction does from the point of view of the signing wallet
+struct Spending {
+    txid: Txid,
+    /// index, address and value of the outputs not belonging to the wallet
+    external: Vec<(usize, Address, u64)>,
+    /// index of the outputs with key derivations of the wallet which are not addresses of the wallet
+    fake_change: Vec<usize>,
+    fee: u64,
+    fee_rate: f64,
+}
+
+impl fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Refused by spending policy {}, {}: {}",
+            self.policy_file.display(),
+            self.rule,
+            self.reason
+        )
+    }
+}
+
+impl HoursRange {
+    fn contains(&self, hour: u8) -> bool {
+        if self.from <= self.to {
+            self.from <= hour && hour < self.to
+        } else {
+            hour >= self.from || hour < self.to
+        }
+    }
+}
+
+impl Spending {
+    fn new(psbt: &PSBT, network: Network, wallet: &WalletJson) -> Result<Self> {
+        let pretty = pretty_print(psbt, network, std::slice::from_ref(wallet))?;
+        let tx = &psbt.global.unsigned_tx;
+        let mut external = vec![];
+        for (i, (output, printed)) in tx.output.iter().zip(pretty.outputs.iter()).enumerate() {
+            if printed.common.wallet_with_path.is_some() {
+                continue;
+            }
+            let address = Address::from_script(&output.script_pubkey, network)
+                .ok_or_else(|| Error::NonDefaultScript)?;
+            external.push((i, address, output.value));
+        }
+        Ok(Spending {
+            txid: tx.txid(),
+            external,
+            fake_change: foreign_change_outputs(psbt, network, wallet)?,
+            fee: pretty.fee.absolute,
+            fee_rate: pretty.fee.rate,
+        })
+    }
+
+    fn amount(&self) -> u64 {
+        self.external.iter().map(|(_, _, value)| value).sum()
+    }
+}
+
+impl PolicyCheck {
+    /// Load the policies in the directories of `key_file` and `wallet_file`, `None` if there isn't any
+    pub fn load(key_file: &Path, wallet_file: &Path, wallet: &WalletJson) -> Result<Option<Self>> {
+        let mut paths = vec![key_file.with_file_name(POLICY_FILE)];
+        let wallet_policy = wallet_file.with_file_name(POLICY_FILE);
+        if !paths.contains(&wallet_policy) {
+            paths.push(wallet_policy);
+        }
+        let wallets_dir = wallet_file.parent().and_then(Path::parent);
+
+        let mut policies = vec![];
+        for path in paths.into_iter().filter(|p| p.exists()) {
+            info!("applying spending policy {:?}", path);
+            let policy: SpendingPolicy = serde_json::from_slice(&std::fs::read(&path)?)?;
+            policy.validate()?;
+            let mut allowed_wallets = vec![];
+            for name in policy.allowed_wallets.iter() {
+                let dir = wallets_dir.ok_or("cannot find the directory of the allowed wallets")?;
+                allowed_wallets.push(read_wallet(&dir.join(name).join("descriptor.json"))?);
+            }
+            policies.push(LoadedPolicy {
+                path,
+                policy,
+                allowed_wallets,
+            });
+        }
+        if policies.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(PolicyCheck {
+            wallet: wallet.clone(),
+            policies,
+        }))
+    }
+
+    /// The rules of every policy not respected by `psbt` at unix time `now`
+    pub fn violations(
+        &self,
+        psbt: &PSBT,
+        network: Network,
+        now: u64,
+    ) -> Result<Vec<PolicyViolation>> {
+        let spending = Spending::new(psbt, network, &self.wallet)?;
+        let mut result = vec![];
+        for loaded in self.policies.iter() {
+            result.extend(loaded.violations(&spending, &self.wallet, network, now)?);
+        }
+        debug!("policy violations {:?}", result);
+        Ok(result)
+    }
+
+    /// Record `psbt` as signed at unix time `now` in the ledger of every policy
+    pub fn record(&self, psbt: &PSBT, network: Network, now: u64) -> Result<()> {
+        let spending = Spending::new(psbt, network, &self.wallet)?;
+        for loaded in self.policies.iter() {
+            let path = loaded.ledger_path();
+            let mut ledger = read_ledger(&path)?;
+            if ledger.entries.iter().any(|e| e.txid == spending.txid) {
+                continue;
+            }
+            ledger.entries.push(LedgerEntry {
+                txid: spending.txid,
+                timestamp: now,
+                amount: spending.amount(),
+            });
+            std::fs::write(&path, serde_json::to_string_pretty(&ledger)?)?;
+        }
+        Ok(())
+    }
+}
+
+impl SpendingPolicy {
+    fn validate(&self) -> Result<()> {
+        if let Some(hours) = self.allowed_hours.as_ref() {
+            if hours.from > 23 || hours.to > 24 {
+                return Err("allowed_hours must be between 0 and 24".into());
+            }
+            if hours.from == hours.to {
+                return Err("allowed_hours is empty, use from 0 to 24 for the whole day".into());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl LoadedPolicy {
+    fn ledger_path(&self) -> PathBuf {
+        self.path.with_file_name(LEDGER_FILE)
+    }
+
+    fn violations(
+        &self,
+        spending: &Spending,
+        wallet: &WalletJson,
+        network: Network,
+        now: u64,
+    ) -> Result<Vec<PolicyViolation>> {
+        let policy = &self.policy;
+        let mut reasons = vec![];
+        let amount = spending.amount();
+
+        if let Some(max) = policy.max_amount {
+            if amount > max {
+                reasons.push((
+                    "max_amount",
+                    format!(
+                        "{} sent outside the wallet, more than {}",
+                        Amount::from_sat(amount),
+                        Amount::from_sat(max)
+                    ),
+                ));
+            }
+        }
+        if let Some(max) = policy.max_fee {
+            if spending.fee > max {
+                reasons.push((
+                    "max_fee",
+                    format!(
+                        "fee {} is more than {}",
+                        Amount::from_sat(spending.fee),
+                        Amount::from_sat(max)
+                    ),
+                ));
+            }
+        }
+        if let Some(max) = policy.max_fee_rate {
+            if spending.fee_rate > max {
+                reasons.push((
+                    "max_fee_rate",
+                    format!(
+                        "fee rate {:.2} sat/vbyte is more than {}",
+                        spending.fee_rate, max
+                    ),
+                ));
+            }
+        }
+        if !policy.allowed_addresses.is_empty() || !self.allowed_wallets.is_empty() {
+            for (i, address, _) in spending.external.iter() {
+                if !self.is_allowed(address, network)? {
+                    reasons.push((
+                        "allowed_destinations",
+                        format!("output #{} to {} is not an allowed destination", i, address),
+                    ));
+                }
+            }
+        }
+        if policy.change_to_same_wallet {
+            for i in spending.fake_change.iter() {
+                reasons.push((
+                    "change_to_same_wallet",
+                    format!(
+                        "output #{} has key derivations of wallet {} but it is not one of its addresses",
+                        i, wallet.name
+                    ),
+                ));
+            }
+        }
+        if let Some(hours) = policy.allowed_hours.as_ref() {
+            let hour = ((now % DAY_SECS) / 3600) as u8;
+            if !hours.contains(hour) {
+                reasons.push((
+                    "allowed_hours",
+                    format!(
+                        "signing at hour {} UTC, allowed from {} to {}",
+                        hour, hours.from, hours.to
+                    ),
+                ));
+            }
+        }
+        if policy.max_amount_per_day.is_some() || policy.max_tx_per_day.is_some() {
+            let ledger = read_ledger(&self.ledger_path())?;
+            let last_day: Vec<&LedgerEntry> = ledger
+                .entries
+                .iter()
+                .filter(|e| e.timestamp + DAY_SECS > now && e.txid != spending.txid)
+                .collect();
+            if let Some(max) = policy.max_tx_per_day {
+                if last_day.len() as u64 + 1 > max as u64 {
+                    reasons.push((
+                        "max_tx_per_day",
+                        format!(
+                            "{} transactions already signed in the last 24 hours, the limit is {}",
+                            last_day.len(),
+                            max
+                        ),
+                    ));
+                }
+            }
+            if let Some(max) = policy.max_amount_per_day {
+                let total = last_day.iter().map(|e| e.amount).sum::<u64>() + amount;
+                if total > max {
+                    reasons.push((
+                        "max_amount_per_day",
+                        format!(
+                            "{} sent outside the wallet in the last 24 hours, more than {}",
+                            Amount::from_sat(total),
+                            Amount::from_sat(max)
+                        ),
+                    ));
+                }
+            }
+        }
+
+        Ok(reasons
+            .into_iter()
+            .map(|(rule, reason)| PolicyViolation {
+                policy_file: self.path.clone(),
+                rule,
+                reason,
+            })
+            .collect())
+    }
+
+    fn is_allowed(&self, address: &Address, network: Network) -> Result<bool> {
+        if self.policy.allowed_addresses.contains(address) {
+            return Ok(true);
+        }
+        for wallet in self.allowed_wallets.iter() {
+            if find_address(network, wallet, address, ALLOWED_WALLETS_MAX_INDEX)?.is_some() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+fn read_ledger(path: &Path) -> Result<SpendingLedger> {
+    if !path.exists() {
+        return Ok(SpendingLedger::default());
+    }
+    Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+}
+
+/// current unix time in seconds
+pub fn now() -> Result<u64> {
+    Ok(SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| e.to_string())?
+        .as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::offline::spending_policy::*;
+    use bitcoin::util::bip32::{DerivationPath, Fingerprint};
+    use std::fs;
+    use std::str::FromStr;
+    use tempdir::TempDir;
+
+    const DESCRIPTOR: &str = "wsh(multi(2,tpubD6NzVbkrYhZ4YfG9CySHqKHFbaLcD7hSDyqRUtCmMKNim5fkiJtTnFeqKsRHMHSK5ddFrhqRr3Ghv1JtuWkBzikuBqKu1xCpjQ9YxoPGgqU/0/*,tpubD6NzVbkrYhZ4WpudNKLizFbGzpsG3jkLF7mc8Vfh1fTDbbBPjDP29My6TaLncaS8VeDPcaNMdUkybucr8Kz9CHSdAtvxnaXyBxPRocefdXN/0/*))#5wstxmwd";
+    // first main address of DESCRIPTOR
+    const WALLET_ADDRESS: &str = "tb1q5nrregep899vnvaa5vdpxcwg8794jqy38nu304kl4d7wm4e92yeqz4jfmk";
+    const EXTERNAL_ADDRESS: &str = "tb1qrxye2d9e5qgsg0qd647rl7drs8p4ytzlylr2ggceppd4djj58gws84d0gv";
+
+    fn rules(violations: &[PolicyViolation]) -> Vec<&str> {
+        violations.iter().map(|v| v.rule).collect()
+    }
+
+    #[test]
+    fn test_spending_policy() {
+        let temp_dir = TempDir::new("test_spending_policy").unwrap();
+        let key_file = temp_dir
+            .path()
+            .join("keys")
+            .join("key")
+            .join("PRIVATE.json");
+        let wallets_dir = temp_dir.path().join("wallets");
+        let wallet_file = wallets_dir.join("wallet").join("descriptor.json");
+        let finger = Fingerprint::from(&[1u8, 2, 3, 4][..]);
+        let mut wallet = WalletJson {
+            name: "wallet".to_string(),
+            descriptor_main: DESCRIPTOR.to_string(),
+            descriptor_change: DESCRIPTOR
+                .replace("/0/*", "/1/*")
+                .split('#')
+                .next()
+                .unwrap()
+                .to_string(),
+            fingerprints: vec![finger].into_iter().collect(),
+            required_sig: 2,
+            policy: None,
+            daemon_opts: None,
+            created_at_height: 0,
+        };
+        for name in &["wallet", "other"] {
+            wallet.name = name.to_string();
+            let path = wallets_dir.join(name);
+            fs::create_dir_all(&path).unwrap();
+            fs::write(
+                path.join("descriptor.json"),
+                serde_json::to_vec(&wallet).unwrap(),
+            )
+            .unwrap();
+        }
+        wallet.name = "wallet".to_string();
+        fs::create_dir_all(key_file.parent().unwrap()).unwrap();
+        assert!(PolicyCheck::load(&key_file, &wallet_file, &wallet)
+            .unwrap()
+            .is_none());
+
+        // output #0 pays 0.0021231 BTC outside the wallet, output #1 goes back to the wallet
+        let bytes = include_bytes!("../../test_data/sign/psbt_testnet.json");
+        let psbt_json: PsbtJson = serde_json::from_slice(bytes).unwrap();
+        let (_, mut psbt) = psbt_from_base64(&psbt_json.psbt).unwrap();
+        let wallet_address = Address::from_str(WALLET_ADDRESS).unwrap();
+        psbt.global.unsigned_tx.output[1].script_pubkey = wallet_address.script_pubkey();
+        let pubkey = bitcoin::PublicKey::from_str(
+            "020ca0e815748c41087075f3840c1edd9400f4db031dbe948b1929b6a93c72386a",
+        )
+        .unwrap();
+        let path = DerivationPath::from_str("m/0/0").unwrap();
+        psbt.outputs[1].hd_keypaths.insert(pubkey, (finger, path));
+
+        let write_policy = |path: PathBuf, policy: &SpendingPolicy| {
+            fs::write(path, serde_json::to_vec(policy).unwrap()).unwrap();
+        };
+        let key_policy = key_file.with_file_name(POLICY_FILE);
+        let check = |policy: &SpendingPolicy, psbt: &PSBT, now: u64| {
+            write_policy(key_policy.clone(), policy);
+            PolicyCheck::load(&key_file, &wallet_file, &wallet)
+                .unwrap()
+                .unwrap()
+                .violations(psbt, Network::Testnet, now)
+                .unwrap()
+        };
+        let ten_am = 10 * 3600;
+
+        let mut policy = SpendingPolicy {
+            max_amount: Some(212_310),
+            max_fee: Some(192),
+            max_fee_rate: Some(1.1),
+            ..Default::default()
+        };
+        assert!(check(&policy, &psbt, ten_am).is_empty());
+        policy.max_amount = Some(200_000);
+        policy.max_fee = Some(100);
+        policy.max_fee_rate = Some(0.5);
+        let violations = check(&policy, &psbt, ten_am);
+        assert_eq!(
+            rules(&violations),
+            vec!["max_amount", "max_fee", "max_fee_rate"]
+        );
+        assert_eq!(
+            violations[0].to_string(),
+            format!(
+                "Refused by spending policy {}, max_amount: 0.00212310 BTC sent outside the wallet, more than 0.00200000 BTC",
+                key_policy.display()
+            )
+        );
+
+        let mut policy = SpendingPolicy {
+            allowed_addresses: vec![wallet_address.clone()],
+            ..Default::default()
+        };
+        assert_eq!(
+            rules(&check(&policy, &psbt, ten_am)),
+            vec!["allowed_destinations"]
+        );
+        policy.allowed_addresses = vec![Address::from_str(EXTERNAL_ADDRESS).unwrap()];
+        assert!(check(&policy, &psbt, ten_am).is_empty());
+        // without key derivations output #1 is outside the wallet but it's an address of `other`
+        let mut to_other = psbt.clone();
+        to_other.outputs[1].hd_keypaths.clear();
+        assert_eq!(
+            rules(&check(&policy, &to_other, ten_am)),
+            vec!["allowed_destinations"]
+        );
+        policy.allowed_wallets = vec!["other".to_string()];
+        assert!(check(&policy, &to_other, ten_am).is_empty());
+        policy.allowed_wallets = vec!["missing".to_string()];
+        write_policy(key_policy.clone(), &policy);
+        assert!(PolicyCheck::load(&key_file, &wallet_file, &wallet).is_err());
+
+        let policy = SpendingPolicy {
+            change_to_same_wallet: true,
+            ..Default::default()
+        };
+        assert!(check(&policy, &psbt, ten_am).is_empty());
+        let mut fake_change = psbt.clone();
+        let path = DerivationPath::from_str("m/1/3").unwrap();
+        fake_change.outputs[0]
+            .hd_keypaths
+            .insert(pubkey, (finger, path));
+        assert_eq!(
+            rules(&check(&policy, &fake_change, ten_am)),
+            vec!["change_to_same_wallet"]
+        );
+
+        let mut policy = SpendingPolicy {
+            allowed_hours: Some(HoursRange { from: 8, to: 18 }),
+            ..Default::default()
+        };
+        assert!(check(&policy, &psbt, ten_am).is_empty());
+        assert_eq!(
+            rules(&check(&policy, &psbt, 18 * 3600)),
+            vec!["allowed_hours"]
+        );
+        policy.allowed_hours = Some(HoursRange { from: 22, to: 6 });
+        assert_eq!(rules(&check(&policy, &psbt, ten_am)), vec!["allowed_hours"]);
+        assert!(check(&policy, &psbt, DAY_SECS + 3600).is_empty());
+        policy.allowed_hours = Some(HoursRange { from: 25, to: 6 });
+        write_policy(key_policy.clone(), &policy);
+        assert!(PolicyCheck::load(&key_file, &wallet_file, &wallet).is_err());
+        policy.allowed_hours = Some(HoursRange { from: 8, to: 8 });
+        write_policy(key_policy.clone(), &policy);
+        assert!(PolicyCheck::load(&key_file, &wallet_file, &wallet).is_err());
+        policy.allowed_hours = Some(HoursRange { from: 0, to: 24 });
+        assert!(check(&policy, &psbt, ten_am).is_empty());
+        assert!(check(&policy, &psbt, DAY_SECS - 1).is_empty());
+        fs::remove_file(&key_policy).unwrap();
+
+        // velocity limits in the wallet policy, tracked in its own ledger
+        let policy = SpendingPolicy {
+            max_tx_per_day: Some(1),
+            max_amount_per_day: Some(300_000),
+            ..Default::default()
+        };
+        write_policy(wallet_file.with_file_name(POLICY_FILE), &policy);
+        let policy_check = PolicyCheck::load(&key_file, &wallet_file, &wallet)
+            .unwrap()
+            .unwrap();
+        assert!(policy_check
+            .violations(&psbt, Network::Testnet, ten_am)
+            .unwrap()
+            .is_empty());
+        policy_check
+            .record(&psbt, Network::Testnet, ten_am)
+            .unwrap();
+        policy_check
+            .record(&psbt, Network::Testnet, ten_am)
+            .unwrap();
+        let ledger = read_ledger(&wallet_file.with_file_name(LEDGER_FILE)).unwrap();
+        assert_eq!(ledger.entries.len(), 1);
+        assert_eq!(ledger.entries[0].amount, 212_310);
+        // signing again the same transaction is not counted twice
+        assert!(policy_check
+            .violations(&psbt, Network::Testnet, ten_am)
+            .unwrap()
+            .is_empty());
+        let mut another = psbt.clone();
+        another.global.unsigned_tx.lock_time += 1;
+        let violations = policy_check
+            .violations(&another, Network::Testnet, ten_am + 60)
+            .unwrap();
+        assert_eq!(
+            rules(&violations),
+            vec!["max_tx_per_day", "max_amount_per_day"]
+        );
+        assert!(policy_check
+            .violations(&another, Network::Testnet, ten_am + DAY_SECS)
+            .unwrap()
+            .is_empty());
+    }
+}
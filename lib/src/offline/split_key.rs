@@ -0,0 +1,195 @@
+use crate::common::encryption::{self, decrypt_key};
+use crate::common::mnemonic::Mnemonic;
+use crate::common::slip39;
+use crate::offline::sign::read_key_file;
+use crate::*;
+use bitcoin::util::bip32::Fingerprint;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::str::FromStr;
+use structopt::StructOpt;
+
+/// Split the entropy of a master key in SLIP-39 mnemonic shares
+#[derive(StructOpt, Debug, Serialize, Deserialize)]
+#[structopt(name = "split_key")]
+pub struct SplitKeyOptions {
+    /// File containing the master key (PRIVATE.json), the key must have a mnemonic
+    #[structopt(short, long, parse(from_os_str))]
+    pub key: PathBuf,
+
+    /// Number of groups required to restore the key
+    #[structopt(long, default_value = "1")]
+    pub group_threshold: u8,
+
+    /// Group of shares specified as threshold`of`count, eg. 2of3 means 3 shares of which 2 are
+    /// required to recover the group, at least 1 is required
+    #[structopt(short, long = "group")]
+    pub groups: Vec<GroupSpec>,
+
+    /// File containing the passphrase to decrypt the key file, required if the key has been saved
+    /// encrypted
+    #[structopt(
+        long = "encryption-passphrase-file",
+        parse(try_from_os_str = encryption::read_passphrase_file)
+    )]
+    pub encryption_passphrase: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct GroupSpec {
+    pub threshold: u8,
+    pub count: u8,
+}
+
+impl FromStr for GroupSpec {
+    type Err = std::io::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let parts: Vec<_> = s.split("of").collect();
+        if parts.len() != 2 {
+            return Err(io_err("group is not in the format <threshold>of<count>"));
+        }
+        let threshold = parts[0]
+            .parse::<u8>()
+            .map_err(|_| io_err("group threshold is not a number"))?;
+        let count = parts[1]
+            .parse::<u8>()
+            .map_err(|_| io_err("group count is not a number"))?;
+        Ok(GroupSpec { threshold, count })
+    }
+}
+
+impl SplitKeyOptions {
+    fn validate(&self) -> Result<()> {
+        if self.groups.is_empty() {
+            return Err("At least one group is mandatory (--group)".into());
+        }
+        if self.group_threshold == 0 || self.group_threshold as usize > self.groups.len() {
+            return Err("--group-threshold must be between 1 and the number of groups".into());
+        }
+
+        Ok(())
+    }
+}
+
+pub fn start(opt: &SplitKeyOptions) -> Result<SplitKeyOutput> {
+    opt.validate()?;
+    let (name, fingerprint, mnemonic) =
+        read_mnemonic(&opt.key, opt.encryption_passphrase.as_deref())?;
+    debug!("splitting key {} {}", name, fingerprint);
+
+    let groups: Vec<_> = opt.groups.iter().map(|g| (g.threshold, g.count)).collect();
+    let shares =
+        slip39::generate_mnemonics(opt.group_threshold, &groups, &mnemonic.to_entropy(), b"", 0)?;
+
+    Ok(SplitKeyOutput {
+        name,
+        fingerprint,
+        group_threshold: opt.group_threshold,
+        groups: opt
+            .groups
+            .iter()
+            .zip(shares)
+            .map(|(g, shares)| SharesGroup {
+                member_threshold: g.threshold,
+                shares,
+            })
+            .collect(),
+    })
+}
+
+/// Read name, fingerprint and mnemonic of a key, the BIP39 passphrase is not needed since the
+/// mnemonic is saved also for passphrase protected keys
+fn read_mnemonic(
+    path: &PathBuf,
+    encryption_passphrase: Option<&str>,
+) -> Result<(String, Fingerprint, Mnemonic)> {
+    let key_file = match read_key_file(path)? {
+        PrivateMasterKeyFile::Encrypted(encrypted) => {
+            let passphrase = encryption_passphrase.ok_or(Error::MissingPassphrase)?;
+            decrypt_key(&encrypted, passphrase)?
+        }
+        key_file => key_file,
+    };
+    let (name, fingerprint, mnemonic) = match key_file {
+        PrivateMasterKeyFile::Plain(key) => (key.name, key.fingerprint, key.mnemonic),
+        PrivateMasterKeyFile::Bip39Protected(key) => {
+            (key.name, key.fingerprint, Some(key.mnemonic))
+        }
+        PrivateMasterKeyFile::Encrypted(_) => return Err(Error::WrongPassphrase),
+    };
+    let mnemonic =
+        mnemonic.ok_or("Key has no mnemonic, only keys created from entropy can be split")?;
+    Ok((name, fingerprint, mnemonic))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::offline::random::{self, RandomOptions};
+    use crate::offline::restore::{self, Nature, RestoreOptions};
+    use crate::offline::split_key::{self, GroupSpec, SplitKeyOptions};
+    use crate::qr::QrStyle;
+    use bitcoin::Network;
+    use std::str::FromStr;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_split_key() {
+        let temp_dir = TempDir::new("test_split_key").unwrap();
+        let temp_dir_str = format!("{}/", temp_dir.path().display());
+
+        let rand_opts = RandomOptions::new("orig".to_string());
+        let key = random::create_key(&temp_dir_str, Network::Testnet, &rand_opts).unwrap();
+
+        let opt = SplitKeyOptions {
+            key: key.private_file.clone(),
+            group_threshold: 2,
+            groups: vec![
+                GroupSpec::from_str("2of3").unwrap(),
+                GroupSpec::from_str("1of1").unwrap(),
+            ],
+            encryption_passphrase: None,
+        };
+        let output = split_key::start(&opt).unwrap();
+        assert_eq!(output.fingerprint, key.key.fingerprint);
+        assert_eq!(output.groups[0].shares.len(), 3);
+        assert_eq!(output.groups[1].shares.len(), 1);
+
+        let shares = [
+            output.groups[0].shares[1].clone(),
+            output.groups[1].shares[0].clone(),
+            output.groups[0].shares[2].clone(),
+        ];
+        let restore_opts = RestoreOptions {
+            key_name: "restored".to_string(),
+            nature: Nature::Slip39,
+            value: shares.join(","),
+            qr_version: 14,
+            qr_style: QrStyle::default(),
+            encryption_passphrase: None,
+            bip39_passphrase: None,
+        };
+        let restored = restore::start(&temp_dir_str, Network::Testnet, &restore_opts).unwrap();
+        assert_eq!(restored.key.xprv, key.key.xprv);
+        assert_eq!(restored.key.mnemonic, key.key.mnemonic);
+
+        let restore_opts = RestoreOptions {
+            key_name: "not_enough".to_string(),
+            nature: Nature::Slip39,
+            value: shares[..2].join(","),
+            qr_version: 14,
+            qr_style: QrStyle::default(),
+            encryption_passphrase: None,
+            bip39_passphrase: None,
+        };
+        assert!(restore::start(&temp_dir_str, Network::Testnet, &restore_opts).is_err());
+
+        assert!(GroupSpec::from_str("2-3").is_err());
+        let opt = SplitKeyOptions {
+            group_threshold: 3,
+            ..opt
+        };
+        assert!(split_key::start(&opt).is_err());
+    }
+}
@@ -0,0 +1,266 @@
+use crate::offline::descriptor::{derive_address, DeriveAddressOpts};
+use crate::qr::{save_qrs, QrStyle};
+use crate::*;
+use bitcoin::{Address, Network};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+/// List main and change addresses of a wallet
+#[derive(StructOpt, Debug, Serialize, Deserialize)]
+#[structopt(name = "list_addresses")]
+pub struct ListAddressesOptions {
+    /// File containing the wallet descriptor (descriptor.json)
+    #[structopt(short, long, parse(from_os_str))]
+    pub wallet_descriptor_file: PathBuf,
+
+    /// Index of the first address
+    #[structopt(long, default_value = "0")]
+    pub start: u32,
+
+    /// Number of main and change addresses to list
+    #[structopt(long, default_value = "20")]
+    pub count: u32,
+
+    /// QR code max version to use (max size), with 0 QR codes of the addresses are not saved
+    #[structopt(long, default_value = "0")]
+    pub qr_version: i16,
+
+    #[structopt(flatten)]
+    #[serde(flatten)]
+    pub qr_style: QrStyle,
+}
+
+/// Check an address given by the online machine belongs to the wallet
+#[derive(StructOpt, Debug, Serialize, Deserialize)]
+#[structopt(name = "verify_address")]
+pub struct VerifyAddressOptions {
+    /// File containing the wallet descriptor (descriptor.json)
+    #[structopt(short, long, parse(from_os_str))]
+    pub wallet_descriptor_file: PathBuf,
+
+    /// Address to verify
+    #[structopt(short, long)]
+    pub address: Address,
+
+    /// Main and change addresses are searched from index 0 up to this index included
+    #[structopt(long, default_value = "1000")]
+    pub max_index: u32,
+
+    /// QR code max version to use (max size)
+    #[structopt(long, default_value = "14")]
+    pub qr_version: i16,
+
+    #[structopt(flatten)]
+    #[serde(flatten)]
+    pub qr_style: QrStyle,
+}
+
+pub fn list(
+    datadir: &str,
+    network: Network,
+    opt: &ListAddressesOptions,
+) -> Result<ListAddressesOutput> {
+    let wallet = read_wallet(&opt.wallet_descriptor_file)?;
+    let end = opt
+        .start
+        .checked_add(opt.count)
+        .ok_or("start plus count overflows")?;
+    let addresses = |descriptor: &str, int_or_ext: u32| -> Result<Vec<WalletAddress>> {
+        (opt.start..end)
+            .map(|index| {
+                let address = wallet_address(network, descriptor, index, int_or_ext)?;
+                with_qr(
+                    datadir,
+                    network,
+                    &wallet,
+                    address,
+                    opt.qr_version,
+                    &opt.qr_style,
+                )
+            })
+            .collect()
+    };
+    let main = addresses(&wallet.descriptor_main, 0)?;
+    let change = addresses(&wallet.descriptor_change, 1)?;
+    Ok(ListAddressesOutput {
+        name: wallet.name,
+        main,
+        change,
+    })
+}
+
+pub fn verify(
+    datadir: &str,
+    network: Network,
+    opt: &VerifyAddressOptions,
+) -> Result<VerifyAddressOutput> {
+    let wallet = read_wallet(&opt.wallet_descriptor_file)?;
+    if opt.address.network != network {
+        return Err(format!("address {} is not on {}", opt.address, network).into());
+    }
+    match find_address(network, &wallet, &opt.address, opt.max_index)? {
+        Some(address) => {
+            debug!("address {} found at {}", address.address, address.path);
+            let address = with_qr(
+                datadir,
+                network,
+                &wallet,
+                address,
+                opt.qr_version,
+                &opt.qr_style,
+            )?;
+            Ok(VerifyAddressOutput {
+                name: wallet.name,
+                address,
+            })
+        }
+        None => Err(format!(
+            "address {} not found in the first {} main and change addresses of wallet {}",
+            opt.address,
+            opt.max_index as u64 + 1,
+            wallet.name
+        )
+        .into()),
+    }
+}
+
+/// Search `address` in main and change addresses of `wallet` from index 0 up to `max_index` included
+pub fn find_address(
+    network: Network,
+    wallet: &WalletJson,
+    address: &Address,
+    max_index: u32,
+) -> Result<Option<WalletAddress>> {
+    for index in 0..=max_index {
+        for (int_or_ext, descriptor) in [&wallet.descriptor_main, &wallet.descriptor_change]
+            .iter()
+            .enumerate()
+        {
+            let derived = wallet_address(network, descriptor, index, int_or_ext as u32)?;
+            if &derived.address == address {
+                return Ok(Some(derived));
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn wallet_address(
+    network: Network,
+    descriptor: &str,
+    index: u32,
+    int_or_ext: u32,
+) -> Result<WalletAddress> {
+    let opts = DeriveAddressOpts {
+        descriptor: descriptor.to_string(),
+        index,
+    };
+    let derived = derive_address(network, &opts, int_or_ext)?;
+    Ok(WalletAddress {
+        address: derived.address,
+        path: derived.path,
+        qr_files: vec![],
+    })
+}
+
+/// save the QR of the address, uppercase so that it's encoded in the more compact alphanumeric mode
+fn with_qr(
+    datadir: &str,
+    network: Network,
+    wallet: &WalletJson,
+    mut address: WalletAddress,
+    qr_version: i16,
+    qr_style: &QrStyle,
+) -> Result<WalletAddress> {
+    if qr_version == 0 {
+        return Ok(address);
+    }
+    let path_builder = PathBuilder::new(datadir, network, Kind::Wallet, Some(wallet.name.clone()));
+    let levels: Vec<String> = address.path.into_iter().map(|c| c.to_string()).collect();
+    let qr_dir = path_builder.file(&format!("qr-address-{}", levels.join("-")))?;
+    let content = format!("bitcoin:{}", address.address).to_uppercase();
+    address.qr_files = save_qrs(content.into_bytes(), qr_dir, qr_version, qr_style)?;
+    Ok(address)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::offline::verify_address::*;
+    use std::fs;
+    use std::str::FromStr;
+    use tempdir::TempDir;
+
+    const DESCRIPTOR: &str = "wsh(multi(2,tpubD6NzVbkrYhZ4YfG9CySHqKHFbaLcD7hSDyqRUtCmMKNim5fkiJtTnFeqKsRHMHSK5ddFrhqRr3Ghv1JtuWkBzikuBqKu1xCpjQ9YxoPGgqU/0/*,tpubD6NzVbkrYhZ4WpudNKLizFbGzpsG3jkLF7mc8Vfh1fTDbbBPjDP29My6TaLncaS8VeDPcaNMdUkybucr8Kz9CHSdAtvxnaXyBxPRocefdXN/0/*))#5wstxmwd";
+
+    #[test]
+    fn test_verify_address() {
+        let temp_dir = TempDir::new("test_verify_address").unwrap();
+        let temp_dir_str = format!("{}/", temp_dir.path().display());
+        let wallet = WalletJson {
+            name: "wallet".to_string(),
+            descriptor_main: DESCRIPTOR.to_string(),
+            descriptor_change: DESCRIPTOR
+                .split('#')
+                .next()
+                .unwrap()
+                .replace("/0/*", "/1/*"),
+            fingerprints: Default::default(),
+            required_sig: 2,
+            policy: None,
+            daemon_opts: None,
+            created_at_height: 0,
+        };
+        let wallet_file = temp_dir.path().join("descriptor.json");
+        fs::write(&wallet_file, serde_json::to_vec(&wallet).unwrap()).unwrap();
+
+        let list_opt = ListAddressesOptions {
+            wallet_descriptor_file: wallet_file.clone(),
+            start: 3,
+            count: 5,
+            qr_version: 0,
+            qr_style: QrStyle::default(),
+        };
+        let listed = list(&temp_dir_str, Network::Testnet, &list_opt).unwrap();
+        assert_eq!(listed.main.len(), 5);
+        assert_eq!(listed.change.len(), 5);
+        assert_eq!(listed.main[0].path.to_string(), "m/0/3");
+        assert_eq!(listed.change[4].path.to_string(), "m/1/7");
+        assert!(listed.main[0].qr_files.is_empty());
+        let first = list(
+            &temp_dir_str,
+            Network::Testnet,
+            &ListAddressesOptions {
+                start: 0,
+                ..list_opt
+            },
+        )
+        .unwrap();
+        // same address given by the node in the descriptor tests
+        assert_eq!(
+            first.main[0].address.to_string(),
+            "tb1q5nrregep899vnvaa5vdpxcwg8794jqy38nu304kl4d7wm4e92yeqz4jfmk"
+        );
+
+        let mut opt = VerifyAddressOptions {
+            wallet_descriptor_file: wallet_file,
+            address: listed.change[2].address.clone(),
+            max_index: 10,
+            qr_version: 14,
+            qr_style: QrStyle::default(),
+        };
+        let verified = verify(&temp_dir_str, Network::Testnet, &opt).unwrap();
+        assert_eq!(verified.address.path.to_string(), "m/1/5");
+        assert!(!verified.address.qr_files.is_empty());
+
+        opt.max_index = 4;
+        assert!(verify(&temp_dir_str, Network::Testnet, &opt).is_err());
+        opt.address =
+            Address::from_str("tb1qz2h8n70cnp0w6290scdl5ycvm0z7sqkrlgy5kgkds0n0fp7wwk6qyn8ywd")
+                .unwrap();
+        opt.max_index = 10;
+        assert!(verify(&temp_dir_str, Network::Testnet, &opt).is_err());
+        assert!(verify(&temp_dir_str, Network::Bitcoin, &opt).is_err());
+    }
+}
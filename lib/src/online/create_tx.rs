@@ -1,5 +1,7 @@
 use crate::offline::sign::save_psbt;
+use crate::online::get_address::DEFAULT_GAP_LIMIT;
 use crate::online::Wallet;
+use crate::qr::QrStyle;
 use crate::*;
 use bitcoin::{Address, Amount, OutPoint};
 use bitcoincore_rpc::bitcoincore_rpc_json::{
@@ -23,6 +25,16 @@ pub struct CreateTxOptions {
     #[structopt(long, long = "coin")]
     pub coins: Vec<OutPoint>,
 
+    /// nSequence of the inputs given with --coin, needed to spend paths with relative timelocks
+    /// (`older`) of policy wallets
+    #[structopt(long, requires = "coins")]
+    pub sequence: Option<u32>,
+
+    /// nLockTime of the transaction, needed to spend paths with absolute timelocks (`after`) of
+    /// policy wallets
+    #[structopt(long)]
+    pub locktime: Option<u32>,
+
     /// Name of the PSBT
     #[structopt(short, long)]
     pub psbt_name: String,
@@ -30,6 +42,9 @@ pub struct CreateTxOptions {
     /// QR code max version to use (max size)
     #[structopt(long, default_value = "14")]
     pub qr_version: i16,
+
+    #[structopt(flatten)]
+    pub qr_style: QrStyle,
 }
 
 #[derive(StructOpt, Debug)]
@@ -44,6 +59,10 @@ impl CreateTxOptions {
             return Err("At least one recipient is mandatory (--recipient)".into());
         }
 
+        if self.sequence.is_some() && self.coins.is_empty() {
+            return Err("--sequence requires the inputs to be specified with --coin".into());
+        }
+
         Ok(())
     }
 
@@ -61,7 +80,7 @@ impl CreateTxOptions {
             vec.push(CreateRawTransactionInput {
                 txid: coin.txid,
                 vout: coin.vout,
-                sequence: None,
+                sequence: self.sequence,
             });
         }
         vec
@@ -98,11 +117,11 @@ impl Wallet {
 
         let mut options: WalletCreateFundedPsbtOptions = Default::default();
         options.include_watching = Some(true);
-        options.change_address = Some(self.get_address(None, true)?.address);
+        options.change_address = Some(self.get_address(None, true, DEFAULT_GAP_LIMIT)?.address);
         let result = self.client.wallet_create_funded_psbt(
             &inputs,
             &outputs,
-            None,
+            opt.locktime.map(|l| l as i64),
             Some(options),
             Some(true),
         );
@@ -122,10 +141,15 @@ impl Wallet {
             }
         };
 
+        // key derivations are filled by the node (bip32derivs) from the key origins in the descriptors
         let mut psbt = psbt_from_rpc(&funded_psbt, &opt.psbt_name)?;
+        if psbt.inputs.iter().any(|i| i.hd_keypaths.is_empty()) {
+            info!("HD key paths not found for some inputs");
+        }
 
         let mut psbts_dir = self.context.psbts_dir()?;
-        let (psbt_file, qr_files) = save_psbt(&mut psbt, &mut psbts_dir, opt.qr_version)?;
+        let (psbt_file, qr_files) =
+            save_psbt(&mut psbt, &mut psbts_dir, opt.qr_version, &opt.qr_style)?;
 
         // detect address reuse
         let transactions = self
@@ -1,74 +1,59 @@
-use crate::offline::descriptor::extract_xpubs;
-use crate::online::{read_xpubs_files, Wallet};
+use crate::offline::create_wallet::WalletDescriptorOptions;
+use crate::offline::descriptor::extract_keys;
+use crate::online::Wallet;
+use crate::qr::QrStyle;
 use crate::*;
-use bitcoin::util::bip32::ExtendedPubKey;
-use bitcoin::Network;
 use bitcoincore_rpc::bitcoincore_rpc_json::{
     ImportMultiOptions, ImportMultiRequest, ImportMultiRescanSince,
 };
 use bitcoincore_rpc::RpcApi;
 use log::debug;
 use log::info;
+use serde_json::{json, Value};
 use std::path::PathBuf;
 use structopt::StructOpt;
 
 #[derive(StructOpt, Debug)]
 pub struct CreateWalletOptions {
-    /// number of signatures required
-    #[structopt(short)]
-    pub r: usize,
-
-    /// Extended Public Keys (xpub) that are composing the wallet, given as String (xprv...)
-    #[structopt(long = "xpub")]
-    pub xpubs: Vec<ExtendedPubKey>,
-
-    /// Extended Public Keys (xpub) that are composing the wallet, given as a json file
-    #[structopt(long = "xpub-file")]
-    pub xpub_files: Vec<PathBuf>,
+    #[structopt(flatten)]
+    pub descriptor: WalletDescriptorOptions,
 
     #[structopt(flatten)]
     pub daemon_opts: DaemonOpts,
 
+    /// Create a descriptor wallet in the node (bitcoin core 0.21+) and import the descriptors
+    /// with `importdescriptors`, the node extends the range of active descriptors automatically
+    #[structopt(long)]
+    pub descriptors: bool,
+
     /// QR code max version to use (max size)
     #[structopt(long, default_value = "14")]
     pub qr_version: i16,
-}
 
-impl CreateWalletOptions {
-    fn validate(&self, network: Network) -> Result<()> {
-        if self.r == 0 {
-            return Err("required signatures cannot be 0".into());
-        }
+    #[structopt(flatten)]
+    pub qr_style: QrStyle,
+}
 
-        if self.r > 15 {
-            return Err("required signatures cannot be greater than 15".into());
-        }
+#[derive(StructOpt, Debug)]
+pub struct RegisterWalletOptions {
+    /// File containing the wallet descriptor (descriptor.json) created offline, if missing the
+    /// wallet with the same name in the data dir is registered
+    #[structopt(long, parse(from_os_str))]
+    pub wallet_descriptor_file: Option<PathBuf>,
 
-        if self.r > (self.xpubs.len() + self.xpub_files.len()) {
-            return Err("required signatures cannot be greater than the number of xpubs".into());
-        }
+    #[structopt(flatten)]
+    pub daemon_opts: DaemonOpts,
 
-        let mut xpubs = read_xpubs_files(&self.xpub_files)?;
-        xpubs.extend(&self.xpubs);
-
-        for xpub in xpubs.iter() {
-            if !(network == xpub.network
-                || (network == Network::Regtest && xpub.network == Network::Testnet))
-            {
-                return Err(format!(
-                    "detected xpub of another network (cmd:{}) (xpub:{})",
-                    network, xpub.network
-                )
-                .into());
-            }
+    /// Register as a descriptor wallet, see `create-wallet --descriptors`
+    #[structopt(long)]
+    pub descriptors: bool,
 
-            if xpubs.iter().filter(|xpub2| *xpub2 == xpub).count() > 1 {
-                return Err("Cannot use same xpub twice".into());
-            }
-        }
+    /// QR code max version to use (max size)
+    #[structopt(long, default_value = "14")]
+    pub qr_version: i16,
 
-        Ok(())
-    }
+    #[structopt(flatten)]
+    pub qr_style: QrStyle,
 }
 
 impl Wallet {
@@ -78,75 +63,161 @@ impl Wallet {
         opt: &CreateWalletOptions,
         height: u64,
     ) -> Result<CreateWalletOutput> {
-        opt.validate(self.context.network)?;
         debug!("create");
+        let mut wallet =
+            opt.descriptor
+                .wallet(&self.context.wallet_name, self.context.network, height)?;
+        wallet.daemon_opts = Some(daemon_opts.clone());
+
+        self.import_descriptors(&wallet, opt.descriptors)?;
 
-        let mut xpubs = read_xpubs_files(&opt.xpub_files)?;
-        xpubs.extend(&opt.xpubs);
+        let wallet_file = self.context.save_wallet(&wallet)?;
+        self.save_wallet_qr_and_index(wallet, wallet_file, opt.qr_version, &opt.qr_style)
+    }
 
-        let mut descriptors = vec![];
-        for i in 0..=1 {
-            let mut xpub_paths = vec![];
-            for xpub in xpubs.iter() {
-                let xpub_path = format!("{}/{}/*", xpub, i);
-                xpub_paths.push(xpub_path)
+    /// Import in the node a wallet created offline, the node connection is saved in the wallet
+    pub fn register(
+        &self,
+        daemon_opts: &DaemonOpts,
+        opt: &RegisterWalletOptions,
+        height: u64,
+    ) -> Result<CreateWalletOutput> {
+        debug!("register");
+        let mut wallet = match &opt.wallet_descriptor_file {
+            Some(path) => read_wallet(path)?,
+            None => {
+                let path = self.context.filename_for_wallet("descriptor.json")?;
+                read_wallet(&path).map_err(|e| Error::FileNotFoundOrCorrupt(path, e.to_string()))?
             }
-            let descriptor = format!("wsh(multi({},{}))", opt.r, xpub_paths.join(","));
-            descriptors.push(descriptor);
+        };
+        if wallet.name != self.context.wallet_name {
+            return Err(format!(
+                "wallet name {} does not match --wallet-name {}",
+                wallet.name, self.context.wallet_name
+            )
+            .into());
         }
+        for descriptor in [&wallet.descriptor_main, &wallet.descriptor_change].iter() {
+            for key in extract_keys(descriptor)? {
+                check_compatibility(self.context.network, key.xpub.network)?;
+            }
+        }
+        if wallet.created_at_height == 0 {
+            wallet.created_at_height = height;
+        }
+        wallet.daemon_opts = Some(daemon_opts.clone());
+
+        self.import_descriptors(&wallet, opt.descriptors)?;
 
-        let descriptor_main = self.client.get_descriptor_info(&descriptors[0])?.descriptor;
-        let descriptor_change = self.client.get_descriptor_info(&descriptors[1])?.descriptor;
+        let wallet_file = self.context.update_wallet(&wallet)?;
+        self.save_wallet_qr_and_index(wallet, wallet_file, opt.qr_version, &opt.qr_style)
+    }
+
+    /// create the watch-only wallet in the node and import main and change descriptors, with
+    /// `descriptors` the node wallet is a descriptor wallet, a legacy one otherwise
+    fn import_descriptors(&self, wallet: &WalletJson, descriptors: bool) -> Result<()> {
+        if descriptors {
+            // createwallet name disable_private_keys blank passphrase avoid_reuse descriptors
+            let args = [
+                self.context.wallet_name.clone().into(),
+                true.into(),
+                true.into(),
+                "".into(),
+                false.into(),
+                true.into(),
+            ];
+            let created: Value = self.client.call("createwallet", &args)?;
+            info!("createwallet {:?}", created);
+            return self.import_active_descriptors(wallet);
+        }
 
         self.client
             .create_wallet(&self.context.wallet_name, Some(true), None, None, None)?;
 
-        let mut multi_request: ImportMultiRequest = Default::default();
-        multi_request.range = Some((0, 1000)); //TODO should be a parameter
-        multi_request.timestamp = ImportMultiRescanSince::Now;
-        multi_request.keypool = Some(true);
-        multi_request.watchonly = Some(true);
+        self.import_multi(wallet, 0, DESCRIPTOR_RANGE)
+    }
+
+    /// import in a legacy wallet of the node the scripts of main and change descriptors from
+    /// index `start` to `end` included
+    pub fn import_multi(&self, wallet: &WalletJson, start: u32, end: u32) -> Result<()> {
+        let multi_request = ImportMultiRequest {
+            range: Some((start as usize, end as usize)),
+            timestamp: ImportMultiRescanSince::Now,
+            keypool: Some(true),
+            watchonly: Some(true),
+            ..Default::default()
+        };
         let mut main = multi_request.clone();
-        main.descriptor = Some(&descriptor_main);
+        main.descriptor = Some(&wallet.descriptor_main);
         main.internal = Some(false);
         let mut change = multi_request.clone();
-        change.descriptor = Some(&descriptor_change);
+        change.descriptor = Some(&wallet.descriptor_change);
         change.internal = Some(true);
 
         let multi_options = ImportMultiOptions {
             rescan: Some(false),
         };
 
-        let import_multi_result = self
+        let results = self
             .client
-            .import_multi(&[main, change], Some(&multi_options));
-        info!("import_multi_result {:?}", import_multi_result);
-
-        let fingerprints = xpubs.iter().map(|x| x.fingerprint()).collect();
-
-        let wallet = WalletJson {
-            name: self.context.wallet_name.to_string(),
-            descriptor_main,
-            descriptor_change,
-            daemon_opts: Some(daemon_opts.clone()),
-            fingerprints,
-            required_sig: opt.r,
-            created_at_height: height,
-        };
-        let indexes = WalletIndexes {
-            main: 0u32,
-            change: 0u32,
+            .import_multi(&[main, change], Some(&multi_options))?;
+        info!("import_multi_result {:?}", results);
+        if let Some(failed) = results.iter().find(|r| !r.success) {
+            let message = failed.error.as_ref().map(|e| e.message.as_str());
+            return Err(
+                format!("importmulti failed: {}", message.unwrap_or("unknown error")).into(),
+            );
+        }
+        Ok(())
+    }
+
+    /// import main and change descriptors as active in a descriptor wallet of the node, so that
+    /// the node extends their range when addresses are used
+    pub fn import_active_descriptors(&self, wallet: &WalletJson) -> Result<()> {
+        let request = |desc: &str, internal: bool| {
+            json!({
+                "desc": desc,
+                "active": true,
+                "internal": internal,
+                "range": [0, DESCRIPTOR_RANGE],
+                "timestamp": "now",
+            })
         };
+        let requests = json!([
+            request(&wallet.descriptor_main, false),
+            request(&wallet.descriptor_change, true),
+        ]);
+        let results: Vec<Value> = self.client.call("importdescriptors", &[requests])?;
+        info!("importdescriptors {:?}", results);
+        if let Some(failed) = results.iter().find(|r| r["success"] != true) {
+            return Err(format!("importdescriptors failed: {}", failed["error"]).into());
+        }
+        Ok(())
+    }
 
-        let wallet_file = self.context.save_wallet(&wallet)?;
-        self.context.save_index(&indexes)?;
+    fn save_wallet_qr_and_index(
+        &self,
+        wallet: WalletJson,
+        wallet_file: PathBuf,
+        qr_version: i16,
+        qr_style: &QrStyle,
+    ) -> Result<CreateWalletOutput> {
+        let indexes_file = self.context.filename_for_wallet("indexes.json")?;
+        if !indexes_file.exists() {
+            let indexes = WalletIndexes {
+                main: 0u32,
+                change: 0u32,
+                imported_range: DESCRIPTOR_RANGE,
+            };
+            self.context.save_index(&indexes)?;
+        }
 
         let mut wallet_for_qr = wallet.clone();
         wallet_for_qr.daemon_opts = None; // no need of this info in the qr code
         let qr_bytes = serde_json::to_vec(&wallet_for_qr)?;
 
         let wallet_qr_path = self.context.path_for_wallet_qr()?;
-        let qr_files = common::qr::save_qrs(qr_bytes, wallet_qr_path, opt.qr_version)?;
+        let qr_files = common::qr::save_qrs(qr_bytes, wallet_qr_path, qr_version, qr_style)?;
 
         let create_wallet = CreateWalletOutput {
             qr_files,
@@ -157,27 +228,3 @@ impl Wallet {
         Ok(create_wallet)
     }
 }
-
-pub fn import_wallet(datadir: &str, network: Network, wallet: &WalletJson) -> Result<()> {
-    extract_xpubs(&wallet.descriptor_main)?
-        .iter()
-        .map(|xpub| check_compatibility(network, xpub.network))
-        .collect::<Result<()>>()?;
-    extract_xpubs(&wallet.descriptor_change)?
-        .iter()
-        .map(|xpub| check_compatibility(network, xpub.network))
-        .collect::<Result<()>>()?;
-    let context = Context {
-        firma_datadir: datadir.to_string(),
-        network,
-        wallet_name: wallet.name.clone(),
-    };
-    context.save_wallet(&wallet)?;
-    let mut wallet_for_qr = wallet.clone();
-    wallet_for_qr.daemon_opts = None; // no need of this info in the qr code
-    let qr_bytes = serde_json::to_vec(&wallet_for_qr)?;
-
-    let wallet_qr_path = context.path_for_wallet_qr()?;
-    common::qr::save_qrs(qr_bytes, wallet_qr_path, 14)?;
-    Ok(())
-}
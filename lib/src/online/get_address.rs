@@ -1,32 +1,49 @@
 use crate::offline::descriptor::DeriveAddressOpts;
 use crate::*;
+use bitcoin::Address;
 use bitcoincore_rpc::RpcApi;
 use log::info;
+use std::collections::HashSet;
 use structopt::StructOpt;
 
+/// Gap limit used when not specified, the default of most wallets
+pub const DEFAULT_GAP_LIMIT: u32 = 20;
+
 #[derive(StructOpt, Debug)]
 pub struct GetAddressOptions {
     /// Explicitly specify address derivation index (by default taken from .firma and incremented)
     #[structopt(long)]
     pub index: Option<u32>,
+
+    /// Warn when more than this number of consecutive addresses have been given out without
+    /// receiving funds, wallets restored from the descriptors stop looking for funds after the gap
+    #[structopt(long, default_value = "20")]
+    pub gap_limit: u32,
 }
 
 impl Wallet {
-    pub fn get_address(&self, cmd_index: Option<u32>, is_change: bool) -> Result<GetAddressOutput> {
+    pub fn get_address(
+        &self,
+        cmd_index: Option<u32>,
+        is_change: bool,
+        gap_limit: u32,
+    ) -> Result<GetAddressOutput> {
         let (wallet, mut indexes) = self.context.load_wallet_and_index()?;
 
         let (int_or_ext, index, descriptor) = if is_change {
-            (1, indexes.change, wallet.descriptor_change)
+            (1, indexes.change, wallet.descriptor_change.clone())
         } else {
             match cmd_index {
-                Some(index) => (0, index, wallet.descriptor_main),
-                None => (0, indexes.main, wallet.descriptor_main),
+                Some(index) => (0, index, wallet.descriptor_main.clone()),
+                None => (0, indexes.main, wallet.descriptor_main.clone()),
             }
         };
         let address_type = if is_change { "change" } else { "external" };
 
         info!("Creating {} address at index {}", address_type, index);
 
+        self.extend_imported_range(&wallet, &mut indexes, index, gap_limit)?;
+
         let addresses = self
             .client
             .derive_addresses(&descriptor, Some([index, index]))?;
@@ -40,14 +57,26 @@ impl Wallet {
         }
         info!("{}", address);
 
-        let opts = DeriveAddressOpts { descriptor, index };
-        let derive_address =
+        let opts = DeriveAddressOpts {
+            descriptor: descriptor.clone(),
+            index,
+        };
+        let mut derive_address =
             crate::offline::descriptor::derive_address(self.context.network, &opts, int_or_ext)?;
         assert_eq!(
             derive_address.address, address,
             "address generated from the node differs from the one generated from miniscript"
         );
 
+        let unused = self.unused_gap(&descriptor, index)?;
+        if unused >= gap_limit {
+            derive_address.warning = Some(format!(
+                "the last {} {} addresses did not receive funds, wallets restored with a gap limit \
+                of {} may not find funds sent to this address",
+                unused, address_type, gap_limit
+            ));
+        }
+
         if is_change {
             indexes.change += 1;
             self.context.save_index(&indexes)?;
@@ -58,4 +87,110 @@ impl Wallet {
 
         Ok(derive_address)
     }
+
+    /// Number of consecutive addresses of `descriptor` before `index` that never received funds,
+    /// according to `listreceivedbyaddress`
+    fn unused_gap(&self, descriptor: &str, index: u32) -> Result<u32> {
+        let received: HashSet<Address> = self
+            .client
+            .list_received_by_address(None, Some(0), Some(false), Some(true))?
+            .into_iter()
+            .map(|r| r.address)
+            .collect();
+        let mut unused = 0;
+        for i in (0..index).rev() {
+            let derived = crate::offline::descriptor::derive_descriptor(descriptor, i)?;
+            if let Some(address) = derived.address(self.context.network) {
+                if received.contains(&address) {
+                    break;
+                }
+            }
+            unused += 1;
+        }
+        Ok(unused)
+    }
+
+    /// The node knows only the addresses imported, extend the range of a legacy wallet when
+    /// `index` plus the gap limit is beyond the imported range. Descriptor wallets of the node
+    /// extend the range by themselves
+    fn extend_imported_range(
+        &self,
+        wallet: &WalletJson,
+        indexes: &mut WalletIndexes,
+        index: u32,
+        gap_limit: u32,
+    ) -> Result<()> {
+        let (start, end) = match range_to_import(index, gap_limit, indexes.imported_range) {
+            Some(range) => range,
+            None => return Ok(()),
+        };
+        let info: serde_json::Value = self.client.call("getwalletinfo", &[])?;
+        if info["descriptors"] == true {
+            return Ok(());
+        }
+        info!("extending imported range from {} to {}", start, end);
+        self.import_multi(wallet, start, end)?;
+        indexes.imported_range = end;
+        self.context.save_index(indexes)
+    }
+}
+
+/// Range of indexes, both included, to import in a legacy wallet of the node so that the node
+/// knows `index` and the following `gap_limit` addresses, None if they are already imported.
+/// The range is extended by at least `DESCRIPTOR_RANGE` to avoid importing at every new address
+/// and it's capped to the last non-hardened derivation index
+fn range_to_import(index: u32, gap_limit: u32, imported_range: u32) -> Option<(u32, u32)> {
+    let needed = index.saturating_add(gap_limit).min(MAX_NON_HARDENED_INDEX);
+    if needed <= imported_range {
+        return None;
+    }
+    let end = index
+        .saturating_add(gap_limit.max(DESCRIPTOR_RANGE))
+        .min(MAX_NON_HARDENED_INDEX);
+    Some((imported_range + 1, end))
+}
+
+const MAX_NON_HARDENED_INDEX: u32 = (1 << 31) - 1;
+
+#[cfg(test)]
+mod tests {
+    use crate::online::get_address::{range_to_import, MAX_NON_HARDENED_INDEX};
+    use crate::DESCRIPTOR_RANGE;
+
+    #[test]
+    fn test_range_to_import() {
+        // index and gap already imported
+        assert_eq!(range_to_import(0, 20, DESCRIPTOR_RANGE), None);
+        assert_eq!(range_to_import(980, 20, DESCRIPTOR_RANGE), None);
+
+        // the gap goes beyond the imported range
+        assert_eq!(
+            range_to_import(981, 20, DESCRIPTOR_RANGE),
+            Some((1001, 981 + DESCRIPTOR_RANGE))
+        );
+        assert_eq!(
+            range_to_import(5000, 20, DESCRIPTOR_RANGE),
+            Some((1001, 5000 + DESCRIPTOR_RANGE))
+        );
+
+        // a gap limit bigger than the default range is entirely imported
+        assert_eq!(
+            range_to_import(0, 5000, DESCRIPTOR_RANGE),
+            Some((1001, 5000))
+        );
+
+        // derivation indexes can't exceed the non-hardened range
+        assert_eq!(
+            range_to_import(MAX_NON_HARDENED_INDEX - 10, 20, DESCRIPTOR_RANGE),
+            Some((1001, MAX_NON_HARDENED_INDEX))
+        );
+        assert_eq!(
+            range_to_import(MAX_NON_HARDENED_INDEX + 1, 20, DESCRIPTOR_RANGE),
+            Some((1001, MAX_NON_HARDENED_INDEX))
+        );
+        assert_eq!(
+            range_to_import(MAX_NON_HARDENED_INDEX + 1, 20, MAX_NON_HARDENED_INDEX),
+            None
+        );
+    }
 }
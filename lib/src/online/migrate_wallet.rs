@@ -0,0 +1,23 @@
+use crate::*;
+use bitcoincore_rpc::RpcApi;
+use log::info;
+use serde_json::Value;
+
+impl Wallet {
+    /// Convert the legacy watch-only wallet in the node to a descriptor wallet with the
+    /// `migratewallet` rpc (bitcoin core 24+), then import the wallet descriptors as active so
+    /// that the node extends their range automatically
+    pub fn migrate(&self) -> Result<Value> {
+        let info: Value = self.client.call("getwalletinfo", &[])?;
+        if info["descriptors"] == true {
+            return Err("the wallet in the node is already a descriptor wallet".into());
+        }
+        let (wallet, _) = self.context.load_wallet_and_index()?;
+        let migrated: Value = self
+            .client
+            .call("migratewallet", &[wallet.name.clone().into()])?;
+        info!("migratewallet {:?}", migrated);
+        self.import_active_descriptors(&wallet)?;
+        Ok(migrated)
+    }
+}
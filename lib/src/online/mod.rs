@@ -1,15 +1,13 @@
 use crate::*;
-use bitcoin::util::bip32::ExtendedPubKey;
 use bitcoincore_rpc::{Auth, Client, RpcApi};
 use log::{debug, info};
-use std::fs;
-use std::path::PathBuf;
 
 pub mod balance;
 pub mod create_tx;
 pub mod create_wallet;
 pub mod get_address;
 pub mod list_coins;
+pub mod migrate_wallet;
 pub mod rescan;
 pub mod send_tx;
 
@@ -27,16 +25,6 @@ impl Wallet {
     }
 }
 
-fn read_xpubs_files(paths: &[PathBuf]) -> Result<Vec<ExtendedPubKey>> {
-    let mut xpubs = vec![];
-    for xpub_path in paths.iter() {
-        let content = fs::read(xpub_path)?;
-        let json: PublicMasterKey = serde_json::from_slice(&content)?;
-        xpubs.push(json.xpub);
-    }
-    Ok(xpubs)
-}
-
 impl Wallet {
     pub fn load_if_unloaded(&self, wallet_name: &str) -> Result<()> {
         match self.client.load_wallet(wallet_name) {